@@ -5,3 +5,16 @@ pub mod network;
 pub mod authentication;
 pub mod packet_sniffer;
 pub mod httpserver;
+pub mod boot_tracker;
+pub mod host_inventory;
+pub mod process_monitor;
+pub mod rootkit_detection;
+pub mod kernel_modules;
+pub mod persistence;
+pub mod accounts;
+pub mod privileged_files;
+pub mod outbound_connections;
+pub mod cryptominer;
+pub mod webshell;
+pub mod audit_log;
+pub mod honeyfiles;
\ No newline at end of file
@@ -0,0 +1,423 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+use crate::lara_core::core_enums::*;
+use crate::lara_core::core_structs::*;
+use crate::lara_core::core_traits::AnalysisModule;
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
+
+const PASSWD: &str = "/etc/passwd";
+const SHADOW: &str = "/etc/shadow";
+const GROUP: &str = "/etc/group";
+const SUDOERS: &str = "/etc/sudoers";
+const SUDOERS_FOLDER: &str = "/etc/sudoers.d";
+// Accounts below this uid belong to services and should never be logged into
+const FIRST_HUMAN_UID: u32 = 1000;
+// Shells that mean the account can't log in
+const NO_LOGIN_SHELLS: [&str; 4] = ["/usr/sbin/nologin", "/sbin/nologin", "/bin/false", "/usr/bin/false"];
+
+#[derive(Debug, Clone, PartialEq)]
+struct Account {
+    uid: u32,
+    gid: u32,
+    home: String,
+    shell: String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct CurrentData {
+    accounts: BTreeMap<String, Account>,
+    // username -> password hash field from shadow
+    passwords: BTreeMap<String, String>,
+    // group name -> (gid, members)
+    groups: BTreeMap<String, (u32, BTreeSet<String>)>,
+    // (file, rule) for every rule line in sudoers and sudoers.d
+    sudo_rules: BTreeSet<(String, String)>,
+}
+
+pub struct AccountMonitor {
+    // This is the data generated by gatherData in current tick, it will be erased by the next tick
+    current_data: CurrentData,
+    //Everything else is persistent memory. The data you set in these will be remembered between ticks
+    // None until the first tick has recorded the existing accounts
+    previous: Option<CurrentData>,
+    privileged_groups: Vec<String>,
+    module_name: String,
+    source: Arc<dyn SystemSource>,
+    failures: Vec<Log>,
+}
+
+impl AnalysisModule for AccountMonitor {
+    fn get_data(&mut self) -> bool {
+        let (passwd, shadow, group) = match (self.read(PASSWD), self.read(SHADOW), self.read(GROUP)) {
+            (Some(passwd), Some(shadow), Some(group)) => (passwd, shadow, group),
+            _ => return false,
+        };
+        let mut sudo_rules = BTreeSet::new();
+        let mut sudoers = vec![SUDOERS.to_string()];
+        match self.source.read_dir(SUDOERS_FOLDER) {
+            Ok(mut names) => {
+                names.sort();
+                // sudo skips files in sudoers.d that end in ~ or contain a dot
+                sudoers.extend(names.into_iter().filter(|name| !name.ends_with('~') && !name.contains('.')).map(|name| format!("{}/{}", SUDOERS_FOLDER, name)));
+            }
+            Err(ChromiaError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => self.failures.push(Log::ids_failure(self.module_name.clone(), &format!("Failed to list {}", SUDOERS_FOLDER), &e)),
+        }
+        for path in sudoers {
+            match self.source.read_to_string(&path) {
+                Ok(content) => sudo_rules.extend(parse_sudoers(&content).into_iter().map(|rule| (path.clone(), rule))),
+                Err(ChromiaError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => self.failures.push(Log::ids_failure(self.module_name.clone(), &format!("Failed to read {}", path), &e)),
+            }
+        }
+        self.current_data = CurrentData {
+            accounts: parse_passwd(&passwd),
+            passwords: parse_shadow(&shadow),
+            groups: parse_group(&group),
+            sudo_rules,
+        };
+        true
+    }
+
+    fn get_testing_data(&mut self) -> bool {
+        todo!()
+    }
+
+    fn perform_analysis(&mut self) -> Vec<Log> {
+        let mut results = std::mem::take(&mut self.failures);
+        // Nothing was read this tick
+        if self.current_data.accounts.is_empty() {
+            return results;
+        }
+        let current = std::mem::take(&mut self.current_data);
+        match self.previous.take() {
+            Some(previous) => results.append(&mut self.report_changes(&previous, &current)),
+            None => {
+                results.push(Log::new(
+                    LogType::Info,
+                    self.module_name.clone(),
+                    format!("Recorded {} accounts, {} groups and {} sudo rules", current.accounts.len(), current.groups.len(), current.sudo_rules.len()),
+                ));
+                // Problems that were already there when Chromia started are still worth knowing about
+                for (name, account) in &current.accounts {
+                    if account.uid == 0 && name != "root" {
+                        results.push(Log::new(LogType::Critical, self.module_name.clone(), format!("Account '{}' has uid 0, giving it full root access", name)));
+                    }
+                }
+                for name in current.passwords.iter().filter(|(_, hash)| hash.is_empty()).map(|(name, _)| name) {
+                    results.push(Log::new(LogType::Serious, self.module_name.clone(), format!("Account '{}' has an empty password, anyone can log in as it", name)));
+                }
+            }
+        }
+        self.previous = Some(current);
+        results
+    }
+
+    fn get_name(&self) -> String {
+        self.module_name.clone()
+    }
+
+    fn build_config_fields(&self) -> Vec<ConfigField> {
+        vec![
+            ConfigField::new("PrivilegedGroups".to_owned(), "Groups that give root or root equivalent access. Adding a user to one of these raises a serious alert".to_owned(), ConfigFieldType::String, self.privileged_groups.clone(), true),
+        ]
+    }
+
+    fn retrieve_config_data(&mut self, data: HashMap<String, Vec<String>>) -> bool {
+        for (field, vals) in data {
+            if field == "PrivilegedGroups" {
+                self.privileged_groups = vals.into_iter().filter(|val| !val.is_empty()).collect();
+            }
+        }
+        true
+    }
+}
+
+impl AccountMonitor {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+
+    fn read(&mut self, path: &str) -> Option<String> {
+        match self.source.read_to_string(path) {
+            Ok(content) => Some(content),
+            Err(e) => {
+                self.failures.push(Log::ids_failure(self.module_name.clone(), &format!("Failed to read {}", path), &e));
+                None
+            }
+        }
+    }
+
+    fn log(&self, log_type: LogType, message: String) -> Log {
+        Log::new(log_type, self.module_name.clone(), message)
+    }
+
+    fn report_changes(&self, previous: &CurrentData, current: &CurrentData) -> Vec<Log> {
+        let mut results = Vec::new();
+        for (name, account) in &current.accounts {
+            match previous.accounts.get(name) {
+                None => {
+                    if account.uid == 0 {
+                        results.push(self.log(LogType::Critical, format!("New account '{}' was created with uid 0, giving it full root access (home {}, shell {})", name, account.home, account.shell)));
+                    } else {
+                        results.push(self.log(LogType::Warning, format!("New account '{}' was created (uid {}, gid {}, home {}, shell {})", name, account.uid, account.gid, account.home, account.shell)));
+                    }
+                }
+                Some(old) => {
+                    if old.uid != account.uid {
+                        let log_type = if account.uid == 0 { LogType::Critical } else { LogType::Warning };
+                        results.push(self.log(log_type, format!("uid of account '{}' changed from {} to {}", name, old.uid, account.uid)));
+                    }
+                    if old.shell != account.shell {
+                        // A service account getting a login shell is a common way to keep a backdoor
+                        let log_type = if account.uid < FIRST_HUMAN_UID && NO_LOGIN_SHELLS.contains(&old.shell.as_str()) && !NO_LOGIN_SHELLS.contains(&account.shell.as_str()) {
+                            LogType::Serious
+                        } else {
+                            LogType::Info
+                        };
+                        let kind = if account.uid < FIRST_HUMAN_UID { "system account" } else { "account" };
+                        results.push(self.log(log_type, format!("Shell of {} '{}' (uid {}) changed from {} to {}", kind, name, account.uid, old.shell, account.shell)));
+                    }
+                    if old.home != account.home {
+                        results.push(self.log(LogType::Info, format!("Home folder of account '{}' changed from {} to {}", name, old.home, account.home)));
+                    }
+                }
+            }
+        }
+        for name in previous.accounts.keys().filter(|name| !current.accounts.contains_key(*name)) {
+            results.push(self.log(LogType::Info, format!("Account '{}' was removed", name)));
+        }
+
+        for (name, hash) in &current.passwords {
+            let old = match previous.passwords.get(name) {
+                Some(old) => old,
+                // New accounts are already reported above, only flag them if they have no password
+                None if hash.is_empty() => {
+                    results.push(self.log(LogType::Serious, format!("New account '{}' has an empty password, anyone can log in as it", name)));
+                    continue;
+                }
+                None => continue,
+            };
+            if old == hash {
+                continue;
+            }
+            if hash.is_empty() {
+                results.push(self.log(LogType::Serious, format!("Password of account '{}' was removed, anyone can now log in as it", name)));
+            } else if is_locked(hash) && !is_locked(old) {
+                results.push(self.log(LogType::Info, format!("Password of account '{}' was locked", name)));
+            } else if is_locked(old) && !is_locked(hash) {
+                results.push(self.log(LogType::Warning, format!("Account '{}' was unlocked and can now log in with a password", name)));
+            } else {
+                results.push(self.log(LogType::Info, format!("Password of account '{}' was changed", name)));
+            }
+        }
+
+        for (group, (gid, members)) in &current.groups {
+            let old_members = previous.groups.get(group).map(|(_, members)| members.clone()).unwrap_or_default();
+            let privileged = self.privileged_groups.contains(group);
+            for member in members.difference(&old_members) {
+                let log_type = if privileged { LogType::Serious } else { LogType::Info };
+                results.push(self.log(log_type, format!("User '{}' was added to group '{}'{}", member, group, if privileged { ", giving it root level access" } else { "" })));
+            }
+            for member in old_members.difference(members) {
+                results.push(self.log(LogType::Info, format!("User '{}' was removed from group '{}'", member, group)));
+            }
+            // Setting a user's primary group puts them in the group without touching /etc/group
+            if privileged {
+                for (name, account) in &current.accounts {
+                    // New accounts are reported with their gid when they are created
+                    let changed_to_group = previous.accounts.get(name).is_some_and(|old| old.gid != *gid);
+                    if account.gid == *gid && changed_to_group && !members.contains(name) {
+                        results.push(self.log(LogType::Serious, format!("Primary group of user '{}' was set to '{}', giving it root level access", name, group)));
+                    }
+                }
+            }
+        }
+
+        for (path, rule) in current.sudo_rules.difference(&previous.sudo_rules) {
+            if rule.contains("NOPASSWD") {
+                results.push(self.log(LogType::Serious, format!("New sudo rule without a password in {}: {}", path, rule)));
+            } else {
+                results.push(self.log(LogType::Warning, format!("New sudo rule in {}: {}", path, rule)));
+            }
+        }
+        for (path, rule) in previous.sudo_rules.difference(&current.sudo_rules) {
+            results.push(self.log(LogType::Info, format!("Sudo rule removed from {}: {}", path, rule)));
+        }
+        results
+    }
+}
+
+// Locked passwords start with ! or *, the rest of the field is the old hash
+fn is_locked(hash: &str) -> bool {
+    hash.starts_with('!') || hash.starts_with('*')
+}
+
+fn parse_passwd(content: &str) -> BTreeMap<String, Account> {
+    let mut accounts = BTreeMap::new();
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        if line.starts_with('#') || fields.len() < 7 {
+            continue;
+        }
+        let (uid, gid) = match (fields[2].parse(), fields[3].parse()) {
+            (Ok(uid), Ok(gid)) => (uid, gid),
+            _ => continue,
+        };
+        accounts.insert(
+            fields[0].to_string(),
+            Account {
+                uid,
+                gid,
+                home: fields[5].to_string(),
+                shell: fields[6].to_string(),
+            },
+        );
+    }
+    accounts
+}
+
+fn parse_shadow(content: &str) -> BTreeMap<String, String> {
+    content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            Some((fields.next()?.to_string(), fields.next()?.to_string()))
+        })
+        .filter(|(name, _)| !name.is_empty())
+        .collect()
+}
+
+fn parse_group(content: &str) -> BTreeMap<String, (u32, BTreeSet<String>)> {
+    let mut groups = BTreeMap::new();
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        if line.starts_with('#') || fields.len() < 4 {
+            continue;
+        }
+        if let Ok(gid) = fields[2].parse() {
+            let members = fields[3].split(',').map(str::trim).filter(|member| !member.is_empty()).map(|member| member.to_string()).collect();
+            groups.insert(fields[0].to_string(), (gid, members));
+        }
+    }
+    groups
+}
+
+// Every rule, alias and Defaults line with comments and continuations joined up. #include lines are kept as they
+// pull in other files
+fn parse_sudoers(content: &str) -> Vec<String> {
+    let mut rules = Vec::new();
+    let mut pending = String::new();
+    for line in content.lines() {
+        let line = line.trim();
+        let is_include = line.starts_with("#include") || line.starts_with("@include");
+        if pending.is_empty() && (line.is_empty() || (line.starts_with('#') && !is_include)) {
+            continue;
+        }
+        match line.strip_suffix('\\') {
+            Some(start) => {
+                pending.push_str(start.trim_end());
+                pending.push(' ');
+            }
+            None => {
+                pending.push_str(line);
+                rules.push(pending.split_whitespace().collect::<Vec<&str>>().join(" "));
+                pending.clear();
+            }
+        }
+    }
+    rules
+}
+
+impl Default for AccountMonitor {
+    fn default() -> Self {
+        Self {
+            current_data: CurrentData::default(),
+            previous: None,
+            privileged_groups: ["sudo", "wheel", "admin", "root", "docker", "lxd", "disk", "shadow"].iter().map(|&s| s.to_string()).collect(),
+            module_name: String::from("Accounts"),
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::source::FakeSource;
+
+    const PASSWD_FILE: &str = "root:x:0:0:root:/root:/bin/bash\nwww-data:x:33:33:www-data:/var/www:/usr/sbin/nologin\nalice:x:1000:1000::/home/alice:/bin/bash\n";
+    const SHADOW_FILE: &str = "root:$y$j9T$abc:19800:0:99999:7:::\nwww-data:*:19800:0:99999:7:::\nalice:$y$j9T$def:19800:0:99999:7:::\n";
+    const GROUP_FILE: &str = "root:x:0:\nsudo:x:27:alice\nwww-data:x:33:\nalice:x:1000:\n";
+
+    fn fake_host() -> Arc<FakeSource> {
+        let source = Arc::new(FakeSource::new());
+        source.set_file(PASSWD, PASSWD_FILE);
+        source.set_file(SHADOW, SHADOW_FILE);
+        source.set_file(GROUP, GROUP_FILE);
+        source.set_file(SUDOERS, "Defaults env_reset\nroot ALL=(ALL:ALL) ALL\n%sudo ALL=(ALL:ALL) ALL\n@includedir /etc/sudoers.d\n");
+        source
+    }
+
+    fn monitor_after_baseline(source: Arc<FakeSource>) -> AccountMonitor {
+        let mut monitor = AccountMonitor::with_source(source);
+        assert!(monitor.get_data());
+        let logs = monitor.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].message, "Recorded 3 accounts, 4 groups and 4 sudo rules");
+        monitor
+    }
+
+    fn tick(monitor: &mut AccountMonitor) -> Vec<String> {
+        assert!(monitor.get_data());
+        monitor.perform_analysis().iter().map(|log| log.build_alert()).collect()
+    }
+
+    #[test]
+    fn test_backdoor_account_changes() {
+        let source = fake_host();
+        let mut monitor = monitor_after_baseline(source.clone());
+
+        source.set_file(PASSWD, &format!("{}toor:x:0:0::/root:/bin/bash\n", PASSWD_FILE.replace("/var/www:/usr/sbin/nologin", "/var/www:/bin/bash")));
+        source.set_file(SHADOW, &format!("{}toor::19800:0:99999:7:::\n", SHADOW_FILE));
+        source.set_file(GROUP, &GROUP_FILE.replace("sudo:x:27:alice", "sudo:x:27:alice,www-data"));
+        let alerts = tick(&mut monitor);
+        assert_eq!(alerts.len(), 4);
+        assert!(alerts[0].contains("[CRITICAL]") && alerts[0].contains("New account 'toor' was created with uid 0"));
+        assert!(alerts[1].contains("[Serious]") && alerts[1].contains("Shell of system account 'www-data' (uid 33) changed from /usr/sbin/nologin to /bin/bash"));
+        assert!(alerts[2].contains("[Serious]") && alerts[2].contains("New account 'toor' has an empty password"));
+        assert!(alerts[3].contains("[Serious]") && alerts[3].contains("User 'www-data' was added to group 'sudo'"));
+
+        assert!(tick(&mut monitor).is_empty());
+    }
+
+    #[test]
+    fn test_nopasswd_sudo_rule() {
+        let source = fake_host();
+        let mut monitor = monitor_after_baseline(source.clone());
+
+        source.set_file("/etc/sudoers.d/backup", "# added by backup tool\nbackup ALL=(root) \\\n    NOPASSWD: /usr/bin/rsync\n");
+        source.set_file(SHADOW, &SHADOW_FILE.replace("$y$j9T$def", "$y$j9T$ghi"));
+        let alerts = tick(&mut monitor);
+        assert_eq!(alerts.len(), 2);
+        assert!(alerts[0].contains("[Info]") && alerts[0].contains("Password of account 'alice' was changed"));
+        assert!(alerts[1].contains("[Serious]") && alerts[1].contains("New sudo rule without a password in /etc/sudoers.d/backup: backup ALL=(root) NOPASSWD: /usr/bin/rsync"));
+    }
+
+    #[test]
+    fn test_unreadable_shadow_is_reported() {
+        let source = fake_host();
+        source.remove_file(SHADOW);
+        let mut monitor = AccountMonitor::with_source(source);
+        assert!(!monitor.get_data());
+        let logs = monitor.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("Failed to read /etc/shadow"));
+    }
+}
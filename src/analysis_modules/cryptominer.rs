@@ -0,0 +1,313 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::lara_core::core_enums::*;
+use crate::lara_core::core_structs::*;
+use crate::lara_core::core_traits::AnalysisModule;
+use crate::linux_bridge::network::{parse_proc_net, SOCKET_TABLES};
+use crate::linux_bridge::process::{clock_ticks_per_second, ProcessInfo};
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
+use crate::linux_bridge::system::parse_uptime;
+
+const UPTIME: &str = "/proc/uptime";
+
+// pid and start time together, pids get reused so the pid alone doesn't identify a process between ticks
+type ProcessKey = (u32, u64);
+
+fn key(process: &ProcessInfo) -> ProcessKey {
+    (process.pid, process.start_time)
+}
+
+// How sure we are a process is a miner. Each process is reported once per level it reaches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Confidence {
+    Possible,
+    Likely,
+    Certain,
+}
+
+#[derive(Debug, Clone, Default)]
+struct CurrentData {
+    // Seconds since boot when the data was read
+    uptime: f64,
+    processes: Vec<ProcessInfo>,
+    // pid -> remote addresses it is connected to on a mining pool port
+    pool_connections: HashMap<u32, Vec<SocketAddr>>,
+}
+
+pub struct CryptominerDetector {
+    // This is the data generated by gatherData in current tick, it will be erased by the next tick
+    current_data: CurrentData,
+    //Everything else is persistent memory. The data you set in these will be remembered between ticks
+    // (uptime, cpu_time) of each process last tick, to work out how much CPU it used since
+    last_sample: HashMap<ProcessKey, (f64, u64)>,
+    // Uptime each process first went over the CPU threshold, cleared as soon as it drops below
+    busy_since: HashMap<ProcessKey, f64>,
+    reported: HashMap<ProcessKey, Confidence>,
+    signatures: Vec<String>,
+    pool_ports: HashSet<u16>,
+    cpu_threshold: f64,
+    sustained_seconds: f64,
+    module_name: String,
+    source: Arc<dyn SystemSource>,
+    failures: Vec<Log>,
+}
+
+impl AnalysisModule for CryptominerDetector {
+    fn get_data(&mut self) -> bool {
+        let uptime = match self.source.read_to_string(UPTIME).map(|uptime| parse_uptime(&uptime)) {
+            Ok(Some(uptime)) => uptime,
+            Ok(None) => {
+                self.failures.push(Log::ids_failure(self.module_name.clone(), "Failed to read the uptime", &ChromiaError::Parse(UPTIME.to_string())));
+                return false;
+            }
+            Err(e) => {
+                self.failures.push(Log::ids_failure(self.module_name.clone(), "Failed to read the uptime", &e));
+                return false;
+            }
+        };
+        let mut pool_sockets: HashMap<u64, SocketAddr> = HashMap::new();
+        for (protocol, path) in SOCKET_TABLES.iter().filter(|(protocol, _)| protocol.starts_with("tcp")) {
+            // tcp6 is missing when IPv6 is disabled, a missing tcp table only costs us the network signal
+            if let Ok(table) = self.source.read_to_string(path) {
+                for socket in parse_proc_net(&table, protocol).into_iter().filter(|socket| socket.is_established() && self.pool_ports.contains(&socket.remote_port)) {
+                    pool_sockets.insert(socket.inode, SocketAddr::new(socket.remote_addr, socket.remote_port));
+                }
+            }
+        }
+        let mut pool_connections: HashMap<u32, Vec<SocketAddr>> = HashMap::new();
+        if !pool_sockets.is_empty() {
+            for (pid, fds) in self.source.socket_fds() {
+                let mut remotes: Vec<SocketAddr> = fds.iter().filter_map(|(_, inode)| pool_sockets.get(inode).copied()).collect();
+                if !remotes.is_empty() {
+                    remotes.sort();
+                    remotes.dedup();
+                    pool_connections.insert(pid, remotes);
+                }
+            }
+        }
+        self.current_data = CurrentData {
+            uptime,
+            processes: self.source.processes(),
+            pool_connections,
+        };
+        true
+    }
+
+    fn get_testing_data(&mut self) -> bool {
+        todo!()
+    }
+
+    fn perform_analysis(&mut self) -> Vec<Log> {
+        let mut results = std::mem::take(&mut self.failures);
+        let current = std::mem::take(&mut self.current_data);
+        if current.processes.is_empty() {
+            return results;
+        }
+        let ticks_per_second = clock_ticks_per_second() as f64;
+        let mut processes: Vec<&ProcessInfo> = current.processes.iter().collect();
+        processes.sort_by_key(|process| process.pid);
+
+        let mut sample = HashMap::new();
+        for process in processes {
+            sample.insert(key(process), (current.uptime, process.cpu_time));
+            // Percent of one core used since last tick, so a miner on four cores shows around 400
+            let cpu = match self.last_sample.get(&key(process)) {
+                Some((uptime, cpu_time)) if current.uptime > *uptime => (process.cpu_time.saturating_sub(*cpu_time) as f64 / ticks_per_second) / (current.uptime - uptime) * 100.0,
+                _ => 0.0,
+            };
+            let busy_for = if cpu >= self.cpu_threshold {
+                Some(current.uptime - *self.busy_since.entry(key(process)).or_insert(current.uptime))
+            } else {
+                self.busy_since.remove(&key(process));
+                None
+            };
+            let sustained = busy_for.filter(|seconds| *seconds >= self.sustained_seconds);
+            let signature = self.matched_signature(process).map(|signature| signature.to_string());
+            let destinations = current.pool_connections.get(&process.pid);
+
+            let confidence = match (signature.is_some(), sustained.is_some(), destinations.is_some()) {
+                (true, true, _) | (true, _, true) | (false, true, true) => Confidence::Certain,
+                (true, false, false) => Confidence::Likely,
+                (false, true, false) | (false, false, true) => Confidence::Possible,
+                (false, false, false) => continue,
+            };
+            if self.reported.get(&key(process)).is_some_and(|reported| *reported >= confidence) {
+                continue;
+            }
+            self.reported.insert(key(process), confidence);
+
+            let mut reasons = Vec::new();
+            if let Some(signature) = signature {
+                reasons.push(format!("matches miner signature '{}'", signature));
+            }
+            if let Some(seconds) = sustained {
+                reasons.push(format!("has used {:.0}% CPU for {:.0}s", cpu, seconds));
+            }
+            if let Some(destinations) = destinations {
+                let destinations: Vec<String> = destinations.iter().map(|destination| destination.to_string()).collect();
+                reasons.push(format!("is connected to mining pool port(s) at {}", destinations.join(", ")));
+            }
+            let (log_type, title) = match confidence {
+                Confidence::Certain => (LogType::Critical, "Cryptominer running"),
+                Confidence::Likely => (LogType::Serious, "Likely cryptominer"),
+                Confidence::Possible => (LogType::Warning, "Possible cryptominer"),
+            };
+            let user = self.source.username(process.uid).unwrap_or_else(|| process.uid.to_string());
+            let exe = process.exe.as_ref().map(|exe| exe.display().to_string()).unwrap_or_else(|| "unknown".to_string());
+            results.push(Log::new(
+                log_type,
+                self.module_name.clone(),
+                format!("{}: pid {} exe {} user {} cmd '{}' {}", title, process.pid, exe, user, process.command_line(), reasons.join(" and ")),
+            ));
+        }
+        self.busy_since.retain(|process, _| sample.contains_key(process));
+        self.reported.retain(|process, _| sample.contains_key(process));
+        self.last_sample = sample;
+        results
+    }
+
+    fn get_name(&self) -> String {
+        self.module_name.clone()
+    }
+
+    fn build_config_fields(&self) -> Vec<ConfigField> {
+        let mut ports: Vec<String> = self.pool_ports.iter().map(|port| port.to_string()).collect();
+        ports.sort();
+        vec![
+            ConfigField::new("Signatures".to_owned(), "Text that marks a process as a miner when found in its name or command line (not case sensitive)".to_owned(), ConfigFieldType::String, self.signatures.clone(), true),
+            ConfigField::new("PoolPorts".to_owned(), "Remote ports mining pools commonly listen on".to_owned(), ConfigFieldType::Integer, ports, true),
+            ConfigField::new("CpuThreshold".to_owned(), "Percent of one core a process must use to count as busy, a process using four cores fully is at 400".to_owned(), ConfigFieldType::Float, vec![self.cpu_threshold.to_string()], false),
+            ConfigField::new("SustainedSeconds".to_owned(), "Seconds a process must stay over CpuThreshold before it counts towards a miner alert".to_owned(), ConfigFieldType::Integer, vec![self.sustained_seconds.to_string()], false),
+        ]
+    }
+
+    fn retrieve_config_data(&mut self, data: HashMap<String, Vec<String>>) -> bool {
+        for (field, vals) in data {
+            let vals: Vec<String> = vals.into_iter().filter(|val| !val.is_empty()).collect();
+            match field.as_str() {
+                "Signatures" => self.signatures = vals.into_iter().map(|signature| signature.to_lowercase()).collect(),
+                "PoolPorts" => match vals.iter().map(|val| val.parse::<u16>()).collect::<Result<HashSet<u16>, _>>() {
+                    Ok(ports) => self.pool_ports = ports,
+                    Err(_) => {
+                        println!("PoolPorts must be a list of port numbers");
+                        return false;
+                    }
+                },
+                "CpuThreshold" => match vals.first().and_then(|v| v.parse::<f64>().ok()) {
+                    Some(threshold) if threshold > 0.0 => self.cpu_threshold = threshold,
+                    _ => {
+                        println!("CpuThreshold must be a positive number");
+                        return false;
+                    }
+                },
+                "SustainedSeconds" => match vals.first().and_then(|v| v.parse::<u32>().ok()) {
+                    Some(seconds) => self.sustained_seconds = seconds as f64,
+                    None => {
+                        println!("SustainedSeconds must be a whole number of seconds");
+                        return false;
+                    }
+                },
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+impl CryptominerDetector {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+
+    fn matched_signature(&self, process: &ProcessInfo) -> Option<&str> {
+        let name = process.name.to_lowercase();
+        let command_line = process.command_line().to_lowercase();
+        self.signatures
+            .iter()
+            .find(|signature| name.contains(signature.as_str()) || command_line.contains(signature.as_str()))
+            .map(|signature| signature.as_str())
+    }
+}
+
+impl Default for CryptominerDetector {
+    fn default() -> Self {
+        Self {
+            current_data: CurrentData::default(),
+            last_sample: HashMap::new(),
+            busy_since: HashMap::new(),
+            reported: HashMap::new(),
+            signatures: ["xmrig", "xmr-stak", "minerd", "cpuminer", "cgminer", "bfgminer", "ethminer", "nbminer", "lolminer", "kdevtmpfsi", "kinsing", "--donate-level", "stratum+tcp://", "stratum+ssl://", "stratum2+tcp://", "--randomx", "--cpu-max-threads-hint"]
+                .iter()
+                .map(|&s| s.to_string())
+                .collect(),
+            pool_ports: [3333, 3334, 4444, 5555, 6666, 7777, 8888, 9999, 14433, 14444, 45560, 45700].into_iter().collect(),
+            cpu_threshold: 80.0,
+            sustained_seconds: 60.0,
+            module_name: String::from("Cryptominer"),
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::source::FakeSource;
+
+    const HEADER: &str = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n";
+    // Connection to 198.51.100.23:3333
+    const POOL: &str = "   0: 0A00000A:9C40 176433C6:0D05 01 00000000:00000000 00:00000000 00000000    33        0 30002 1 0000000000000000 20 4 1 10 -1\n";
+
+    fn fake_process(pid: u32, name: &str, argv: &[&str], exe: &str, cpu_time: u64) -> ProcessInfo {
+        ProcessInfo::fake(pid, name).with_uid(33).with_argv(argv).with_exe(exe).with_cpu_time(cpu_time)
+    }
+
+    // Sets the fake clock to `uptime` seconds since boot with `process` as the only process running
+    fn tick(detector: &mut CryptominerDetector, source: &FakeSource, uptime: f64, process: ProcessInfo) -> Vec<Log> {
+        source.set_file(UPTIME, &format!("{:.2} 1000.00\n", uptime));
+        source.set_processes(vec![process]);
+        assert!(detector.get_data());
+        detector.perform_analysis()
+    }
+
+    #[test]
+    fn test_renamed_miner_found_by_cpu_and_pool_connection() {
+        let source = Arc::new(FakeSource::new());
+        source.add_user(33, "www-data");
+        source.set_file("/proc/net/tcp", &format!("{}{}", HEADER, POOL));
+        source.set_socket_fd(4410, 7, 30002);
+        let mut detector = CryptominerDetector::with_source(source.clone());
+        let ticks = clock_ticks_per_second();
+        let miner = |cpu_seconds: u64| fake_process(4410, "kworker", &["/tmp/.k/kworker", "-c", "conf.json"], "/tmp/.k/kworker", cpu_seconds * ticks);
+
+        // A pool connection on its own is only a possible miner
+        let logs = tick(&mut detector, &source, 100.0, miner(0));
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[Warning]"));
+        // Four cores for 30s is not sustained yet
+        assert!(tick(&mut detector, &source, 130.0, miner(120)).is_empty());
+        let logs = tick(&mut detector, &source, 190.0, miner(360));
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[CRITICAL]"));
+        assert_eq!(logs[0].message, "Cryptominer running: pid 4410 exe /tmp/.k/kworker user www-data cmd '/tmp/.k/kworker -c conf.json' has used 400% CPU for 60s and is connected to mining pool port(s) at 198.51.100.23:3333");
+        assert!(tick(&mut detector, &source, 220.0, miner(480)).is_empty());
+    }
+
+    #[test]
+    fn test_signature_in_command_line() {
+        let source = Arc::new(FakeSource::new());
+        source.set_file("/proc/net/tcp", HEADER);
+        let mut detector = CryptominerDetector::with_source(source.clone());
+        let logs = tick(&mut detector, &source, 100.0, fake_process(5000, "systemd-helper", &["./systemd-helper", "--donate-level=1", "-o", "pool.example:443"], "/dev/shm/systemd-helper", 0));
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[Serious]"));
+        assert!(logs[0].message.starts_with("Likely cryptominer: pid 5000 exe /dev/shm/systemd-helper user 33"));
+        assert!(logs[0].message.ends_with("matches miner signature '--donate-level'"));
+    }
+}
@@ -1,10 +1,10 @@
 use crate::lara_core::*;
+use core_enums::ChromiaError;
 use core_structs::*;
 use core_traits::AnalysisModule;
-use dirhash::hash;
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
 use std::collections::HashMap;
-use std::path::Path;
-use std::process::Command;
+use std::sync::Arc;
 #[derive(Debug, Clone)]
 struct CurrentData {
     new_hashes_files: HashMap<String, String>,
@@ -19,58 +19,29 @@ pub struct FIM {
     pub previous_hashes_folders: HashMap<String, String>,
     module_name: String,
     firstLoop: bool,
+    source: Arc<dyn SystemSource>,
+    failures: Vec<Log>,
+    services: Arc<CoreServices>,
+    // path -> (modified, size) of the files in monitored folders when they were last scanned for signatures
+    folder_files: HashMap<String, (i64, u64)>,
 }
 
 // Function to generate hash using the key
-fn genhash(key: &str) -> (bool, String) {
-    let output = match Command::new("/bin/Chromia/Data/b3sum")
-        .arg(key)
-        .arg("--no-names")
-        .output()
-    {
-        Ok(output) => output,
-        Err(err) => {
-            eprintln!("Failed to execute command for key '{}': {}", key, err);
-            return (false, String::new());
-        }
-    };
-    // Convert output to string
-    let stdout_str = String::from_utf8_lossy(&output.stdout).into_owned();
-    let stderr_str = String::from_utf8_lossy(&output.stderr).into_owned();
-
-    //println!("{}", stdout_str);
-
-    if !stderr_str.is_empty() {
-        eprintln!("stderr for key '{}': {}", key, stderr_str);
-    }
-
-    (true, stdout_str)
+fn genhash(source: &dyn SystemSource, key: &str) -> Result<String, ChromiaError> {
+    source.file_hash(key)
 }
 
-fn genhash_folders(key: &str) -> (bool, String) {
-    let dir_hash = hash(Path::new(key));
-
+fn genhash_folders(source: &dyn SystemSource, key: &str) -> Result<String, ChromiaError> {
     // Calculate the hash of the directory
-    match dir_hash {
-        Ok(hash_value) => {
-            // println!("dirhash is {}", &hash_value);
-            (true, hash_value.to_string())
-        }
-        Err(err) => {
-            let errorm = err.to_string();
-            // println!("Error in dirhash: {}", errorm);
-            (false, errorm)
-        }
-    }
-
-    //println!("{}", stdout_str);
+    source.folder_hash(key)
 }
 
 // Update section function
 fn update_section_files(
+    source: &dyn SystemSource,
     previous_hashes_files: &HashMap<String, String>,
     new_hashes_files: &mut HashMap<String, String>,
-) -> bool {
+) -> Result<(), ChromiaError> {
     /*println!("previous_hashes:");
     for (key, hash) in previous_hashes.iter() {
         println!("Key: '{}', Hash: '{}'", key, hash);
@@ -80,22 +51,16 @@ fn update_section_files(
 
     // Iterate over each file path in previous_hashes
     for (key, _) in previous_hashes_files {
-        if Path::new(key).exists() {
+        if source.path_exists(key) {
             // println!("{} exists!", key);
         } else {
             // println!("{} DOES NOT exi?st!", key);
             // Optionally handle files that existed before but are now missing
         }
 
-        let (hash_success, hash) = genhash(key);
-
-        if hash_success {
-            // Insert the key and hash into the updated HashMap
-            updated_section.insert(key.clone(), hash);
-        } else {
-            eprintln!("Failed to generate hash for key '{}'", key);
-            return false;
-        }
+        let hash = genhash(source, key).inspect_err(|_| eprintln!("Failed to generate hash for key '{}'", key))?;
+        // Insert the key and hash into the updated HashMap
+        updated_section.insert(key.clone(), hash);
     }
 
     // Print the contents of updated_section
@@ -106,13 +71,14 @@ fn update_section_files(
 
     // Update new_hashes with the new hashes
     *new_hashes_files = updated_section;
-    true
+    Ok(())
 }
 
 fn update_section_folders(
+    source: &dyn SystemSource,
     previous_hashes_folders: &HashMap<String, String>,
     new_hashes_folders: &mut HashMap<String, String>,
-) -> bool {
+) -> Result<(), ChromiaError> {
     /*println!("previous_hashes:");
     for (key, hash) in previous_hashes.iter() {
         println!("Key: '{}', Hash: '{}'", key, hash);
@@ -122,22 +88,16 @@ fn update_section_folders(
 
     // Iterate over each file path in previous_hashes
     for (key, _) in previous_hashes_folders {
-        if Path::new(key).exists() {
+        if source.path_exists(key) {
             // println!("{} exists!", key);
         } else {
             println!("{} DOES NOT exist!", key);
             // Optionally handle files that existed before but are now missing
         }
 
-        let (hash_success, hash) = genhash_folders(key);
-
-        if hash_success {
-            // Insert the key and hash into the updated HashMap
-            updated_section_folders.insert(key.clone(), hash);
-        } else {
-            eprintln!("Failed to generate hash for key '{}'", key);
-            return false;
-        }
+        let hash = genhash_folders(source, key).inspect_err(|_| eprintln!("Failed to generate hash for key '{}'", key))?;
+        // Insert the key and hash into the updated HashMap
+        updated_section_folders.insert(key.clone(), hash);
     }
 
     // Print the contents of updated_section
@@ -148,22 +108,26 @@ fn update_section_folders(
 
     // Update new_hashes with the new hashes
     *new_hashes_folders = updated_section_folders;
-    true
+    Ok(())
 }
 
 impl AnalysisModule for FIM {
     fn get_data(&mut self) -> bool {
         // Update the section and handle the result
-        if !update_section_files(
+        if let Err(e) = update_section_files(
+            self.source.as_ref(),
             &mut self.previous_hashes_files,
             &mut self.current_data.new_hashes_files,
         ) {
+            self.failures.push(Log::ids_failure(self.module_name.clone(), "Failed to hash monitored files", &e));
             return false; // Return false if update_section fails
         }
-        if !update_section_folders(
+        if let Err(e) = update_section_folders(
+            self.source.as_ref(),
             &mut self.previous_hashes_folders,
             &mut self.current_data.new_hashes_folders,
         ) {
+            self.failures.push(Log::ids_failure(self.module_name.clone(), "Failed to hash monitored folders", &e));
             return false; // Return false if update_section fails
         }
 
@@ -184,7 +148,10 @@ impl AnalysisModule for FIM {
     }
 
     fn perform_analysis(&mut self) -> Vec<core_structs::Log> {
+        let failures = std::mem::take(&mut self.failures);
         let mut results: Vec<core_structs::Log> = Vec::new();
+        let mut intel_matches = self.threat_intel_alerts();
+        intel_matches.extend(self.signature_alerts());
 
         // Iterate over each filepath and hash in the new_hashes
         for (filepath, new_hash) in &self.current_data.new_hashes_files {
@@ -232,8 +199,12 @@ impl AnalysisModule for FIM {
         self.previous_hashes_folders = self.current_data.new_hashes_folders.clone();
         if self.firstLoop {
             self.firstLoop = false;
-            return vec![];
+            let mut results = intel_matches;
+            results.extend(failures);
+            return results;
         }else{
+            results.extend(intel_matches);
+            results.extend(failures);
             return results;
         }
     }
@@ -289,6 +260,10 @@ impl AnalysisModule for FIM {
 
         return true;
     }
+
+    fn attach_services(&mut self, services: Arc<CoreServices>) {
+        self.services = services;
+    }
 }
 
 impl Default for FIM {
@@ -298,6 +273,10 @@ impl Default for FIM {
             previous_hashes_folders: HashMap::new(),
             module_name: String::from("FIM"),
             firstLoop:true,
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
+            services: Arc::new(CoreServices::default()),
+            folder_files: HashMap::new(),
             current_data: CurrentData {
                 new_hashes_files: HashMap::new(),
                 new_hashes_folders: HashMap::new(),
@@ -314,16 +293,101 @@ impl Clone for FIM {
             previous_hashes_folders: self.previous_hashes_folders.clone(),
             module_name: self.module_name.clone(),
             firstLoop: self.firstLoop,
+            source: Arc::clone(&self.source),
+            failures: Vec::new(),
+            services: Arc::clone(&self.services),
+            folder_files: self.folder_files.clone(),
         }
     }
 }
+impl FIM {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+    // Checks new and changed hashes against the threat intel lists. Blocklists are mostly SHA-256, so files with new
+    // content are hashed with that as well
+    fn threat_intel_alerts(&self) -> Vec<Log> {
+        let mut results = Vec::new();
+        if self.services.threat_intel.is_none() {
+            return results;
+        }
+        let mut files: Vec<(&String, &String)> = self.current_data.new_hashes_files.iter().collect();
+        files.sort();
+        for (filepath, new_hash) in files {
+            if self.previous_hashes_files.get(filepath) == Some(new_hash) {
+                continue;
+            }
+            let what = format!("Object '{}'", filepath);
+            let sha256 = self.source.file_sha256(filepath).ok();
+            let found = self.services.check_hash(&self.module_name, new_hash, &what).or_else(|| self.services.check_hash(&self.module_name, sha256.as_deref()?, &what));
+            results.extend(found);
+        }
+        let mut folders: Vec<(&String, &String)> = self.current_data.new_hashes_folders.iter().collect();
+        folders.sort();
+        for (folderpath, new_hash) in folders {
+            if self.previous_hashes_folders.get(folderpath) != Some(new_hash) {
+                results.extend(self.services.check_hash(&self.module_name, new_hash, &format!("Folder '{}'", folderpath)));
+            }
+        }
+        results
+    }
+    // Scans monitored files whose hash changed, and the new and changed files inside monitored folders whose hash
+    // changed, with the signature rules. Everything is scanned once when Chromia starts
+    fn signature_alerts(&mut self) -> Vec<Log> {
+        let mut results = Vec::new();
+        if self.services.signatures.is_none() {
+            return results;
+        }
+        let mut files: Vec<(&String, &String)> = self.current_data.new_hashes_files.iter().collect();
+        files.sort();
+        for (filepath, new_hash) in files {
+            let why = match self.previous_hashes_files.get(filepath) {
+                Some(previous_hash) if previous_hash == new_hash => continue,
+                Some(previous_hash) if !previous_hash.is_empty() => "changed file",
+                _ => "monitored file",
+            };
+            results.extend(self.services.scan_file(&self.module_name, self.source.as_ref(), filepath, why));
+        }
+        let mut folders: Vec<String> = self.current_data.new_hashes_folders.iter().filter(|(folder, hash)| self.previous_hashes_folders.get(*folder) != Some(*hash)).map(|(folder, _)| folder.clone()).collect();
+        folders.sort();
+        for folder in folders {
+            let prefix = format!("{}/", folder.trim_end_matches('/'));
+            let first_scan = !self.folder_files.keys().any(|path| path.starts_with(&prefix));
+            let mut entries = match self.source.list_files(&folder) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    results.push(Log::ids_failure(self.module_name.clone(), &format!("Could not list '{}' to scan it for signatures", folder), &e));
+                    continue;
+                }
+            };
+            entries.sort_by(|a, b| a.path.cmp(&b.path));
+            // Forget files that were deleted, so they are scanned again if they come back
+            self.folder_files.retain(|path, _| !path.starts_with(&prefix) || entries.iter().any(|entry| &entry.path == path));
+            for entry in entries {
+                let why = match self.folder_files.insert(entry.path.clone(), (entry.modified, entry.size)) {
+                    Some(seen) if seen == (entry.modified, entry.size) => continue,
+                    Some(_) => "changed file",
+                    None if first_scan => "monitored file",
+                    None => "new file",
+                };
+                results.extend(self.services.scan_file(&self.module_name, self.source.as_ref(), &entry.path, why));
+            }
+        }
+        results
+    }
+}
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::collections::HashMap;
     use std::fs::{self, File};
     use std::io::Write;
-    use std::path::Path;
+    use crate::linux_bridge::signatures::RuleSet;
+    use crate::linux_bridge::source::FakeSource;
+    use crate::linux_bridge::threat_intel::ThreatIntel;
 
     fn create_temp_file_with_content(path: &str, content: &str) {
         let mut file = File::create(path).expect("Failed to create test file");
@@ -336,8 +400,7 @@ mod tests {
         let test_file = "./test_file.txt";
         create_temp_file_with_content(test_file, "Test content");
 
-        let (success, hash) = genhash(test_file);
-        assert!(success);
+        let hash = genhash(&LinuxSource::default(), test_file).unwrap();
         assert!(!hash.is_empty());
         fs::remove_file(test_file).expect("Failed to remove test file");
     }
@@ -347,8 +410,7 @@ mod tests {
         let test_folder = "/tmp/test_folder";
         fs::create_dir(test_folder).expect("Failed to create test folder");
 
-        let (success, hash) = genhash_folders(test_folder);
-        assert!(success);
+        let hash = genhash_folders(&LinuxSource::default(), test_folder).unwrap();
         assert!(!hash.is_empty());
         fs::remove_dir(test_folder).expect("Failed to remove test folder");
     }
@@ -362,12 +424,27 @@ mod tests {
 
         previous_hashes_files.insert(test_file.to_string(), String::new());
 
-        let success = update_section_files(&previous_hashes_files, &mut new_hashes_files);
-        assert!(success);
+        let result = update_section_files(&LinuxSource::default(), &previous_hashes_files, &mut new_hashes_files);
+        assert!(result.is_ok());
         assert!(new_hashes_files.contains_key(test_file));
         fs::remove_file(test_file).expect("Failed to remove test file");
     }
 
+    #[test]
+    fn test_update_section_files_records_hash() {
+        let mut previous_hashes_files = HashMap::new();
+        let mut new_hashes_files: HashMap<String, String> = HashMap::new();
+        let test_file = "/etc/test_file.txt";
+        let source = FakeSource::new();
+        source.set_hash(test_file, "af1349b9f5f9a1a6a0404dea36dcc949");
+
+        previous_hashes_files.insert(test_file.to_string(), String::new());
+
+        let result = update_section_files(&source, &previous_hashes_files, &mut new_hashes_files);
+        assert!(result.is_ok());
+        assert_eq!(new_hashes_files.get(test_file).unwrap(), "af1349b9f5f9a1a6a0404dea36dcc949");
+    }
+
     #[test]
     fn test_update_section_folders() {
         let mut previous_hashes_folders = HashMap::new();
@@ -377,9 +454,76 @@ mod tests {
 
         previous_hashes_folders.insert(test_folder.to_string(), String::new());
 
-        let success = update_section_folders(&previous_hashes_folders, &mut new_hashes_folders);
-        assert!(success);
+        let result = update_section_folders(&LinuxSource::default(), &previous_hashes_folders, &mut new_hashes_folders);
+        assert!(result.is_ok());
         assert!(new_hashes_folders.contains_key(test_folder));
         fs::remove_dir(test_folder).expect("Failed to remove test folder");
     }
+
+    #[test]
+    fn test_perform_analysis_detects_modified_file() {
+        let source = Arc::new(FakeSource::new());
+        source.set_hash("/etc/passwd", "1111");
+        let mut fim = FIM::with_source(source.clone());
+        let mut config = HashMap::new();
+        config.insert("files".to_string(), vec!["/etc/passwd".to_string()]);
+        fim.retrieve_config_data(config);
+
+        // the first loop only builds the baseline
+        assert!(fim.get_data());
+        assert!(fim.perform_analysis().is_empty());
+
+        source.set_hash("/etc/passwd", "2222");
+        assert!(fim.get_data());
+        let logs = fim.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].message.contains("Object '/etc/passwd' has been modified! previous hash was 1111 and new hash is 2222"));
+    }
+
+    #[test]
+    fn test_threat_intel_hash_match() {
+        let source = Arc::new(FakeSource::new());
+        source.set_hash("/usr/bin/sshd", "275a021bbfb6489e54d471899f7db9d1663fc695ec2fe2a2c4538aabf651fd0f");
+        let mut fim = FIM::with_source(source.clone());
+        let mut config = HashMap::new();
+        config.insert("files".to_string(), vec!["/usr/bin/sshd".to_string()]);
+        fim.retrieve_config_data(config);
+        let intel = ThreatIntel::from_list("hashes.txt", "275A021BBFB6489E54D471899F7DB9D1663FC695EC2FE2A2C4538AABF651FD0F\n");
+        fim.attach_services(Arc::new(CoreServices { threat_intel: Some(Arc::new(intel)), ..Default::default() }));
+
+        // A match is raised on the first loop as well, and only again if the file changes
+        assert!(fim.get_data());
+        let logs = fim.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[CRITICAL]"));
+        assert!(logs[0].message.contains("Threat intel match: Object '/usr/bin/sshd' has hash 275a021bbfb6489e54d471899f7db9d1663fc695ec2fe2a2c4538aabf651fd0f, it is listed in 'hashes.txt'"));
+        assert!(fim.get_data());
+        assert!(fim.perform_analysis().is_empty());
+    }
+
+    #[test]
+    fn test_signature_scan_of_changed_files() {
+        let source = Arc::new(FakeSource::new());
+        source.set_hash("/var/www", "1111");
+        source.set_file("/var/www/index.php", "<?php echo 'hi'; ?>");
+        let mut fim = FIM::with_source(source.clone());
+        let mut config = HashMap::new();
+        config.insert("folders".to_string(), vec!["/var/www".to_string()]);
+        fim.retrieve_config_data(config);
+        let rules = RuleSet::compile("rule PHP_Eval_Post { meta: description = \"eval of POST data\" strings: $a = \"eval($_POST\" nocase condition: $a }").unwrap();
+        fim.attach_services(Arc::new(CoreServices { signatures: Some(Arc::new(rules)), ..Default::default() }));
+        assert!(fim.get_data());
+        assert!(fim.perform_analysis().is_empty());
+
+        // A shell is dropped next to index.php, which isn't scanned again because it hasn't changed
+        source.set_hash("/var/www", "2222");
+        source.set_file("/var/www/up.php", "<?php EVAL($_POST['c']); ?>");
+        source.set_modified("/var/www/index.php", 0);
+        assert!(fim.get_data());
+        let logs = fim.perform_analysis();
+        let matches: Vec<&Log> = logs.iter().filter(|log| matches!(log.log_type, core_enums::LogType::Serious) && log.message.starts_with("Signature match")).collect();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].message, "Signature match: new file '/var/www/up.php' matches rule 'PHP_Eval_Post' (eval of POST data), strings $a");
+        assert!(logs.iter().any(|log| log.message.contains("Folder '/var/www' has been modified!")));
+    }
 }
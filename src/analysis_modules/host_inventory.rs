@@ -0,0 +1,422 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use chrono::Utc;
+use ini::Ini;
+
+use crate::lara_core::core_enums::*;
+use crate::lara_core::core_structs::*;
+use crate::lara_core::core_traits::AnalysisModule;
+use crate::linux_bridge::network::{parse_proc_net, SOCKET_TABLES};
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
+use crate::linux_bridge::system::{parse_modules, parse_mounts, parse_os_release};
+
+const OS_RELEASE: &str = "/etc/os-release";
+const KERNEL_RELEASE: &str = "/proc/sys/kernel/osrelease";
+const HOSTNAME: &str = "/proc/sys/kernel/hostname";
+const PASSWD: &str = "/etc/passwd";
+const MODULES: &str = "/proc/modules";
+const MOUNTS: &str = "/proc/mounts";
+// Long package lists are cut off in alerts, the full list is in the state file
+const MAX_LISTED: usize = 20;
+
+// Everything Chromia records about the host. Sorted collections so the state file and the
+// `chromia inventory` dump come out the same way every time
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Inventory {
+    pub os_release: String,
+    pub kernel: String,
+    pub hostname: String,
+    // package name -> version
+    pub packages: BTreeMap<String, String>,
+    // username -> uid
+    pub users: BTreeMap<String, u32>,
+    // Eg "tcp 0.0.0.0:22"
+    pub listening: BTreeSet<String>,
+    pub kernel_modules: BTreeSet<String>,
+    // Eg "/dev/nvme0n1p5 on / type ext4"
+    pub mounts: BTreeSet<String>,
+}
+
+impl Inventory {
+    // The OS, kernel, hostname, users and packages must be readable. Socket tables, modules and mounts
+    // can be missing (no IPv6, inside a container) and are left empty when they are.
+    pub fn collect(source: &dyn SystemSource) -> Result<Self, ChromiaError> {
+        let os_release = source.read_to_string(OS_RELEASE)?;
+        let mut inventory = Inventory {
+            os_release: parse_os_release(&os_release, "PRETTY_NAME").unwrap_or_default(),
+            kernel: source.read_to_string(KERNEL_RELEASE)?.trim().to_string(),
+            hostname: source.read_to_string(HOSTNAME)?.trim().to_string(),
+            packages: source.installed_packages()?.into_iter().collect(),
+            users: parse_users(&source.read_to_string(PASSWD)?),
+            ..Default::default()
+        };
+        for (protocol, path) in SOCKET_TABLES {
+            if let Ok(table) = source.read_to_string(path) {
+                for socket in parse_proc_net(&table, protocol).into_iter().filter(|socket| socket.is_listening()) {
+                    inventory.listening.insert(format!("{} {}", protocol, SocketAddr::new(socket.local_addr, socket.local_port)));
+                }
+            }
+        }
+        if let Ok(modules) = source.read_to_string(MODULES) {
+            inventory.kernel_modules = parse_modules(&modules).into_iter().map(|module| module.name).collect();
+        }
+        if let Ok(mounts) = source.read_to_string(MOUNTS) {
+            inventory.mounts = parse_mounts(&mounts)
+                .into_iter()
+                .map(|mount| format!("{} on {} type {}", mount.device, mount.mount_point, mount.fs_type))
+                .collect();
+        }
+        Ok(inventory)
+    }
+
+    pub fn to_ini(&self) -> String {
+        let mut ini = Ini::new();
+        ini.with_section(Some("Host"))
+            .set("osRelease", self.os_release.clone())
+            .set("kernel", self.kernel.clone())
+            .set("hostname", self.hostname.clone());
+        for (name, version) in &self.packages {
+            ini.with_section(Some("Packages")).set(name.clone(), version.clone());
+        }
+        for (name, uid) in &self.users {
+            ini.with_section(Some("Users")).set(name.clone(), uid.to_string());
+        }
+        for (section, values) in [("Listening", &self.listening), ("KernelModules", &self.kernel_modules), ("Mounts", &self.mounts)] {
+            for value in values {
+                ini.with_section(Some(section)).add("entry[]", value.clone());
+            }
+        }
+        let mut content = Vec::new();
+        // Writing into a Vec can't fail
+        let _ = ini.write_to(&mut content);
+        String::from_utf8_lossy(&content).into_owned()
+    }
+
+    pub fn from_ini(content: &str) -> Option<Self> {
+        let ini = Ini::load_from_str(content).ok()?;
+        let host = ini.section(Some("Host"))?;
+        let entries = |section: &str| -> BTreeSet<String> {
+            ini.section(Some(section))
+                .map(|props| props.get_all("entry[]").map(|value| value.to_string()).collect())
+                .unwrap_or_default()
+        };
+        Some(Inventory {
+            os_release: host.get("osRelease")?.to_string(),
+            kernel: host.get("kernel")?.to_string(),
+            hostname: host.get("hostname")?.to_string(),
+            packages: ini
+                .section(Some("Packages"))
+                .map(|props| props.iter().map(|(name, version)| (name.to_string(), version.to_string())).collect())
+                .unwrap_or_default(),
+            users: ini
+                .section(Some("Users"))
+                .map(|props| props.iter().filter_map(|(name, uid)| Some((name.to_string(), uid.parse().ok()?))).collect())
+                .unwrap_or_default(),
+            listening: entries("Listening"),
+            kernel_modules: entries("KernelModules"),
+            mounts: entries("Mounts"),
+        })
+    }
+}
+
+pub struct HostInventory {
+    // This is the data generated by gatherData in current tick, it will be erased by the next tick.
+    // None on ticks between snapshots
+    current_data: Option<Inventory>,
+    //Everything else is persistent memory. The data you set in these will be remembered between ticks
+    previous: Option<Inventory>,
+    last_snapshot: Option<i64>,
+    state_file: String,
+    snapshot_interval: i64,
+    module_name: String,
+    source: Arc<dyn SystemSource>,
+    failures: Vec<Log>,
+}
+
+impl AnalysisModule for HostInventory {
+    fn get_data(&mut self) -> bool {
+        self.current_data = None;
+        let now = Utc::now().timestamp();
+        if let Some(last) = self.last_snapshot {
+            if now - last < self.snapshot_interval {
+                return true;
+            }
+        }
+        // Retried next interval rather than every tick if the snapshot fails
+        self.last_snapshot = Some(now);
+        match Inventory::collect(self.source.as_ref()) {
+            Ok(inventory) => {
+                self.current_data = Some(inventory);
+                true
+            }
+            Err(e) => {
+                self.failures.push(Log::ids_failure(self.module_name.clone(), "Failed to take host inventory", &e));
+                false
+            }
+        }
+    }
+
+    fn get_testing_data(&mut self) -> bool {
+        todo!()
+    }
+
+    fn perform_analysis(&mut self) -> Vec<Log> {
+        let mut results = std::mem::take(&mut self.failures);
+        let current = match self.current_data.take() {
+            Some(current) => current,
+            None => return results,
+        };
+        // On the first snapshot compare against the inventory saved by the last run of Chromia
+        let previous = self.previous.take().or_else(|| {
+            let content = self.source.read_to_string(&self.state_file).ok()?;
+            Inventory::from_ini(&content)
+        });
+        match &previous {
+            Some(previous) => results.append(&mut self.report_drift(previous, &current)),
+            None => results.push(Log::new(
+                LogType::Info,
+                self.module_name.clone(),
+                format!("Recorded host inventory for {} ({}, kernel {}): {} packages, {} users, {} listening sockets, {} kernel modules, {} mounts", current.hostname, current.os_release, current.kernel, current.packages.len(), current.users.len(), current.listening.len(), current.kernel_modules.len(), current.mounts.len()),
+            )),
+        }
+        if let Err(e) = self.source.write_string(&self.state_file, &current.to_ini()) {
+            results.push(Log::ids_failure(self.module_name.clone(), &format!("Could not save host inventory to '{}'", self.state_file), &e));
+        }
+        self.previous = Some(current);
+        results
+    }
+
+    fn get_name(&self) -> String {
+        self.module_name.clone()
+    }
+
+    fn build_config_fields(&self) -> Vec<ConfigField> {
+        vec![
+            ConfigField::new("StateFile".to_owned(), "File the last host inventory is saved to so drift is also caught across restarts of Chromia".to_owned(), ConfigFieldType::String, vec![self.state_file.clone()], false),
+            ConfigField::new("SnapshotInterval".to_owned(), "Seconds between inventory snapshots. Reading the package database is slow so keep this high".to_owned(), ConfigFieldType::Integer, vec![self.snapshot_interval.to_string()], false),
+        ]
+    }
+
+    fn retrieve_config_data(&mut self, data: HashMap<String, Vec<String>>) -> bool {
+        for (field, vals) in data {
+            match field.as_str() {
+                "StateFile" => {
+                    if let Some(path) = vals.first().filter(|path| !path.is_empty()) {
+                        self.state_file = path.clone();
+                    }
+                }
+                "SnapshotInterval" => match vals.first().and_then(|v| v.parse::<i64>().ok()) {
+                    Some(interval) if interval > 0 => self.snapshot_interval = interval,
+                    _ => {
+                        println!("SnapshotInterval must be a positive whole number of seconds");
+                        return false;
+                    }
+                },
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+impl HostInventory {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+
+    fn report_drift(&self, previous: &Inventory, current: &Inventory) -> Vec<Log> {
+        let mut results = Vec::new();
+        let mut log = |log_type: LogType, message: String| results.push(Log::new(log_type, self.module_name.clone(), message));
+
+        if previous.hostname != current.hostname {
+            log(LogType::Warning, format!("Hostname changed from '{}' to '{}'", previous.hostname, current.hostname));
+        }
+        if previous.kernel != current.kernel {
+            log(LogType::Warning, format!("Kernel changed from {} to {}. Confirm this was a planned upgrade", previous.kernel, current.kernel));
+        }
+        if previous.os_release != current.os_release {
+            log(LogType::Info, format!("OS release changed from '{}' to '{}'", previous.os_release, current.os_release));
+        }
+
+        let installed: Vec<String> = current
+            .packages
+            .iter()
+            .filter(|(name, _)| !previous.packages.contains_key(*name))
+            .map(|(name, version)| format!("{} {}", name, version))
+            .collect();
+        let removed: Vec<String> = previous.packages.keys().filter(|name| !current.packages.contains_key(*name)).cloned().collect();
+        let upgraded: Vec<String> = current
+            .packages
+            .iter()
+            .filter_map(|(name, version)| {
+                let old = previous.packages.get(name)?;
+                (old != version).then(|| format!("{} {} -> {}", name, old, version))
+            })
+            .collect();
+        if !installed.is_empty() {
+            log(LogType::Info, format!("{} new package(s) installed: {}", installed.len(), summarise(&installed)));
+        }
+        if !removed.is_empty() {
+            log(LogType::Info, format!("{} package(s) removed: {}", removed.len(), summarise(&removed)));
+        }
+        if !upgraded.is_empty() {
+            log(LogType::Info, format!("{} package(s) changed version: {}", upgraded.len(), summarise(&upgraded)));
+        }
+
+        for (name, uid) in &current.users {
+            match previous.users.get(name) {
+                None => log(LogType::Warning, format!("New user account '{}' (uid {})", name, uid)),
+                Some(old_uid) if old_uid != uid => log(LogType::Warning, format!("User '{}' changed uid from {} to {}", name, old_uid, uid)),
+                _ => {}
+            }
+        }
+        for name in previous.users.keys().filter(|name| !current.users.contains_key(*name)) {
+            log(LogType::Info, format!("User account '{}' was removed", name));
+        }
+
+        for socket in current.listening.difference(&previous.listening) {
+            log(LogType::Warning, format!("New listening service on {}", socket));
+        }
+        for socket in previous.listening.difference(&current.listening) {
+            log(LogType::Info, format!("Service on {} is no longer listening", socket));
+        }
+        for module in current.kernel_modules.difference(&previous.kernel_modules) {
+            log(LogType::Warning, format!("Kernel module '{}' was loaded", module));
+        }
+        for module in previous.kernel_modules.difference(&current.kernel_modules) {
+            log(LogType::Info, format!("Kernel module '{}' was unloaded", module));
+        }
+        for mount in current.mounts.difference(&previous.mounts) {
+            log(LogType::Info, format!("New mount: {}", mount));
+        }
+        for mount in previous.mounts.difference(&current.mounts) {
+            log(LogType::Info, format!("Filesystem unmounted: {}", mount));
+        }
+        results
+    }
+}
+
+// Keyed by username so a second uid 0 account (toor) shows up next to root
+fn parse_users(passwd: &str) -> BTreeMap<String, u32> {
+    passwd
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            Some((fields.first()?.to_string(), fields.get(2)?.parse().ok()?))
+        })
+        .collect()
+}
+
+fn summarise(items: &[String]) -> String {
+    if items.len() <= MAX_LISTED {
+        return items.join(", ");
+    }
+    format!("{} and {} more", items[..MAX_LISTED].join(", "), items.len() - MAX_LISTED)
+}
+
+impl Default for HostInventory {
+    fn default() -> Self {
+        Self {
+            current_data: None,
+            previous: None,
+            last_snapshot: None,
+            state_file: String::from("/var/lib/Chromia/inventory.ini"),
+            snapshot_interval: 3600,
+            module_name: String::from("HostInventory"),
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::source::FakeSource;
+
+    const STATE: &str = "/var/lib/Chromia/inventory.ini";
+
+    fn fake_host() -> Arc<FakeSource> {
+        let source = Arc::new(FakeSource::new());
+        source.set_file(OS_RELEASE, "NAME=\"Ubuntu\"\nPRETTY_NAME=\"Ubuntu 24.04.1 LTS\"\nID=ubuntu\n");
+        source.set_file(KERNEL_RELEASE, "6.8.0-41-generic\n");
+        source.set_file(HOSTNAME, "web01\n");
+        source.set_file(PASSWD, "root:x:0:0:root:/root:/bin/bash\nerik:x:1000:1000::/home/erik:/bin/bash\n");
+        source.set_file("/proc/net/tcp", "header\n   0: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21034 1 0000000000000000 100 0 0 10 0\n");
+        source.set_file(MODULES, "ext4 1069056 1 - Live 0x0000000000000000\n");
+        source.set_file(MOUNTS, "/dev/sda1 / ext4 rw,relatime 0 0\n");
+        source.set_packages(&[("openssh-server", "1:9.6p1-3ubuntu13"), ("curl", "8.5.0-2ubuntu10")]);
+        source
+    }
+
+    #[test]
+    fn test_collect_and_ini_round_trip() {
+        let inventory = Inventory::collect(fake_host().as_ref()).unwrap();
+        assert_eq!(inventory.os_release, "Ubuntu 24.04.1 LTS");
+        assert_eq!(inventory.hostname, "web01");
+        assert_eq!(inventory.users.get("erik"), Some(&1000));
+        assert!(inventory.listening.contains("tcp 0.0.0.0:22"));
+        assert!(inventory.mounts.contains("/dev/sda1 on / type ext4"));
+        assert_eq!(Inventory::from_ini(&inventory.to_ini()), Some(inventory));
+    }
+
+    #[test]
+    fn test_first_snapshot_is_recorded() {
+        let source = fake_host();
+        let mut inventory = HostInventory::with_source(source.clone());
+        assert!(inventory.get_data());
+        let logs = inventory.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].message.starts_with("Recorded host inventory for web01"));
+        assert!(source.read_to_string(STATE).unwrap().contains("openssh-server"));
+    }
+
+    #[test]
+    fn test_drift_since_last_run_is_reported() {
+        let source = fake_host();
+        let saved = Inventory::collect(source.as_ref()).unwrap();
+        source.set_file(STATE, &saved.to_ini());
+        source.set_file(KERNEL_RELEASE, "6.8.0-45-generic\n");
+        source.set_packages(&[("openssh-server", "1:9.6p1-3ubuntu13"), ("curl", "8.5.0-2ubuntu10"), ("nmap", "7.94")]);
+        source.set_file("/proc/net/tcp", "header\n   0: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21034 1 0000000000000000 100 0 0 10 0\n   1: 00000000:115C 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 21035 1 0000000000000000 100 0 0 10 0\n");
+
+        let mut inventory = HostInventory::with_source(source);
+        assert!(inventory.get_data());
+        let messages: Vec<String> = inventory.perform_analysis().into_iter().map(|log| log.message).collect();
+        assert_eq!(messages, vec![
+            "Kernel changed from 6.8.0-41-generic to 6.8.0-45-generic. Confirm this was a planned upgrade".to_string(),
+            "1 new package(s) installed: nmap 7.94".to_string(),
+            "New listening service on tcp 0.0.0.0:4444".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_new_account_sharing_a_uid_is_reported() {
+        let source = fake_host();
+        let saved = Inventory::collect(source.as_ref()).unwrap();
+        source.set_file(STATE, &saved.to_ini());
+        source.set_file(PASSWD, "root:x:0:0:root:/root:/bin/bash\ntoor:x:0:0::/root:/bin/bash\nerik:x:1000:1000::/home/erik:/bin/bash\n");
+
+        let mut inventory = HostInventory::with_source(source);
+        assert!(inventory.get_data());
+        let messages: Vec<String> = inventory.perform_analysis().into_iter().map(|log| log.message).collect();
+        assert_eq!(messages, vec!["New user account 'toor' (uid 0)".to_string()]);
+    }
+
+    #[test]
+    fn test_unreadable_host_is_reported_as_ids_failure() {
+        let source = fake_host();
+        source.remove_file(HOSTNAME);
+        let mut inventory = HostInventory::with_source(source);
+        assert!(!inventory.get_data());
+        let logs = inventory.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[INTERNAL ERROR]"));
+    }
+}
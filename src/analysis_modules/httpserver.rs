@@ -1,6 +1,5 @@
-use std::collections::HashMap;
-use std::fs;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use colored::Colorize;
 use core_enums::LogType;
     
@@ -8,6 +7,7 @@ use crate::lara_core::*;
 use crate::ConfigField;
 use crate::Log;
 use core_traits::AnalysisModule;
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
 use rand::Rng;
 
 
@@ -15,6 +15,8 @@ use rand::Rng;
 struct CurrentData {
     logs:HashMap<String, (usize, String)>,
     veclogs: Vec<WebLog>,
+    // (client, host) of requests with a Referer from another site
+    referers: Vec<(String, String)>,
 }
 struct WebLog {
     ip:String,
@@ -30,10 +32,14 @@ pub struct HTTPServer {
     errorinitial:bool,
     accessinitial:bool,
     clients: HashMap<String, usize>,
+    // (address or domain, threat intel list) pairs already alerted on
+    reported_intel: HashSet<(String, String)>,
     module_name: String,
     access_path:String,
     error_path:String,
-
+    source: Arc<dyn SystemSource>,
+    failures: Vec<Log>,
+    services: Arc<core_structs::CoreServices>,
 }
 
 impl AnalysisModule for HTTPServer{
@@ -41,14 +47,21 @@ impl AnalysisModule for HTTPServer{
     // This is called at the start of a tick to gather the data into CurrentData struct. If there is an error return false
     fn get_data(&mut self) -> bool {
         self.current_data.logs = HashMap::new();
-        if(!Path::new(&self.access_path).exists()){
-            eprint!("Could not find Appache Access log file. Provided URI is'{}'. Chromia will still run",&self.error_path);
-        }
-        if(!Path::new(&&self.error_path).exists()){
-            eprint!("Could not find Appache Error log file. Provided URI is'{}'",&self.error_path);
-        }
-        let errordump:String = fs::read_to_string(&self.error_path).expect("Should have been able to read the file");
-        let accessdump:String = fs::read_to_string(&self.access_path).expect("Should have been able to read the file");
+        self.current_data.referers = Vec::new();
+        let errordump:String = match self.source.read_to_string(&self.error_path) {
+            Ok(dump) => dump,
+            Err(e) => {
+                self.failures.push(Log::ids_failure(self.module_name.clone(), &format!("Could not read Appache Error log '{}'", self.error_path), &e));
+                return false;
+            }
+        };
+        let accessdump:String = match self.source.read_to_string(&self.access_path) {
+            Ok(dump) => dump,
+            Err(e) => {
+                self.failures.push(Log::ids_failure(self.module_name.clone(), &format!("Could not read Appache Access log '{}'", self.access_path), &e));
+                return false;
+            }
+        };
         let errorlines: Vec<&str> = errordump.lines().collect();
         let accesslines: Vec<&str> = accessdump.lines().collect();
         let accesslineslen: usize = accesslines.len();
@@ -114,6 +127,9 @@ impl AnalysisModule for HTTPServer{
             let codesplit: Vec<&str> = nls[2].split_whitespace().collect();
             let nlcode:&str = codesplit[0];
             let nlrequest:&str = nls[1];
+            if let Some(host) = nls.get(3).and_then(|referer| referer_host(referer)) {
+                self.current_data.referers.push((nlip.to_string(), host));
+            }
             let mut msg:String = String::from("request: ".to_string());
             msg.push_str(nlrequest);
             msg.push_str(" code: ");
@@ -233,8 +249,18 @@ impl AnalysisModule for HTTPServer{
     // Take the current data gathered from one of the functions above, using this data, 
     // plus the persistent data stored in the object to create logs (AKA alerts) 
     fn perform_analysis(&mut self) -> Vec<crate::Log> {
-        let mut results: Vec<core_structs::Log> = Vec::new();
+        let mut results: Vec<core_structs::Log> = std::mem::take(&mut self.failures);
         let self_name = self.get_name();
+        let services = self.services.clone();
+        // Checked before the clients below are taken out of current data
+        let mut clients: Vec<&String> = self.current_data.logs.keys().collect();
+        clients.sort();
+        for client in clients {
+            results.extend(services.check_ip_once(&self_name, client, "made requests to the web server", &mut self.reported_intel));
+        }
+        for (client, host) in &self.current_data.referers {
+            results.extend(services.check_domain_once(&self_name, host, &format!("referred client {} to the web server", client), &mut self.reported_intel));
+        }
         for (client, score) in self.clients.iter_mut(){
             if self.current_data.logs.contains_key(client) {
                 let (new_score, err_msg) =&self.current_data.logs[client];
@@ -256,7 +282,7 @@ impl AnalysisModule for HTTPServer{
                     }else{
                         level = LogType::Info;
                     }
-                    let error_msg = format!("Client [{}] - {} client rating:{}", client,  err_msg, score);
+                    let error_msg = format!("Client [{}] - {} client rating:{}", services.describe_ip(client),  err_msg, score);
                     results.push(Log::new(level, self_name.clone(), error_msg))
                 } 
                 self.current_data.logs.remove(client);
@@ -283,7 +309,7 @@ impl AnalysisModule for HTTPServer{
                 }else{
                     level = LogType::Info;
                 }
-                let error_msg = format!("Client [{}] - {}", client,  err_msg);
+                let error_msg = format!("Client [{}] - {}", services.describe_ip(client),  err_msg);
                 results.push(Log::new(level, self_name.clone(), error_msg))
             } 
         }
@@ -303,7 +329,7 @@ impl AnalysisModule for HTTPServer{
     fn retrieve_config_data(&mut self, data: HashMap<String,Vec<String>>) -> bool{
         for (field, vals) in data.into_iter(){
             if field == "Access-Log Path"{
-                if !Path::new(&vals[0]).exists(){
+                if !self.source.path_exists(&vals[0]){
                     let msg = format!("{}",format!("Could not find specified path for Appache Access logs '{}'",&vals[0].italic()).red().bold());
                     println!("{}",msg);
                     return false;
@@ -311,7 +337,7 @@ impl AnalysisModule for HTTPServer{
                     self.access_path = vals[0].to_string();
                 }
             }else if field=="Error-Log Path"{
-                if !Path::new(&vals[0]).exists(){
+                if !self.source.path_exists(&vals[0]){
                     let msg = format!("{}",format!("Could not find specified path for Appache error logs '{}'",&vals[0].italic()).red().bold());
                     println!("{}",msg);
                     return false;
@@ -322,6 +348,9 @@ impl AnalysisModule for HTTPServer{
         }
         return true;
     }
+    fn attach_services(&mut self, services: Arc<core_structs::CoreServices>) {
+        self.services = services;
+    }
 }
 // Must implement on your module, defines a default constructor. This is where any code that should run when IDS is FIRST LOADED. 
 // You should also initialize an empty current data struct like this
@@ -335,11 +364,122 @@ impl Default for HTTPServer {
             access_path:"".to_string(),
             error_path:"".to_string(),
             clients: HashMap::new(),
+            reported_intel: HashSet::new(),
             module_name: String::from("HTTPServerModule"),
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
+            services: Arc::new(core_structs::CoreServices::default()),
             current_data: CurrentData {
                 logs: HashMap::new(),
                 veclogs: Vec::new(),
+                referers: Vec::new(),
             },
         }
     }
 }
+// The host of a Referer header, Eg evil.example from "https://evil.example:8443/page". "-" means there wasn't one
+fn referer_host(referer: &str) -> Option<String> {
+    let rest = referer.split_once("://")?.1;
+    let host = rest.split(['/', '?', '#']).next()?.rsplit('@').next()?;
+    let host = host.split(':').next()?;
+    if host.is_empty() {
+        return None;
+    }
+    Some(host.to_lowercase())
+}
+impl HTTPServer {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::source::FakeSource;
+    use crate::linux_bridge::threat_intel::ThreatIntel;
+
+    const ACCESS: &str = "/var/log/apache2/access.log";
+    const ERROR: &str = "/var/log/apache2/error.log";
+
+    fn configured_server(source: Arc<FakeSource>) -> HTTPServer {
+        let mut server = HTTPServer::with_source(source);
+        let mut config = HashMap::new();
+        config.insert("Access-Log Path".to_string(), vec![ACCESS.to_string()]);
+        config.insert("Error-Log Path".to_string(), vec![ERROR.to_string()]);
+        assert!(server.retrieve_config_data(config));
+        server
+    }
+
+    #[test]
+    fn test_retrieve_config_data_missing_log() {
+        let mut server = HTTPServer::with_source(Arc::new(FakeSource::new()));
+        let mut config = HashMap::new();
+        config.insert("Access-Log Path".to_string(), vec![ACCESS.to_string()]);
+        assert!(!server.retrieve_config_data(config));
+    }
+
+    #[test]
+    fn test_forbidden_requests_raise_alert() {
+        let line = "203.0.113.7 - - [14/Oct/2024:13:35:30 +1100] \"GET /admin HTTP/1.1\" 403 437 \"-\" \"curl/8.5.0\"";
+        let source = Arc::new(FakeSource::new());
+        source.set_file(ACCESS, line);
+        source.set_file(ERROR, "");
+        let mut server = configured_server(source.clone());
+
+        // the first tick only records how long the logs are
+        assert!(server.get_data());
+        assert!(server.perform_analysis().is_empty());
+
+        source.set_file(ACCESS, &[line, line].join("\n"));
+        assert!(server.get_data());
+        let logs = server.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].message.contains("Client [203.0.113.7] - request: GET /admin HTTP/1.1 code: 403 Forbidden"));
+        assert!(logs[0].build_alert().contains("[Warning]"));
+    }
+
+    #[test]
+    fn test_threat_intel_clients_and_referers() {
+        let line = |ip: &str, referer: &str| format!("{} - - [14/Oct/2024:13:35:30 +1100] \"GET / HTTP/1.1\" 200 437 \"{}\" \"curl/8.5.0\"", ip, referer);
+        let source = Arc::new(FakeSource::new());
+        source.set_file(ACCESS, &line("192.0.2.1", "-"));
+        source.set_file(ERROR, "");
+        let mut server = configured_server(source.clone());
+        let intel = ThreatIntel::from_list("blocklist.txt", "198.51.100.0/24\nphish.example\n");
+        server.attach_services(Arc::new(core_structs::CoreServices { threat_intel: Some(Arc::new(intel)), ..Default::default() }));
+        assert!(server.get_data());
+        assert!(server.perform_analysis().is_empty());
+
+        let lines = [line("192.0.2.1", "-"), line("198.51.100.23", "-"), line("192.0.2.5", "https://login.phish.example:8443/account?id=1")];
+        source.set_file(ACCESS, &lines.join("\n"));
+        assert!(server.get_data());
+        let alerts: Vec<String> = server.perform_analysis().iter().map(|log| log.build_alert()).collect();
+        assert_eq!(alerts.len(), 2);
+        assert!(alerts[0].contains("[CRITICAL]") && alerts[0].ends_with("Threat intel match: ip address '198.51.100.23' made requests to the web server, it is listed in 'blocklist.txt'"));
+        assert!(alerts[1].contains("[CRITICAL]") && alerts[1].ends_with("Threat intel match: domain 'login.phish.example' referred client 192.0.2.5 to the web server, it is listed in 'blocklist.txt'"));
+
+        // The same client and referer in later requests aren't raised again
+        let more = [line("192.0.2.1", "-"), line("198.51.100.23", "-"), line("192.0.2.5", "https://login.phish.example/")];
+        source.set_file(ACCESS, &[lines.join("\n"), more.join("\n")].join("\n"));
+        assert!(server.get_data());
+        assert!(!server.perform_analysis().iter().any(|log| log.message.starts_with("Threat intel match")));
+    }
+
+    #[test]
+    fn test_rotated_away_log_is_reported_as_ids_failure() {
+        let source = Arc::new(FakeSource::new());
+        source.set_file(ACCESS, "");
+        source.set_file(ERROR, "");
+        let mut server = configured_server(source.clone());
+
+        source.remove_file(ACCESS);
+        assert!(!server.get_data());
+        let logs = server.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[INTERNAL ERROR]"));
+        assert!(logs[0].message.contains(ACCESS));
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::lara_core::core_enums::*;
+use crate::lara_core::core_structs::*;
+use crate::lara_core::core_traits::AnalysisModule;
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
+use crate::linux_bridge::system::{decode_taint, parse_modules, KernelModule};
+
+const PROC_MODULES: &str = "/proc/modules";
+const TAINTED: &str = "/proc/sys/kernel/tainted";
+const MODULES_DISABLED: &str = "/proc/sys/kernel/modules_disabled";
+// Module taint letters worth an alert: out of tree, unsigned and force loaded
+const SUSPICIOUS_TAINTS: [char; 3] = ['O', 'E', 'F'];
+
+#[derive(Debug, Clone, Default)]
+struct CurrentData {
+    modules: HashMap<String, KernelModule>,
+    tainted: u64,
+    modules_disabled: bool,
+}
+
+pub struct KernelModuleMonitor {
+    // This is the data generated by gatherData in current tick, it will be erased by the next tick
+    current_data: CurrentData,
+    //Everything else is persistent memory. The data you set in these will be remembered between ticks
+    // None until the first tick has recorded what was loaded when Chromia started
+    baseline: Option<CurrentData>,
+    allowed_modules: HashSet<String>,
+    module_name: String,
+    source: Arc<dyn SystemSource>,
+    failures: Vec<Log>,
+}
+
+impl AnalysisModule for KernelModuleMonitor {
+    fn get_data(&mut self) -> bool {
+        let modules = match self.source.read_to_string(PROC_MODULES) {
+            Ok(modules) => modules,
+            Err(e) => return self.data_failed(PROC_MODULES, e),
+        };
+        let tainted = match self.source.read_to_string(TAINTED) {
+            Ok(tainted) => tainted,
+            Err(e) => return self.data_failed(TAINTED, e),
+        };
+        let tainted = match tainted.trim().parse() {
+            Ok(tainted) => tainted,
+            Err(_) => return self.data_failed(TAINTED, ChromiaError::Parse(format!("'{}' as a taint mask", tainted.trim()))),
+        };
+        // Only present when the kernel was built with module support
+        let modules_disabled = self.source.read_to_string(MODULES_DISABLED).map(|disabled| disabled.trim() == "1").unwrap_or(false);
+        self.current_data = CurrentData {
+            modules: parse_modules(&modules).into_iter().map(|module| (module.name.clone(), module)).collect(),
+            tainted,
+            modules_disabled,
+        };
+        true
+    }
+
+    fn get_testing_data(&mut self) -> bool {
+        todo!()
+    }
+
+    fn perform_analysis(&mut self) -> Vec<Log> {
+        let mut results = std::mem::take(&mut self.failures);
+        let current = self.current_data.clone();
+        let previous = match self.baseline.replace(current.clone()) {
+            Some(previous) => previous,
+            None => {
+                results.push(Log::new(
+                    LogType::Info,
+                    self.module_name.clone(),
+                    format!("Recorded {} loaded kernel modules, kernel taint {}{}", current.modules.len(), current.tainted, if current.modules_disabled { ", module loading is disabled" } else { "" }),
+                ));
+                // Modules that were already loaded before Chromia started are still checked for taints
+                let mut loaded: Vec<&KernelModule> = current.modules.values().collect();
+                loaded.sort_by(|a, b| a.name.cmp(&b.name));
+                for module in loaded {
+                    results.extend(self.check_taints(module));
+                }
+                return results;
+            }
+        };
+
+        let mut names: Vec<&String> = current.modules.keys().collect();
+        names.sort();
+        for name in names {
+            let module = &current.modules[name];
+            if self.allowed_modules.contains(name) {
+                continue;
+            }
+            match previous.modules.get(name) {
+                None => {
+                    results.push(Log::new(
+                        LogType::Warning,
+                        self.module_name.clone(),
+                        format!("Kernel module '{}' was loaded (size {}, used by {})", name, module.size, if module.used_by.is_empty() { "nothing".to_string() } else { module.used_by.join(", ") }),
+                    ));
+                    results.extend(self.check_taints(module));
+                }
+                Some(old) if old.size != module.size => {
+                    results.push(Log::new(
+                        LogType::Warning,
+                        self.module_name.clone(),
+                        format!("Kernel module '{}' was reloaded with a different size ({} -> {}), the module file may have been replaced", name, old.size, module.size),
+                    ));
+                    results.extend(self.check_taints(module));
+                }
+                _ => {}
+            }
+        }
+        let mut unloaded: Vec<&String> = previous.modules.keys().filter(|name| !current.modules.contains_key(*name) && !self.allowed_modules.contains(*name)).collect();
+        unloaded.sort();
+        for name in unloaded {
+            results.push(Log::new(LogType::Info, self.module_name.clone(), format!("Kernel module '{}' was unloaded", name)));
+        }
+
+        // Flags only set by modules on the allow list, Eg O for vboxdrv, are expected
+        let new_taints: Vec<(char, &str)> = decode_taint(current.tainted & !previous.tainted)
+            .into_iter()
+            .filter(|(letter, _)| {
+                let tainting: Vec<&String> = current.modules.values().filter(|module| module.taints.contains(*letter)).map(|module| &module.name).collect();
+                tainting.is_empty() || !tainting.iter().all(|name| self.allowed_modules.contains(*name))
+            })
+            .collect();
+        if !new_taints.is_empty() {
+            let flags: Vec<String> = new_taints.iter().map(|(letter, description)| format!("{} ({})", letter, description)).collect();
+            results.push(Log::new(
+                LogType::Warning,
+                self.module_name.clone(),
+                format!("Kernel taint changed from {} to {}, newly set: {}", previous.tainted, current.tainted, flags.join(", ")),
+            ));
+        }
+
+        if current.modules_disabled != previous.modules_disabled {
+            if current.modules_disabled {
+                results.push(Log::new(LogType::Info, self.module_name.clone(), "kernel.modules_disabled was set to 1, no more modules can be loaded until reboot".to_string()));
+            } else {
+                // The kernel never allows this to go back to 0, so this means the value is being faked
+                results.push(Log::new(LogType::Serious, self.module_name.clone(), "kernel.modules_disabled went from 1 back to 0, which the kernel does not allow. /proc may be tampered with".to_string()));
+            }
+        }
+        results
+    }
+
+    fn get_name(&self) -> String {
+        self.module_name.clone()
+    }
+
+    fn build_config_fields(&self) -> Vec<ConfigField> {
+        vec![
+            ConfigField::new("AllowedModules".to_owned(), "Kernel modules that may be loaded and unloaded without an alert, Eg nvidia or vboxdrv".to_owned(), ConfigFieldType::String, self.allowed_modules.iter().cloned().collect(), true),
+        ]
+    }
+
+    fn retrieve_config_data(&mut self, data: HashMap<String, Vec<String>>) -> bool {
+        for (field, vals) in data {
+            if field == "AllowedModules" {
+                // lsmod shows dashes as underscores, accept either
+                self.allowed_modules = vals.into_iter().filter(|val| !val.is_empty()).map(|val| val.replace('-', "_")).collect();
+            }
+        }
+        true
+    }
+}
+
+impl KernelModuleMonitor {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+
+    fn data_failed(&mut self, path: &str, error: ChromiaError) -> bool {
+        self.failures.push(Log::ids_failure(self.module_name.clone(), &format!("Failed to read {}", path), &error));
+        false
+    }
+
+    fn check_taints(&self, module: &KernelModule) -> Option<Log> {
+        if self.allowed_modules.contains(&module.name) || !module.taints.contains(SUSPICIOUS_TAINTS) {
+            return None;
+        }
+        let mut reasons = Vec::new();
+        if module.taints.contains('O') {
+            reasons.push("built out of tree");
+        }
+        if module.taints.contains('E') {
+            reasons.push("not signed");
+        }
+        if module.taints.contains('F') {
+            reasons.push("force loaded");
+        }
+        Some(Log::new(
+            LogType::Serious,
+            self.module_name.clone(),
+            format!("Kernel module '{}' is {} (taint flags {}). Rootkits are usually loaded this way, add it to AllowedModules if it is expected", module.name, reasons.join(" and "), module.taints),
+        ))
+    }
+}
+
+impl Default for KernelModuleMonitor {
+    fn default() -> Self {
+        Self {
+            current_data: CurrentData::default(),
+            baseline: None,
+            allowed_modules: HashSet::new(),
+            module_name: String::from("KernelModules"),
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::source::FakeSource;
+
+    const EXT4: &str = "ext4 1069056 1 - Live 0x0000000000000000\n";
+
+    fn fake_kernel(modules: &str, tainted: u64) -> Arc<FakeSource> {
+        let source = Arc::new(FakeSource::new());
+        source.set_file(PROC_MODULES, modules);
+        source.set_file(TAINTED, &format!("{}\n", tainted));
+        source.set_file(MODULES_DISABLED, "0\n");
+        source
+    }
+
+    fn tick(monitor: &mut KernelModuleMonitor) -> Vec<Log> {
+        assert!(monitor.get_data());
+        monitor.perform_analysis()
+    }
+
+    #[test]
+    fn test_unsigned_module_loaded_after_baseline() {
+        let source = fake_kernel(EXT4, 0);
+        let mut monitor = KernelModuleMonitor::with_source(source.clone());
+        assert_eq!(tick(&mut monitor).len(), 1);
+
+        source.set_file(PROC_MODULES, &format!("{}diamorphine 16384 0 - Live 0x0000000000000000 (OE)\n", EXT4));
+        source.set_file(TAINTED, "12288\n");
+        let logs = tick(&mut monitor);
+        let messages: Vec<&str> = logs.iter().map(|log| log.message.as_str()).collect();
+        assert_eq!(messages.len(), 3);
+        assert!(messages[0].starts_with("Kernel module 'diamorphine' was loaded"));
+        assert!(messages[1].starts_with("Kernel module 'diamorphine' is built out of tree and not signed"));
+        assert_eq!(messages[2], "Kernel taint changed from 0 to 12288, newly set: O (out of tree module loaded), E (unsigned module loaded)");
+    }
+
+    #[test]
+    fn test_allowed_module_is_ignored() {
+        let source = fake_kernel(EXT4, 0);
+        let mut monitor = KernelModuleMonitor::with_source(source.clone());
+        let mut config = HashMap::new();
+        config.insert("AllowedModules".to_string(), vec!["vboxdrv".to_string()]);
+        assert!(monitor.retrieve_config_data(config));
+        tick(&mut monitor);
+
+        source.set_file(PROC_MODULES, &format!("{}vboxdrv 696320 0 - Live 0x0000000000000000 (O)\n", EXT4));
+        assert!(tick(&mut monitor).is_empty());
+        source.set_file(PROC_MODULES, "");
+        let logs = tick(&mut monitor);
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].message, "Kernel module 'ext4' was unloaded");
+    }
+
+    #[test]
+    fn test_taint_from_allowed_module_is_ignored() {
+        let source = fake_kernel(EXT4, 0);
+        let mut monitor = KernelModuleMonitor::with_source(source.clone());
+        let mut config = HashMap::new();
+        config.insert("AllowedModules".to_string(), vec!["vboxdrv".to_string()]);
+        assert!(monitor.retrieve_config_data(config));
+        tick(&mut monitor);
+
+        source.set_file(PROC_MODULES, &format!("{}vboxdrv 696320 0 - Live 0x0000000000000000 (OE)\n", EXT4));
+        source.set_file(TAINTED, "12288\n");
+        assert!(tick(&mut monitor).is_empty());
+
+        // A soft lockup isn't down to any module
+        source.set_file(TAINTED, "28672\n");
+        let logs = tick(&mut monitor);
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].message, "Kernel taint changed from 12288 to 28672, newly set: L (soft lockup occurred)");
+    }
+
+    #[test]
+    fn test_modules_disabled_reverting_is_serious() {
+        let source = fake_kernel(EXT4, 0);
+        source.set_file(MODULES_DISABLED, "1\n");
+        let mut monitor = KernelModuleMonitor::with_source(source.clone());
+        tick(&mut monitor);
+
+        source.set_file(MODULES_DISABLED, "0\n");
+        let logs = tick(&mut monitor);
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[Serious]"));
+    }
+}
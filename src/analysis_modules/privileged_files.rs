@@ -0,0 +1,435 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use chrono::Utc;
+use ini::Ini;
+
+use crate::lara_core::core_enums::*;
+use crate::lara_core::core_structs::*;
+use crate::lara_core::core_traits::AnalysisModule;
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
+use crate::linux_bridge::system::PrivilegedFile;
+
+// Capabilities that are as good as root for whoever can run the file
+const DANGEROUS_CAPABILITIES: [&str; 7] = ["cap_setuid", "cap_setgid", "cap_sys_admin", "cap_dac_override", "cap_dac_read_search", "cap_sys_ptrace", "cap_sys_module"];
+
+// What is recorded for each privileged file
+#[derive(Debug, Clone, PartialEq)]
+struct PrivilegedRecord {
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    capabilities: String,
+    sha256: String,
+    world_writable_folder: bool,
+}
+
+impl PrivilegedRecord {
+    fn kind(&self) -> String {
+        let mut kinds = Vec::new();
+        if self.mode & 0o4000 != 0 {
+            kinds.push("SUID");
+        }
+        if self.mode & 0o2000 != 0 {
+            kinds.push("SGID");
+        }
+        if !self.capabilities.is_empty() {
+            kinds.push("capability");
+        }
+        kinds.join("/")
+    }
+}
+
+fn to_ini(files: &BTreeMap<String, PrivilegedRecord>) -> String {
+    let mut ini = Ini::new();
+    for (path, record) in files {
+        ini.with_section(Some(path.clone()))
+            .set("mode", format!("{:o}", record.mode))
+            .set("uid", record.uid.to_string())
+            .set("gid", record.gid.to_string())
+            .set("capabilities", record.capabilities.clone())
+            .set("sha256", record.sha256.clone())
+            .set("worldWritableFolder", record.world_writable_folder.to_string());
+    }
+    let mut content = Vec::new();
+    // Writing into a Vec can't fail
+    let _ = ini.write_to(&mut content);
+    String::from_utf8_lossy(&content).into_owned()
+}
+
+fn from_ini(content: &str) -> Option<BTreeMap<String, PrivilegedRecord>> {
+    let ini = Ini::load_from_str(content).ok()?;
+    let mut files = BTreeMap::new();
+    for (path, props) in ini.iter() {
+        let path = match path {
+            Some(path) => path,
+            None => continue,
+        };
+        files.insert(
+            path.to_string(),
+            PrivilegedRecord {
+                mode: u32::from_str_radix(props.get("mode")?, 8).ok()?,
+                uid: props.get("uid")?.parse().ok()?,
+                gid: props.get("gid")?.parse().ok()?,
+                capabilities: props.get("capabilities").unwrap_or_default().to_string(),
+                sha256: props.get("sha256").unwrap_or_default().to_string(),
+                world_writable_folder: props.get("worldWritableFolder") == Some("true"),
+            },
+        );
+    }
+    Some(files)
+}
+
+// Privileged files found by a scan and what went wrong while scanning
+type ScanResult = (BTreeMap<String, PrivilegedRecord>, Vec<Log>);
+
+// Walks the roots and hashes every privileged file found. Runs on the scan thread
+fn scan(source: &dyn SystemSource, roots: &[String], module_name: &str) -> ScanResult {
+    let mut failures = Vec::new();
+    let mut found: Vec<PrivilegedFile> = Vec::new();
+    for root in roots {
+        match source.privileged_files(root) {
+            Ok(files) => found.extend(files),
+            // Roots like /dev/shm don't exist everywhere
+            Err(ChromiaError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => failures.push(Log::ids_failure(module_name.to_string(), &format!("Failed to scan {} for privileged files", root), &e)),
+        }
+    }
+    let mut files = BTreeMap::new();
+    // Roots on the same filesystem (Eg / and /home) find the same files twice
+    for file in found {
+        if files.contains_key(&file.path) {
+            continue;
+        }
+        let sha256 = match source.file_sha256(&file.path) {
+            Ok(hash) => hash.trim().to_string(),
+            // The file was removed between the walk and hashing it, it will be gone next scan too
+            Err(_) => String::new(),
+        };
+        files.insert(
+            file.path,
+            PrivilegedRecord {
+                mode: file.mode,
+                uid: file.uid,
+                gid: file.gid,
+                capabilities: file.capabilities.unwrap_or_default(),
+                sha256,
+                world_writable_folder: file.world_writable_folder,
+            },
+        );
+    }
+    (files, failures)
+}
+
+pub struct PrivilegedFileMonitor {
+    // This is the data generated by gatherData in current tick, it will be erased by the next tick.
+    // None on ticks between scans
+    current_data: Option<BTreeMap<String, PrivilegedRecord>>,
+    //Everything else is persistent memory. The data you set in these will be remembered between ticks
+    previous: Option<BTreeMap<String, PrivilegedRecord>>,
+    last_scan: Option<i64>,
+    // The scan that is still walking the filesystem, if any
+    scan: Option<JoinHandle<ScanResult>>,
+    roots: Vec<String>,
+    home_folders: Vec<String>,
+    state_file: String,
+    scan_interval: i64,
+    module_name: String,
+    source: Arc<dyn SystemSource>,
+    failures: Vec<Log>,
+}
+
+impl AnalysisModule for PrivilegedFileMonitor {
+    fn get_data(&mut self) -> bool {
+        self.current_data = None;
+        if let Some(scan) = self.scan.take() {
+            if !scan.is_finished() {
+                self.scan = Some(scan);
+                return true;
+            }
+            match scan.join() {
+                Ok((files, mut failures)) => {
+                    self.current_data = Some(files);
+                    self.failures.append(&mut failures);
+                }
+                Err(_) => self.failures.push(Log::new(LogType::IDSFailure, self.module_name.clone(), String::from("The privileged file scan stopped unexpectedly"))),
+            }
+            return self.failures.is_empty();
+        }
+        let now = Utc::now().timestamp();
+        if let Some(last) = self.last_scan {
+            if now - last < self.scan_interval {
+                return true;
+            }
+        }
+        self.last_scan = Some(now);
+        // Walking the whole filesystem takes minutes on big hosts so it runs on a thread of its own. The
+        // result is picked up by the first tick after the walk is done
+        let source = Arc::clone(&self.source);
+        let roots = self.roots.clone();
+        let module_name = self.module_name.clone();
+        self.scan = Some(std::thread::spawn(move || scan(source.as_ref(), &roots, &module_name)));
+        true
+    }
+
+    fn get_testing_data(&mut self) -> bool {
+        todo!()
+    }
+
+    fn perform_analysis(&mut self) -> Vec<Log> {
+        let mut results = std::mem::take(&mut self.failures);
+        let current = match self.current_data.take() {
+            Some(current) => current,
+            None => return results,
+        };
+        // On the first scan compare against the baseline saved by the last run of Chromia
+        let previous = self.previous.take().or_else(|| from_ini(&self.source.read_to_string(&self.state_file).ok()?));
+        match &previous {
+            Some(previous) => results.append(&mut self.report_changes(previous, &current)),
+            None => {
+                results.push(Log::new(
+                    LogType::Info,
+                    self.module_name.clone(),
+                    format!("Recorded {} SUID, SGID and capability files under {}", current.len(), self.roots.join(", ")),
+                ));
+                // Anything already sitting somewhere risky is reported even though it is part of the baseline
+                for (path, record) in &current {
+                    if let Some(location) = self.risky_location(path, record) {
+                        results.push(Log::new(LogType::Serious, self.module_name.clone(), format!("{} file {} is in {}: {}", record.kind(), path, location, self.describe(record))));
+                    }
+                }
+            }
+        }
+        if let Err(e) = self.source.write_string(&self.state_file, &to_ini(&current)) {
+            results.push(Log::ids_failure(self.module_name.clone(), &format!("Could not save privileged file baseline to '{}'", self.state_file), &e));
+        }
+        self.previous = Some(current);
+        results
+    }
+
+    fn get_name(&self) -> String {
+        self.module_name.clone()
+    }
+
+    fn build_config_fields(&self) -> Vec<ConfigField> {
+        vec![
+            ConfigField::new("Roots".to_owned(), "Folders to search for SUID/SGID files and file capabilities. Each is searched without crossing into other filesystems, so list every mount to cover".to_owned(), ConfigFieldType::String, self.roots.clone(), true),
+            ConfigField::new("HomeFolders".to_owned(), "Folders holding user home folders. Privileged files in here are treated as serious".to_owned(), ConfigFieldType::String, self.home_folders.clone(), true),
+            ConfigField::new("StateFile".to_owned(), "File the baseline is saved to so new files are also caught across restarts of Chromia".to_owned(), ConfigFieldType::String, vec![self.state_file.clone()], false),
+            ConfigField::new("ScanInterval".to_owned(), "Seconds between scans. Walking the whole filesystem is slow so keep this high".to_owned(), ConfigFieldType::Integer, vec![self.scan_interval.to_string()], false),
+        ]
+    }
+
+    fn retrieve_config_data(&mut self, data: HashMap<String, Vec<String>>) -> bool {
+        for (field, vals) in data {
+            match field.as_str() {
+                "Roots" => self.roots = vals.into_iter().filter(|val| !val.is_empty()).collect(),
+                "HomeFolders" => self.home_folders = vals.into_iter().filter(|val| !val.is_empty()).collect(),
+                "StateFile" => {
+                    if let Some(path) = vals.first().filter(|path| !path.is_empty()) {
+                        self.state_file = path.clone();
+                    }
+                }
+                "ScanInterval" => match vals.first().and_then(|v| v.parse::<i64>().ok()) {
+                    Some(interval) if interval > 0 => self.scan_interval = interval,
+                    _ => {
+                        println!("ScanInterval must be a positive whole number of seconds");
+                        return false;
+                    }
+                },
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+impl PrivilegedFileMonitor {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+
+    fn user(&self, uid: u32) -> String {
+        match self.source.username(uid) {
+            Some(name) => format!("{}({})", name, uid),
+            None => uid.to_string(),
+        }
+    }
+
+    fn describe(&self, record: &PrivilegedRecord) -> String {
+        let mut description = format!("mode {:04o}, owner {}, group {}", record.mode, self.user(record.uid), record.gid);
+        if !record.capabilities.is_empty() {
+            description.push_str(&format!(", capabilities {}", record.capabilities));
+        }
+        if !record.sha256.is_empty() {
+            description.push_str(&format!(", sha256 {}", record.sha256));
+        }
+        description
+    }
+
+    fn risky_location(&self, path: &str, record: &PrivilegedRecord) -> Option<&'static str> {
+        if record.world_writable_folder {
+            return Some("a world writable folder");
+        }
+        if self.home_folders.iter().any(|home| path.starts_with(&format!("{}/", home.trim_end_matches('/')))) {
+            return Some("a home folder");
+        }
+        None
+    }
+
+    fn report_changes(&self, previous: &BTreeMap<String, PrivilegedRecord>, current: &BTreeMap<String, PrivilegedRecord>) -> Vec<Log> {
+        let mut results = Vec::new();
+        for (path, record) in current {
+            match previous.get(path) {
+                None => {
+                    let location = self.risky_location(path, record);
+                    let dangerous = DANGEROUS_CAPABILITIES.iter().any(|capability| record.capabilities.split(['=', ',']).any(|granted| granted == *capability));
+                    // SUID files not owned by root only give that user's rights, still odd but less severe
+                    let log_type = if location.is_some() || dangerous { LogType::Serious } else { LogType::Warning };
+                    let mut message = format!("New {} file {} ({})", record.kind(), path, self.describe(record));
+                    if let Some(location) = location {
+                        message.push_str(&format!(" in {}", location));
+                    }
+                    results.push(Log::new(log_type, self.module_name.clone(), message));
+                }
+                Some(old) if old != record => {
+                    let mut changes = Vec::new();
+                    if old.mode != record.mode {
+                        changes.push(format!("mode {:04o} -> {:04o}", old.mode, record.mode));
+                    }
+                    if old.uid != record.uid || old.gid != record.gid {
+                        changes.push(format!("owner {}:{} -> {}:{}", self.user(old.uid), old.gid, self.user(record.uid), record.gid));
+                    }
+                    if old.capabilities != record.capabilities {
+                        changes.push(format!("capabilities '{}' -> '{}'", old.capabilities, record.capabilities));
+                    }
+                    if old.sha256 != record.sha256 {
+                        changes.push(format!("sha256 {} -> {}", old.sha256, record.sha256));
+                    }
+                    if changes.is_empty() {
+                        continue;
+                    }
+                    results.push(Log::new(
+                        LogType::Warning,
+                        self.module_name.clone(),
+                        format!("{} file {} changed: {}. Confirm this was a package upgrade", record.kind(), path, changes.join(", ")),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        for (path, record) in previous.iter().filter(|(path, _)| !current.contains_key(*path)) {
+            results.push(Log::new(LogType::Info, self.module_name.clone(), format!("{} file {} is no longer privileged or was removed", record.kind(), path)));
+        }
+        results
+    }
+}
+
+impl Default for PrivilegedFileMonitor {
+    fn default() -> Self {
+        Self {
+            current_data: None,
+            previous: None,
+            last_scan: None,
+            scan: None,
+            roots: ["/", "/home", "/tmp", "/var/tmp", "/dev/shm"].iter().map(|&s| s.to_string()).collect(),
+            home_folders: ["/home", "/root"].iter().map(|&s| s.to_string()).collect(),
+            state_file: String::from("/var/lib/Chromia/privileged_files.ini"),
+            scan_interval: 3600,
+            module_name: String::from("PrivilegedFiles"),
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::source::FakeSource;
+    use crate::linux_bridge::system::decode_capabilities;
+
+    const STATE: &str = "/var/lib/Chromia/privileged_files.ini";
+
+    fn privileged(path: &str, mode: u32, uid: u32, capabilities: Option<&str>, world_writable_folder: bool) -> PrivilegedFile {
+        PrivilegedFile {
+            path: path.to_string(),
+            mode,
+            uid,
+            gid: 0,
+            capabilities: capabilities.map(|caps| caps.to_string()),
+            world_writable_folder,
+        }
+    }
+
+    fn fake_host() -> Arc<FakeSource> {
+        let source = Arc::new(FakeSource::new());
+        source.add_user(0, "root");
+        source.set_privileged_files(vec![privileged("/usr/bin/passwd", 0o4755, 0, None, false), privileged("/usr/bin/ping", 0o755, 0, Some("cap_net_raw=ep"), false)]);
+        source.set_hash("/usr/bin/passwd", "aa11");
+        source.set_hash("/usr/bin/ping", "bb22");
+        source
+    }
+
+    // Starts a scan and waits for the tick that picks up its result
+    fn run_scan(monitor: &mut PrivilegedFileMonitor) {
+        assert!(monitor.get_data());
+        while monitor.scan.is_some() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            assert!(monitor.get_data());
+        }
+    }
+
+    #[test]
+    fn test_decode_capabilities() {
+        // Revision 2 header with the effective bit, permitted cap_setuid (bit 7) and cap_net_raw (bit 13)
+        let mut raw = Vec::new();
+        for word in [0x0200_0001u32, (1 << 7) | (1 << 13), 0, 0, 0] {
+            raw.extend_from_slice(&word.to_le_bytes());
+        }
+        assert_eq!(decode_capabilities(&raw), Some("cap_setuid,cap_net_raw=ep".to_string()));
+        assert_eq!(decode_capabilities(&raw[..4]), None);
+    }
+
+    #[test]
+    fn test_baseline_is_saved_and_new_files_alert_after_restart() {
+        let source = fake_host();
+        let mut monitor = PrivilegedFileMonitor::with_source(source.clone());
+        run_scan(&mut monitor);
+        let logs = monitor.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].message, "Recorded 2 SUID, SGID and capability files under /, /home, /tmp, /var/tmp, /dev/shm");
+        assert_eq!(from_ini(&source.read_to_string(STATE).unwrap()).unwrap().len(), 2);
+
+        let mut files = source.privileged_files("/").unwrap();
+        files.push(privileged("/tmp/.sh", 0o4777, 0, None, true));
+        files.push(privileged("/usr/bin/python3.12", 0o755, 0, Some("cap_setuid=ep"), false));
+        source.set_privileged_files(files);
+        source.set_hash("/tmp/.sh", "cc33");
+        source.set_hash("/usr/bin/passwd", "dd44");
+
+        // A new instance has to pick up the baseline from the state file
+        let mut monitor = PrivilegedFileMonitor::with_source(source);
+        run_scan(&mut monitor);
+        let alerts: Vec<String> = monitor.perform_analysis().iter().map(|log| log.build_alert()).collect();
+        assert_eq!(alerts.len(), 3);
+        assert!(alerts[0].contains("[Serious]") && alerts[0].ends_with("New SUID file /tmp/.sh (mode 4777, owner root(0), group 0, sha256 cc33) in a world writable folder"));
+        assert!(alerts[1].contains("[Warning]") && alerts[1].ends_with("SUID file /usr/bin/passwd changed: sha256 aa11 -> dd44. Confirm this was a package upgrade"));
+        assert!(alerts[2].contains("[Serious]") && alerts[2].contains("New capability file /usr/bin/python3.12 (mode 0755, owner root(0), group 0, capabilities cap_setuid=ep"));
+    }
+
+    #[test]
+    fn test_scan_only_runs_every_interval() {
+        let source = fake_host();
+        let mut monitor = PrivilegedFileMonitor::with_source(source.clone());
+        run_scan(&mut monitor);
+        monitor.perform_analysis();
+        source.set_privileged_files(vec![privileged("/home/erik/.x", 0o6755, 1000, None, false)]);
+        assert!(monitor.get_data());
+        assert!(monitor.perform_analysis().is_empty());
+    }
+}
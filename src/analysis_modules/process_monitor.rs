@@ -0,0 +1,318 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::lara_core::core_enums::*;
+use crate::lara_core::core_structs::*;
+use crate::lara_core::core_traits::AnalysisModule;
+use crate::linux_bridge::process::ProcessInfo;
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
+
+// Walking further than this up the tree means /proc changed under us mid read
+const MAX_LINEAGE_DEPTH: usize = 32;
+
+// pid and start time together, pids get reused so the pid alone doesn't identify a process between ticks
+type ProcessKey = (u32, u64);
+
+fn key(process: &ProcessInfo) -> ProcessKey {
+    (process.pid, process.start_time)
+}
+
+#[derive(Debug, Clone, Default)]
+struct CurrentData {
+    processes: HashMap<u32, ProcessInfo>,
+}
+
+pub struct ProcessMonitor {
+    // This is the data generated by gatherData in current tick, it will be erased by the next tick
+    current_data: CurrentData,
+    //Everything else is persistent memory. The data you set in these will be remembered between ticks
+    // processes seen last tick, only processes that are not in here are checked for lineage and location
+    seen: HashSet<ProcessKey>,
+    // processes already reported for running a deleted binary
+    deleted_reported: HashSet<ProcessKey>,
+    web_servers: Vec<String>,
+    shells: Vec<String>,
+    suspicious_paths: Vec<String>,
+    module_name: String,
+    source: Arc<dyn SystemSource>,
+}
+
+impl AnalysisModule for ProcessMonitor {
+    fn get_data(&mut self) -> bool {
+        self.current_data.processes = self.source.processes().into_iter().map(|process| (process.pid, process)).collect();
+        // /proc always has at least Chromia itself in it, an empty table means it could not be read
+        !self.current_data.processes.is_empty()
+    }
+
+    fn get_testing_data(&mut self) -> bool {
+        todo!()
+    }
+
+    fn perform_analysis(&mut self) -> Vec<Log> {
+        let mut results = Vec::new();
+        let mut new_processes: Vec<&ProcessInfo> = self
+            .current_data
+            .processes
+            .values()
+            .filter(|process| !self.seen.contains(&key(process)))
+            .collect();
+        new_processes.sort_by_key(|process| process.pid);
+
+        // Shells already reported as started by a web server, so a chain like apache2 -> sh -> python3
+        // raises one alert for the sh rather than one per process
+        let mut web_children: HashSet<u32> = HashSet::new();
+        for process in new_processes {
+            if let Some(log) = self.check_web_lineage(process, &mut web_children) {
+                results.push(log);
+            }
+            if let Some(log) = self.check_location(process) {
+                results.push(log);
+            }
+        }
+        results.append(&mut self.check_deleted());
+
+        self.seen = self.current_data.processes.values().map(key).collect();
+        self.deleted_reported.retain(|process| self.seen.contains(process));
+        results
+    }
+
+    fn get_name(&self) -> String {
+        self.module_name.clone()
+    }
+
+    fn build_config_fields(&self) -> Vec<ConfigField> {
+        vec![
+            ConfigField::new("WebServers".to_owned(), "Process names of web servers. Any shell or interpreter started beneath one of these is alerted on. Names match by prefix, so php-fpm also covers php-fpm8.2".to_owned(), ConfigFieldType::String, self.web_servers.clone(), true),
+            ConfigField::new("Shells".to_owned(), "Shells and interpreters that a web server should never start. Names match exactly apart from a version, so python also covers python3.12".to_owned(), ConfigFieldType::String, self.shells.clone(), true),
+            ConfigField::new("SuspiciousPaths".to_owned(), "Folders that programs should never be run from, usually world writable".to_owned(), ConfigFieldType::String, self.suspicious_paths.clone(), true),
+        ]
+    }
+
+    fn retrieve_config_data(&mut self, data: HashMap<String, Vec<String>>) -> bool {
+        for (field, vals) in data {
+            let vals: Vec<String> = vals.into_iter().filter(|val| !val.is_empty()).collect();
+            match field.as_str() {
+                "WebServers" => self.web_servers = vals,
+                "Shells" => self.shells = vals,
+                "SuspiciousPaths" => self.suspicious_paths = vals,
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+impl ProcessMonitor {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+
+    // The process followed by its parent, grandparent and so on up to init (or the first ancestor that has exited)
+    fn lineage(&self, process: &ProcessInfo) -> Vec<&ProcessInfo> {
+        let mut chain = vec![];
+        let mut next = self.current_data.processes.get(&process.pid);
+        while let Some(current) = next {
+            chain.push(current);
+            if current.ppid == 0 || current.ppid == current.pid || chain.len() >= MAX_LINEAGE_DEPTH {
+                break;
+            }
+            next = self.current_data.processes.get(&current.ppid);
+        }
+        chain
+    }
+
+    fn describe(&self, process: &ProcessInfo) -> String {
+        let user = self.source.username(process.uid).unwrap_or_else(|| process.uid.to_string());
+        let lineage: Vec<String> = self.lineage(process).iter().rev().map(|ancestor| format!("{}({})", ancestor.name, ancestor.pid)).collect();
+        format!("pid {} user {} cmd '{}' lineage {}", process.pid, user, process.command_line(), lineage.join(" -> "))
+    }
+
+    fn check_web_lineage(&self, process: &ProcessInfo, web_children: &mut HashSet<u32>) -> Option<Log> {
+        if !is_shell(process, &self.shells) {
+            return None;
+        }
+        if web_children.contains(&process.ppid) {
+            web_children.insert(process.pid);
+            return None;
+        }
+        let lineage = self.lineage(process);
+        let server = lineage.iter().skip(1).find(|ancestor| matches_prefix(&ancestor.name, &self.web_servers))?;
+        web_children.insert(process.pid);
+        Some(Log::new(
+            LogType::Serious,
+            self.module_name.clone(),
+            format!("Web server '{}' started a shell or interpreter, possible web shell or remote code execution: {}", server.name, self.describe(process)),
+        ))
+    }
+
+    fn check_location(&self, process: &ProcessInfo) -> Option<Log> {
+        let exe = process.exe.as_ref()?;
+        let folder = self.suspicious_paths.iter().find(|folder| exe.starts_with(Path::new(folder)))?;
+        Some(Log::new(
+            LogType::Serious,
+            self.module_name.clone(),
+            format!("Process is running from {} ({}): {}", folder, exe.display(), self.describe(process)),
+        ))
+    }
+
+    // Checked on every tick, not just for new processes, as the binary can be removed at any point after it starts
+    fn check_deleted(&mut self) -> Vec<Log> {
+        let mut results = Vec::new();
+        let mut deleted: Vec<&ProcessInfo> = self
+            .current_data
+            .processes
+            .values()
+            .filter(|process| process.exe_deleted && !self.deleted_reported.contains(&key(process)))
+            .collect();
+        deleted.sort_by_key(|process| process.pid);
+        let reported: Vec<ProcessKey> = deleted.iter().map(|process| key(process)).collect();
+        for process in deleted {
+            let exe = process.exe.as_ref().map(|exe| exe.display().to_string()).unwrap_or_default();
+            let in_suspicious_path = process
+                .exe
+                .as_ref()
+                .is_some_and(|path| self.suspicious_paths.iter().any(|folder| path.starts_with(Path::new(folder))));
+            // memfd_create binaries never existed on disk, a common way to run malware without touching the filesystem
+            let log_type = if exe.starts_with("/memfd:") || in_suspicious_path {
+                LogType::Serious
+            } else {
+                LogType::Warning
+            };
+            results.push(Log::new(
+                log_type,
+                self.module_name.clone(),
+                format!("Process is running a binary that has been deleted from disk ({}). This is normal briefly after a package upgrade, otherwise the file may have been removed to hide it: {}", exe, self.describe(process)),
+            ));
+        }
+        self.deleted_reported.extend(reported);
+        results
+    }
+}
+
+fn matches_prefix(name: &str, prefixes: &[String]) -> bool {
+    prefixes.iter().any(|prefix| name.starts_with(prefix.as_str()))
+}
+
+// Exact match on the name of the binary, allowing only a version after it so python also covers python3.12
+// but sh doesn't cover shutdown or sha256sum
+fn is_shell(process: &ProcessInfo, shells: &[String]) -> bool {
+    let binary = process.exe.as_ref().and_then(|exe| exe.file_name()).map(|name| name.to_string_lossy().into_owned());
+    [binary.as_deref(), Some(process.name.as_str())].iter().flatten().any(|name| {
+        shells.iter().any(|shell| name.strip_prefix(shell.as_str()).is_some_and(|version| version.chars().all(|c| c.is_ascii_digit() || c == '.')))
+    })
+}
+
+impl Default for ProcessMonitor {
+    fn default() -> Self {
+        Self {
+            current_data: CurrentData::default(),
+            seen: HashSet::new(),
+            deleted_reported: HashSet::new(),
+            web_servers: ["apache2", "httpd", "nginx", "php-fpm", "lighttpd", "tomcat"].iter().map(|&s| s.to_string()).collect(),
+            shells: ["sh", "bash", "dash", "zsh", "ksh", "python", "perl", "ruby", "nc", "ncat", "socat"].iter().map(|&s| s.to_string()).collect(),
+            suspicious_paths: ["/tmp", "/dev/shm", "/var/tmp"].iter().map(|&s| s.to_string()).collect(),
+            module_name: String::from("ProcessMonitor"),
+            source: Arc::new(LinuxSource::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::source::FakeSource;
+
+    fn fake_process(pid: u32, ppid: u32, name: &str, exe: &str) -> ProcessInfo {
+        ProcessInfo::fake(pid, name).with_ppid(ppid).with_uid(33).with_argv(&[exe]).with_exe(exe)
+    }
+
+    fn base_system() -> Vec<ProcessInfo> {
+        vec![
+            fake_process(1, 0, "systemd", "/usr/lib/systemd/systemd"),
+            fake_process(800, 1, "apache2", "/usr/sbin/apache2"),
+            fake_process(801, 800, "apache2", "/usr/sbin/apache2"),
+        ]
+    }
+
+    fn monitor_after_baseline(source: Arc<FakeSource>) -> ProcessMonitor {
+        source.add_user(33, "www-data");
+        source.set_processes(base_system());
+        let mut monitor = ProcessMonitor::with_source(source);
+        assert!(monitor.get_data());
+        assert!(monitor.perform_analysis().is_empty());
+        monitor
+    }
+
+    #[test]
+    fn test_web_server_spawning_shell_raises_one_alert_per_chain() {
+        let source = Arc::new(FakeSource::new());
+        let mut monitor = monitor_after_baseline(source.clone());
+
+        let mut processes = base_system();
+        processes.push(fake_process(4410, 801, "sh", "/usr/bin/dash"));
+        processes.push(fake_process(4411, 4410, "python3", "/usr/bin/python3.12"));
+        source.set_processes(processes);
+        monitor.get_data();
+        let logs = monitor.perform_analysis();
+
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[Serious]"));
+        assert!(logs[0].message.contains("Web server 'apache2' started a shell"));
+        assert!(logs[0].message.contains("user www-data"));
+        assert!(logs[0].message.contains("lineage systemd(1) -> apache2(800) -> apache2(801) -> sh(4410)"));
+    }
+
+    #[test]
+    fn test_programs_named_like_a_shell_are_not_shells() {
+        let source = Arc::new(FakeSource::new());
+        let mut monitor = monitor_after_baseline(source.clone());
+
+        let mut processes = base_system();
+        processes.push(fake_process(4420, 801, "sha256sum", "/usr/bin/sha256sum"));
+        processes.push(fake_process(4421, 801, "shred", "/usr/bin/shred"));
+        processes.push(fake_process(4422, 801, "perl5.36", "/usr/bin/perl5.36"));
+        source.set_processes(processes);
+        monitor.get_data();
+        let logs = monitor.perform_analysis();
+
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].message.contains("cmd '/usr/bin/perl5.36'"));
+    }
+
+    #[test]
+    fn test_process_running_from_tmp() {
+        let source = Arc::new(FakeSource::new());
+        let mut monitor = monitor_after_baseline(source.clone());
+
+        let mut processes = base_system();
+        processes.push(fake_process(5000, 1, "kworkerds", "/dev/shm/.x/kworkerds"));
+        source.set_processes(processes);
+        monitor.get_data();
+        let logs = monitor.perform_analysis();
+
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].message.starts_with("Process is running from /dev/shm (/dev/shm/.x/kworkerds)"));
+    }
+
+    #[test]
+    fn test_deleted_binary_reported_once() {
+        let source = Arc::new(FakeSource::new());
+        let mut monitor = monitor_after_baseline(source.clone());
+
+        let mut processes = base_system();
+        processes[1].exe_deleted = true;
+        source.set_processes(processes);
+        monitor.get_data();
+        let logs = monitor.perform_analysis();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[Warning]"));
+        assert!(logs[0].message.contains("deleted from disk (/usr/sbin/apache2)"));
+
+        monitor.get_data();
+        assert!(monitor.perform_analysis().is_empty());
+    }
+}
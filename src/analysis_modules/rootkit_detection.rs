@@ -0,0 +1,362 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::lara_core::core_enums::*;
+use crate::lara_core::core_structs::*;
+use crate::lara_core::core_traits::AnalysisModule;
+use crate::linux_bridge::network::{parse_proc_net, SOCKET_TABLES};
+use crate::linux_bridge::process::{parse_status_tgid, split_nul};
+use crate::linux_bridge::source::{LinuxSource, SystemSource};
+use crate::linux_bridge::system::parse_modules;
+
+const PID_MAX: &str = "/proc/sys/kernel/pid_max";
+const PROC_MODULES: &str = "/proc/modules";
+const SYS_MODULE: &str = "/sys/module";
+// Used when pid_max can't be read, the kernel default on 32 bit systems
+const DEFAULT_PID_MAX: u32 = 32768;
+
+#[derive(Debug, Clone, Default)]
+struct CurrentData {
+    hidden_pids: Vec<u32>,
+    // (protocol, port)
+    hidden_ports: Vec<(String, u16)>,
+    // Modules in /sys/module but not /proc/modules, and the other way round
+    missing_from_proc: Vec<String>,
+    missing_from_sys: Vec<String>,
+}
+
+// Looks for things a rootkit hides from the normal views of the system by asking the kernel a second way:
+// pids from /proc against kill(pid, 0), /proc/net against binding each port and /proc/modules against /sys/module.
+// The pid and port sweeps are spread over several ticks.
+pub struct RootkitDetector {
+    // This is the data generated by gatherData in current tick, it will be erased by the next tick
+    current_data: CurrentData,
+    //Everything else is persistent memory. The data you set in these will be remembered between ticks
+    next_pid: u32,
+    next_port: u16,
+    pids_per_tick: u32,
+    ports_per_tick: u16,
+    reported_pids: HashSet<u32>,
+    reported_ports: HashSet<(String, u16)>,
+    reported_modules: HashSet<String>,
+    module_name: String,
+    source: Arc<dyn SystemSource>,
+    failures: Vec<Log>,
+}
+
+impl AnalysisModule for RootkitDetector {
+    fn get_data(&mut self) -> bool {
+        self.current_data = CurrentData {
+            hidden_pids: self.sweep_pids(),
+            hidden_ports: self.sweep_ports(),
+            ..Default::default()
+        };
+        match self.compare_modules() {
+            Ok((missing_from_proc, missing_from_sys)) => {
+                self.current_data.missing_from_proc = missing_from_proc;
+                self.current_data.missing_from_sys = missing_from_sys;
+                true
+            }
+            Err(e) => {
+                self.failures.push(Log::ids_failure(self.module_name.clone(), "Could not compare /proc/modules with /sys/module", &e));
+                false
+            }
+        }
+    }
+
+    fn get_testing_data(&mut self) -> bool {
+        todo!()
+    }
+
+    fn perform_analysis(&mut self) -> Vec<Log> {
+        let mut results = std::mem::take(&mut self.failures);
+        for pid in self.current_data.hidden_pids.clone() {
+            if !self.reported_pids.insert(pid) {
+                continue;
+            }
+            let cmdline = self
+                .source
+                .read_to_string(&format!("/proc/{}/cmdline", pid))
+                .map(|cmdline| split_nul(cmdline.as_bytes()).join(" "))
+                .unwrap_or_default();
+            results.push(Log::new(
+                LogType::Critical,
+                self.module_name.clone(),
+                format!("Hidden process: pid {} exists but is missing from the /proc listing, a rootkit may be hiding it from ps. Command line: '{}'", pid, cmdline),
+            ));
+        }
+        for (protocol, port) in self.current_data.hidden_ports.clone() {
+            if !self.reported_ports.insert((protocol.clone(), port)) {
+                continue;
+            }
+            results.push(Log::new(
+                LogType::Critical,
+                self.module_name.clone(),
+                format!("Hidden port: {} port {} is in use but no socket for it is listed in /proc/net, a rootkit may be hiding it from netstat and ss", protocol, port),
+            ));
+        }
+        for module in self.current_data.missing_from_proc.clone() {
+            if self.reported_modules.insert(module.clone()) {
+                results.push(Log::new(
+                    LogType::Critical,
+                    self.module_name.clone(),
+                    format!("Hidden kernel module: '{}' is in /sys/module but missing from /proc/modules (and lsmod)", module),
+                ));
+            }
+        }
+        for module in self.current_data.missing_from_sys.clone() {
+            if self.reported_modules.insert(module.clone()) {
+                results.push(Log::new(
+                    LogType::Critical,
+                    self.module_name.clone(),
+                    format!("Hidden kernel module: '{}' is in /proc/modules but has removed itself from /sys/module", module),
+                ));
+            }
+        }
+        results
+    }
+
+    fn get_name(&self) -> String {
+        self.module_name.clone()
+    }
+
+    fn build_config_fields(&self) -> Vec<ConfigField> {
+        vec![
+            ConfigField::new("PidsPerTick".to_owned(), "Number of pids probed for hidden processes each tick".to_owned(), ConfigFieldType::Integer, vec![self.pids_per_tick.to_string()], false),
+            ConfigField::new("PortsPerTick".to_owned(), "Number of TCP and UDP ports bind probed for hidden sockets each tick".to_owned(), ConfigFieldType::Integer, vec![self.ports_per_tick.to_string()], false),
+        ]
+    }
+
+    fn retrieve_config_data(&mut self, data: HashMap<String, Vec<String>>) -> bool {
+        for (field, vals) in data {
+            match field.as_str() {
+                "PidsPerTick" => match vals.first().and_then(|v| v.parse::<u32>().ok()) {
+                    Some(count) if count > 0 => self.pids_per_tick = count,
+                    _ => {
+                        println!("PidsPerTick must be a positive whole number");
+                        return false;
+                    }
+                },
+                "PortsPerTick" => match vals.first().and_then(|v| v.parse::<u16>().ok()) {
+                    Some(count) if count > 0 => self.ports_per_tick = count,
+                    _ => {
+                        println!("PortsPerTick must be a whole number between 1 and 65535");
+                        return false;
+                    }
+                },
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+impl RootkitDetector {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+
+    fn sweep_pids(&mut self) -> Vec<u32> {
+        let pid_max = self
+            .source
+            .read_to_string(PID_MAX)
+            .ok()
+            .and_then(|max| max.trim().parse().ok())
+            .unwrap_or(DEFAULT_PID_MAX);
+        let start = self.next_pid.max(1);
+        let end = start.saturating_add(self.pids_per_tick).min(pid_max);
+        self.next_pid = if end >= pid_max { 1 } else { end };
+
+        let listed: HashSet<u32> = self.source.pids().into_iter().collect();
+        let candidates: Vec<u32> = (start..end)
+            .filter(|pid| !listed.contains(pid))
+            .filter(|pid| self.source.pid_alive(*pid) || self.source.path_exists(&format!("/proc/{}", pid)))
+            .filter(|pid| !self.is_thread(*pid))
+            .collect();
+        if candidates.is_empty() {
+            return candidates;
+        }
+        // List again to rule out processes that started between the listing and the probe
+        let listed: HashSet<u32> = self.source.pids().into_iter().collect();
+        candidates.into_iter().filter(|pid| !listed.contains(pid) && self.source.pid_alive(*pid)).collect()
+    }
+
+    // Threads can be reached at /proc/<tid> and signalled like a process but are never listed in /proc
+    fn is_thread(&self, pid: u32) -> bool {
+        match self.source.read_to_string(&format!("/proc/{}/status", pid)) {
+            Ok(status) => parse_status_tgid(&status).is_some_and(|tgid| tgid != pid),
+            Err(_) => false,
+        }
+    }
+
+    // Ports of every socket the kernel lists, in any state. A connected or TIME_WAIT socket also stops the port
+    // being bound, so only listeners would give false alarms.
+    fn listed_ports(&self) -> HashSet<(String, u16)> {
+        let mut ports = HashSet::new();
+        for (protocol, path) in SOCKET_TABLES {
+            if let Ok(table) = self.source.read_to_string(path) {
+                let family = protocol.trim_end_matches('6').to_string();
+                for socket in parse_proc_net(&table, protocol) {
+                    ports.insert((family.clone(), socket.local_port));
+                }
+            }
+        }
+        ports
+    }
+
+    fn sweep_ports(&mut self) -> Vec<(String, u16)> {
+        let start = self.next_port.max(1);
+        // Inclusive so 65535 is probed before wrapping back to port 1
+        let end = (start as u32 + self.ports_per_tick.max(1) as u32 - 1).min(u16::MAX as u32) as u16;
+        self.next_port = if end == u16::MAX { 1 } else { end + 1 };
+
+        let listed = self.listed_ports();
+        let mut candidates = Vec::new();
+        for port in start..=end {
+            for protocol in ["tcp", "udp"] {
+                if !listed.contains(&(protocol.to_string(), port)) && self.source.port_bound(protocol, port) {
+                    candidates.push((protocol.to_string(), port));
+                }
+            }
+        }
+        if candidates.is_empty() {
+            return candidates;
+        }
+        // Read the tables again in case a socket was opened while probing
+        let listed = self.listed_ports();
+        candidates.into_iter().filter(|candidate| !listed.contains(candidate)).collect()
+    }
+
+    fn compare_modules(&self) -> Result<(Vec<String>, Vec<String>), ChromiaError> {
+        let proc_modules: HashSet<String> = parse_modules(&self.source.read_to_string(PROC_MODULES)?).into_iter().map(|module| module.name).collect();
+        // Built in modules also have a folder in /sys/module, only loadable ones have an initstate file
+        let sys_modules: HashSet<String> = self
+            .source
+            .read_dir(SYS_MODULE)?
+            .into_iter()
+            .filter(|name| self.source.path_exists(&format!("{}/{}/initstate", SYS_MODULE, name)))
+            .collect();
+        let mut missing_from_proc: Vec<String> = sys_modules.difference(&proc_modules).cloned().collect();
+        let mut missing_from_sys: Vec<String> = proc_modules.difference(&sys_modules).cloned().collect();
+        if !missing_from_proc.is_empty() || !missing_from_sys.is_empty() {
+            // A module loading or unloading between the two reads shows up here once, read /proc/modules again
+            let proc_modules: HashSet<String> = parse_modules(&self.source.read_to_string(PROC_MODULES)?).into_iter().map(|module| module.name).collect();
+            missing_from_proc.retain(|name| !proc_modules.contains(name));
+            missing_from_sys.retain(|name| proc_modules.contains(name) && !self.source.path_exists(&format!("{}/{}", SYS_MODULE, name)));
+        }
+        missing_from_proc.sort();
+        missing_from_sys.sort();
+        Ok((missing_from_proc, missing_from_sys))
+    }
+}
+
+impl Default for RootkitDetector {
+    fn default() -> Self {
+        Self {
+            current_data: CurrentData::default(),
+            next_pid: 1,
+            next_port: 1,
+            // A full sweep of the default pid_max takes 8 ticks and the port range 512, keeping each tick to a few milliseconds
+            pids_per_tick: 4096,
+            ports_per_tick: 128,
+            reported_pids: HashSet::new(),
+            reported_ports: HashSet::new(),
+            reported_modules: HashSet::new(),
+            module_name: String::from("RootkitDetection"),
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::process::ProcessInfo;
+    use crate::linux_bridge::source::FakeSource;
+
+    fn fake_process(pid: u32) -> ProcessInfo {
+        ProcessInfo::fake(pid, "proc")
+    }
+
+    fn clean_system() -> Arc<FakeSource> {
+        let source = Arc::new(FakeSource::new());
+        source.set_file(PID_MAX, "4096\n");
+        source.set_processes(vec![fake_process(1), fake_process(400), fake_process(401)]);
+        source.set_file("/proc/net/tcp", "header\n   0: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21034 1 0000000000000000 100 0 0 10 0\n");
+        source.set_bound_ports(&[("tcp", 22)]);
+        source.set_file(PROC_MODULES, "ext4 1069056 1 - Live 0x0000000000000000\n");
+        source.set_file("/sys/module/ext4/initstate", "live\n");
+        source.set_file("/sys/module/printk/parameters/time", "Y\n");
+        source
+    }
+
+    fn run_tick(detector: &mut RootkitDetector) -> Vec<Log> {
+        assert!(detector.get_data());
+        detector.perform_analysis()
+    }
+
+    #[test]
+    fn test_clean_system_raises_nothing() {
+        let mut detector = RootkitDetector::with_source(clean_system());
+        assert!(run_tick(&mut detector).is_empty());
+    }
+
+    #[test]
+    fn test_hidden_pid_reported_once_and_threads_ignored() {
+        let source = clean_system();
+        source.set_hidden_pids(&[402, 1337]);
+        source.set_file("/proc/402/status", "Name:\tworker\nTgid:\t401\nPid:\t402\n");
+        source.set_file("/proc/1337/cmdline", "/usr/bin/.sshd\0-D\0");
+        let mut detector = RootkitDetector::with_source(source);
+
+        let logs = run_tick(&mut detector);
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].build_alert().contains("[CRITICAL]"));
+        assert!(logs[0].message.starts_with("Hidden process: pid 1337"));
+        assert!(logs[0].message.contains("'/usr/bin/.sshd -D'"));
+
+        detector.next_pid = 1;
+        assert!(run_tick(&mut detector).is_empty());
+    }
+
+    #[test]
+    fn test_hidden_port() {
+        let source = clean_system();
+        source.set_bound_ports(&[("tcp", 22), ("udp", 53)]);
+        let mut detector = RootkitDetector::with_source(source);
+
+        let logs = run_tick(&mut detector);
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].message.starts_with("Hidden port: udp port 53 is in use"));
+    }
+
+    #[test]
+    fn test_port_sweep_reaches_last_port_and_wraps() {
+        let source = clean_system();
+        source.set_bound_ports(&[("tcp", 22), ("tcp", 65535)]);
+        let mut detector = RootkitDetector::with_source(source);
+        detector.next_port = 65500;
+
+        let logs = run_tick(&mut detector);
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].message.starts_with("Hidden port: tcp port 65535 is in use"));
+        assert_eq!(detector.next_port, 1);
+        assert!(run_tick(&mut detector).is_empty());
+        assert_eq!(detector.next_port, 129);
+    }
+
+    #[test]
+    fn test_module_hidden_from_proc_modules() {
+        let source = clean_system();
+        source.set_file("/sys/module/diamorphine/initstate", "live\n");
+        let mut detector = RootkitDetector::with_source(source);
+
+        let logs = run_tick(&mut detector);
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].message.contains("'diamorphine' is in /sys/module but missing from /proc/modules"));
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use chrono::Utc;
+
+use crate::lara_core::core_enums::*;
+use crate::lara_core::core_structs::*;
+use crate::lara_core::core_traits::AnalysisModule;
+use crate::linux_bridge::source::{LinuxSource, LogTail, SystemSource};
+
+// Functions that run commands or code, across the languages web servers run
+const EXEC_SIGNATURES: [&str; 14] = [
+    "system(", "shell_exec(", "passthru(", "exec(", "popen(", "proc_open(", "pcntl_exec(", "runtime.getruntime().exec(", "processbuilder(", "process.start(", "os.system(", "subprocess.", "cmd.exe /c", "/bin/sh -c",
+];
+// Ways of hiding a payload from a casual read of the file
+const DECODE_SIGNATURES: [&str; 7] = ["base64_decode(", "gzinflate(", "gzuncompress(", "str_rot13(", "convert.frombase64string(", "hex2bin(", "\\x65\\x76\\x61\\x6c"];
+const EVAL_SIGNATURES: [&str; 4] = ["eval(", "assert(", "create_function(", "preg_replace(\"/.*/e"];
+// Where a request's parameters and headers end up
+const INPUT_SIGNATURES: [&str; 7] = ["$_get", "$_post", "$_request", "$_cookie", "$_server['http_", "request.getparameter(", "request.form"];
+// Shannon entropy (bits per byte) above which a file is mostly packed or encoded data, plain source sits around 4.5-5
+const HIGH_ENTROPY: f64 = 5.8;
+const MIN_ENTROPY_SIZE: usize = 1024;
+
+// A new or modified script that scored over the threshold
+#[derive(Debug, Clone)]
+struct Suspect {
+    score: u32,
+    reasons: Vec<String>,
+    change: &'static str,
+}
+
+// One line of an access log in common or combined format
+#[derive(Debug, Clone, PartialEq)]
+struct Request {
+    ip: String,
+    method: String,
+    path: String,
+    status: String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct CurrentData {
+    // Requests written to the access logs since last tick
+    requests: Vec<Request>,
+    // path -> modification time of every script in the document roots. None on ticks between scans
+    scripts: Option<HashMap<String, i64>>,
+}
+
+pub struct WebshellDetector {
+    // This is the data generated by gatherData in current tick, it will be erased by the next tick
+    current_data: CurrentData,
+    //Everything else is persistent memory. The data you set in these will be remembered between ticks
+    // None until the first scan has recorded the scripts already being served
+    known_scripts: Option<HashMap<String, i64>>,
+    suspects: HashMap<String, Suspect>,
+    // (script, client ip) pairs already alerted on
+    reported_requests: HashSet<(String, String)>,
+    // Requests for scripts since the last scan, checked again once the scan has scored any new uploads
+    pending_requests: Vec<Request>,
+    // How far each access log has been read
+    log_tails: HashMap<String, LogTail>,
+    last_scan: Option<i64>,
+    document_roots: Vec<String>,
+    access_logs: Vec<String>,
+    extensions: Vec<String>,
+    score_threshold: u32,
+    scan_interval: i64,
+    module_name: String,
+    source: Arc<dyn SystemSource>,
+    failures: Vec<Log>,
+}
+
+impl AnalysisModule for WebshellDetector {
+    fn get_data(&mut self) -> bool {
+        let mut requests = Vec::new();
+        for path in self.access_logs.clone() {
+            let tail = self.log_tails.entry(path.clone()).or_default();
+            match tail.read_new(self.source.as_ref(), &path) {
+                Ok(new) => requests.extend(new.lines().filter_map(parse_access_line)),
+                // Only one of Apache, httpd and nginx is usually installed
+                Err(ChromiaError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => self.failures.push(Log::ids_failure(self.module_name.clone(), &format!("Could not read access log '{}'", path), &e)),
+            }
+        }
+
+        let now = Utc::now().timestamp();
+        let mut scripts = None;
+        if self.last_scan.is_none_or(|last| now - last >= self.scan_interval) {
+            self.last_scan = Some(now);
+            let mut found = HashMap::new();
+            for root in self.document_roots.clone() {
+                match self.source.list_files(&root) {
+                    Ok(files) => found.extend(files.into_iter().filter(|file| self.is_script(&file.path)).map(|file| (file.path, file.modified))),
+                    Err(ChromiaError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => self.failures.push(Log::ids_failure(self.module_name.clone(), &format!("Could not scan document root '{}'", root), &e)),
+                }
+            }
+            scripts = Some(found);
+        }
+        self.current_data = CurrentData { requests, scripts };
+        self.failures.is_empty()
+    }
+
+    fn get_testing_data(&mut self) -> bool {
+        todo!()
+    }
+
+    fn perform_analysis(&mut self) -> Vec<Log> {
+        let mut results = std::mem::take(&mut self.failures);
+        let current = std::mem::take(&mut self.current_data);
+        let scanned = current.scripts.is_some();
+
+        if let Some(scripts) = current.scripts {
+            if let Some(known) = &self.known_scripts {
+                let mut changed: Vec<(&String, &'static str)> = scripts
+                    .iter()
+                    .filter_map(|(path, modified)| match known.get(path) {
+                        None => Some((path, "new")),
+                        Some(old) if old != modified => Some((path, "modified")),
+                        _ => None,
+                    })
+                    .collect();
+                changed.sort();
+                for (path, change) in changed {
+                    let content = match self.source.read_bytes(path, u64::MAX) {
+                        Ok(Some(content)) => content,
+                        _ => continue,
+                    };
+                    let (score, reasons) = score_script(&content);
+                    if score < self.score_threshold {
+                        self.suspects.remove(path);
+                        continue;
+                    }
+                    results.push(Log::new(
+                        LogType::Warning,
+                        self.module_name.clone(),
+                        format!("Suspicious {} script {} (score {}: {})", change, path, score, reasons.join(", ")),
+                    ));
+                    self.suspects.insert(path.clone(), Suspect { score, reasons, change });
+                }
+            }
+            self.suspects.retain(|path, _| scripts.contains_key(path));
+            self.reported_requests.retain(|(path, _)| scripts.contains_key(path));
+            self.known_scripts = Some(scripts);
+        }
+
+        // A shell is usually uploaded and requested within one scan interval, so requests are kept until the
+        // next scan has had the chance to score it
+        let mut pending = std::mem::take(&mut self.pending_requests);
+        let first_new = pending.len();
+        pending.extend(current.requests.into_iter().filter(|request| self.is_script(&request.path)));
+        let to_check = if scanned { &pending[..] } else { &pending[first_new..] };
+        results.extend(self.correlate(to_check));
+        if !scanned {
+            self.pending_requests = pending;
+        }
+        results
+    }
+
+    fn get_name(&self) -> String {
+        self.module_name.clone()
+    }
+
+    fn build_config_fields(&self) -> Vec<ConfigField> {
+        vec![
+            ConfigField::new("DocumentRoots".to_owned(), "Folders web servers serve files from, a request for /a.php is matched to <root>/a.php".to_owned(), ConfigFieldType::String, self.document_roots.clone(), true),
+            ConfigField::new("AccessLogs".to_owned(), "Access logs in common or combined format, missing logs are skipped".to_owned(), ConfigFieldType::String, self.access_logs.clone(), true),
+            ConfigField::new("ScriptExtensions".to_owned(), "File extensions of server side scripts".to_owned(), ConfigFieldType::String, self.extensions.clone(), true),
+            ConfigField::new("ScoreThreshold".to_owned(), "Signature score at which a new or modified script is treated as a possible web shell".to_owned(), ConfigFieldType::Integer, vec![self.score_threshold.to_string()], false),
+            ConfigField::new("ScanInterval".to_owned(), "Seconds between scans of the document roots".to_owned(), ConfigFieldType::Integer, vec![self.scan_interval.to_string()], false),
+        ]
+    }
+
+    fn retrieve_config_data(&mut self, data: HashMap<String, Vec<String>>) -> bool {
+        for (field, vals) in data {
+            let vals: Vec<String> = vals.into_iter().filter(|val| !val.is_empty()).collect();
+            match field.as_str() {
+                "DocumentRoots" => self.document_roots = vals,
+                "AccessLogs" => self.access_logs = vals,
+                "ScriptExtensions" => self.extensions = vals.into_iter().map(|extension| extension.trim_start_matches('.').to_lowercase()).collect(),
+                "ScoreThreshold" => match vals.first().and_then(|v| v.parse::<u32>().ok()) {
+                    Some(threshold) if threshold > 0 => self.score_threshold = threshold,
+                    _ => {
+                        println!("ScoreThreshold must be a positive whole number");
+                        return false;
+                    }
+                },
+                "ScanInterval" => match vals.first().and_then(|v| v.parse::<i64>().ok()) {
+                    Some(interval) if interval > 0 => self.scan_interval = interval,
+                    _ => {
+                        println!("ScanInterval must be a positive whole number of seconds");
+                        return false;
+                    }
+                },
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+impl WebshellDetector {
+    pub fn with_source(source: Arc<dyn SystemSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+
+    fn is_script(&self, path: &str) -> bool {
+        path.rsplit_once('.').is_some_and(|(_, extension)| self.extensions.contains(&extension.to_lowercase()))
+    }
+
+    fn correlate(&mut self, requests: &[Request]) -> Vec<Log> {
+        let mut results = Vec::new();
+        for request in requests {
+            for root in &self.document_roots {
+                let path = format!("{}{}", root.trim_end_matches('/'), request.path);
+                let suspect = match self.suspects.get(&path) {
+                    Some(suspect) => suspect,
+                    None => continue,
+                };
+                if !self.reported_requests.insert((path.clone(), request.ip.clone())) {
+                    continue;
+                }
+                results.push(Log::new(
+                    LogType::Critical,
+                    self.module_name.clone(),
+                    format!(
+                        "Possible web shell in use: {} requested {} {} (status {}). The script is {} and scored {} ({})",
+                        request.ip, request.method, path, request.status, suspect.change, suspect.score, suspect.reasons.join(", ")
+                    ),
+                ));
+            }
+        }
+        results
+    }
+}
+
+// Scores a script on the signatures it contains. Each kind of signature counts once, with extra weight for the
+// combinations web shells are built from. Scripts don't have to be valid UTF-8 to run, and packed payloads often aren't
+fn score_script(content: &[u8]) -> (u32, Vec<String>) {
+    let lower = String::from_utf8_lossy(content).to_lowercase();
+    let find = |signatures: &[&'static str]| -> Option<&'static str> { signatures.iter().copied().find(|signature| contains_signature(&lower, signature)) };
+    let mut score = 0;
+    let mut reasons = Vec::new();
+    let exec = find(&EXEC_SIGNATURES);
+    let decode = find(&DECODE_SIGNATURES);
+    let eval = find(&EVAL_SIGNATURES);
+    let input = find(&INPUT_SIGNATURES);
+    for (found, weight, kind) in [(exec, 2, "runs commands"), (decode, 2, "decodes data"), (eval, 3, "evaluates code"), (input, 1, "reads request input")] {
+        if let Some(signature) = found {
+            score += weight;
+            reasons.push(format!("{} ({})", kind, signature.trim_end_matches('(')));
+        }
+    }
+    if eval.is_some() && decode.is_some() {
+        score += 3;
+        reasons.push("evaluates decoded data".to_string());
+    }
+    if (exec.is_some() || eval.is_some()) && input.is_some() {
+        score += 3;
+        reasons.push("passes request input to code or commands".to_string());
+    }
+    if content.len() >= MIN_ENTROPY_SIZE {
+        let entropy = shannon_entropy(content);
+        if entropy >= HIGH_ENTROPY {
+            score += 3;
+            reasons.push(format!("obfuscated (entropy {:.1} bits per byte)", entropy));
+        }
+    }
+    (score, reasons)
+}
+
+// A signature only counts where it starts a name, so exec( doesn't match curl_exec( or $pdo->exec( and eval(
+// doesn't match retrieval(
+fn contains_signature(content: &str, signature: &str) -> bool {
+    content.match_indices(signature).any(|(start, _)| {
+        let before = &content[..start];
+        !(before.ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == '$') || before.ends_with("->") || before.ends_with("::"))
+    })
+}
+
+fn shannon_entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts.iter().filter(|count| **count > 0).map(|count| *count as f64 / len).map(|p| -p * p.log2()).sum()
+}
+
+// 203.0.113.9 - - [18/Oct/2026:10:12:01 +0000] "POST /uploads/x.php?c=id HTTP/1.1" 200 512 "-" "curl/8.5.0"
+fn parse_access_line(line: &str) -> Option<Request> {
+    let mut quoted = line.split('"');
+    let ip = quoted.next()?.split_whitespace().next()?.to_string();
+    let mut request = quoted.next()?.split_whitespace();
+    let method = request.next()?.to_string();
+    let target = request.next()?;
+    let status = quoted.next()?.split_whitespace().next()?.to_string();
+    let path = target.split(['?', '#']).next()?.replace("%20", " ");
+    if !path.starts_with('/') {
+        return None;
+    }
+    Some(Request { ip, method, path, status })
+}
+
+impl Default for WebshellDetector {
+    fn default() -> Self {
+        Self {
+            current_data: CurrentData::default(),
+            known_scripts: None,
+            suspects: HashMap::new(),
+            reported_requests: HashSet::new(),
+            pending_requests: Vec::new(),
+            log_tails: HashMap::new(),
+            last_scan: None,
+            document_roots: ["/var/www/html", "/srv/http", "/usr/share/nginx/html"].iter().map(|&s| s.to_string()).collect(),
+            access_logs: ["/var/log/apache2/access.log", "/var/log/httpd/access_log", "/var/log/nginx/access.log"].iter().map(|&s| s.to_string()).collect(),
+            extensions: ["php", "phtml", "php5", "php7", "phar", "jsp", "jspx", "asp", "aspx", "ashx", "cgi", "pl", "py"].iter().map(|&s| s.to_string()).collect(),
+            score_threshold: 5,
+            scan_interval: 60,
+            module_name: String::from("Webshell"),
+            source: Arc::new(LinuxSource::default()),
+            failures: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::source::FakeSource;
+
+    const ACCESS_LOG: &str = "/var/log/apache2/access.log";
+
+    fn fake_server() -> Arc<FakeSource> {
+        let source = Arc::new(FakeSource::new());
+        source.set_file("/var/www/html/index.php", "<?php echo 'hello'; ?>");
+        source.set_file("/var/www/html/style.css", "body {}");
+        source.set_file(ACCESS_LOG, "198.51.100.4 - - [18/Oct/2026:10:00:00 +0000] \"GET /index.php HTTP/1.1\" 200 12 \"-\" \"Mozilla/5.0\"\n");
+        source
+    }
+
+    fn tick(detector: &mut WebshellDetector) -> Vec<String> {
+        detector.get_data();
+        detector.perform_analysis().iter().map(|log| log.build_alert()).collect()
+    }
+
+    #[test]
+    fn test_score_script() {
+        let (score, reasons) = score_script(b"<?php @eval(base64_decode($_POST['x'])); ?>");
+        assert_eq!(score, 12);
+        assert_eq!(reasons, vec!["decodes data (base64_decode)", "evaluates code (eval)", "reads request input ($_post)", "evaluates decoded data", "passes request input to code or commands"]);
+        assert!(score_script(b"<?php include 'header.php'; echo htmlspecialchars($_GET['q']); ?>").0 < 5);
+    }
+
+    #[test]
+    fn test_signatures_inside_other_names_are_ignored() {
+        let (score, reasons) = score_script(b"<?php $r = curl_exec($ch); $pdo->exec($sql); Cache::system($k); filesystem($_GET['p']); retrieval($x); ?>");
+        assert_eq!(score, 1);
+        assert_eq!(reasons, vec!["reads request input ($_get)"]);
+    }
+
+    #[test]
+    fn test_score_script_that_is_not_utf8() {
+        let mut content = b"<?php /* \xff\xfe */ system($_GET['c']); ?>".to_vec();
+        content.extend_from_slice(&[0xc3, 0x28, 0xa0, 0xa1]);
+        assert_eq!(score_script(&content).0, 6);
+    }
+
+    #[test]
+    fn test_new_suspicious_script_being_requested() {
+        let source = fake_server();
+        let mut detector = WebshellDetector::with_source(source.clone());
+        detector.scan_interval = 1;
+        assert!(tick(&mut detector).is_empty());
+
+        source.set_file("/var/www/html/uploads/avatar.php", "<?php system($_GET['c']); ?>");
+        detector.last_scan = None;
+        let alerts = tick(&mut detector);
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].contains("[Warning]") && alerts[0].ends_with("Suspicious new script /var/www/html/uploads/avatar.php (score 6: runs commands (system), reads request input ($_get), passes request input to code or commands)"));
+
+        let log = source.read_to_string(ACCESS_LOG).unwrap();
+        source.set_file(ACCESS_LOG, &format!("{}203.0.113.9 - - [18/Oct/2026:10:12:01 +0000] \"GET /uploads/avatar.php?c=id HTTP/1.1\" 200 33 \"-\" \"curl/8.5.0\"\n", log));
+        let alerts = tick(&mut detector);
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].contains("[CRITICAL]") && alerts[0].contains("Possible web shell in use: 203.0.113.9 requested GET /var/www/html/uploads/avatar.php (status 200). The script is new and scored 6"));
+    }
+
+    #[test]
+    fn test_request_before_the_scan_that_finds_the_script() {
+        let source = fake_server();
+        let mut detector = WebshellDetector::with_source(source.clone());
+        assert!(tick(&mut detector).is_empty());
+
+        source.set_file("/var/www/html/uploads/avatar.php", "<?php system($_GET['c']); ?>");
+        let log = source.read_to_string(ACCESS_LOG).unwrap();
+        source.set_file(ACCESS_LOG, &format!("{}203.0.113.9 - - [18/Oct/2026:10:00:20 +0000] \"GET /uploads/avatar.php?c=id HTTP/1.1\" 200 33 \"-\" \"curl/8.5.0\"\n", log));
+        assert!(tick(&mut detector).is_empty());
+
+        detector.last_scan = None;
+        let alerts = tick(&mut detector);
+        assert_eq!(alerts.len(), 2);
+        assert!(alerts[0].contains("Suspicious new script /var/www/html/uploads/avatar.php"));
+        assert!(alerts[1].contains("Possible web shell in use: 203.0.113.9 requested GET /var/www/html/uploads/avatar.php"));
+        assert!(detector.pending_requests.is_empty());
+    }
+
+    #[test]
+    fn test_modified_script_that_is_not_suspicious() {
+        let source = fake_server();
+        let mut detector = WebshellDetector::with_source(source.clone());
+        tick(&mut detector);
+        source.set_modified("/var/www/html/index.php", 1_700_000_000);
+        source.set_file("/var/www/html/index.php", "<?php echo 'hello world'; ?>");
+        detector.last_scan = None;
+        assert!(tick(&mut detector).is_empty());
+    }
+
+    #[test]
+    fn test_requests_written_just_before_rotation_are_read() {
+        let source = fake_server();
+        let mut detector = WebshellDetector::with_source(source.clone());
+        tick(&mut detector);
+        source.set_file("/var/www/html/x.php", "<?php system($_GET['c']); ?>");
+        detector.last_scan = None;
+        assert_eq!(tick(&mut detector).len(), 1);
+
+        let log = source.read_to_string(ACCESS_LOG).unwrap();
+        source.set_file(ACCESS_LOG, &format!("{}203.0.113.9 - - [18/Oct/2026:10:12:01 +0000] \"GET /x.php?c=id HTTP/1.1\" 200 33 \"-\" \"curl/8.5.0\"\n", log));
+        source.rotate_file(ACCESS_LOG);
+        source.set_file(ACCESS_LOG, "198.51.100.4 - - [18/Oct/2026:10:12:05 +0000] \"GET /index.php HTTP/1.1\" 200 12 \"-\" \"Mozilla/5.0\"\n");
+        let alerts = tick(&mut detector);
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].contains("Possible web shell in use: 203.0.113.9 requested GET /var/www/html/x.php"));
+    }
+}
@@ -24,4 +24,37 @@ impl LogType {
             LogType::IDSFailure => "INTERNAL ERROR"
         }
     }
-}
\ No newline at end of file
+}
+// Errors returned by the linux bridge. Modules should turn these into IDSFailure logs instead of panicking
+#[derive(Debug)]
+pub enum ChromiaError {
+    Io(std::io::Error),
+    // The program Chromia tried to run is not installed
+    CommandMissing(String),
+    // Output or a file was not in the expected format
+    Parse(String),
+    // Chromia does not have the rights to read or run something (is it running as root?)
+    Permission(String),
+    // The config file is missing or malformed
+    Config(String),
+}
+impl std::fmt::Display for ChromiaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChromiaError::Io(e) => write!(f, "IO error: {}", e),
+            ChromiaError::CommandMissing(program) => write!(f, "Command '{}' could not be found, is it installed?", program),
+            ChromiaError::Parse(msg) => write!(f, "Could not parse {}", msg),
+            ChromiaError::Permission(msg) => write!(f, "Permission denied: {}", msg),
+            ChromiaError::Config(msg) => write!(f, "Config error: {}", msg),
+        }
+    }
+}
+impl std::error::Error for ChromiaError {}
+impl From<std::io::Error> for ChromiaError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::PermissionDenied => ChromiaError::Permission(e.to_string()),
+            _ => ChromiaError::Io(e),
+        }
+    }
+}
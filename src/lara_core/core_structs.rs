@@ -16,6 +16,10 @@ impl Log {
             message: message,
         }
     }
+    // Turns an error from the linux bridge into an INTERNAL ERROR log, context says what the module was doing
+    pub fn ids_failure(module: String, context: &str, error: &ChromiaError) -> Self {
+        Self::new(LogType::IDSFailure, module, format!("{}: {}", context, error))
+    }
     pub fn build_alert(&self) -> String {
         let mut log: String = String::from("[");
         log.push_str(&self.time.format("%Y-%m-%d %H:%M:%S").to_string());
@@ -60,4 +64,85 @@ impl ConfigField {
         }
         return result;
     }
-}
\ No newline at end of file
+}
+// Shared helpers built once from the [CoreSystem] config and handed to every module through attach_services
+#[derive(Default, Clone)]
+pub struct CoreServices {
+    // None when no GeoIP database is configured
+    pub geoip: Option<std::sync::Arc<dyn crate::linux_bridge::geoip::GeoLookup>>,
+    // None when no threat intel folder is configured
+    pub threat_intel: Option<std::sync::Arc<crate::linux_bridge::threat_intel::ThreatIntel>>,
+    // None when no signature rules are configured
+    pub signatures: Option<std::sync::Arc<crate::linux_bridge::signatures::RuleSet>>,
+}
+impl CoreServices {
+    pub fn geo(&self, ip: &str) -> Option<crate::linux_bridge::geoip::GeoInfo> {
+        self.geoip.as_ref()?.lookup(&ip.parse().ok()?)
+    }
+    // "203.0.113.9 (AU, Melbourne, AS13335 Cloudflare, Inc.)", or just the address when there is nothing to add
+    pub fn describe_ip(&self, ip: &str) -> String {
+        match self.geo(ip) {
+            Some(info) => format!("{} ({})", ip, info),
+            None => ip.to_string(),
+        }
+    }
+    // Picks up changes to the threat intel folder, called at the start of every tick. Files that couldn't be loaded
+    // come back as IDSFailure logs
+    pub fn refresh(&self) -> Vec<Log> {
+        let intel = match &self.threat_intel {
+            Some(intel) => intel,
+            None => return Vec::new(),
+        };
+        intel.refresh().into_iter().map(|(context, e)| Log::ids_failure(String::from("ThreatIntel"), &context, &e)).collect()
+    }
+    // A Critical alert when the address is a known indicator. seen says where, Eg "logged in as 'root'"
+    pub fn check_ip(&self, module_name: &str, ip: &str, seen: &str) -> Option<Log> {
+        let source = self.threat_intel.as_ref()?.match_ip(ip)?;
+        let msg = format!("Threat intel match: ip address '{}' {}, it is listed in {}", self.describe_ip(ip), seen, source);
+        Some(Log::new(LogType::Critical, module_name.to_string(), msg))
+    }
+    // Same as check_ip for modules that see the same addresses every tick. reported holds the (address, list) pairs
+    // already raised, an address is only raised again when it turns up on another list
+    pub fn check_ip_once(&self, module_name: &str, ip: &str, seen: &str, reported: &mut std::collections::HashSet<(String, String)>) -> Option<Log> {
+        let source = self.threat_intel.as_ref()?.match_ip(ip)?;
+        if !reported.insert((ip.to_string(), source.to_string())) {
+            return None;
+        }
+        self.check_ip(module_name, ip, seen)
+    }
+    pub fn check_domain(&self, module_name: &str, domain: &str, seen: &str) -> Option<Log> {
+        let source = self.threat_intel.as_ref()?.match_domain(domain)?;
+        let msg = format!("Threat intel match: domain '{}' {}, it is listed in {}", domain, seen, source);
+        Some(Log::new(LogType::Critical, module_name.to_string(), msg))
+    }
+    pub fn check_domain_once(&self, module_name: &str, domain: &str, seen: &str, reported: &mut std::collections::HashSet<(String, String)>) -> Option<Log> {
+        let source = self.threat_intel.as_ref()?.match_domain(domain)?;
+        if !reported.insert((domain.to_lowercase(), source.to_string())) {
+            return None;
+        }
+        self.check_domain(module_name, domain, seen)
+    }
+    // A Serious alert for every signature rule the file matches. why says how it came to be scanned, Eg "changed file"
+    pub fn scan_file(&self, module_name: &str, source: &dyn crate::linux_bridge::source::SystemSource, path: &str, why: &str) -> Vec<Log> {
+        let rules = match &self.signatures {
+            Some(rules) => rules,
+            None => return Vec::new(),
+        };
+        match rules.scan_file(source, path) {
+            Ok(found) => found
+                .unwrap_or_default()
+                .iter()
+                .map(|rule| Log::new(LogType::Serious, module_name.to_string(), format!("Signature match: {} '{}' matches rule {}", why, path, rule)))
+                .collect(),
+            // Temporary files are often gone again before they can be scanned
+            Err(ChromiaError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => vec![Log::ids_failure(module_name.to_string(), &format!("Could not scan '{}' for signatures", path), &e)],
+        }
+    }
+    // what is the file the hash was taken from, Eg "'/usr/bin/ls'"
+    pub fn check_hash(&self, module_name: &str, hash: &str, what: &str) -> Option<Log> {
+        let source = self.threat_intel.as_ref()?.match_hash(hash)?;
+        let msg = format!("Threat intel match: {} has hash {}, it is listed in {}", what, hash.trim(), source);
+        Some(Log::new(LogType::Critical, module_name.to_string(), msg))
+    }
+}
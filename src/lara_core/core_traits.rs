@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::lara_core::core_structs::*;
 
@@ -22,4 +23,9 @@ pub trait AnalysisModule {
 
     fn build_config_fields(&self) -> Vec<ConfigField>;
     fn retrieve_config_data(&mut self, data: HashMap<String, Vec<String>>) -> bool;
+    /**
+     * Hands the module the services shared between modules, Eg GeoIP lookups. Called once after the config is loaded,
+     * modules that don't use any can leave this out
+     */
+    fn attach_services(&mut self, _services: Arc<CoreServices>) {}
 }
@@ -1,4 +1,11 @@
+pub mod audit;
 pub mod auth;
+pub mod geoip;
 pub mod network;
+pub mod process;
+pub mod source;
 pub mod system;
-pub mod sam;
\ No newline at end of file
+pub mod sam;
+pub mod threat_intel;
+pub mod signatures;
+pub mod file_watch;
\ No newline at end of file
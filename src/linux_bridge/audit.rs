@@ -0,0 +1,270 @@
+use std::collections::{BTreeMap, HashMap};
+
+// auid of processes that were never part of a login session, Eg daemons started at boot
+pub const AUID_UNSET: u32 = u32::MAX;
+
+// Fields auditd hex encodes when the value has spaces, quotes or control characters in it. Quoted values are plain text
+const ENCODED_FIELDS: [&str; 8] = ["exe", "comm", "cwd", "name", "proctitle", "key", "cmd", "acct"];
+// Userspace messages are written as a single record, kernel events are several records closed by an EOE record
+const USERSPACE_PREFIXES: [&str; 9] = ["USER_", "CRED_", "LOGIN", "ADD_", "DEL_", "GRP_", "SERVICE_", "DAEMON_", "ANOM_"];
+// (arch, syscall) of setuid, setreuid, setresuid and setfsuid on x86_64 and aarch64
+const SETUID_SYSCALLS: [(&str, u32); 8] = [
+    ("c000003e", 105), ("c000003e", 113), ("c000003e", 117), ("c000003e", 122),
+    ("c00000b7", 146), ("c00000b7", 145), ("c00000b7", 147), ("c00000b7", 151),
+];
+
+// One line of audit.log
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditRecord {
+    pub record_type: String,
+    pub time: i64,
+    pub serial: u64,
+    pub fields: HashMap<String, String>,
+}
+
+// Every record written for one serial number, in the order they were logged
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEvent {
+    pub time: i64,
+    pub serial: u64,
+    pub records: Vec<AuditRecord>,
+}
+
+impl AuditEvent {
+    pub fn record(&self, record_type: &str) -> Option<&AuditRecord> {
+        self.records.iter().find(|record| record.record_type == record_type)
+    }
+
+    pub fn field(&self, record_type: &str, field: &str) -> Option<&str> {
+        self.record(record_type).and_then(|record| record.fields.get(field)).map(|value| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditAction {
+    // A program was started with execve
+    Exec { argv: Vec<String>, cwd: String },
+    // A syscall touched a path covered by an audit watch (-w or -F path=) tagged with a key
+    FileAccess { syscall: u32, paths: Vec<String> },
+    // A process changed its uid, or sudo ran a command for someone. target_uid is None when it's not known
+    PrivilegeChange { op: String, target_uid: Option<u32>, command: Option<String> },
+}
+
+// An audit event reduced to who did what
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditActivity {
+    pub time: i64,
+    pub serial: u64,
+    pub pid: u32,
+    // The login uid, this follows a user through sudo and su
+    pub auid: u32,
+    pub uid: u32,
+    pub euid: u32,
+    pub exe: String,
+    pub key: Option<String>,
+    pub success: bool,
+    pub action: AuditAction,
+}
+
+// Parses a line of audit.log Eg
+// type=SYSCALL msg=audit(1697620000.123:456): arch=c000003e syscall=59 success=yes exit=0 pid=2211 auid=1000 uid=1000 exe="/usr/bin/cat" key="identity"
+// Lines that aren't audit records are skipped. In the enriched log format the translated fields after the 0x1d
+// separator are dropped, they can be looked up from the numeric ones.
+pub fn parse_audit_line(line: &str) -> Option<AuditRecord> {
+    let line = line.split('\x1d').next()?;
+    let rest = line.strip_prefix("type=")?;
+    let (record_type, rest) = rest.split_once(' ')?;
+    let rest = rest.strip_prefix("msg=audit(")?;
+    let (stamp, rest) = rest.split_once("):")?;
+    let (time, serial) = stamp.split_once(':')?;
+    let time = time.split('.').next()?.parse().ok()?;
+    let serial = serial.parse().ok()?;
+    let mut fields = HashMap::new();
+    parse_fields(rest, record_type, &mut fields);
+    Some(AuditRecord { record_type: record_type.to_string(), time, serial, fields })
+}
+
+// Splits key=value pairs, values can be "quoted", 'quoted' or bare. Userspace records nest their own fields
+// inside msg='...', those are flattened into the same map.
+fn parse_fields(text: &str, record_type: &str, fields: &mut HashMap<String, String>) {
+    let mut rest = text.trim_start();
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim();
+        let (value, remaining, quoted) = match after.chars().next() {
+            Some(quote @ ('"' | '\'')) => match after[1..].split_once(quote) {
+                Some((value, remaining)) => (value, remaining, true),
+                None => (&after[1..], "", true),
+            },
+            _ => match after.split_once(' ') {
+                Some((value, remaining)) => (value, remaining, false),
+                None => (after, "", false),
+            },
+        };
+        if key == "msg" && quoted {
+            parse_fields(value, record_type, fields);
+        } else {
+            let encoded = ENCODED_FIELDS.contains(&key) || (record_type == "EXECVE" && is_argument(key));
+            let value = if !quoted && encoded { decode_hex(value).unwrap_or_else(|| value.to_string()) } else { value.to_string() };
+            fields.insert(key.to_string(), value);
+        }
+        rest = remaining.trim_start();
+    }
+}
+
+fn is_argument(key: &str) -> bool {
+    key.strip_prefix('a').is_some_and(|index| !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()))
+}
+
+// "2F746D702F612062" -> "/tmp/a b". Returns None for values that aren't hex, Eg (null)
+fn decode_hex(value: &str) -> Option<String> {
+    if !value.len().is_multiple_of(2) || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let bytes: Vec<u8> = (0..value.len()).step_by(2).filter_map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok()).collect();
+    // proctitle separates arguments with NUL
+    Some(String::from_utf8_lossy(&bytes).replace('\0', " "))
+}
+
+// Collects records into events. Kernel events are handed out when their EOE record arrives, or one push after their
+// last record if it never does, so an event split across two reads of the log is still put back together.
+#[derive(Debug, Default)]
+pub struct EventAssembler {
+    pending: BTreeMap<u64, (AuditEvent, bool)>,
+}
+
+impl EventAssembler {
+    pub fn push(&mut self, records: Vec<AuditRecord>) -> Vec<AuditEvent> {
+        let mut complete = Vec::new();
+        for (_, fresh) in self.pending.values_mut() {
+            *fresh = false;
+        }
+        for record in records {
+            let serial = record.serial;
+            if record.record_type == "EOE" {
+                if let Some((event, _)) = self.pending.remove(&serial) {
+                    complete.push(event);
+                }
+                continue;
+            }
+            if USERSPACE_PREFIXES.iter().any(|prefix| record.record_type.starts_with(prefix)) {
+                complete.push(AuditEvent { time: record.time, serial, records: vec![record] });
+                continue;
+            }
+            let (event, fresh) = self.pending.entry(serial).or_insert_with(|| (AuditEvent { time: record.time, serial, records: Vec::new() }, true));
+            event.records.push(record);
+            *fresh = true;
+        }
+        // Events left over from an earlier push that got nothing new in this one aren't going to be finished
+        let stale: Vec<u64> = self.pending.iter().filter(|(_, (_, fresh))| !fresh).map(|(serial, _)| *serial).collect();
+        for serial in stale {
+            if let Some((event, _)) = self.pending.remove(&serial) {
+                complete.push(event);
+            }
+        }
+        complete.sort_by_key(|event| event.serial);
+        complete
+    }
+}
+
+// Works out what an event was about. Events that aren't an exec, a keyed file access or a privilege change give None
+pub fn classify(event: &AuditEvent) -> Option<AuditActivity> {
+    let number = |record: &str, field: &str| event.field(record, field).and_then(|value| value.parse::<u32>().ok());
+    if let Some(record) = event.record("USER_CMD") {
+        let fields = &record.fields;
+        return Some(AuditActivity {
+            time: event.time,
+            serial: event.serial,
+            pid: number("USER_CMD", "pid").unwrap_or(0),
+            auid: number("USER_CMD", "auid").unwrap_or(AUID_UNSET),
+            uid: number("USER_CMD", "uid").unwrap_or(0),
+            euid: number("USER_CMD", "uid").unwrap_or(0),
+            exe: fields.get("exe").cloned().unwrap_or_default(),
+            key: None,
+            success: fields.get("res").is_some_and(|res| res == "success"),
+            action: AuditAction::PrivilegeChange { op: "sudo".to_string(), target_uid: None, command: fields.get("cmd").cloned() },
+        });
+    }
+
+    let syscall = event.record("SYSCALL")?;
+    let fields = &syscall.fields;
+    let key = fields.get("key").filter(|key| *key != "(null)").cloned();
+    let syscall_number = number("SYSCALL", "syscall")?;
+    let arch = fields.get("arch").map(|arch| arch.as_str()).unwrap_or("");
+    let action = if let Some(execve) = event.record("EXECVE") {
+        let argc = execve.fields.get("argc").and_then(|argc| argc.parse::<usize>().ok()).unwrap_or(0);
+        let argv = (0..argc).filter_map(|i| execve.fields.get(&format!("a{}", i)).cloned()).collect();
+        AuditAction::Exec { argv, cwd: event.field("CWD", "cwd").unwrap_or("").to_string() }
+    } else if SETUID_SYSCALLS.contains(&(arch, syscall_number)) {
+        // The first argument is the new uid, syscall arguments are logged in hex
+        let target_uid = fields.get("a0").and_then(|a0| u32::from_str_radix(a0, 16).ok());
+        AuditAction::PrivilegeChange { op: "setuid".to_string(), target_uid, command: None }
+    } else if key.is_some() && event.record("PATH").is_some() {
+        // nametype=PARENT records are the folder the file is in, the file itself is the more useful one
+        let paths = event
+            .records
+            .iter()
+            .filter(|record| record.record_type == "PATH" && record.fields.get("nametype").is_none_or(|kind| kind != "PARENT"))
+            .filter_map(|record| record.fields.get("name").cloned())
+            .collect();
+        AuditAction::FileAccess { syscall: syscall_number, paths }
+    } else {
+        return None;
+    };
+    Some(AuditActivity {
+        time: event.time,
+        serial: event.serial,
+        pid: number("SYSCALL", "pid").unwrap_or(0),
+        auid: number("SYSCALL", "auid").unwrap_or(AUID_UNSET),
+        uid: number("SYSCALL", "uid").unwrap_or(0),
+        euid: number("SYSCALL", "euid").unwrap_or(0),
+        exe: fields.get("exe").cloned().unwrap_or_default(),
+        key,
+        success: fields.get("success").is_none_or(|success| success == "yes"),
+        action,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXEC_EVENT: &str = "type=SYSCALL msg=audit(1697620000.123:456): arch=c000003e syscall=59 success=yes exit=0 a0=55d1 a1=55d2 ppid=2200 pid=2211 auid=1000 uid=1000 gid=1000 euid=0 comm=\"pkexec\" exe=\"/usr/bin/pkexec\" key=(null)\x1dARCH=x86_64 SYSCALL=execve AUID=\"erik\"
+type=EXECVE msg=audit(1697620000.123:456): argc=3 a0=\"pkexec\" a1=\"--user\" a2=2F746D702F6120622E7368
+type=CWD msg=audit(1697620000.123:456): cwd=\"/home/erik\"
+type=PATH msg=audit(1697620000.123:456): item=0 name=\"/usr/bin/pkexec\" inode=1234 nametype=NORMAL
+type=PROCTITLE msg=audit(1697620000.123:456): proctitle=706B6578656300
+type=EOE msg=audit(1697620000.123:456):
+";
+
+    #[test]
+    fn test_parse_audit_line() {
+        let record = parse_audit_line("type=USER_CMD msg=audit(1697620100.500:470): pid=2300 uid=1000 auid=1000 ses=3 msg='cwd=\"/home/erik\" cmd=636174202F6574632F736861646F77 exe=\"/usr/bin/sudo\" terminal=pts/0 res=success'").unwrap();
+        assert_eq!(record.record_type, "USER_CMD");
+        assert_eq!((record.time, record.serial), (1697620100, 470));
+        assert_eq!(record.fields["cmd"], "cat /etc/shadow");
+        assert_eq!(record.fields["exe"], "/usr/bin/sudo");
+        assert_eq!(record.fields["res"], "success");
+        assert_eq!(record.fields["auid"], "1000");
+        assert!(parse_audit_line("not an audit record").is_none());
+    }
+
+    #[test]
+    fn test_event_split_across_pushes_is_reassembled() {
+        let records: Vec<AuditRecord> = EXEC_EVENT.lines().filter_map(parse_audit_line).collect();
+        let mut assembler = EventAssembler::default();
+        assert!(assembler.push(records[..2].to_vec()).is_empty());
+        let events = assembler.push(records[2..].to_vec());
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].records.len(), 5);
+
+        let activity = classify(&events[0]).unwrap();
+        assert_eq!((activity.pid, activity.auid, activity.uid, activity.euid), (2211, 1000, 1000, 0));
+        assert_eq!(activity.key, None);
+        assert_eq!(activity.action, AuditAction::Exec { argv: vec!["pkexec".to_string(), "--user".to_string(), "/tmp/a b.sh".to_string()], cwd: "/home/erik".to_string() });
+
+        // Without an EOE the event is handed out one push later
+        let mut assembler = EventAssembler::default();
+        assert!(assembler.push(records[..4].to_vec()).is_empty());
+        assert_eq!(assembler.push(Vec::new()).len(), 1);
+    }
+}
@@ -1,101 +1,309 @@
-use std::process::Command;
+use crate::lara_core::core_enums::ChromiaError;
+use crate::linux_bridge::system;
+use std::collections::HashMap;
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveDateTime};
 
 //Function to view the user who  logged in and out and how long for 
-pub fn last() -> String {
-    let output = Command::new("last")
-        .output()
-        .expect("Failed to execute command");
-    let last = String::from_utf8_lossy(&output.stdout);
-    return last.to_string();
+pub fn last() -> Result<String, ChromiaError> {
+    system::run_command("last", &[])
 }
 
 //Function to view the last reboot of the system
-pub fn last_reboot() -> String {
-    let output = Command::new("last")
-        .arg("reboot")
-        .output()
-        .expect("Failed to execute command");
-    let last_reboot = String::from_utf8_lossy(&output.stdout);
-    return last_reboot.to_string();
+pub fn last_reboot() -> Result<String, ChromiaError> {
+    system::run_command("last", &["reboot"])
 }
 
 //Function to view the last shutdown of the system
-pub fn last_shutdown() -> String {
-    let output = Command::new("last")
-        .arg("shutdown")
-        .output()
-        .expect("Failed to execute command");
-    let last_shutdown = String::from_utf8_lossy(&output.stdout);
-    return last_shutdown.to_string();
+pub fn last_shutdown() -> Result<String, ChromiaError> {
+    system::run_command("last", &["shutdown"])
 }
 
 //Function to view the last login of the system
-pub fn last_login() -> String {
-    let output = Command::new("last")
-        .arg("-F")
-        .output()
-        .expect("Failed to execute command");
-    let last_login = String::from_utf8_lossy(&output.stdout);
-    return last_login.to_string();
+pub fn last_login() -> Result<String, ChromiaError> {
+    system::run_command("last", &["-F"])
 }
 
 //Funtion to view the plan text UTMP dump Eg this is a dump of the WTMP binary file
 //This file contains the history of all the logins and logouts and restarts of the system, this dump should not be need
 //as we are using the previous command calls
-pub fn wtmp_dump() -> String {
-    let output = Command::new("utmpdump")
-        .arg("/var/log/wtmp")
-        .output()
-        .expect("Failed to execute command");
-    let wtmpdump = String::from_utf8_lossy(&output.stdout);
-    return wtmpdump.to_string();
+pub fn wtmp_dump() -> Result<String, ChromiaError> {
+    system::run_command("utmpdump", &["/var/log/wtmp"])
 }
 
 //Function to get the current user of the system. Eg Erik
-pub fn system_user() -> String {
-   let output = Command::new("whoami")
-        .output()
-        .expect("Failed to execute command");
-    let user = String::from_utf8_lossy(&output.stdout);
-    return user.to_string();
+pub fn system_user() -> Result<String, ChromiaError> {
+    system::run_command("whoami", &[])
 }
 
 //prints the current uptime of the system
-pub fn system_uptime() -> String {
-    let output = Command::new("uptime")
-        .output()
-        .expect("Failed to execute command");
-    let uptime = String::from_utf8_lossy(&output.stdout);
-    return uptime.to_string();
+pub fn system_uptime() -> Result<String, ChromiaError> {
+    system::run_command("uptime", &[])
 }
 
 //prints all the current users on the system
-pub fn all_system_user() -> String {
-   let output = Command::new("who")
-        .output()
-        .expect("Failed to execute command");
-    let user = String::from_utf8_lossy(&output.stdout);
-    return user.to_string();
+pub fn all_system_user() -> Result<String, ChromiaError> {
+    system::run_command("who", &[])
 }
 
 //prints the last system boot time
-pub fn all_system_user_boottime() -> String {
-   let output = Command::new("who -b")
-        .output()
-        .expect("Failed to execute command");
-    let user = String::from_utf8_lossy(&output.stdout);
-    return user.to_string();
+pub fn all_system_user_boottime() -> Result<String, ChromiaError> {
+    system::run_command("who", &["-b"])
 }
 
 //Function to get the BTMP dump file
 //NOTE: You will be prompted to enter your password to view the file, this will be like entering 
 //sudo utmpdump /var/log/btmp in the terminal and entering your password where it does not show up
-pub fn btmp_dump() -> String {
-    let output = Command::new("sudo")
-        .arg("utmpdump")
-        .arg("/var/log/btmp")
-        .output()
-        .expect("Failed to execute command");
-    let btmpdump = String::from_utf8_lossy(&output.stdout);
-    return btmpdump.to_string();
-}
\ No newline at end of file
+pub fn btmp_dump() -> Result<String, ChromiaError> {
+    system::run_command("sudo", &["utmpdump", "/var/log/btmp"])
+}
+
+// One record of a utmpdump listing. ut_type is the utmp record type, the ones Chromia cares about are
+// 1 RUN_LVL (shutdown and runlevel changes), 2 BOOT_TIME, 7 USER_PROCESS (logins) and 8 DEAD_PROCESS (logouts).
+#[derive(Debug, Clone, PartialEq)]
+pub struct UtmpRecord {
+    pub ut_type: u16,
+    pub pid: u32,
+    pub user: String,
+    pub line: String,
+    pub host: String,
+    pub addr: String,
+    pub time: Option<DateTime<FixedOffset>>,
+}
+
+// Parses the output of utmpdump. Each line looks like
+// [7] [01234] [ts/0] [erik    ] [pts/0       ] [192.168.1.5         ] [192.168.1.5    ] [2024-09-08T09:28:11,123456+10:00]
+// Lines that don't have all 8 fields are skipped.
+pub fn parse_utmpdump(dump: &str) -> Vec<UtmpRecord> {
+    let mut records = Vec::new();
+    for line in dump.lines() {
+        let inner = match line.trim().strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            Some(inner) => inner,
+            None => continue,
+        };
+        let fields: Vec<&str> = inner.split("] [").map(|field| field.trim()).collect();
+        if fields.len() != 8 {
+            continue;
+        }
+        let ut_type = match fields[0].parse() {
+            Ok(ut_type) => ut_type,
+            Err(_) => continue,
+        };
+        records.push(UtmpRecord {
+            ut_type,
+            pid: fields[1].parse().unwrap_or(0),
+            user: fields[3].to_string(),
+            line: fields[4].to_string(),
+            host: fields[5].to_string(),
+            addr: fields[6].to_string(),
+            time: DateTime::parse_from_str(fields[7], "%Y-%m-%dT%H:%M:%S,%6f%:z").ok(),
+        });
+    }
+    records
+}
+
+// What a line of auth.log (Debian) or secure (RHEL) recorded
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthEventKind {
+    // method is how sshd authenticated the user, Eg password, publickey or keyboard-interactive/pam
+    Accepted { method: String },
+    Failed { method: String, invalid_user: bool },
+    // sshd was asked for an account that doesn't exist
+    InvalidUser,
+    // reason is why sudo refused, Eg "user NOT in sudoers". None when the command was run
+    Sudo { target: String, command: String, reason: Option<String> },
+    Su { target: String, success: bool },
+    SessionOpened { service: String },
+    SessionClosed { service: String },
+    // A PAM module rejected a password, service is the program that asked, Eg sshd or login
+    PamFailure { service: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthEvent {
+    // Local time the line was written
+    pub time: NaiveDateTime,
+    pub program: String,
+    pub pid: Option<u32>,
+    // Who the event is about. For sudo and su this is who ran it, the account they became is in the kind
+    pub user: String,
+    pub ip: Option<String>,
+    pub port: Option<u16>,
+    pub kind: AuthEventKind,
+}
+
+// Parses a line of auth.log or secure. Both the traditional syslog timestamp and the RFC 3339 one rsyslog writes on
+// newer Debian are understood:
+// Oct 18 10:12:01 web1 sshd[1234]: Accepted publickey for erik from 203.0.113.9 port 51234 ssh2: ED25519 SHA256:...
+// 2026-10-18T10:12:01.123456+00:00 web1 sudo:     erik : TTY=pts/0 ; PWD=/home/erik ; USER=root ; COMMAND=/usr/bin/id
+// Traditional timestamps have no year, they are taken to be in the year up to now. Lines about anything else give None.
+pub fn parse_auth_line(line: &str, now: NaiveDateTime) -> Option<AuthEvent> {
+    let (time, rest) = parse_syslog_time(line, now)?;
+    let (_host, rest) = rest.split_once(' ')?;
+    let (tag, message) = rest.split_once(": ")?;
+    let (program, pid) = match tag.split_once('[') {
+        Some((program, pid)) => (program, pid.trim_end_matches(']').parse().ok()),
+        None => (tag, None),
+    };
+    let event = |user: &str, ip: Option<&str>, port: Option<u16>, kind: AuthEventKind| {
+        Some(AuthEvent { time, program: program.to_string(), pid, user: user.to_string(), ip: ip.map(|ip| ip.to_string()), port, kind })
+    };
+    let message = message.trim();
+
+    if let Some((service, detail)) = pam_message(message) {
+        return parse_pam(service, detail, event);
+    }
+    match program {
+        // OpenSSH 9.8 moved authentication into sshd-session
+        "sshd" | "sshd-session" => {
+            if let Some(rest) = message.strip_prefix("Accepted ") {
+                let (method, rest) = rest.split_once(" for ")?;
+                let (user, ip, port) = user_from(rest)?;
+                return event(user, Some(ip), port, AuthEventKind::Accepted { method: method.to_string() });
+            }
+            if let Some(rest) = message.strip_prefix("Failed ") {
+                let (method, rest) = rest.split_once(" for ")?;
+                let (rest, invalid_user) = match rest.strip_prefix("invalid user ") {
+                    Some(rest) => (rest, true),
+                    None => (rest, false),
+                };
+                let (user, ip, port) = user_from(rest)?;
+                return event(user, Some(ip), port, AuthEventKind::Failed { method: method.to_string(), invalid_user });
+            }
+            let rest = message.strip_prefix("Invalid user ")?;
+            let (user, ip, port) = user_from(rest)?;
+            event(user, Some(ip), port, AuthEventKind::InvalidUser)
+        }
+        "sudo" => {
+            let (user, rest) = message.split_once(" : ")?;
+            let mut fields = HashMap::new();
+            let mut reason = None;
+            for part in rest.split(" ; ") {
+                match part.split_once('=') {
+                    Some((key, value)) => {
+                        fields.insert(key.trim(), value.trim());
+                    }
+                    None => reason = Some(part.trim().to_string()),
+                }
+            }
+            let command = fields.get("COMMAND")?.to_string();
+            let target = fields.get("USER").copied().unwrap_or("root").to_string();
+            event(user.trim(), None, None, AuthEventKind::Sudo { target, command, reason })
+        }
+        _ => None,
+    }
+}
+
+// "erik from 203.0.113.9 port 51234 ssh2" -> (erik, 203.0.113.9, 51234). The user can be empty or have spaces in it
+fn user_from(text: &str) -> Option<(&str, &str, Option<u16>)> {
+    let (user, rest) = text.rsplit_once(" from ")?;
+    let mut words = rest.split_whitespace();
+    let ip = words.next()?;
+    let port = match words.next() {
+        Some("port") => words.next().and_then(|port| port.parse().ok()),
+        _ => None,
+    };
+    Some((user.trim(), ip, port))
+}
+
+// "pam_unix(sshd:auth): authentication failure; ..." -> (sshd, "authentication failure; ...")
+fn pam_message(message: &str) -> Option<(&str, &str)> {
+    let rest = message.strip_prefix("pam_")?;
+    let (_, rest) = rest.split_once('(')?;
+    let (context, detail) = rest.split_once("): ")?;
+    let (service, _) = context.split_once(':')?;
+    Some((service, detail))
+}
+
+fn parse_pam<F>(service: &str, detail: &str, event: F) -> Option<AuthEvent>
+where
+    F: Fn(&str, Option<&str>, Option<u16>, AuthEventKind) -> Option<AuthEvent>,
+{
+    // su logs "(to root) erik on pts/0" as well on Debian, the PAM lines are used for it as RHEL only writes those
+    let is_su = service == "su" || service == "su-l";
+    // "user root(uid=0) by erik(uid=1000)", older PAM leaves out the first uid
+    let account = |text: &str| text.split('(').next().unwrap_or("").trim().to_string();
+    if let Some(rest) = detail.strip_prefix("session opened for user ") {
+        let (target, by) = match rest.split_once(" by ") {
+            Some((target, by)) => (account(target), account(by)),
+            None => (account(rest), String::new()),
+        };
+        if is_su {
+            return event(&by, None, None, AuthEventKind::Su { target, success: true });
+        }
+        return event(&target, None, None, AuthEventKind::SessionOpened { service: service.to_string() });
+    }
+    if let Some(rest) = detail.strip_prefix("session closed for user ") {
+        return event(&account(rest), None, None, AuthEventKind::SessionClosed { service: service.to_string() });
+    }
+    let rest = detail.strip_prefix("authentication failure;")?;
+    // logname=erik uid=1000 euid=0 tty=/dev/pts/0 ruser=erik rhost=  user=root
+    let fields: HashMap<&str, &str> = rest.split_whitespace().filter_map(|pair| pair.split_once('=')).collect();
+    let user = fields.get("user").copied().unwrap_or("");
+    if is_su {
+        let by = fields.get("ruser").or(fields.get("logname")).copied().unwrap_or("");
+        return event(by, None, None, AuthEventKind::Su { target: user.to_string(), success: false });
+    }
+    let ip = fields.get("rhost").copied().filter(|rhost| !rhost.is_empty());
+    event(user, ip, None, AuthEventKind::PamFailure { service: service.to_string() })
+}
+
+// Splits the timestamp off a syslog line, returning it as local time
+fn parse_syslog_time(line: &str, now: NaiveDateTime) -> Option<(NaiveDateTime, &str)> {
+    let (first, rest) = line.split_once(' ')?;
+    if let Ok(time) = DateTime::parse_from_rfc3339(first) {
+        return Some((time.with_timezone(&Local).naive_local(), rest));
+    }
+    // "Oct  8 10:12:01 " is always 16 characters
+    let stamp = line.get(..15)?;
+    let rest = line.get(16..)?;
+    let time = NaiveDateTime::parse_from_str(&format!("{} {}", now.year(), stamp), "%Y %b %e %H:%M:%S").ok()?;
+    // A December line read in January is from last year
+    if time - now > chrono::Duration::days(1) {
+        return Some((time.with_year(now.year() - 1)?, rest));
+    }
+    Some((time, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> NaiveDateTime {
+        NaiveDateTime::parse_from_str("2026-10-18 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_parse_sshd_lines() {
+        let accepted = parse_auth_line("Oct 18 10:12:01 web1 sshd[1234]: Accepted publickey for erik from 203.0.113.9 port 51234 ssh2: ED25519 SHA256:abc", now()).unwrap();
+        assert_eq!(accepted.time.to_string(), "2026-10-18 10:12:01");
+        assert_eq!((accepted.program.as_str(), accepted.pid, accepted.user.as_str()), ("sshd", Some(1234), "erik"));
+        assert_eq!((accepted.ip.as_deref(), accepted.port), (Some("203.0.113.9"), Some(51234)));
+        assert_eq!(accepted.kind, AuthEventKind::Accepted { method: "publickey".to_string() });
+
+        let failed = parse_auth_line("Dec 31 23:59:58 web1 sshd[99]: Failed password for invalid user admin from 2001:db8::5 port 40022 ssh2", now()).unwrap();
+        assert_eq!(failed.time.to_string(), "2025-12-31 23:59:58");
+        assert_eq!((failed.user.as_str(), failed.ip.as_deref()), ("admin", Some("2001:db8::5")));
+        assert_eq!(failed.kind, AuthEventKind::Failed { method: "password".to_string(), invalid_user: true });
+
+        let invalid = parse_auth_line("Oct  8 03:00:00 web1 sshd-session[7]: Invalid user  from 198.51.100.4 port 5555", now()).unwrap();
+        assert_eq!((invalid.user.as_str(), invalid.kind), ("", AuthEventKind::InvalidUser));
+        assert!(parse_auth_line("Oct 18 10:12:01 web1 sshd[1234]: Connection closed by 203.0.113.9 port 51234", now()).is_none());
+    }
+
+    #[test]
+    fn test_parse_sudo_su_and_pam_lines() {
+        let sudo = parse_auth_line("Oct 18 10:15:00 web1 sudo:     erik : 3 incorrect password attempts ; TTY=pts/0 ; PWD=/home/erik ; USER=root ; COMMAND=/usr/bin/cat /etc/shadow", now()).unwrap();
+        assert_eq!(sudo.user, "erik");
+        assert_eq!(sudo.kind, AuthEventKind::Sudo { target: "root".to_string(), command: "/usr/bin/cat /etc/shadow".to_string(), reason: Some("3 incorrect password attempts".to_string()) });
+
+        let su = parse_auth_line("Oct 18 10:16:00 web1 su[2222]: pam_unix(su-l:session): session opened for user root by erik(uid=1000)", now()).unwrap();
+        assert_eq!((su.user.as_str(), su.kind), ("erik", AuthEventKind::Su { target: "root".to_string(), success: true }));
+
+        let failure = parse_auth_line("2026-10-18T10:17:00.000000+00:00 web1 sshd[1300]: pam_unix(sshd:auth): authentication failure; logname= uid=0 euid=0 tty=ssh ruser= rhost=203.0.113.9  user=erik", now()).unwrap();
+        assert_eq!((failure.user.as_str(), failure.ip.as_deref()), ("erik", Some("203.0.113.9")));
+        assert_eq!(failure.kind, AuthEventKind::PamFailure { service: "sshd".to_string() });
+
+        let closed = parse_auth_line("Oct 18 10:18:00 web1 systemd-logind[400]: pam_unix(login:session): session closed for user erik", now()).unwrap();
+        assert_eq!(closed.kind, AuthEventKind::SessionClosed { service: "login".to_string() });
+    }
+}
@@ -0,0 +1,156 @@
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor};
+
+use crate::lara_core::core_enums::ChromiaError;
+
+// How long the watch thread blocks before checking whether it was stopped
+const POLL_TIMEOUT_MS: i32 = 250;
+
+// What happened to a file inside a watched folder
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileActivity {
+    Opened,
+    Modified,
+    // Permissions, owner or other attributes changed
+    AttributesChanged,
+    // Deleted or moved out of the way
+    Removed,
+}
+
+impl FileActivity {
+    // Moving a file over the watched name counts as modifying it, moving it away as removing it
+    pub fn from_mask(mask: AddWatchFlags) -> Option<FileActivity> {
+        if mask.contains(AddWatchFlags::IN_ISDIR) {
+            None
+        } else if mask.intersects(AddWatchFlags::IN_DELETE | AddWatchFlags::IN_MOVED_FROM) {
+            Some(FileActivity::Removed)
+        } else if mask.intersects(AddWatchFlags::IN_MODIFY | AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_CREATE) {
+            Some(FileActivity::Modified)
+        } else if mask.contains(AddWatchFlags::IN_ATTRIB) {
+            Some(FileActivity::AttributesChanged)
+        } else if mask.contains(AddWatchFlags::IN_OPEN) {
+            Some(FileActivity::Opened)
+        } else {
+            None
+        }
+    }
+}
+
+// Watches folders with inotify on a thread of its own and hands every file activity to a callback as it
+// happens, which gives the callback a chance to look at the process while it still has the file open.
+// The notify crate can't be used for this as it never asks inotify for open events.
+// Dropping the watch stops the thread and waits for it, so no event is delivered after that.
+pub struct FileWatch {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FileWatch {
+    pub fn start(folders: &[PathBuf], mut on_activity: impl FnMut(PathBuf, FileActivity) + Send + 'static) -> Result<FileWatch, ChromiaError> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC).map_err(std::io::Error::from)?;
+        let mask = AddWatchFlags::IN_OPEN
+            | AddWatchFlags::IN_MODIFY
+            | AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_ATTRIB
+            | AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_DELETE
+            | AddWatchFlags::IN_MOVED_FROM
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_ONLYDIR;
+        let mut watched: Vec<(WatchDescriptor, PathBuf)> = Vec::new();
+        for folder in folders {
+            match inotify.add_watch(folder.as_path(), mask) {
+                Ok(descriptor) => watched.push((descriptor, folder.clone())),
+                Err(errno) => {
+                    let _ = nix::unistd::close(inotify.as_raw_fd());
+                    return Err(std::io::Error::from(errno).into());
+                }
+            }
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let thread = std::thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                let mut fds = [PollFd::new(inotify.as_raw_fd(), PollFlags::POLLIN)];
+                if !matches!(poll(&mut fds, POLL_TIMEOUT_MS), Ok(ready) if ready > 0) {
+                    continue;
+                }
+                for event in inotify.read_events().unwrap_or_default() {
+                    if let Some((path, activity)) = resolve(&watched, &event) {
+                        on_activity(path, activity);
+                    }
+                }
+            }
+            let _ = nix::unistd::close(inotify.as_raw_fd());
+        });
+        Ok(FileWatch { stop, thread: Some(thread) })
+    }
+}
+
+impl Drop for FileWatch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn resolve(watched: &[(WatchDescriptor, PathBuf)], event: &InotifyEvent) -> Option<(PathBuf, FileActivity)> {
+    let folder = watched.iter().find(|(descriptor, _)| *descriptor == event.wd).map(|(_, folder)| folder)?;
+    let name = event.name.as_ref()?;
+    Some((Path::new(folder).join(name), FileActivity::from_mask(event.mask)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_activity_from_mask() {
+        assert_eq!(FileActivity::from_mask(AddWatchFlags::IN_OPEN), Some(FileActivity::Opened));
+        assert_eq!(FileActivity::from_mask(AddWatchFlags::IN_CLOSE_WRITE), Some(FileActivity::Modified));
+        assert_eq!(FileActivity::from_mask(AddWatchFlags::IN_MOVED_FROM), Some(FileActivity::Removed));
+        assert_eq!(FileActivity::from_mask(AddWatchFlags::IN_ATTRIB), Some(FileActivity::AttributesChanged));
+        assert_eq!(FileActivity::from_mask(AddWatchFlags::IN_OPEN | AddWatchFlags::IN_ISDIR), None);
+        assert_eq!(FileActivity::from_mask(AddWatchFlags::IN_CLOSE_NOWRITE), None);
+    }
+
+    #[test]
+    fn test_watch_reports_opens_and_writes() {
+        let folder = std::env::temp_dir().join(format!("chromia-file-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let file = folder.join("id_rsa");
+        std::fs::write(&file, "key").unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let watch = FileWatch::start(std::slice::from_ref(&folder), move |path, activity| {
+            let _ = sender.send((path, activity));
+        })
+        .unwrap();
+
+        std::fs::read_to_string(&file).unwrap();
+        std::fs::write(&file, "changed").unwrap();
+        std::fs::remove_file(&file).unwrap();
+        let mut seen = Vec::new();
+        while let Ok((path, activity)) = receiver.recv_timeout(Duration::from_secs(2)) {
+            assert_eq!(path, file);
+            seen.push(activity);
+            if activity == FileActivity::Removed {
+                break;
+            }
+        }
+        drop(watch);
+        std::fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(seen.first(), Some(&FileActivity::Opened));
+        assert!(seen.contains(&FileActivity::Modified));
+        assert_eq!(seen.last(), Some(&FileActivity::Removed));
+    }
+}
@@ -0,0 +1,127 @@
+use std::net::IpAddr;
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+
+use crate::lara_core::core_enums::ChromiaError;
+
+// Where an address is and who owns it. Every part is optional, a Country database has no cities and private
+// addresses are in no database at all
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GeoInfo {
+    // ISO 3166 code, Eg AU
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<u32>,
+    pub as_org: Option<String>,
+}
+
+impl std::fmt::Display for GeoInfo {
+    // AU, Melbourne, AS13335 Cloudflare, Inc.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts: Vec<String> = Vec::new();
+        parts.extend(self.country.clone());
+        parts.extend(self.city.clone());
+        match (self.asn, &self.as_org) {
+            (Some(asn), Some(org)) => parts.push(format!("AS{} {}", asn, org)),
+            (Some(asn), None) => parts.push(format!("AS{}", asn)),
+            (None, Some(org)) => parts.push(org.clone()),
+            (None, None) => {}
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+// Looks up where an address is. Modules get this through CoreServices so tests can hand them a fake
+pub trait GeoLookup: Send + Sync {
+    fn lookup(&self, ip: &IpAddr) -> Option<GeoInfo>;
+}
+
+// MaxMind format (mmdb) databases read from disk, Eg GeoLite2-City.mmdb and GeoLite2-ASN.mmdb. Nothing is fetched
+// over the network, the files are kept up to date by whoever installs them (Eg geoipupdate)
+pub struct MmdbGeoIp {
+    // A City or Country database
+    location: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl MmdbGeoIp {
+    // Either path can be empty to go without that database, but not both
+    pub fn open(location_path: &str, asn_path: &str) -> Result<Self, ChromiaError> {
+        let open = |path: &str| -> Result<Option<Reader<Vec<u8>>>, ChromiaError> {
+            if path.is_empty() {
+                return Ok(None);
+            }
+            Reader::open_readfile(path).map(Some).map_err(|e| match e {
+                MaxMindDBError::IoError(msg) => ChromiaError::Config(format!("could not open GeoIP database '{}': {}", path, msg)),
+                e => ChromiaError::Parse(format!("GeoIP database '{}': {}", path, e)),
+            })
+        };
+        let geoip = MmdbGeoIp { location: open(location_path)?, asn: open(asn_path)? };
+        if geoip.location.is_none() && geoip.asn.is_none() {
+            return Err(ChromiaError::Config("no GeoIP database was given".to_string()));
+        }
+        Ok(geoip)
+    }
+}
+
+impl GeoLookup for MmdbGeoIp {
+    fn lookup(&self, ip: &IpAddr) -> Option<GeoInfo> {
+        let mut info = GeoInfo::default();
+        if let Some(city) = self.location.as_ref().and_then(|reader| reader.lookup::<geoip2::City>(*ip).ok()) {
+            info.country = city.country.and_then(|country| country.iso_code).map(|code| code.to_string());
+            info.city = city.city.and_then(|city| city.names).and_then(|names| names.get("en").map(|name| name.to_string()));
+        }
+        if let Some(asn) = self.asn.as_ref().and_then(|reader| reader.lookup::<geoip2::Asn>(*ip).ok()) {
+            info.asn = asn.autonomous_system_number;
+            info.as_org = asn.autonomous_system_organization.map(|org| org.to_string());
+        }
+        if info == GeoInfo::default() {
+            return None;
+        }
+        Some(info)
+    }
+}
+
+#[cfg(test)]
+pub use fake::FakeGeoIp;
+
+#[cfg(test)]
+mod fake {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    // Answers lookups from a table set up by the test
+    #[derive(Default)]
+    pub struct FakeGeoIp {
+        entries: Mutex<HashMap<IpAddr, GeoInfo>>,
+    }
+
+    impl FakeGeoIp {
+        pub fn set(&self, ip: &str, country: &str, city: &str, asn: u32, as_org: &str) {
+            let info = GeoInfo { country: Some(country.to_string()), city: Some(city.to_string()), asn: Some(asn), as_org: Some(as_org.to_string()) };
+            self.entries.lock().unwrap().insert(ip.parse().unwrap(), info);
+        }
+    }
+
+    impl GeoLookup for FakeGeoIp {
+        fn lookup(&self, ip: &IpAddr) -> Option<GeoInfo> {
+            self.entries.lock().unwrap().get(ip).cloned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geo_info_display() {
+        let full = GeoInfo { country: Some("AU".to_string()), city: Some("Melbourne".to_string()), asn: Some(13335), as_org: Some("Cloudflare, Inc.".to_string()) };
+        assert_eq!(full.to_string(), "AU, Melbourne, AS13335 Cloudflare, Inc.");
+        let country_only = GeoInfo { country: Some("NL".to_string()), ..Default::default() };
+        assert_eq!(country_only.to_string(), "NL");
+        assert!(MmdbGeoIp::open("", "").is_err());
+        assert!(matches!(MmdbGeoIp::open("/nonexistent/GeoLite2-City.mmdb", ""), Err(ChromiaError::Config(_))));
+    }
+}
@@ -1,7 +1,326 @@
-// use pnet::datalink as pnet_datalink;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpListener, UdpSocket};
+use std::time::{Duration, Instant};
 
-// pub fn ipadd() {
-//     for interface in pnet_datalink::interfaces() {
-//         println!("{}", interface);
-//     }
-// }
\ No newline at end of file
+use pnet::datalink;
+use pnet::packet::ethernet::EthernetPacket;
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::tcp::TcpPacket;
+use pnet::packet::udp::UdpPacket;
+use pnet::packet::Packet;
+
+use crate::lara_core::core_enums::ChromiaError;
+use crate::linux_bridge::process;
+
+/// Struct to hold packet data (source IP and port).
+#[derive(Debug, Clone)]
+pub struct PacketData {
+    pub source_ip: Option<String>,
+    pub source_port: Option<u16>,
+}
+
+// The socket tables the kernel exposes under /proc/net, paired with the protocol name used in alerts
+pub const SOCKET_TABLES: [(&str, &str); 4] = [
+    ("tcp", "/proc/net/tcp"),
+    ("tcp6", "/proc/net/tcp6"),
+    ("udp", "/proc/net/udp"),
+    ("udp6", "/proc/net/udp6"),
+];
+// TCP_LISTEN from include/net/tcp_states.h. Unconnected UDP sockets sit in TCP_CLOSE
+const TCP_ESTABLISHED: u8 = 0x01;
+const TCP_LISTEN: u8 = 0x0A;
+const TCP_CLOSE: u8 = 0x07;
+
+// One row of /proc/net/tcp, tcp6, udp or udp6
+#[derive(Debug, Clone, PartialEq)]
+pub struct SocketEntry {
+    pub protocol: String,
+    pub local_addr: IpAddr,
+    pub local_port: u16,
+    pub remote_addr: IpAddr,
+    pub remote_port: u16,
+    pub state: u8,
+    pub uid: u32,
+    // Links the socket to a process through the socket:[inode] links in /proc/<pid>/fd
+    pub inode: u64,
+}
+
+impl SocketEntry {
+    // A TCP socket waiting for connections or a UDP socket bound to a port and not connected to anything.
+    // A UDP client that sends without connecting looks the same, callers that alert need to tell them apart
+    pub fn is_listening(&self) -> bool {
+        if self.protocol.starts_with("tcp") {
+            self.state == TCP_LISTEN
+        } else {
+            self.state == TCP_CLOSE && self.remote_port == 0
+        }
+    }
+
+    // A TCP connection that is up, or a UDP socket connected to one remote address
+    pub fn is_established(&self) -> bool {
+        self.state == TCP_ESTABLISHED
+    }
+}
+
+//Function to parse one of the /proc/net socket tables. Each row looks like
+//  0: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21034 1 ...
+//Where the fields are slot, local address:port, remote address:port, state, queues, timers, retransmits, uid, timeout and inode.
+//Addresses are hex in the kernel's byte order so 0100007F is 127.0.0.1
+pub fn parse_proc_net(table: &str, protocol: &str) -> Vec<SocketEntry> {
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 {
+                return None;
+            }
+            let (local_addr, local_port) = parse_hex_endpoint(fields[1])?;
+            let (remote_addr, remote_port) = parse_hex_endpoint(fields[2])?;
+            Some(SocketEntry {
+                protocol: protocol.to_string(),
+                local_addr,
+                local_port,
+                remote_addr,
+                remote_port,
+                state: u8::from_str_radix(fields[3], 16).ok()?,
+                uid: fields[7].parse().ok()?,
+                inode: fields[9].parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+// "0100007F:0016" -> (127.0.0.1, 22). IPv6 addresses are four 32 bit words, each in host byte order
+fn parse_hex_endpoint(endpoint: &str) -> Option<(IpAddr, u16)> {
+    let (addr, port) = endpoint.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let addr = match addr.len() {
+        8 => IpAddr::V4(Ipv4Addr::from(u32::from_str_radix(addr, 16).ok()?.to_le_bytes())),
+        32 => {
+            let mut bytes = [0u8; 16];
+            for (i, word) in bytes.chunks_mut(4).enumerate() {
+                let value = u32::from_str_radix(&addr[i * 8..i * 8 + 8], 16).ok()?;
+                word.copy_from_slice(&value.to_le_bytes());
+            }
+            IpAddr::V6(Ipv6Addr::from(bytes))
+        }
+        _ => return None,
+    };
+    Some((addr, port))
+}
+
+//Function to check if something already holds a port by trying to bind it ourselves on every IPv4 and IPv6 address.
+//This asks the kernel directly, so it still sees sockets a rootkit has removed from /proc/net. Only EADDRINUSE counts,
+//ports below 1024 can't be probed without root and come back as false.
+pub fn port_bound(protocol: &str, port: u16) -> bool {
+    let in_use = |kind: ErrorKind| kind == ErrorKind::AddrInUse;
+    for addr in [IpAddr::V4(Ipv4Addr::UNSPECIFIED), IpAddr::V6(Ipv6Addr::UNSPECIFIED)] {
+        let result = if protocol.starts_with("udp") {
+            UdpSocket::bind((addr, port)).map(|_| ())
+        } else {
+            TcpListener::bind((addr, port)).map(|_| ())
+        };
+        if let Err(e) = result {
+            if in_use(e.kind()) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+//Function to map socket inodes to the process holding them (pid, name) from the socket:[inode] links in /proc/<pid>/fd.
+//Other users' fds need root to read, their sockets are left out
+pub fn socket_owners() -> HashMap<u64, (u32, String)> {
+    let mut owners = HashMap::new();
+    for (pid, fds) in socket_fds() {
+        let name = fs::read_to_string(format!("/proc/{}/comm", pid)).map(|comm| comm.trim().to_string()).unwrap_or_default();
+        for (_, inode) in fds {
+            owners.entry(inode).or_insert_with(|| (pid, name.clone()));
+        }
+    }
+    owners
+}
+
+//Function to list the open sockets of every process as pid -> [(fd number, socket inode)]
+pub fn socket_fds() -> HashMap<u32, Vec<(u32, u64)>> {
+    let mut sockets = HashMap::new();
+    for pid in process::list_pids() {
+        let fds = match fs::read_dir(format!("/proc/{}/fd", pid)) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        let mut process_sockets = Vec::new();
+        for fd in fds.flatten() {
+            let number = match fd.file_name().to_str().and_then(|name| name.parse().ok()) {
+                Some(number) => number,
+                None => continue,
+            };
+            if let Some(inode) = fs::read_link(fd.path()).ok().and_then(|link| parse_socket_link(&link.to_string_lossy())) {
+                process_sockets.push((number, inode));
+            }
+        }
+        if !process_sockets.is_empty() {
+            process_sockets.sort_unstable();
+            sockets.insert(pid, process_sockets);
+        }
+    }
+    sockets
+}
+
+// "socket:[21034]" -> 21034
+pub fn parse_socket_link(link: &str) -> Option<u64> {
+    link.strip_prefix("socket:[")?.strip_suffix(']')?.parse().ok()
+}
+
+//Function to list the names of every network interface on the host Eg lo, eth0, wlan0
+pub fn interface_names() -> Vec<String> {
+    datalink::interfaces().into_iter().map(|iface| iface.name).collect()
+}
+
+//Function to capture packets on an interface for the given duration. on_packet is called as each packet arrives
+//so callers can see packets while the capture is still running. Needs root (or CAP_NET_RAW) to open the channel.
+pub fn capture_packets(interface_name: &str, duration: Duration, on_packet: &mut dyn FnMut(PacketData)) -> Result<(), ChromiaError> {
+    let interface = datalink::interfaces()
+        .into_iter()
+        .find(|iface| iface.name == interface_name)
+        .ok_or_else(|| ChromiaError::Config(format!("Interface '{}' not found", interface_name)))?;
+
+    // Create a channel to capture packets
+    let mut rx = match datalink::channel(&interface, Default::default())? {
+        datalink::Channel::Ethernet(_, rx) => rx,
+        _ => return Err(ChromiaError::Parse(format!("Unsupported channel type on '{}'", interface_name))),
+    };
+
+    let start_time = Instant::now();
+    while Instant::now().duration_since(start_time) < duration {
+        match rx.next() {
+            Ok(packet) => {
+                let ethernet = match EthernetPacket::new(packet) {
+                    Some(ethernet) => ethernet,
+                    None => continue,
+                };
+                let mut packet_data = PacketData {
+                    source_ip: None,
+                    source_port: None,
+                };
+
+                // Handle IPv4 packets
+                if let Some(ipv4) = Ipv4Packet::new(ethernet.payload()) {
+                    match ipv4.get_next_level_protocol() {
+                        IpNextHeaderProtocols::Tcp => {
+                            if let Some(tcp) = TcpPacket::new(ipv4.payload()) {
+                                packet_data.source_port = Some(tcp.get_source());
+                            }
+                        }
+                        IpNextHeaderProtocols::Udp => {
+                            if let Some(udp) = UdpPacket::new(ipv4.payload()) {
+                                packet_data.source_port = Some(udp.get_source());
+                            }
+                        }
+                        _ => {}
+                    }
+                    packet_data.source_ip = Some(ipv4.get_source().to_string());
+                }
+                on_packet(packet_data);
+            }
+            Err(e) => {
+                println!("Error receiving packet: {:?}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+// A block of addresses written as 10.0.0.0/8 or 2001:db8::/32. A bare address is a block of one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => prefix_matches(u32::from(network) as u128, u32::from(*ip) as u128, self.prefix, 32),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => prefix_matches(u128::from(network), u128::from(*ip), self.prefix, 128),
+            // ::ffff:203.0.113.9 is how an IPv4 client shows up on a dual stack socket
+            (IpAddr::V4(_), IpAddr::V6(ip)) => ip.to_ipv4_mapped().is_some_and(|ip| self.contains(&IpAddr::V4(ip))),
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(network: u128, ip: u128, prefix: u8, bits: u8) -> bool {
+    let host_bits = (bits - prefix) as u32;
+    network.checked_shr(host_bits).unwrap_or(0) == ip.checked_shr(host_bits).unwrap_or(0)
+}
+
+impl std::str::FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match text.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (text.trim(), None),
+        };
+        let network: IpAddr = address.parse().map_err(|_| format!("'{}' is not an IP address", address))?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok().filter(|prefix| *prefix <= bits).ok_or_else(|| format!("'{}' is not a prefix length between 0 and {}", prefix, bits))?,
+            None => bits,
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_net_tcp() {
+        let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n   0: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21034 1 0000000000000000 100 0 0 10 0\n   1: 0100007F:9C40 0100007F:0016 01 00000000:00000000 00:00000000 00000000  1000        0 55120 1 0000000000000000 20 4 30 10 -1\n";
+        let sockets = parse_proc_net(table, "tcp");
+        assert_eq!(sockets.len(), 2);
+        assert_eq!(sockets[0].local_addr, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(sockets[0].local_port, 22);
+        assert!(sockets[0].is_listening());
+        assert_eq!(sockets[1].local_addr, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(sockets[1].remote_port, 22);
+        assert_eq!(sockets[1].uid, 1000);
+        assert!(!sockets[1].is_listening());
+    }
+
+    #[test]
+    fn test_parse_proc_net_ipv6() {
+        let table = "header\n   0: 00000000000000000000000001000000:0035 00000000000000000000000000000000:0000 07 00000000:00000000 00:00000000 00000000   101        0 18870 2 0000000000000000 0\n";
+        let sockets = parse_proc_net(table, "udp6");
+        assert_eq!(sockets[0].local_addr, IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert_eq!(sockets[0].local_port, 53);
+        assert!(sockets[0].is_listening());
+    }
+
+    #[test]
+    fn test_cidr_contains() {
+        let private: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(private.contains(&"10.200.3.4".parse().unwrap()));
+        assert!(private.contains(&"::ffff:10.1.1.1".parse().unwrap()));
+        assert!(!private.contains(&"11.0.0.1".parse().unwrap()));
+        let host: Cidr = "2001:db8::5".parse().unwrap();
+        assert_eq!(host.to_string(), "2001:db8::5/128");
+        assert!(host.contains(&"2001:db8::5".parse().unwrap()) && !host.contains(&"2001:db8::6".parse().unwrap()));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(&"203.0.113.9".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err() && "bad/8".parse::<Cidr>().is_err());
+    }
+}
@@ -0,0 +1,481 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use std::time::SystemTime;
+
+use regex::Regex;
+
+use crate::lara_core::core_enums::ChromiaError;
+
+// A single process read from /proc/<pid>. start_time is in clock ticks since boot (field 22 of
+// /proc/<pid>/stat) and cpu_time the user plus system CPU time it has used (fields 14 and 15), also in
+// clock ticks. Use clock_ticks_per_second() to convert them into seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub ppid: u32,
+    pub name: String,
+    pub uid: u32,
+    pub euid: u32,
+    pub start_time: u64,
+    pub cpu_time: u64,
+    pub argv: Vec<String>,
+    pub exe: Option<PathBuf>,
+    pub exe_deleted: bool,
+    pub cwd: Option<PathBuf>,
+    pub environ: Vec<String>,
+    pub tty: Option<String>,
+    pub container_id: Option<String>,
+}
+
+impl ProcessInfo {
+    // The full command line the way ps prints it. Kernel threads have no argv so the name is shown in brackets
+    pub fn command_line(&self) -> String {
+        if self.argv.is_empty() {
+            format!("[{}]", self.name)
+        } else {
+            self.argv.join(" ")
+        }
+    }
+}
+
+// Builds processes for tests: a process of root's started by init, with only the fields a test cares about changed
+#[cfg(test)]
+impl ProcessInfo {
+    pub fn fake(pid: u32, name: &str) -> Self {
+        ProcessInfo {
+            pid,
+            ppid: 1,
+            name: name.to_string(),
+            uid: 0,
+            euid: 0,
+            start_time: pid as u64 * 10,
+            cpu_time: 0,
+            argv: Vec::new(),
+            exe: None,
+            exe_deleted: false,
+            cwd: None,
+            environ: Vec::new(),
+            tty: None,
+            container_id: None,
+        }
+    }
+
+    pub fn with_ppid(mut self, ppid: u32) -> Self {
+        self.ppid = ppid;
+        self
+    }
+
+    // Sets the real and effective uid
+    pub fn with_uid(mut self, uid: u32) -> Self {
+        self.uid = uid;
+        self.euid = uid;
+        self
+    }
+
+    pub fn with_argv(mut self, argv: &[&str]) -> Self {
+        self.argv = argv.iter().map(|arg| arg.to_string()).collect();
+        self
+    }
+
+    pub fn with_exe(mut self, exe: &str) -> Self {
+        self.exe = Some(PathBuf::from(exe));
+        self
+    }
+
+    pub fn with_tty(mut self, tty: Option<&str>) -> Self {
+        self.tty = tty.map(|tty| tty.to_string());
+        self
+    }
+
+    pub fn with_cpu_time(mut self, cpu_time: u64) -> Self {
+        self.cpu_time = cpu_time;
+        self
+    }
+}
+
+// Fields pulled out of /proc/<pid>/stat
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatFields {
+    pub name: String,
+    pub ppid: u32,
+    pub tty_nr: u64,
+    pub start_time: u64,
+    pub cpu_time: u64,
+}
+
+//Function to list every process currently in /proc. Processes that exit while being read are skipped.
+pub fn list_processes() -> Vec<ProcessInfo> {
+    let mut processes = Vec::new();
+    for pid in list_pids() {
+        if let Ok(process) = read_process(pid) {
+            processes.push(process);
+        }
+    }
+    processes
+}
+
+//Function to list the numeric directories of /proc, sorted by pid
+pub fn list_pids() -> Vec<u32> {
+    let mut pids: Vec<u32> = match fs::read_dir("/proc") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().to_str().and_then(|name| name.parse().ok()))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    pids.sort_unstable();
+    pids
+}
+
+//Function to find the processes that have a file open, by reading every /proc/<pid>/fd link
+pub fn file_holders(path: &str) -> Vec<u32> {
+    let target = Path::new(path);
+    let mut holders = Vec::new();
+    for pid in list_pids() {
+        let fds = match fs::read_dir(format!("/proc/{}/fd", pid)) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        if fds.flatten().filter_map(|fd| fs::read_link(fd.path()).ok()).any(|link| strip_deleted(&link).0 == target) {
+            holders.push(pid);
+        }
+    }
+    holders
+}
+
+//Function to read a single process. stat and status must be readable, the rest (exe, cwd, environ) usually
+//need root for processes owned by other users so they are left empty when they can't be read.
+pub fn read_process(pid: u32) -> Result<ProcessInfo, ChromiaError> {
+    let base = PathBuf::from(format!("/proc/{}", pid));
+    let stat = parse_stat(&fs::read_to_string(base.join("stat"))?)
+        .ok_or_else(|| ChromiaError::Parse(format!("/proc/{}/stat", pid)))?;
+    let (uid, euid) = parse_status_uids(&fs::read_to_string(base.join("status"))?).unwrap_or((0, 0));
+    let argv = fs::read(base.join("cmdline")).map(|raw| split_nul(&raw)).unwrap_or_default();
+    let environ = fs::read(base.join("environ")).map(|raw| split_nul(&raw)).unwrap_or_default();
+    let (exe, exe_deleted) = match fs::read_link(base.join("exe")) {
+        Ok(link) => {
+            let (path, deleted) = strip_deleted(&link);
+            (Some(path), deleted)
+        }
+        Err(_) => (None, false),
+    };
+    let cwd = fs::read_link(base.join("cwd")).ok();
+    let container_id = fs::read_to_string(base.join("cgroup"))
+        .ok()
+        .and_then(|cgroup| parse_container_id(&cgroup));
+
+    Ok(ProcessInfo {
+        pid,
+        ppid: stat.ppid,
+        name: stat.name,
+        uid,
+        euid,
+        start_time: stat.start_time,
+        cpu_time: stat.cpu_time,
+        argv,
+        exe,
+        exe_deleted,
+        cwd,
+        environ,
+        tty: decode_tty(stat.tty_nr),
+        container_id,
+    })
+}
+
+//Function to check a pid exists by sending it signal 0. Unlike listing /proc this goes straight to the
+//kernel's pid table, so it still finds processes a rootkit has hidden from readdir. EPERM means it exists
+//but belongs to another user.
+pub fn pid_alive(pid: u32) -> bool {
+    let pid = match i32::try_from(pid) {
+        Ok(pid) if pid > 0 => pid,
+        _ => return false,
+    };
+    match nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None) {
+        Ok(()) => true,
+        Err(errno) => errno == nix::errno::Errno::EPERM,
+    }
+}
+
+// Returns the Tgid: line of /proc/<pid>/status, the pid of the process a thread belongs to
+pub fn parse_status_tgid(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Tgid:"))
+        .and_then(|tgid| tgid.trim().parse().ok())
+}
+
+//Function to get the number of clock ticks per second used by the start_time and cpu_time fields
+pub fn clock_ticks_per_second() -> u64 {
+    match nix::unistd::sysconf(nix::unistd::SysconfVar::CLK_TCK) {
+        Ok(Some(ticks)) if ticks > 0 => ticks as u64,
+        _ => 100,
+    }
+}
+
+// The process name is wrapped in brackets and may itself contain spaces or brackets,
+// so everything after the LAST ')' is split on whitespace. Fields are counted from 1 as in proc(5).
+pub fn parse_stat(stat: &str) -> Option<StatFields> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    if close < open {
+        return None;
+    }
+    let name = stat[open + 1..close].to_string();
+    // rest[0] is field 3 (state)
+    let rest: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+    Some(StatFields {
+        name,
+        ppid: rest.get(1)?.parse().ok()?,
+        tty_nr: rest.get(4)?.parse().ok()?,
+        start_time: rest.get(19)?.parse().ok()?,
+        cpu_time: rest.get(11)?.parse::<u64>().ok()? + rest.get(12)?.parse::<u64>().ok()?,
+    })
+}
+
+// Returns the (real, effective) uid from the Uid: line of /proc/<pid>/status
+pub fn parse_status_uids(status: &str) -> Option<(u32, u32)> {
+    let line = status.lines().find(|line| line.starts_with("Uid:"))?;
+    let ids: Vec<u32> = line
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|id| id.parse().ok())
+        .collect();
+    Some((*ids.first()?, *ids.get(1)?))
+}
+
+// cmdline and environ are NUL separated and NUL terminated
+pub fn split_nul(raw: &[u8]) -> Vec<String> {
+    raw.split(|byte| *byte == 0)
+        .filter(|part| !part.is_empty())
+        .map(|part| String::from_utf8_lossy(part).into_owned())
+        .collect()
+}
+
+// The kernel appends " (deleted)" to the exe link once the binary is removed from disk
+pub fn strip_deleted(link: &Path) -> (PathBuf, bool) {
+    let text = link.to_string_lossy();
+    match text.strip_suffix(" (deleted)") {
+        Some(path) => (PathBuf::from(path), true),
+        None => (link.to_path_buf(), false),
+    }
+}
+
+// Decodes the tty_nr device number from stat into a name such as pts/3 or tty1
+pub fn decode_tty(tty_nr: u64) -> Option<String> {
+    if tty_nr == 0 {
+        return None;
+    }
+    let major = (tty_nr >> 8) & 0xfff;
+    let minor = (tty_nr & 0xff) | ((tty_nr >> 12) & 0xfff00);
+    match major {
+        4 if minor < 64 => Some(format!("tty{}", minor)),
+        4 => Some(format!("ttyS{}", minor - 64)),
+        136..=143 => Some(format!("pts/{}", (major - 136) * 256 + minor)),
+        _ => Some(format!("{}:{}", major, minor)),
+    }
+}
+
+// Finds the container a process belongs to from its cgroup paths. Docker, containerd, podman and
+// kubernetes all use a 64 character hex id somewhere in the path, lxc uses /lxc/<name>
+pub fn parse_container_id(cgroup: &str) -> Option<String> {
+    static HEX_ID: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[0-9a-f]{64}").unwrap());
+    for line in cgroup.lines() {
+        let path = line.splitn(3, ':').nth(2).unwrap_or("");
+        if let Some(id) = HEX_ID.find(path) {
+            return Some(id.as_str().to_string());
+        }
+        if let Some(rest) = path.strip_prefix("/lxc/").or_else(|| path.strip_prefix("/lxc.payload.")) {
+            if let Some(name) = rest.split('/').next().filter(|name| !name.is_empty()) {
+                return Some(name.to_string());
+            }
+        }
+    }
+    None
+}
+
+// Parses /etc/passwd into a uid -> username map
+pub fn parse_passwd(passwd: &str) -> HashMap<u32, String> {
+    let mut users = HashMap::new();
+    for line in passwd.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() >= 3 {
+            if let Ok(uid) = fields[2].parse::<u32>() {
+                users.entry(uid).or_insert_with(|| fields[0].to_string());
+            }
+        }
+    }
+    users
+}
+
+// What UserResolver knows about /etc/passwd
+#[derive(Default)]
+struct UserCache {
+    users: HashMap<u32, String>,
+    // Looked up before and not in the file, Eg uids of containers or deleted users
+    unknown_uids: HashSet<u32>,
+    unknown_names: HashSet<String>,
+    // Modification time of the file when it was last read
+    modified: Option<SystemTime>,
+}
+
+// Resolves uids to usernames from /etc/passwd without starting an `id` process for every lookup.
+// Misses are cached too. A uid or name that hasn't been looked up before checks whether the file has changed
+// (eg: a user was just added), and only then is it read again.
+pub struct UserResolver {
+    passwd_path: PathBuf,
+    cache: Mutex<UserCache>,
+}
+
+impl UserResolver {
+    pub fn new(passwd_path: &str) -> Self {
+        Self {
+            passwd_path: PathBuf::from(passwd_path),
+            cache: Mutex::new(UserCache::default()),
+        }
+    }
+
+    // Builds a resolver from passwd content that will never touch the disk
+    pub fn from_passwd(passwd: &str) -> Self {
+        Self {
+            passwd_path: PathBuf::new(),
+            cache: Mutex::new(UserCache {
+                users: parse_passwd(passwd),
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn username(&self, uid: u32) -> Option<String> {
+        let mut cache = self.cache.lock().unwrap();
+        if !cache.users.contains_key(&uid) && !cache.unknown_uids.contains(&uid) {
+            self.reload_if_changed(&mut cache);
+            if !cache.users.contains_key(&uid) {
+                cache.unknown_uids.insert(uid);
+            }
+        }
+        cache.users.get(&uid).cloned()
+    }
+
+    pub fn uid(&self, username: &str) -> Option<u32> {
+        let mut cache = self.cache.lock().unwrap();
+        let find = |cache: &UserCache| cache.users.iter().find(|(_, name)| *name == username).map(|(uid, _)| *uid);
+        if find(&cache).is_none() && !cache.unknown_names.contains(username) {
+            self.reload_if_changed(&mut cache);
+            if find(&cache).is_none() {
+                cache.unknown_names.insert(username.to_string());
+            }
+        }
+        find(&cache)
+    }
+
+    // Same as username but falls back to the numeric uid the way ls does
+    pub fn display_name(&self, uid: u32) -> String {
+        self.username(uid).unwrap_or_else(|| uid.to_string())
+    }
+
+    fn reload_if_changed(&self, cache: &mut UserCache) {
+        if self.passwd_path.as_os_str().is_empty() {
+            return;
+        }
+        let modified = match fs::metadata(&self.passwd_path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(_) => return,
+        };
+        if cache.modified == Some(modified) {
+            return;
+        }
+        if let Ok(passwd) = fs::read_to_string(&self.passwd_path) {
+            *cache = UserCache {
+                users: parse_passwd(&passwd),
+                modified: Some(modified),
+                ..Default::default()
+            };
+        }
+    }
+}
+
+impl Default for UserResolver {
+    fn default() -> Self {
+        Self::new("/etc/passwd")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat_with_spaces_in_name() {
+        let stat = "1234 (my (weird) proc) S 1 1234 1234 34817 1234 4194304 100 0 0 0 5 3 0 0 20 0 1 0 98765 1000000 200 18446744073709551615";
+        let fields = parse_stat(stat).unwrap();
+        assert_eq!(fields.name, "my (weird) proc");
+        assert_eq!(fields.ppid, 1);
+        assert_eq!(fields.tty_nr, 34817);
+        assert_eq!(fields.start_time, 98765);
+        assert_eq!(fields.cpu_time, 8);
+    }
+
+    #[test]
+    fn test_parse_status_uids() {
+        let status = "Name:\tbash\nUid:\t1000\t0\t0\t0\nGid:\t1000\t1000\t1000\t1000\n";
+        assert_eq!(parse_status_uids(status), Some((1000, 0)));
+    }
+
+    #[test]
+    fn test_parse_status_tgid() {
+        assert_eq!(parse_status_tgid("Name:\tfirefox\nTgid:\t2001\nPid:\t2044\n"), Some(2001));
+    }
+
+    #[test]
+    fn test_decode_tty() {
+        assert_eq!(decode_tty(0), None);
+        assert_eq!(decode_tty(34817), Some("pts/1".to_string()));
+        assert_eq!(decode_tty(1025), Some("tty1".to_string()));
+    }
+
+    #[test]
+    fn test_strip_deleted() {
+        let (path, deleted) = strip_deleted(Path::new("/tmp/x (deleted)"));
+        assert_eq!(path, PathBuf::from("/tmp/x"));
+        assert!(deleted);
+    }
+
+    #[test]
+    fn test_parse_container_id() {
+        let id = "a".repeat(64);
+        let docker = format!("0::/system.slice/docker-{}.scope\n", id);
+        assert_eq!(parse_container_id(&docker), Some(id));
+        assert_eq!(parse_container_id("0::/lxc/web01/init.scope\n"), Some("web01".to_string()));
+        assert_eq!(parse_container_id("0::/user.slice/user-1000.slice\n"), None);
+    }
+
+    #[test]
+    fn test_user_resolver_from_passwd() {
+        let resolver = UserResolver::from_passwd("root:x:0:0:root:/root:/bin/bash\nerik:x:1000:1000::/home/erik:/bin/bash\n");
+        assert_eq!(resolver.username(1000), Some("erik".to_string()));
+        assert_eq!(resolver.uid("root"), Some(0));
+        assert_eq!(resolver.display_name(4242), "4242");
+    }
+
+    #[test]
+    fn test_user_resolver_rereads_passwd_only_when_it_changed() {
+        let path = std::env::temp_dir().join(format!("chromia-passwd-{}", std::process::id()));
+        fs::write(&path, "root:x:0:0:root:/root:/bin/bash\n").unwrap();
+        let resolver = UserResolver::new(path.to_str().unwrap());
+        assert_eq!(resolver.username(0), Some("root".to_string()));
+        assert_eq!(resolver.username(4242), None);
+        assert!(resolver.cache.lock().unwrap().unknown_uids.contains(&4242));
+
+        fs::write(&path, "root:x:0:0:root:/root:/bin/bash\nerik:x:1000:1000::/home/erik:/bin/bash\n").unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        assert_eq!(resolver.uid("erik"), Some(1000));
+        assert!(resolver.cache.lock().unwrap().unknown_uids.is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
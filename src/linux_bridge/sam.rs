@@ -1,48 +1,33 @@
-use std::process::Command;
 use std::str;
+use crate::lara_core::core_enums::ChromiaError;
+use crate::linux_bridge::system;
 //Function call to check the read speed of a disk using the dd command, and return the read speed as a string.
 //1024+0 records in
 //1024+0 records out
 //1073741824 bytes (1.1 GB, 1.0 GiB) copied, 0.355575 s, 3.0 GB/s
 //Where 1073741824 bytes is the total bytes read, 0.355575 s is the time taken to write the bytes, and 3.0 GB/s is the read speed.
 //Function call to check  the write speed of a disk using the dd command, and return the write speed as a string.
-pub fn disk_write_speed() -> String {
-    let output = Command::new("dd")
-        .arg("if=/dev/zero")
-        .arg("of=/tmp/test")
-        .arg("bs=1M")
-        .arg("count=1024")
-        .output()
-        .expect("Failed to execute command");
-    let last = str::from_utf8(&output.stderr).unwrap();
-    return last.to_string();
+pub fn disk_write_speed() -> Result<String, ChromiaError> {
+    let output = system::run_command_output("dd", &["if=/dev/zero", "of=/tmp/test", "bs=1M", "count=1024"])?;
+    let last = str::from_utf8(&output.stderr).map_err(|_| ChromiaError::Parse("dd output as UTF-8".to_owned()))?;
+    Ok(last.to_string())
 }
 
 //1024+0 records in
 //1024+0 records out
 //1073741824 bytes (1.1 GB, 1.0 GiB) copied, 0.118897 s, 9.0 GB/s
 //This function is called after the disk read speed function is called, as it requires the test file to be present.
-pub fn disk_read_speed() -> String {
-    let output = Command::new("dd")
-        .arg("if=/tmp/test")
-        .arg("of=/dev/null")
-        .arg("bs=1M")
-        .arg("count=1024")
-        .output()
-        .expect("Failed to execute command");
-    let last = str::from_utf8(&output.stderr).unwrap();
-    return last.to_string();
+pub fn disk_read_speed() -> Result<String, ChromiaError> {
+    let output = system::run_command_output("dd", &["if=/tmp/test", "of=/dev/null", "bs=1M", "count=1024"])?;
+    let last = str::from_utf8(&output.stderr).map_err(|_| ChromiaError::Parse("dd output as UTF-8".to_owned()))?;
+    Ok(last.to_string())
 }
 
 //This function is called after the disk read speed function is called, as it requires the test file to be present, and removes the test file.
 //Function to remove the test file created by the disk write speed function.
-pub fn remove_read_write_file() {
-    let output = Command::new("rm")
-        .arg("/tmp/test")
-        .output()
-        .expect("Failed to execute command");
-    let last = str::from_utf8(&output.stderr).unwrap();
-    println!("{}", last);
+pub fn remove_read_write_file() -> Result<(), ChromiaError> {
+    std::fs::remove_file("/tmp/test")?;
+    Ok(())
 }
 
 //Function to check the disk usage // Filesystem      Size  Used Avail Use% Mounted on
@@ -58,46 +43,31 @@ pub fn remove_read_write_file() {
 // tmpfs           2.4G   22M  2.4G   1% /run/user/1000
 //The first column is the filesystem, the second column is the size of the filesystem, the third column is the amount of space used,
 // the fourth column is the amount of space available, the fifth column is the percentage of space used, and the sixth column is the mount point.
-pub fn disk_usage() -> String {
-    let output = Command::new("df")
-        .arg("-h")
-        .output()
-        .expect("Failed to execute command");
-    let last = str::from_utf8(&output.stdout).unwrap();
-    return last.to_string();
+pub fn disk_usage() -> Result<String, ChromiaError> {
+    let output = system::run_command_output("df", &["-h"])?;
+    let last = str::from_utf8(&output.stdout).map_err(|_| ChromiaError::Parse("df output as UTF-8".to_owned()))?;
+    Ok(last.to_string())
 }
 
 //Fucntion to check for packet loss using the ping command, and return the packet loss as a string.
 //Displays statistics for all network interfaces.
-pub fn network_packet_dropped_errors() -> String {
-    let output = Command::new("ip")
-        .arg("-s")
-        .arg("link")
-        .output()
-        .expect("Failed to execute command");
-    let last = str::from_utf8(&output.stdout).unwrap();
-    return last.to_string();
+pub fn network_packet_dropped_errors() -> Result<String, ChromiaError> {
+    let output = system::run_command_output("ip", &["-s", "link"])?;
+    let last = str::from_utf8(&output.stdout).map_err(|_| ChromiaError::Parse("ip output as UTF-8".to_owned()))?;
+    Ok(last.to_string())
 }
 
 //Function to pull all the CPU information from the /proc/cpuinfo file, and return the CPU information as a string.
-pub fn cpu_info() -> String {
-    let output = Command::new("cat")
-        .arg("/proc/cpuinfo")
-        .output()
-        .expect("Failed to execute command");
-    let last = str::from_utf8(&output.stdout).unwrap();
-    return last.to_string();
+pub fn cpu_info() -> Result<String, ChromiaError> {
+    system::sys_file_read("/proc/cpuinfo")
 }
 //Function to pull the memory usage
-pub fn memory_usage() -> String {
-    let output = Command::new("free")
-        .output()
-        .expect("Failed to execute command");
-    let last = str::from_utf8(&output.stdout).unwrap();
-    return last.to_string();
+pub fn memory_usage() -> Result<String, ChromiaError> {
+    let output = system::run_command_output("free", &[])?;
+    let last = str::from_utf8(&output.stdout).map_err(|_| ChromiaError::Parse("free output as UTF-8".to_owned()))?;
+    Ok(last.to_string())
 }
 
-use std::process::{Stdio};
 
 //Function to pull the CPU usage  from the top command, store the result in a buffer and return the CPU usage as a string.
   // Run the `top` command and capture its output
@@ -120,18 +90,9 @@ use std::process::{Stdio};
 // RES is the resident memory used by the process which is the physical memory used by the process,
 // SHR is the shared memory used by the process which is the memory shared by the process,
 // S is the status of the process which can be S (sleeping), R (running), D (uninterruptible sleep), Z (zombie), or T (stopped).
-pub fn cpu_usage() -> String {
-    let output = Command::new("top")
-        .arg("-b")   // Run in batch mode, this is necessary to prevent the program from hanging this allows for a capture of the output at the time of execution
-        .arg("-n")   
-        .arg("1")    
-        .stdout(Stdio::piped())
-        .spawn()
-        .expect("Failed to start `top` command");
-    let output = output
-        .wait_with_output() // Wait for the command to finish and capture its output
-        .expect("Failed to read `top` output");
-    String::from_utf8_lossy(&output.stdout).to_string()
+pub fn cpu_usage() -> Result<String, ChromiaError> {
+    // Run in batch mode, this is necessary to prevent the program from hanging this allows for a capture of the output at the time of execution
+    system::run_command("top", &["-b", "-n", "1"])
 }
 
 
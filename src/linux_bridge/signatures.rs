@@ -0,0 +1,901 @@
+use std::collections::HashMap;
+
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::lara_core::core_enums::ChromiaError;
+use crate::linux_bridge::source::SystemSource;
+
+// Files bigger than this aren't scanned
+pub const MAX_SCAN_SIZE: u64 = 64 * 1024 * 1024;
+// Matches of one string past this aren't looked for, a string that is all through a big file would take forever
+const MAX_MATCHES: usize = 10_000;
+
+// A string from the strings: section, whatever kind it was written as it is matched with a regex over the bytes
+#[derive(Debug)]
+struct Pattern {
+    id: String,
+    regex: Regex,
+    fullword: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug)]
+enum Quantifier {
+    All,
+    Any,
+    None,
+    AtLeast(i64),
+}
+
+#[derive(Debug)]
+enum Expr {
+    Bool(bool),
+    Int(i64),
+    Filesize,
+    // $a
+    Matched(String),
+    // #a
+    Count(String),
+    // @a[i] and !a[i], i counts from 1
+    Offset(String, Box<Expr>),
+    Length(String, Box<Expr>),
+    // $a at 100
+    At(String, Box<Expr>),
+    // $a in (0..1024)
+    In(String, Box<Expr>, Box<Expr>),
+    // 2 of ($a, $b*)
+    Of(Quantifier, Vec<String>),
+    // uint16(0), uint32be(@a[1])
+    ReadInt { bytes: usize, big_endian: bool, offset: Box<Expr> },
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Cmp(Box<Expr>, CmpOp, Box<Expr>),
+    Arith(Box<Expr>, ArithOp, Box<Expr>),
+}
+
+// string id -> (offset, length) of every match
+type Matches = HashMap<String, Vec<(usize, usize)>>;
+
+impl Expr {
+    fn eval_bool(&self, data: &[u8], matches: &Matches) -> bool {
+        let offsets = |id: &String| matches.get(id).map(|found| found.as_slice()).unwrap_or_default();
+        match self {
+            Expr::Bool(value) => *value,
+            Expr::Matched(id) => !offsets(id).is_empty(),
+            Expr::At(id, offset) => offset.eval_int(data, matches).is_some_and(|offset| offsets(id).iter().any(|(at, _)| *at as i64 == offset)),
+            Expr::In(id, start, end) => match (start.eval_int(data, matches), end.eval_int(data, matches)) {
+                (Some(start), Some(end)) => offsets(id).iter().any(|(at, _)| (start..=end).contains(&(*at as i64))),
+                _ => false,
+            },
+            Expr::Of(quantifier, ids) => {
+                let matched = ids.iter().filter(|id| !offsets(id).is_empty()).count() as i64;
+                match quantifier {
+                    Quantifier::All => matched == ids.len() as i64,
+                    Quantifier::Any => matched > 0,
+                    Quantifier::None => matched == 0,
+                    Quantifier::AtLeast(count) => matched >= *count,
+                }
+            }
+            Expr::Not(expr) => !expr.eval_bool(data, matches),
+            Expr::And(left, right) => left.eval_bool(data, matches) && right.eval_bool(data, matches),
+            Expr::Or(left, right) => left.eval_bool(data, matches) || right.eval_bool(data, matches),
+            Expr::Cmp(left, op, right) => match (left.eval_int(data, matches), right.eval_int(data, matches)) {
+                (Some(left), Some(right)) => match op {
+                    CmpOp::Eq => left == right,
+                    CmpOp::Ne => left != right,
+                    CmpOp::Lt => left < right,
+                    CmpOp::Le => left <= right,
+                    CmpOp::Gt => left > right,
+                    CmpOp::Ge => left >= right,
+                },
+                _ => false,
+            },
+            // A number on its own is true when it isn't 0, Eg "condition: #a"
+            _ => self.eval_int(data, matches).is_some_and(|value| value != 0),
+        }
+    }
+    // None is undefined, Eg the offset of a match that doesn't exist or reading past the end of the file
+    fn eval_int(&self, data: &[u8], matches: &Matches) -> Option<i64> {
+        let nth = |id: &String, index: &Expr| -> Option<(usize, usize)> {
+            let index = index.eval_int(data, matches)?;
+            matches.get(id)?.get(usize::try_from(index).ok()?.checked_sub(1)?).copied()
+        };
+        match self {
+            Expr::Int(value) => Some(*value),
+            Expr::Filesize => Some(data.len() as i64),
+            Expr::Count(id) => Some(matches.get(id).map_or(0, |found| found.len()) as i64),
+            Expr::Offset(id, index) => nth(id, index).map(|(offset, _)| offset as i64),
+            Expr::Length(id, index) => nth(id, index).map(|(_, length)| length as i64),
+            Expr::ReadInt { bytes, big_endian, offset } => {
+                let offset = usize::try_from(offset.eval_int(data, matches)?).ok()?;
+                let mut raw: Vec<u8> = data.get(offset..offset.checked_add(*bytes)?)?.to_vec();
+                if !big_endian {
+                    raw.reverse();
+                }
+                Some(raw.iter().fold(0i64, |value, byte| (value << 8) | *byte as i64))
+            }
+            Expr::Arith(left, op, right) => {
+                let (left, right) = (left.eval_int(data, matches)?, right.eval_int(data, matches)?);
+                match op {
+                    ArithOp::Add => left.checked_add(right),
+                    ArithOp::Sub => left.checked_sub(right),
+                    ArithOp::Mul => left.checked_mul(right),
+                    ArithOp::Div => left.checked_div(right),
+                    ArithOp::Mod => left.checked_rem(right),
+                }
+            }
+            _ => Some(self.eval_bool(data, matches) as i64),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    pub name: String,
+    pub tags: Vec<String>,
+    pub meta: Vec<(String, String)>,
+    strings: Vec<Pattern>,
+    condition: Expr,
+}
+
+// A rule that matched, with the strings that were found
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleMatch {
+    pub rule: String,
+    pub tags: Vec<String>,
+    // From the description meta field, when the rule has one
+    pub description: Option<String>,
+    pub strings: Vec<String>,
+}
+
+impl std::fmt::Display for RuleMatch {
+    // 'Webshell_Eval' (PHP eval of request data), strings $php, $eval
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}'", self.rule)?;
+        if let Some(description) = &self.description {
+            write!(f, " ({})", description)?;
+        }
+        if !self.strings.is_empty() {
+            write!(f, ", strings {}", self.strings.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// Rules written in a subset of YARA: text, hex and regex strings and conditions made of string matches, counts,
+// offsets, at, in, quantifiers (any/all/none/N of), filesize and uint8/16/32 reads. Modules, includes, imports and
+// the xor and base64 modifiers aren't supported
+#[derive(Debug, Default)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub fn compile(text: &str) -> Result<RuleSet, ChromiaError> {
+        let mut cursor = Cursor { text, pos: 0 };
+        let mut rules: Vec<Rule> = Vec::new();
+        loop {
+            cursor.skip_space();
+            if cursor.done() {
+                break;
+            }
+            let rule = parse_rule(&mut cursor).map_err(|e| ChromiaError::Parse(format!("line {}: {}", cursor.line(), e)))?;
+            if rules.iter().any(|other| other.name == rule.name) {
+                return Err(ChromiaError::Parse(format!("line {}: rule '{}' is defined twice", cursor.line(), rule.name)));
+            }
+            rules.push(rule);
+        }
+        Ok(RuleSet { rules })
+    }
+    pub fn scan(&self, data: &[u8]) -> Vec<RuleMatch> {
+        let mut results = Vec::new();
+        for rule in &self.rules {
+            let mut matches: Matches = HashMap::new();
+            for pattern in &rule.strings {
+                matches.insert(pattern.id.clone(), find_all(pattern, data));
+            }
+            if rule.condition.eval_bool(data, &matches) {
+                results.push(RuleMatch {
+                    rule: rule.name.clone(),
+                    tags: rule.tags.clone(),
+                    description: rule.meta.iter().find(|(key, _)| key == "description").map(|(_, value)| value.clone()),
+                    strings: rule.strings.iter().filter(|pattern| !matches[&pattern.id].is_empty()).map(|pattern| pattern.id.clone()).collect(),
+                });
+            }
+        }
+        results
+    }
+    // None when the file is too big to scan
+    pub fn scan_file(&self, source: &dyn SystemSource, path: &str) -> Result<Option<Vec<RuleMatch>>, ChromiaError> {
+        Ok(source.read_bytes(path, MAX_SCAN_SIZE)?.map(|data| self.scan(&data)))
+    }
+}
+
+// Loads a rule file, or every .yar and .yara file in a folder. Files with errors are returned with what's wrong and
+// left out, the rest are still used
+pub fn load_rules(source: &dyn SystemSource, path: &str) -> (RuleSet, Vec<(String, ChromiaError)>) {
+    let mut paths: Vec<String> = match source.list_files(path) {
+        Ok(files) => files.into_iter().map(|file| file.path).filter(|file| file.ends_with(".yar") || file.ends_with(".yara")).collect(),
+        Err(_) => vec![path.to_string()],
+    };
+    paths.sort();
+    let mut set = RuleSet::default();
+    let mut problems = Vec::new();
+    for path in paths {
+        match source.read_to_string(&path).and_then(|text| RuleSet::compile(&text)) {
+            Ok(rules) => set.rules.extend(rules.rules),
+            Err(e) => problems.push((format!("Could not load signature rules from '{}'", path), e)),
+        }
+    }
+    (set, problems)
+}
+
+fn find_all(pattern: &Pattern, data: &[u8]) -> Vec<(usize, usize)> {
+    let word = |byte: Option<&u8>| byte.is_some_and(|byte| byte.is_ascii_alphanumeric());
+    let mut found = Vec::new();
+    let mut start = 0;
+    // Overlapping matches count, "aa" is in "aaa" twice
+    while let Some(m) = pattern.regex.find_at(data, start) {
+        let standalone = !word(m.start().checked_sub(1).and_then(|before| data.get(before))) && !word(data.get(m.end()));
+        if !pattern.fullword || standalone {
+            found.push((m.start(), m.len()));
+            if found.len() >= MAX_MATCHES {
+                break;
+            }
+        }
+        start = m.start() + 1;
+        if start > data.len() {
+            break;
+        }
+    }
+    found
+}
+
+struct Cursor<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+    fn done(&self) -> bool {
+        self.pos >= self.text.len()
+    }
+    fn line(&self) -> usize {
+        self.text[..self.pos.min(self.text.len())].matches('\n').count() + 1
+    }
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+    // Whitespace and // and /* */ comments
+    fn skip_space(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                self.pos += trimmed.find("*/").map_or(trimmed.len(), |end| end + 2);
+            } else {
+                return;
+            }
+        }
+    }
+    fn ident(&mut self) -> Option<&'a str> {
+        self.skip_space();
+        let rest = self.rest();
+        let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        if len == 0 || rest.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        self.pos += len;
+        Some(&rest[..len])
+    }
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            return true;
+        }
+        false
+    }
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            return Ok(());
+        }
+        Err(format!("expected '{}' but found '{}'", token, self.rest().chars().take(20).collect::<String>()))
+    }
+    // A section name, Eg strings:, without taking it
+    fn at_section(&mut self) -> Option<&'a str> {
+        self.skip_space();
+        ["meta", "strings", "condition"].into_iter().find(|section| {
+            self.rest().strip_prefix(section).is_some_and(|after| after.trim_start().starts_with(':'))
+        })
+    }
+}
+
+fn parse_rule(cursor: &mut Cursor) -> Result<Rule, String> {
+    match cursor.ident() {
+        Some("rule") => {}
+        Some(word @ ("import" | "include" | "global" | "private")) => return Err(format!("'{}' isn't supported", word)),
+        _ => return Err("expected 'rule'".to_string()),
+    }
+    let name = cursor.ident().ok_or("expected the rule name")?.to_string();
+    let mut tags = Vec::new();
+    if cursor.eat(":") {
+        while let Some(tag) = cursor.ident() {
+            tags.push(tag.to_string());
+        }
+    }
+    cursor.expect("{")?;
+    let mut meta = Vec::new();
+    let mut strings: Vec<Pattern> = Vec::new();
+    loop {
+        let section = cursor.at_section().ok_or_else(|| format!("expected meta:, strings: or condition: in rule '{}'", name))?;
+        cursor.expect(section)?;
+        cursor.expect(":")?;
+        match section {
+            "meta" => {
+                while cursor.at_section().is_none() {
+                    let key = cursor.ident().ok_or("expected a meta field name")?.to_string();
+                    cursor.expect("=")?;
+                    cursor.skip_space();
+                    let value = if cursor.peek() == Some('"') {
+                        String::from_utf8_lossy(&parse_text(cursor)?).into_owned()
+                    } else {
+                        cursor.ident().map(str::to_string).or_else(|| parse_number(cursor).map(|n| n.to_string())).ok_or("expected a meta value")?
+                    };
+                    meta.push((key, value));
+                }
+            }
+            "strings" => {
+                while cursor.at_section().is_none() {
+                    let pattern = parse_string(cursor)?;
+                    if strings.iter().any(|other| other.id == pattern.id) {
+                        return Err(format!("{} is defined twice", pattern.id));
+                    }
+                    strings.push(pattern);
+                }
+            }
+            _ => {
+                // Conditions have no braces of their own, so the rule ends at the next one
+                let rest = cursor.rest();
+                let end = rest.find('}').ok_or_else(|| format!("rule '{}' is missing its closing brace", name))?;
+                let ids: Vec<String> = strings.iter().map(|pattern| pattern.id.clone()).collect();
+                let condition = parse_condition(&rest[..end], &ids)?;
+                cursor.pos += end + 1;
+                return Ok(Rule { name, tags, meta, strings, condition });
+            }
+        }
+    }
+}
+
+fn parse_number(cursor: &mut Cursor) -> Option<i64> {
+    cursor.skip_space();
+    let rest = cursor.rest();
+    let negative = rest.starts_with('-');
+    let digits = &rest[negative as usize..];
+    let len = digits.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(digits.len());
+    let value = parse_int(&digits[..len])?;
+    cursor.pos += negative as usize + len;
+    Some(if negative { -value } else { value })
+}
+
+// 100, 0x1F, 16KB or 1MB
+fn parse_int(text: &str) -> Option<i64> {
+    let (digits, scale) = match text.strip_suffix("KB").or_else(|| text.strip_suffix("kb")) {
+        Some(digits) => (digits, 1024),
+        None => match text.strip_suffix("MB").or_else(|| text.strip_suffix("mb")) {
+            Some(digits) => (digits, 1024 * 1024),
+            None => (text, 1),
+        },
+    };
+    let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i64>().ok()?,
+    };
+    value.checked_mul(scale)
+}
+
+// "text" with \" \\ \n \r \t and \xHH escapes
+fn parse_text(cursor: &mut Cursor) -> Result<Vec<u8>, String> {
+    cursor.expect("\"")?;
+    let mut bytes = Vec::new();
+    loop {
+        match cursor.bump().ok_or("unterminated string")? {
+            '"' => return Ok(bytes),
+            '\\' => match cursor.bump().ok_or("unterminated string")? {
+                'n' => bytes.push(b'\n'),
+                'r' => bytes.push(b'\r'),
+                't' => bytes.push(b'\t'),
+                'x' => {
+                    let hex: String = [cursor.bump(), cursor.bump()].into_iter().flatten().collect();
+                    bytes.push(u8::from_str_radix(&hex, 16).map_err(|_| format!("'\\x{}' isn't a hex escape", hex))?);
+                }
+                c @ ('"' | '\\') => bytes.push(c as u8),
+                c => return Err(format!("unknown escape '\\{}'", c)),
+            },
+            c => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+}
+
+fn escape_bytes(bytes: &[u8], wide: bool) -> String {
+    bytes.iter().map(|byte| if wide { format!("\\x{:02x}\\x00", byte) } else { format!("\\x{:02x}", byte) }).collect()
+}
+
+// $name = "text" | { hex } | /regex/ followed by modifiers
+fn parse_string(cursor: &mut Cursor) -> Result<Pattern, String> {
+    cursor.expect("$")?;
+    let id = format!("${}", cursor.ident().ok_or("strings need a name, Eg $a")?);
+    cursor.expect("=")?;
+    cursor.skip_space();
+    enum Kind {
+        Text(Vec<u8>),
+        Hex(String),
+        Regex(String, bool, bool),
+    }
+    let kind = match cursor.peek() {
+        Some('"') => Kind::Text(parse_text(cursor)?),
+        Some('{') => {
+            cursor.bump();
+            let end = cursor.rest().find('}').ok_or_else(|| format!("{} is missing its closing brace", id))?;
+            let hex = hex_regex(&cursor.rest()[..end]).map_err(|e| format!("{}: {}", id, e))?;
+            cursor.pos += end + 1;
+            Kind::Hex(hex)
+        }
+        Some('/') => {
+            cursor.bump();
+            let mut body = String::new();
+            loop {
+                match cursor.bump().ok_or_else(|| format!("{} is missing its closing /", id))? {
+                    '/' => break,
+                    '\\' if cursor.peek() == Some('/') => {
+                        cursor.bump();
+                        body.push('/');
+                    }
+                    '\\' => {
+                        body.push('\\');
+                        body.extend(cursor.bump());
+                    }
+                    c => body.push(c),
+                }
+            }
+            let (mut nocase, mut dotall) = (false, false);
+            while let Some(flag @ ('i' | 's')) = cursor.peek() {
+                cursor.bump();
+                if flag == 'i' { nocase = true } else { dotall = true }
+            }
+            Kind::Regex(body, nocase, dotall)
+        }
+        _ => return Err(format!("{} must be a text, hex or regex string", id)),
+    };
+    let (mut nocase, mut wide, mut ascii, mut fullword) = (false, false, false, false);
+    while cursor.at_section().is_none() && !cursor.rest().trim_start().starts_with('$') {
+        match cursor.ident() {
+            Some("nocase") => nocase = true,
+            Some("wide") => wide = true,
+            Some("ascii") => ascii = true,
+            Some("fullword") => fullword = true,
+            Some("private") => {}
+            Some(modifier) => return Err(format!("the {} modifier isn't supported", modifier)),
+            None => return Err(format!("unexpected '{}' after {}", cursor.rest().chars().take(20).collect::<String>(), id)),
+        }
+    }
+    let (pattern, nocase, dotall) = match kind {
+        Kind::Text(bytes) => {
+            let pattern = match (wide, ascii) {
+                (true, true) => format!("(?:{}|{})", escape_bytes(&bytes, false), escape_bytes(&bytes, true)),
+                (true, false) => escape_bytes(&bytes, true),
+                _ => escape_bytes(&bytes, false),
+            };
+            (pattern, nocase, false)
+        }
+        Kind::Hex(pattern) if !(nocase || wide || fullword) => (pattern, false, true),
+        Kind::Hex(_) => return Err(format!("{}: hex strings can't have modifiers", id)),
+        Kind::Regex(_, _, _) if wide => return Err(format!("{}: wide regex strings aren't supported", id)),
+        Kind::Regex(body, regex_nocase, dotall) => (body, nocase || regex_nocase, dotall),
+    };
+    let regex = RegexBuilder::new(&pattern)
+        .unicode(false)
+        .case_insensitive(nocase)
+        .dot_matches_new_line(dotall)
+        .build()
+        .map_err(|e| format!("{} is not a valid pattern: {}", id, e))?;
+    Ok(Pattern { id, regex, fullword })
+}
+
+// { 4D 5A ?? 9? [2-4] ( 0A | 0D 0A ) } as a regex over bytes
+fn hex_regex(hex: &str) -> Result<String, String> {
+    let mut pattern = String::new();
+    let chars: Vec<char> = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut i = 0;
+    let mut bytes = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '(' => pattern.push_str("(?:"),
+            '|' => pattern.push('|'),
+            ')' => pattern.push(')'),
+            '[' => {
+                let end = chars[i..].iter().position(|c| *c == ']').ok_or("a jump is missing its ]")? + i;
+                let jump: String = chars[i + 1..end].iter().collect();
+                let bound = |text: &str| text.parse::<u32>().map_err(|_| format!("[{}] isn't a jump", jump));
+                pattern.push_str(&match jump.split_once('-') {
+                    Some(("", "")) => "(?s:.)*".to_string(),
+                    Some((low, "")) => format!("(?s:.){{{},}}", bound(low)?),
+                    Some((low, high)) => format!("(?s:.){{{},{}}}", bound(low)?, bound(high)?),
+                    None => format!("(?s:.){{{}}}", bound(&jump)?),
+                });
+                i = end;
+            }
+            high => {
+                let low = *chars.get(i + 1).ok_or("hex bytes are two digits each")?;
+                let digit = |c: char| c.to_digit(16).ok_or_else(|| format!("'{}' isn't a hex digit", c));
+                pattern.push_str(&match (high, low) {
+                    ('?', '?') => "(?s:.)".to_string(),
+                    ('?', low) => {
+                        let low = digit(low)?;
+                        format!("[{}]", (0..16).map(|high| format!("\\x{:x}{:x}", high, low)).collect::<String>())
+                    }
+                    (high, '?') => format!("[\\x{:x}0-\\x{:x}f]", digit(high)?, digit(high)?),
+                    (high, low) => format!("\\x{:x}{:x}", digit(high)?, digit(low)?),
+                });
+                bytes += 1;
+                i += 1;
+            }
+        }
+        i += 1;
+    }
+    if bytes == 0 {
+        return Err("hex strings need at least one byte".to_string());
+    }
+    Ok(pattern)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Int(i64),
+    // $a, #a, @a or !a with the sigil first, the name can end in * inside an of set
+    Ref(char, String),
+    Sym(&'static str),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    const SYMBOLS: [&str; 16] = ["..", "==", "!=", "<=", ">=", "<", ">", "(", ")", "[", "]", ",", "+", "-", "*", "\\"];
+    let mut tokens = Vec::new();
+    let mut cursor = Cursor { text, pos: 0 };
+    loop {
+        cursor.skip_space();
+        let rest = cursor.rest();
+        let c = match rest.chars().next() {
+            Some(c) => c,
+            None => return Ok(tokens),
+        };
+        let named = |start: usize| -> usize { start + rest[start..].find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '*')).unwrap_or(rest.len() - start) };
+        if matches!(c, '$' | '#' | '@' | '!') && !rest.starts_with("!=") {
+            let end = named(1);
+            tokens.push(Token::Ref(c, rest[1..end].to_string()));
+            cursor.pos += end;
+        } else if c.is_ascii_digit() {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            tokens.push(Token::Int(parse_int(&rest[..end]).ok_or_else(|| format!("'{}' isn't a number", &rest[..end]))?));
+            cursor.pos += end;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let word = cursor.ident().unwrap_or_default();
+            tokens.push(Token::Word(word.to_string()));
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)).copied() {
+            tokens.push(Token::Sym(symbol));
+            cursor.pos += symbol.len();
+        } else if c == '%' {
+            tokens.push(Token::Sym("%"));
+            cursor.pos += 1;
+        } else {
+            return Err(format!("unexpected '{}' in the condition", c));
+        }
+    }
+}
+
+fn parse_condition(text: &str, ids: &[String]) -> Result<Expr, String> {
+    let mut parser = Parser { tokens: tokenize(text)?, pos: 0, ids };
+    let expr = parser.or()?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(expr),
+        Some(token) => Err(format!("unexpected {:?} in the condition", token)),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    // Every string the rule defines
+    ids: &'a [String],
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+    fn eat_word(&mut self, word: &str) -> bool {
+        if self.peek() == Some(&Token::Word(word.to_string())) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+    fn eat_sym(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Sym(found)) if *found == symbol) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+    fn expect_sym(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat_sym(symbol) {
+            return Ok(());
+        }
+        Err(format!("expected '{}' in the condition", symbol))
+    }
+    fn string(&self, name: &str) -> Result<String, String> {
+        let id = format!("${}", name);
+        if !self.ids.contains(&id) {
+            return Err(format!("{} isn't defined", id));
+        }
+        Ok(id)
+    }
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat_word("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.eat_word("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.eat_word("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        let left = self.sum()?;
+        let op = match self.peek() {
+            Some(Token::Sym("==")) => CmpOp::Eq,
+            Some(Token::Sym("!=")) => CmpOp::Ne,
+            Some(Token::Sym("<")) => CmpOp::Lt,
+            Some(Token::Sym("<=")) => CmpOp::Le,
+            Some(Token::Sym(">")) => CmpOp::Gt,
+            Some(Token::Sym(">=")) => CmpOp::Ge,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        Ok(Expr::Cmp(Box::new(left), op, Box::new(self.sum()?)))
+    }
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Sym("+")) => ArithOp::Add,
+                Some(Token::Sym("-")) => ArithOp::Sub,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Arith(Box::new(expr), op, Box::new(self.product()?));
+        }
+    }
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Sym("*")) => ArithOp::Mul,
+                Some(Token::Sym("\\")) => ArithOp::Div,
+                Some(Token::Sym("%")) => ArithOp::Mod,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Arith(Box::new(expr), op, Box::new(self.primary()?));
+        }
+    }
+    // The [i] after @a or !a, the first match when there isn't one
+    fn index(&mut self) -> Result<Box<Expr>, String> {
+        if self.eat_sym("[") {
+            let index = self.sum()?;
+            self.expect_sym("]")?;
+            return Ok(Box::new(index));
+        }
+        Ok(Box::new(Expr::Int(1)))
+    }
+    fn of(&mut self, quantifier: Quantifier) -> Result<Expr, String> {
+        if !self.eat_word("of") {
+            return Err("expected 'of' after the quantifier".to_string());
+        }
+        if self.eat_word("them") {
+            return Ok(Expr::Of(quantifier, self.ids.to_vec()));
+        }
+        self.expect_sym("(")?;
+        let mut ids: Vec<String> = Vec::new();
+        loop {
+            match self.next() {
+                Some(Token::Ref('$', name)) => match name.strip_suffix('*') {
+                    Some(prefix) => {
+                        let prefix = format!("${}", prefix);
+                        let found: Vec<String> = self.ids.iter().filter(|id| id.starts_with(&prefix)).cloned().collect();
+                        if found.is_empty() {
+                            return Err(format!("no strings start with {}", prefix));
+                        }
+                        ids.extend(found);
+                    }
+                    None => ids.push(self.string(&name)?),
+                },
+                _ => return Err("expected a string like $a or $a* in the set".to_string()),
+            }
+            if !self.eat_sym(",") {
+                break;
+            }
+        }
+        self.expect_sym(")")?;
+        Ok(Expr::Of(quantifier, ids))
+    }
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next().ok_or("the condition ended early")? {
+            Token::Sym("(") => {
+                let expr = self.or()?;
+                self.expect_sym(")")?;
+                Ok(expr)
+            }
+            Token::Int(count) if self.peek() == Some(&Token::Word("of".to_string())) => self.of(Quantifier::AtLeast(count)),
+            Token::Int(value) => Ok(Expr::Int(value)),
+            Token::Word(word) => match word.as_str() {
+                "true" => Ok(Expr::Bool(true)),
+                "false" => Ok(Expr::Bool(false)),
+                "filesize" => Ok(Expr::Filesize),
+                "any" => self.of(Quantifier::Any),
+                "all" => self.of(Quantifier::All),
+                "none" => self.of(Quantifier::None),
+                "uint8" | "uint16" | "uint32" | "uint16be" | "uint32be" => {
+                    self.expect_sym("(")?;
+                    let offset = self.sum()?;
+                    self.expect_sym(")")?;
+                    let bits: usize = word.trim_start_matches("uint").trim_end_matches("be").parse().unwrap_or(8);
+                    Ok(Expr::ReadInt { bytes: bits / 8, big_endian: word.ends_with("be"), offset: Box::new(offset) })
+                }
+                _ => Err(format!("'{}' isn't supported in conditions", word)),
+            },
+            Token::Ref('$', name) => {
+                let id = self.string(&name)?;
+                if self.eat_word("at") {
+                    return Ok(Expr::At(id, Box::new(self.sum()?)));
+                }
+                if self.eat_word("in") {
+                    self.expect_sym("(")?;
+                    let start = self.sum()?;
+                    self.expect_sym("..")?;
+                    let end = self.sum()?;
+                    self.expect_sym(")")?;
+                    return Ok(Expr::In(id, Box::new(start), Box::new(end)));
+                }
+                Ok(Expr::Matched(id))
+            }
+            Token::Ref('#', name) => Ok(Expr::Count(self.string(&name)?)),
+            Token::Ref('@', name) => Ok(Expr::Offset(self.string(&name)?, self.index()?)),
+            Token::Ref(_, name) => Ok(Expr::Length(self.string(&name)?, self.index()?)),
+            token => Err(format!("unexpected {:?} in the condition", token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+        // Seen in webshells dropped through upload forms
+        rule Webshell_Eval : webshell php {
+            meta:
+                description = "PHP eval of request data"
+                score = 80
+            strings:
+                $php = "<?php" nocase
+                $eval = /eval\s*\(\s*\$_(POST|GET|REQUEST)/
+                $b64 = "base64_decode" fullword
+            condition:
+                $php at 0 and ($eval or #b64 > 1)
+        }
+
+        rule ELF_Miner {
+            strings:
+                $elf = { 7F 45 4C 46 }
+                $pool = "stratum+tcp://" wide ascii
+                $cfg = { 22 75 72 6C 22 [1-3] 3A ( 20 | 09 ) ?? }
+            condition:
+                uint32(0) == 0x464C457F and $elf at 0 and any of ($pool, $cfg) and filesize < 1MB
+        }
+
+        /* Offsets and counts */
+        rule Marker_Twice {
+            strings:
+                $m = "MARK"
+            condition:
+                #m == 2 and @m[2] - @m[1] >= 8 and !m[1] == 4 and $m in (0..16) and not $m at 1
+        }
+    "#;
+
+    #[test]
+    fn test_rules_match_files() {
+        let rules = RuleSet::compile(RULES).unwrap();
+        assert_eq!(rules.rules.len(), 3);
+        assert_eq!(rules.rules[0].tags, vec!["webshell", "php"]);
+
+        let shell = b"<?PHP @eval( $_POST['x']); base64_decode($y);";
+        let found = rules.scan(shell);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].to_string(), "'Webshell_Eval' (PHP eval of request data), strings $php, $eval, $b64");
+        // base64_decode twice without eval, but the second one is part of a longer word
+        assert!(rules.scan(b"<?php base64_decode(1); my_base64_decoder(2);").is_empty());
+        assert_eq!(rules.scan(b"<?php base64_decode(1); base64_decode(2);").len(), 1);
+
+        let mut elf = b"\x7fELF\x02\x01".to_vec();
+        elf.extend("s\0t\0r\0a\0t\0u\0m\0+\0t\0c\0p\0:\0/\0/\0".as_bytes());
+        assert_eq!(rules.scan(&elf)[0].rule, "ELF_Miner");
+        let mut config = b"\x7fELF".to_vec();
+        config.extend(b"{\"url\"  :\t\"pool\"}");
+        assert_eq!(rules.scan(&config)[0].strings, vec!["$elf", "$cfg"]);
+        assert!(rules.scan(b"\x7fELF stratum+tcpx").is_empty());
+
+        assert_eq!(rules.scan(b"xxMARKxxxxxxMARKxx")[0].rule, "Marker_Twice");
+        assert!(rules.scan(b"xxMARKxMARK").is_empty());
+        assert!(rules.scan(b"xMARKxxxxxxxxMARK").is_empty());
+    }
+
+    #[test]
+    fn test_rule_errors() {
+        let error = |text: &str| match RuleSet::compile(text) {
+            Err(ChromiaError::Parse(msg)) => msg,
+            other => panic!("expected a parse error, got {:?}", other.map(|set| set.rules.len())),
+        };
+        assert!(error("import \"pe\"").contains("'import' isn't supported"));
+        assert!(error("rule a { strings: $a = \"x\" condition: $b }").contains("$b isn't defined"));
+        assert!(error("rule a { strings: $a = \"x\" xor condition: $a }").contains("the xor modifier isn't supported"));
+        assert!(error("rule a { strings: $a = { 4D 5 } condition: $a }").contains("$a"));
+        assert!(error("rule a { condition: true }\n\nrule a { condition: false }").starts_with("line 3"));
+        assert!(error("rule a { strings: $a = /(/ condition: $a }").contains("not a valid pattern"));
+    }
+}
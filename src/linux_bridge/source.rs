@@ -0,0 +1,567 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::lara_core::core_enums::ChromiaError;
+use crate::linux_bridge::network::{self, PacketData};
+use crate::linux_bridge::process::{self, ProcessInfo, UserResolver};
+use crate::linux_bridge::system::{FileEntry, PrivilegedFile};
+use crate::linux_bridge::{auth, sam, system};
+
+// Everything an analysis module reads from the host goes through this trait so modules can be handed a
+// fake system in unit tests. LinuxSource is what Chromia runs with, FakeSource (tests only) serves crafted data.
+pub trait SystemSource: Send + Sync {
+    fn btmp_dump(&self) -> Result<String, ChromiaError>;
+    fn wtmp_dump(&self) -> Result<String, ChromiaError>;
+    fn cpu_usage(&self) -> Result<String, ChromiaError>;
+    fn memory_usage(&self) -> Result<String, ChromiaError>;
+    fn read_to_string(&self, path: &str) -> Result<String, ChromiaError>;
+    // The raw contents of a file, None when it is bigger than max_len
+    fn read_bytes(&self, path: &str, max_len: u64) -> Result<Option<Vec<u8>>, ChromiaError>;
+    // Everything in a file from offset to its end
+    fn read_from(&self, path: &str, offset: u64) -> Result<Vec<u8>, ChromiaError>;
+    // Tells a log that grew apart from one that was rotated or truncated
+    fn inode_and_size(&self, path: &str) -> Result<(u64, u64), ChromiaError>;
+    fn path_exists(&self, path: &str) -> bool;
+    // Used by modules to persist state between runs of Chromia, creates the parent folder if needed
+    fn write_string(&self, path: &str, content: &str) -> Result<(), ChromiaError>;
+    // uid of the owner of a file
+    fn file_owner(&self, path: &str) -> Result<u32, ChromiaError>;
+    fn file_hash(&self, path: &str) -> Result<String, ChromiaError>;
+    fn folder_hash(&self, path: &str) -> Result<String, ChromiaError>;
+    fn processes(&self) -> Vec<ProcessInfo>;
+    fn username(&self, uid: u32) -> Option<String>;
+    fn uid(&self, username: &str) -> Option<u32>;
+    fn interfaces(&self) -> Vec<String>;
+    fn capture_packets(&self, interface: &str, duration: Duration, on_packet: &mut dyn FnMut(PacketData)) -> Result<(), ChromiaError>;
+    // socket inode -> (pid, process name) of the process holding it
+    fn socket_owners(&self) -> HashMap<u64, (u32, String)>;
+    // pid -> [(fd number, socket inode)] for every process with a socket open
+    fn socket_fds(&self) -> HashMap<u32, Vec<(u32, u64)>>;
+    // package name -> version from dpkg or rpm
+    fn installed_packages(&self) -> Result<HashMap<String, String>, ChromiaError>;
+    // Names of the entries in a folder
+    fn read_dir(&self, path: &str) -> Result<Vec<String>, ChromiaError>;
+    // pids listed in /proc
+    fn pids(&self) -> Vec<u32>;
+    // Asks the kernel directly whether a pid exists, without going through /proc
+    fn pid_alive(&self, pid: u32) -> bool;
+    // True if binding the port fails because something already holds it
+    fn port_bound(&self, protocol: &str, port: u16) -> bool;
+    // SUID/SGID files and files with capabilities under a folder, without leaving its filesystem
+    fn privileged_files(&self, root: &str) -> Result<Vec<PrivilegedFile>, ChromiaError>;
+    fn file_sha256(&self, path: &str) -> Result<String, ChromiaError>;
+    // Every regular file under a folder, with its modification time and size
+    fn list_files(&self, root: &str) -> Result<Vec<FileEntry>, ChromiaError>;
+    // pids of the processes that have a file open
+    fn file_holders(&self, path: &str) -> Vec<u32>;
+    // Writes a file that doesn't exist yet, already owned by the user (and their primary group) and with its permission
+    // bits so it is never readable by anyone else. The file is removed again if it can't be handed over
+    fn create_owned_file(&self, path: &str, content: &str, uid: u32, mode: u32) -> Result<(), ChromiaError>;
+}
+
+#[derive(Default)]
+pub struct LinuxSource {
+    users: UserResolver,
+}
+
+impl SystemSource for LinuxSource {
+    fn btmp_dump(&self) -> Result<String, ChromiaError> {
+        auth::btmp_dump()
+    }
+    fn wtmp_dump(&self) -> Result<String, ChromiaError> {
+        auth::wtmp_dump()
+    }
+    fn cpu_usage(&self) -> Result<String, ChromiaError> {
+        sam::cpu_usage()
+    }
+    fn memory_usage(&self) -> Result<String, ChromiaError> {
+        sam::memory_usage()
+    }
+    fn read_to_string(&self, path: &str) -> Result<String, ChromiaError> {
+        Ok(fs::read_to_string(path)?)
+    }
+    fn read_bytes(&self, path: &str, max_len: u64) -> Result<Option<Vec<u8>>, ChromiaError> {
+        if fs::metadata(path)?.len() > max_len {
+            return Ok(None);
+        }
+        Ok(Some(fs::read(path)?))
+    }
+    fn read_from(&self, path: &str, offset: u64) -> Result<Vec<u8>, ChromiaError> {
+        let mut file = fs::File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        Ok(content)
+    }
+    fn inode_and_size(&self, path: &str) -> Result<(u64, u64), ChromiaError> {
+        let metadata = fs::metadata(path)?;
+        Ok((metadata.ino(), metadata.len()))
+    }
+    fn path_exists(&self, path: &str) -> bool {
+        Path::new(path).exists()
+    }
+    fn write_string(&self, path: &str, content: &str) -> Result<(), ChromiaError> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        system::sys_file_write(path, content)
+    }
+    fn file_owner(&self, path: &str) -> Result<u32, ChromiaError> {
+        Ok(fs::metadata(path).map(|metadata| metadata.uid())?)
+    }
+    // Files are hashed with the b3sum binary shipped in Chromia's install folder
+    fn file_hash(&self, path: &str) -> Result<String, ChromiaError> {
+        let output = system::run_command_output("/bin/Chromia/Data/b3sum", &[path, "--no-names"])?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !stderr.is_empty() {
+            eprintln!("stderr for key '{}': {}", path, stderr);
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+    fn folder_hash(&self, path: &str) -> Result<String, ChromiaError> {
+        dirhash::hash(Path::new(path))
+            .map(|hash| hash.to_string())
+            .map_err(|err| ChromiaError::Io(std::io::Error::other(err.to_string())))
+    }
+    fn processes(&self) -> Vec<ProcessInfo> {
+        process::list_processes()
+    }
+    fn username(&self, uid: u32) -> Option<String> {
+        self.users.username(uid)
+    }
+    // Users from LDAP or other NSS sources aren't in /etc/passwd, getpwnam finds those
+    fn uid(&self, username: &str) -> Option<u32> {
+        self.users.uid(username).or_else(|| nix::unistd::User::from_name(username).ok().flatten().map(|user| user.uid.as_raw()))
+    }
+    fn interfaces(&self) -> Vec<String> {
+        network::interface_names()
+    }
+    fn capture_packets(&self, interface: &str, duration: Duration, on_packet: &mut dyn FnMut(PacketData)) -> Result<(), ChromiaError> {
+        network::capture_packets(interface, duration, on_packet)
+    }
+    fn socket_owners(&self) -> HashMap<u64, (u32, String)> {
+        network::socket_owners()
+    }
+    fn socket_fds(&self) -> HashMap<u32, Vec<(u32, u64)>> {
+        network::socket_fds()
+    }
+    fn installed_packages(&self) -> Result<HashMap<String, String>, ChromiaError> {
+        system::installed_packages()
+    }
+    fn read_dir(&self, path: &str) -> Result<Vec<String>, ChromiaError> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(path)? {
+            names.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        Ok(names)
+    }
+    fn pids(&self) -> Vec<u32> {
+        process::list_pids()
+    }
+    fn pid_alive(&self, pid: u32) -> bool {
+        process::pid_alive(pid)
+    }
+    fn port_bound(&self, protocol: &str, port: u16) -> bool {
+        network::port_bound(protocol, port)
+    }
+    fn privileged_files(&self, root: &str) -> Result<Vec<PrivilegedFile>, ChromiaError> {
+        system::find_privileged_files(root)
+    }
+    fn file_sha256(&self, path: &str) -> Result<String, ChromiaError> {
+        system::sha256_file(path)
+    }
+    fn list_files(&self, root: &str) -> Result<Vec<FileEntry>, ChromiaError> {
+        system::list_files(root)
+    }
+    fn file_holders(&self, path: &str) -> Vec<u32> {
+        process::file_holders(path)
+    }
+    fn create_owned_file(&self, path: &str, content: &str, uid: u32, mode: u32) -> Result<(), ChromiaError> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        system::create_owned_file(path, content, uid, mode)
+    }
+}
+
+// Remembers how far into a log file has been read. Only the bytes written since the last read are read, so a log
+// of hundreds of MB costs no more than a small one. Rotation is noticed by the inode changing, when it happens
+// whatever was written to the old file (now path.1) since the last read is picked up before the new file. A log
+// truncated in place (logrotate's copytruncate) is read again from the start.
+#[derive(Debug, Clone, Default)]
+pub struct LogTail {
+    // (inode, bytes read) of the file being followed
+    position: Option<(u64, u64)>,
+}
+
+impl LogTail {
+    // Returns what was written since the last call. The first call only records where the log ends, what is
+    // already in it is history. Logs can hold bytes that aren't UTF-8 (anything a client sends ends up in them),
+    // those are replaced rather than failing the read.
+    pub fn read_new(&mut self, source: &dyn SystemSource, path: &str) -> Result<String, ChromiaError> {
+        let (inode, size) = source.inode_and_size(path)?;
+        let mut new = Vec::new();
+        let start = match self.position {
+            None => size,
+            Some((old_inode, offset)) if old_inode == inode && offset <= size => offset,
+            Some((old_inode, offset)) => {
+                let rotated = format!("{}.1", path);
+                if old_inode != inode && source.inode_and_size(&rotated).is_ok_and(|(rotated_inode, _)| rotated_inode == old_inode) {
+                    new.extend(source.read_from(&rotated, offset).unwrap_or_default());
+                }
+                0
+            }
+        };
+        let mut read = 0;
+        if self.position.is_some() {
+            let content = source.read_from(path, start)?;
+            read = content.len() as u64;
+            new.extend(content);
+        }
+        self.position = Some((inode, start + read));
+        Ok(String::from_utf8_lossy(&new).into_owned())
+    }
+}
+
+#[cfg(test)]
+pub use fake::FakeSource;
+
+#[cfg(test)]
+mod fake {
+    use super::*;
+    use std::sync::Mutex;
+
+    // In memory system for unit tests. Every setter takes &self so a test can keep a handle to the
+    // source after giving it to a module and change what the "system" looks like between ticks.
+    #[derive(Default)]
+    pub struct FakeSource {
+        btmp: Mutex<String>,
+        wtmp: Mutex<String>,
+        cpu: Mutex<String>,
+        memory: Mutex<String>,
+        files: Mutex<HashMap<String, String>>,
+        owners: Mutex<HashMap<String, u32>>,
+        hashes: Mutex<HashMap<String, String>>,
+        processes: Mutex<Vec<ProcessInfo>>,
+        users: Mutex<HashMap<u32, String>>,
+        interfaces: Mutex<Vec<String>>,
+        packets: Mutex<Vec<PacketData>>,
+        socket_owners: Mutex<HashMap<u64, (u32, String)>>,
+        socket_fds: Mutex<HashMap<u32, Vec<(u32, u64)>>>,
+        missing_commands: Mutex<Vec<String>>,
+        packages: Mutex<HashMap<String, String>>,
+        hidden_pids: Mutex<Vec<u32>>,
+        bound_ports: Mutex<Vec<(String, u16)>>,
+        privileged_files: Mutex<Vec<PrivilegedFile>>,
+        modified: Mutex<HashMap<String, i64>>,
+        modes: Mutex<HashMap<String, u32>>,
+        file_holders: Mutex<HashMap<String, Vec<u32>>>,
+        uid_lookups: Mutex<usize>,
+        chown_fails: Mutex<bool>,
+        // Every file set gets its own inode, handed on to path.1 by rotate_file
+        inodes: Mutex<HashMap<String, u64>>,
+        next_inode: Mutex<u64>,
+    }
+
+    impl FakeSource {
+        pub fn new() -> Self {
+            Self::default()
+        }
+        // How many times uid has been called
+        pub fn uid_lookups(&self) -> usize {
+            *self.uid_lookups.lock().unwrap()
+        }
+        pub fn set_btmp(&self, dump: &str) {
+            *self.btmp.lock().unwrap() = dump.to_string();
+        }
+        pub fn set_wtmp(&self, dump: &str) {
+            *self.wtmp.lock().unwrap() = dump.to_string();
+        }
+        pub fn set_cpu_usage(&self, output: &str) {
+            *self.cpu.lock().unwrap() = output.to_string();
+        }
+        pub fn set_memory_usage(&self, output: &str) {
+            *self.memory.lock().unwrap() = output.to_string();
+        }
+        pub fn set_file(&self, path: &str, content: &str) {
+            self.files.lock().unwrap().insert(path.to_string(), content.to_string());
+            if !self.inodes.lock().unwrap().contains_key(path) {
+                let mut next_inode = self.next_inode.lock().unwrap();
+                *next_inode += 1;
+                self.inodes.lock().unwrap().insert(path.to_string(), *next_inode);
+            }
+        }
+        pub fn remove_file(&self, path: &str) {
+            self.files.lock().unwrap().remove(path);
+            self.inodes.lock().unwrap().remove(path);
+        }
+        // Renames path to path.1 the way logrotate does, the next set_file of path creates a new file
+        pub fn rotate_file(&self, path: &str) {
+            let rotated = format!("{}.1", path);
+            let mut files = self.files.lock().unwrap();
+            if let Some(content) = files.remove(path) {
+                files.insert(rotated.clone(), content);
+            }
+            let mut inodes = self.inodes.lock().unwrap();
+            if let Some(inode) = inodes.remove(path) {
+                inodes.insert(rotated, inode);
+            }
+        }
+        pub fn set_owner(&self, path: &str, uid: u32) {
+            self.owners.lock().unwrap().insert(path.to_string(), uid);
+        }
+        // Used for both file_hash and folder_hash
+        pub fn set_hash(&self, path: &str, hash: &str) {
+            self.hashes.lock().unwrap().insert(path.to_string(), hash.to_string());
+        }
+        pub fn set_processes(&self, processes: Vec<ProcessInfo>) {
+            *self.processes.lock().unwrap() = processes;
+        }
+        pub fn add_user(&self, uid: u32, username: &str) {
+            self.users.lock().unwrap().insert(uid, username.to_string());
+        }
+        pub fn set_interfaces(&self, interfaces: &[&str]) {
+            *self.interfaces.lock().unwrap() = interfaces.iter().map(|name| name.to_string()).collect();
+        }
+        pub fn set_packets(&self, packets: Vec<PacketData>) {
+            *self.packets.lock().unwrap() = packets;
+        }
+        pub fn set_socket_owner(&self, inode: u64, pid: u32, name: &str) {
+            self.socket_owners.lock().unwrap().insert(inode, (pid, name.to_string()));
+        }
+        pub fn set_socket_fd(&self, pid: u32, fd: u32, inode: u64) {
+            self.socket_fds.lock().unwrap().entry(pid).or_default().push((fd, inode));
+        }
+        pub fn set_packages(&self, packages: &[(&str, &str)]) {
+            *self.packages.lock().unwrap() = packages.iter().map(|(name, version)| (name.to_string(), version.to_string())).collect();
+        }
+        // pids that are alive but left out of pids(), the way a rootkit hides them from /proc
+        pub fn set_hidden_pids(&self, pids: &[u32]) {
+            *self.hidden_pids.lock().unwrap() = pids.to_vec();
+        }
+        pub fn set_bound_ports(&self, ports: &[(&str, u16)]) {
+            *self.bound_ports.lock().unwrap() = ports.iter().map(|(protocol, port)| (protocol.to_string(), *port)).collect();
+        }
+        // Modification time list_files reports for a file, files without one show 0
+        pub fn set_modified(&self, path: &str, modified: i64) {
+            self.modified.lock().unwrap().insert(path.to_string(), modified);
+        }
+        // Permission bits given to a file by create_owned_file
+        pub fn mode(&self, path: &str) -> Option<u32> {
+            self.modes.lock().unwrap().get(path).copied()
+        }
+        // Makes create_owned_file fail the way it does when Chromia isn't allowed to chown
+        pub fn set_chown_fails(&self, fails: bool) {
+            *self.chown_fails.lock().unwrap() = fails;
+        }
+        pub fn set_file_holders(&self, path: &str, pids: &[u32]) {
+            self.file_holders.lock().unwrap().insert(path.to_string(), pids.to_vec());
+        }
+        pub fn set_privileged_files(&self, files: Vec<PrivilegedFile>) {
+            *self.privileged_files.lock().unwrap() = files;
+        }
+        // Makes the dumps backed by this program (utmpdump, top or free) fail the way a host without it would
+        pub fn set_command_missing(&self, program: &str) {
+            self.missing_commands.lock().unwrap().push(program.to_string());
+        }
+        fn run(&self, program: &str, output: &Mutex<String>) -> Result<String, ChromiaError> {
+            if self.missing_commands.lock().unwrap().iter().any(|missing| missing == program) {
+                return Err(ChromiaError::CommandMissing(program.to_string()));
+            }
+            Ok(output.lock().unwrap().clone())
+        }
+    }
+
+    fn not_found(path: &str) -> ChromiaError {
+        ChromiaError::Io(std::io::Error::new(std::io::ErrorKind::NotFound, format!("'{}' does not exist in FakeSource", path)))
+    }
+
+    impl SystemSource for FakeSource {
+        fn btmp_dump(&self) -> Result<String, ChromiaError> {
+            self.run("utmpdump", &self.btmp)
+        }
+        fn wtmp_dump(&self) -> Result<String, ChromiaError> {
+            self.run("utmpdump", &self.wtmp)
+        }
+        fn cpu_usage(&self) -> Result<String, ChromiaError> {
+            self.run("top", &self.cpu)
+        }
+        fn memory_usage(&self) -> Result<String, ChromiaError> {
+            self.run("free", &self.memory)
+        }
+        fn read_to_string(&self, path: &str) -> Result<String, ChromiaError> {
+            self.files.lock().unwrap().get(path).cloned().ok_or_else(|| not_found(path))
+        }
+        fn read_bytes(&self, path: &str, max_len: u64) -> Result<Option<Vec<u8>>, ChromiaError> {
+            let content = self.read_to_string(path)?;
+            Ok((content.len() as u64 <= max_len).then(|| content.into_bytes()))
+        }
+        fn read_from(&self, path: &str, offset: u64) -> Result<Vec<u8>, ChromiaError> {
+            let content = self.read_to_string(path)?.into_bytes();
+            Ok(content.get(offset as usize..).unwrap_or_default().to_vec())
+        }
+        fn inode_and_size(&self, path: &str) -> Result<(u64, u64), ChromiaError> {
+            let size = self.read_to_string(path)?.len() as u64;
+            Ok((self.inodes.lock().unwrap().get(path).copied().unwrap_or_default(), size))
+        }
+        fn path_exists(&self, path: &str) -> bool {
+            self.files.lock().unwrap().contains_key(path) || self.hashes.lock().unwrap().contains_key(path)
+        }
+        fn write_string(&self, path: &str, content: &str) -> Result<(), ChromiaError> {
+            self.set_file(path, content);
+            Ok(())
+        }
+        fn file_owner(&self, path: &str) -> Result<u32, ChromiaError> {
+            self.owners.lock().unwrap().get(path).copied().ok_or_else(|| not_found(path))
+        }
+        fn file_hash(&self, path: &str) -> Result<String, ChromiaError> {
+            self.hashes.lock().unwrap().get(path).cloned().ok_or_else(|| not_found(path))
+        }
+        fn folder_hash(&self, path: &str) -> Result<String, ChromiaError> {
+            self.file_hash(path)
+        }
+        fn processes(&self) -> Vec<ProcessInfo> {
+            self.processes.lock().unwrap().clone()
+        }
+        fn username(&self, uid: u32) -> Option<String> {
+            self.users.lock().unwrap().get(&uid).cloned()
+        }
+        fn uid(&self, username: &str) -> Option<u32> {
+            *self.uid_lookups.lock().unwrap() += 1;
+            self.users.lock().unwrap().iter().find(|(_, name)| *name == username).map(|(uid, _)| *uid)
+        }
+        fn interfaces(&self) -> Vec<String> {
+            self.interfaces.lock().unwrap().clone()
+        }
+        fn capture_packets(&self, interface: &str, _duration: Duration, on_packet: &mut dyn FnMut(PacketData)) -> Result<(), ChromiaError> {
+            if !self.interfaces.lock().unwrap().iter().any(|name| name == interface) {
+                return Err(ChromiaError::Config(format!("Interface '{}' not found", interface)));
+            }
+            for packet in self.packets.lock().unwrap().drain(..) {
+                on_packet(packet);
+            }
+            Ok(())
+        }
+        fn socket_owners(&self) -> HashMap<u64, (u32, String)> {
+            self.socket_owners.lock().unwrap().clone()
+        }
+        fn socket_fds(&self) -> HashMap<u32, Vec<(u32, u64)>> {
+            self.socket_fds.lock().unwrap().clone()
+        }
+        fn installed_packages(&self) -> Result<HashMap<String, String>, ChromiaError> {
+            Ok(self.packages.lock().unwrap().clone())
+        }
+        // Folders only exist through the files and hashes under them
+        fn read_dir(&self, path: &str) -> Result<Vec<String>, ChromiaError> {
+            let prefix = format!("{}/", path.trim_end_matches('/'));
+            let mut names: Vec<String> = self
+                .files
+                .lock()
+                .unwrap()
+                .keys()
+                .chain(self.hashes.lock().unwrap().keys())
+                .filter_map(|file| file.strip_prefix(&prefix))
+                .filter_map(|rest| rest.split('/').next())
+                .map(|name| name.to_string())
+                .collect();
+            if names.is_empty() {
+                return Err(not_found(path));
+            }
+            names.sort();
+            names.dedup();
+            Ok(names)
+        }
+        fn pids(&self) -> Vec<u32> {
+            let hidden = self.hidden_pids.lock().unwrap();
+            self.processes.lock().unwrap().iter().map(|process| process.pid).filter(|pid| !hidden.contains(pid)).collect()
+        }
+        fn pid_alive(&self, pid: u32) -> bool {
+            self.processes.lock().unwrap().iter().any(|process| process.pid == pid) || self.hidden_pids.lock().unwrap().contains(&pid)
+        }
+        fn port_bound(&self, protocol: &str, port: u16) -> bool {
+            self.bound_ports.lock().unwrap().iter().any(|(bound, bound_port)| protocol.starts_with(bound.as_str()) && *bound_port == port)
+        }
+        fn privileged_files(&self, root: &str) -> Result<Vec<PrivilegedFile>, ChromiaError> {
+            let prefix = format!("{}/", root.trim_end_matches('/'));
+            Ok(self.privileged_files.lock().unwrap().iter().filter(|file| file.path.starts_with(&prefix)).cloned().collect())
+        }
+        fn file_sha256(&self, path: &str) -> Result<String, ChromiaError> {
+            self.file_hash(path)
+        }
+        fn list_files(&self, root: &str) -> Result<Vec<FileEntry>, ChromiaError> {
+            let prefix = format!("{}/", root.trim_end_matches('/'));
+            let modified = self.modified.lock().unwrap();
+            let files: Vec<FileEntry> = self
+                .files
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(path, _)| path.starts_with(&prefix))
+                .map(|(path, content)| FileEntry {
+                    path: path.clone(),
+                    modified: modified.get(path).copied().unwrap_or(0),
+                    size: content.len() as u64,
+                })
+                .collect();
+            if files.is_empty() {
+                return Err(not_found(root));
+            }
+            Ok(files)
+        }
+        fn file_holders(&self, path: &str) -> Vec<u32> {
+            self.file_holders.lock().unwrap().get(path).cloned().unwrap_or_default()
+        }
+        fn create_owned_file(&self, path: &str, content: &str, uid: u32, mode: u32) -> Result<(), ChromiaError> {
+            if self.files.lock().unwrap().contains_key(path) {
+                return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("{} already exists", path)).into());
+            }
+            if *self.chown_fails.lock().unwrap() {
+                return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, format!("Can't chown {}", path)).into());
+            }
+            self.set_file(path, content);
+            self.set_owner(path, uid);
+            self.modes.lock().unwrap().insert(path.to_string(), mode);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn append(path: &str, bytes: &[u8]) {
+        fs::OpenOptions::new().create(true).append(true).open(path).unwrap().write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn test_log_tail_reads_invalid_utf8_and_follows_rotation() {
+        let folder = std::env::temp_dir().join(format!("chromia-logtail-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("auth.log").display().to_string();
+        let source = LinuxSource::default();
+        let mut tail = LogTail::default();
+
+        append(&path, b"history\n");
+        assert_eq!(tail.read_new(&source, &path).unwrap(), "");
+        append(&path, b"sshd: Invalid user \xff\xfe from 198.51.100.4\n");
+        assert_eq!(tail.read_new(&source, &path).unwrap(), "sshd: Invalid user \u{fffd}\u{fffd} from 198.51.100.4\n");
+        assert_eq!(tail.read_new(&source, &path).unwrap(), "");
+
+        append(&path, b"before rotation\n");
+        fs::rename(&path, format!("{}.1", path)).unwrap();
+        append(&path, b"after rotation\n");
+        assert_eq!(tail.read_new(&source, &path).unwrap(), "before rotation\nafter rotation\n");
+
+        // copytruncate empties the file in place
+        fs::OpenOptions::new().write(true).truncate(true).open(&path).unwrap();
+        append(&path, b"new\n");
+        assert_eq!(tail.read_new(&source, &path).unwrap(), "new\n");
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
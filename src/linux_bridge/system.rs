@@ -2,10 +2,14 @@ use chrono::Local;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io;
-use std::io::*;
+use std::io::{BufRead, BufReader, Write};
 use ini::Ini;
+use sha2::{Digest, Sha256};
+use std::os::unix::fs::MetadataExt;
 
+use std::process::{Command, Output};
 use std::str;
+use crate::lara_core::core_enums::ChromiaError;
  //I am aware this is currently not being used, but I am keeping it here for future reference.
 
 
@@ -20,33 +24,378 @@ pub fn system_time() -> String {
 }
 
 //Function to get and print system name "Ubuntu"
-pub fn system_name() -> String {
-    return System::name().unwrap();
+pub fn system_name() -> Result<String, ChromiaError> {
+    System::name().ok_or_else(|| ChromiaError::Parse("system name from /etc/os-release".to_owned()))
 }
 
 // Function to get and print system host name "eriklaptop"
-pub fn system_host_name() -> String {
-    return System::host_name().unwrap();
+pub fn system_host_name() -> Result<String, ChromiaError> {
+    System::host_name().ok_or_else(|| ChromiaError::Parse("host name".to_owned()))
 }
 
 // Function to get and print system kernel version "5.4.0-42-generic"
-pub fn system_kernel_version() -> String {
-    return System::kernel_version().unwrap();
+pub fn system_kernel_version() -> Result<String, ChromiaError> {
+    System::kernel_version().ok_or_else(|| ChromiaError::Parse("kernel version".to_owned()))
 }
 
 // Function to get and print system OS version "20.04.1"
-pub fn system_os_version() -> String {
+pub fn system_os_version() -> Result<String, ChromiaError> {
+    System::os_version().ok_or_else(|| ChromiaError::Parse("OS version from /etc/os-release".to_owned()))
+}
+
+//Function to run a program and return the full output. A program that is not installed comes back as
+//CommandMissing instead of a panic, so a host without eg utmpdump only loses the module that needs it.
+pub fn run_command_output(program: &str, args: &[&str]) -> Result<Output, ChromiaError> {
+    let output = Command::new(program).args(args).output().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => ChromiaError::CommandMissing(program.to_owned()),
+        _ => ChromiaError::from(e),
+    })?;
+    // sudo and sh report a missing program through exit code 127 rather than failing to start. Which
+    // program that was can't be told from here, so stderr is passed on as it names it
+    if output.status.code() == Some(127) {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(ChromiaError::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("'{} {}' exited with 127, command not found: {}", program, args.join(" "), stderr),
+        )));
+    }
+    if !output.status.success() && output.stdout.is_empty() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        if stderr.to_lowercase().contains("permission denied") {
+            return Err(ChromiaError::Permission(format!("{} {}: {}", program, args.join(" "), stderr)));
+        }
+    }
+    Ok(output)
+}
+
+//Function to run a program and return what it printed to stdout
+pub fn run_command(program: &str, args: &[&str]) -> Result<String, ChromiaError> {
+    let output = run_command_output(program, args)?;
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+
+//Function to get the boot time of the system as a unix timestamp from the btime line of /proc/stat.
+//The kernel works this out from the wall clock minus time since boot, so it moves if the clock is changed.
+pub fn parse_boot_time(stat: &str) -> Option<i64> {
+    stat.lines()
+        .find(|line| line.starts_with("btime "))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|btime| btime.parse().ok())
+}
+
+//Function to get the seconds since boot from /proc/uptime Eg "35265.21 140245.52"
+pub fn parse_uptime(uptime: &str) -> Option<f64> {
+    uptime.split_whitespace().next().and_then(|secs| secs.parse().ok())
+}
+
+//Function to get a field from /etc/os-release Eg PRETTY_NAME="Ubuntu 24.04.1 LTS" -> Ubuntu 24.04.1 LTS
+pub fn parse_os_release(os_release: &str, field: &str) -> Option<String> {
+    os_release.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        if key.trim() != field {
+            return None;
+        }
+        Some(value.trim().trim_matches('"').trim_matches('\'').to_string())
+    })
+}
+
+// A loaded kernel module from /proc/modules
+#[derive(Debug, Clone, PartialEq)]
+pub struct KernelModule {
+    pub name: String,
+    pub size: u64,
+    pub used_by: Vec<String>,
+    // Live, Loading or Unloading
+    pub state: String,
+    // Taint flags the module set when it loaded, Eg "OE" for an out of tree unsigned module. Empty if none
+    pub taints: String,
+}
 
-    return System::os_version().unwrap();
+//Function to parse /proc/modules, each line looks like
+//nf_tables 360448 3 nft_ct,nft_chain_nat, Live 0x0000000000000000 (OE)
+//Where the fields are name, size, reference count, dependants, state, load address and (optionally) taint flags
+pub fn parse_modules(modules: &str) -> Vec<KernelModule> {
+    modules
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 5 {
+                return None;
+            }
+            Some(KernelModule {
+                name: fields[0].to_string(),
+                size: fields[1].parse().unwrap_or(0),
+                used_by: fields[3].split(',').filter(|dep| !dep.is_empty() && *dep != "-").map(|dep| dep.to_string()).collect(),
+                state: fields[4].to_string(),
+                taints: fields.get(6).map(|taints| taints.trim_matches(|c| c == '(' || c == ')').to_string()).unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+// Bits of /proc/sys/kernel/tainted and the letter the kernel prints for each in oops messages and /proc/modules
+const TAINT_FLAGS: [(char, &str); 19] = [
+    ('P', "proprietary module loaded"),
+    ('F', "module was force loaded"),
+    ('S', "kernel running on an out of specification system"),
+    ('R', "module was force unloaded"),
+    ('M', "processor reported a machine check exception"),
+    ('B', "bad page referenced or unexpected page flags"),
+    ('U', "taint requested by userspace"),
+    ('D', "kernel died recently (oops or BUG)"),
+    ('A', "ACPI table overridden by user"),
+    ('W', "kernel issued a warning"),
+    ('C', "staging driver loaded"),
+    ('I', "workaround for platform firmware bug applied"),
+    ('O', "out of tree module loaded"),
+    ('E', "unsigned module loaded"),
+    ('L', "soft lockup occurred"),
+    ('K', "kernel has been live patched"),
+    ('X', "auxiliary taint"),
+    ('T', "kernel built with struct randomization plugin"),
+    ('N', "an in-kernel test has been run"),
+];
+
+//Function to turn the number in /proc/sys/kernel/tainted into the flags that are set Eg 12288 -> [('O', ..), ('E', ..)]
+pub fn decode_taint(mask: u64) -> Vec<(char, &'static str)> {
+    TAINT_FLAGS
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| mask & (1 << bit) != 0)
+        .map(|(_, flag)| *flag)
+        .collect()
+}
+
+// A mounted filesystem from /proc/mounts
+#[derive(Debug, Clone, PartialEq)]
+pub struct MountEntry {
+    pub device: String,
+    pub mount_point: String,
+    pub fs_type: String,
+    pub options: Vec<String>,
+}
+
+//Function to parse /proc/mounts Eg "/dev/nvme0n1p5 / ext4 rw,relatime 0 0". Spaces in paths are written as \040
+pub fn parse_mounts(mounts: &str) -> Vec<MountEntry> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 {
+                return None;
+            }
+            Some(MountEntry {
+                device: unescape_mount(fields[0]),
+                mount_point: unescape_mount(fields[1]),
+                fs_type: fields[2].to_string(),
+                options: fields[3].split(',').map(|option| option.to_string()).collect(),
+            })
+        })
+        .collect()
 }
 
+fn unescape_mount(field: &str) -> String {
+    field.replace("\\040", " ").replace("\\011", "\t").replace("\\012", "\n").replace("\\134", "\\")
+}
+
+//Function to get the installed packages (name -> version) from the dpkg database at /var/lib/dpkg/status.
+//Packages that were removed but still have config files left behind are skipped.
+pub fn parse_dpkg_status(status: &str) -> HashMap<String, String> {
+    let mut packages = HashMap::new();
+    for paragraph in status.split("\n\n") {
+        let mut name = None;
+        let mut version = None;
+        let mut installed = false;
+        for line in paragraph.lines() {
+            if let Some(value) = line.strip_prefix("Package: ") {
+                name = Some(value.trim());
+            } else if let Some(value) = line.strip_prefix("Version: ") {
+                version = Some(value.trim());
+            } else if let Some(value) = line.strip_prefix("Status: ") {
+                installed = value.trim().ends_with(" installed");
+            }
+        }
+        if let (Some(name), Some(version), true) = (name, version, installed) {
+            packages.insert(name.to_string(), version.to_string());
+        }
+    }
+    packages
+}
+
+//Function to parse the output of rpm -qa --qf '%{NAME} %{VERSION}-%{RELEASE}\n'
+pub fn parse_rpm_query(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, version)| (name.to_string(), version.trim().to_string()))
+        .collect()
+}
+
+//Function to list installed packages from dpkg (Debian/Ubuntu) or rpm (RHEL/Fedora/SUSE).
+//Hosts with neither come back empty rather than as an error.
+pub fn installed_packages() -> Result<HashMap<String, String>, ChromiaError> {
+    if std::path::Path::new("/var/lib/dpkg/status").exists() {
+        return Ok(parse_dpkg_status(&sys_file_read("/var/lib/dpkg/status")?));
+    }
+    match run_command("rpm", &["-qa", "--qf", "%{NAME} %{VERSION}-%{RELEASE}\\n"]) {
+        Ok(output) => Ok(parse_rpm_query(&output)),
+        Err(ChromiaError::CommandMissing(_)) => Ok(HashMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+// A file that runs with more privileges than the user starting it: SUID/SGID bits or file capabilities
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrivilegedFile {
+    pub path: String,
+    // Permission bits including the SUID (0o4000) and SGID (0o2000) bits
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    // Capabilities from the security.capability xattr in getcap's format, Eg "cap_net_raw=ep"
+    pub capabilities: Option<String>,
+    // The folder the file is in can be written to by anyone, Eg /tmp
+    pub world_writable_folder: bool,
+}
+
+// Capability numbers from linux/capability.h
+const CAPABILITY_NAMES: [&str; 41] = [
+    "cap_chown", "cap_dac_override", "cap_dac_read_search", "cap_fowner", "cap_fsetid", "cap_kill", "cap_setgid", "cap_setuid",
+    "cap_setpcap", "cap_linux_immutable", "cap_net_bind_service", "cap_net_broadcast", "cap_net_admin", "cap_net_raw", "cap_ipc_lock",
+    "cap_ipc_owner", "cap_sys_module", "cap_sys_rawio", "cap_sys_chroot", "cap_sys_ptrace", "cap_sys_pacct", "cap_sys_admin",
+    "cap_sys_boot", "cap_sys_nice", "cap_sys_resource", "cap_sys_time", "cap_sys_tty_config", "cap_mknod", "cap_lease",
+    "cap_audit_write", "cap_audit_control", "cap_setfcap", "cap_mac_override", "cap_mac_admin", "cap_syslog", "cap_wake_alarm",
+    "cap_block_suspend", "cap_audit_read", "cap_perfmon", "cap_bpf", "cap_checkpoint_restore",
+];
+
+//Function to decode the raw security.capability xattr (struct vfs_cap_data) into getcap's format Eg "cap_net_raw,cap_setuid=ep".
+//The header holds the revision and the effective flag, followed by 32 bit permitted/inheritable masks (two of each from revision 2 on)
+pub fn decode_capabilities(raw: &[u8]) -> Option<String> {
+    let word = |index: usize| -> Option<u64> { Some(u32::from_le_bytes(raw.get(index * 4..index * 4 + 4)?.try_into().ok()?) as u64) };
+    let header = word(0)?;
+    let (mut permitted, mut inheritable) = (word(1)?, word(2)?);
+    if header & 0xFF00_0000 >= 0x0200_0000 {
+        permitted |= word(3)? << 32;
+        inheritable |= word(4)? << 32;
+    }
+    let names: Vec<String> = (0..64)
+        .filter(|bit| (permitted | inheritable) & (1 << bit) != 0)
+        .map(|bit| CAPABILITY_NAMES.get(bit).map(|name| name.to_string()).unwrap_or_else(|| format!("cap_{}", bit)))
+        .collect();
+    if names.is_empty() {
+        return None;
+    }
+    let mut flags = String::new();
+    if header & 1 != 0 {
+        flags.push('e');
+    }
+    if inheritable != 0 {
+        flags.push('i');
+    }
+    if permitted != 0 {
+        flags.push('p');
+    }
+    Some(format!("{}={}", names.join(","), flags))
+}
+
+//Function to walk a folder for SUID/SGID files and files with capabilities. Stays on the filesystem the folder is on
+//(like find -xdev) so /proc and network mounts aren't walked. Folders that can't be read are skipped.
+pub fn find_privileged_files(root: &str) -> Result<Vec<PrivilegedFile>, ChromiaError> {
+    let root_metadata = std::fs::symlink_metadata(root)?;
+    let mut found = Vec::new();
+    let mut folders = vec![(std::path::PathBuf::from(root), root_metadata.mode() & 0o002 != 0)];
+    while let Some((folder, world_writable_folder)) = folders.pop() {
+        let entries = match std::fs::read_dir(&folder) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let metadata = match path.symlink_metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if metadata.dev() != root_metadata.dev() {
+                continue;
+            }
+            if metadata.is_dir() {
+                folders.push((path, metadata.mode() & 0o002 != 0));
+                continue;
+            }
+            if !metadata.is_file() {
+                continue;
+            }
+            let capabilities = xattr::get(&path, "security.capability").ok().flatten().and_then(|raw| decode_capabilities(&raw));
+            if metadata.mode() & 0o6000 != 0 || capabilities.is_some() {
+                found.push(PrivilegedFile {
+                    path: path.to_string_lossy().into_owned(),
+                    mode: metadata.mode() & 0o7777,
+                    uid: metadata.uid(),
+                    gid: metadata.gid(),
+                    capabilities,
+                    world_writable_folder,
+                });
+            }
+        }
+    }
+    Ok(found)
+}
+
+// A regular file found by list_files
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileEntry {
+    pub path: String,
+    // Last modification time in seconds since the epoch
+    pub modified: i64,
+    pub size: u64,
+}
+
+//Function to list every regular file under a folder with its modification time and size. Symlinks are not
+//followed and folders that can't be read are skipped.
+pub fn list_files(root: &str) -> Result<Vec<FileEntry>, ChromiaError> {
+    let mut found = Vec::new();
+    let mut folders = vec![std::path::PathBuf::from(root)];
+    // The root itself has to be readable, anything under it is best effort
+    std::fs::read_dir(root)?;
+    while let Some(folder) = folders.pop() {
+        let entries = match std::fs::read_dir(&folder) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let metadata = match path.symlink_metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if metadata.is_dir() {
+                folders.push(path);
+            } else if metadata.is_file() {
+                found.push(FileEntry {
+                    path: path.to_string_lossy().into_owned(),
+                    modified: metadata.mtime(),
+                    size: metadata.size(),
+                });
+            }
+        }
+    }
+    Ok(found)
+}
+
+//Function to get the SHA-256 of a file as hex, the hash most threat intel and VirusTotal lookups use
+pub fn sha256_file(path: &str) -> Result<String, ChromiaError> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
 
 /*
 Function to read the content of a file, with a path specified as a parameter, and return the content as a string (content), hence returns an io::Result<String> type.
 The file information is stored in the buffer, and the content is read line by line and stored in the content variable.
 The function returns the content as a string, and the function breaks if the content cant be read.
 */
-pub fn sys_file_read(filepath: &str) -> io::Result<String> {
+pub fn sys_file_read(filepath: &str) -> Result<String, ChromiaError> {
     let file = File::open(filepath)?;
     let mut reader = BufReader::new(file);
     let mut content = String::new();
@@ -68,7 +417,7 @@ pub fn sys_file_read(filepath: &str) -> io::Result<String> {
 //This could be modifed to return a Result<String> type, if we wanted to pass through the OK(log_content), as to move the information to another function.
 //This could be Result<()> if we dont want to return anything, or you could remove the Result<> and return.
 //This file_read() in theory should be able to kept now where ever we want to read a file and off the main function.
-pub fn file_read() -> std::io::Result<String> {
+pub fn file_read() -> Result<String, ChromiaError> {
     // Declare the path to the file
     let path = "/proc/net/tcp";
     // Read the content of the file
@@ -80,11 +429,12 @@ pub fn file_read() -> std::io::Result<String> {
     // Return Ok if the file is read successfully
     return Ok(log_content);
 }
-pub fn read_csv(path: String) -> io::Result<HashMap<String,HashMap<String,Vec<String>>>>{
-    let conf_res = Ini::load_from_file(path);
+pub fn read_csv(path: String) -> Result<HashMap<String,HashMap<String,Vec<String>>>, ChromiaError>{
+    let conf_res = Ini::load_from_file(&path);
     let conf = match conf_res{
         Ok(conf) => conf,
-        Err(_e) =>  panic!("Malformed config.ini file. Please update document to follow correct formatting"),
+        Err(ini::Error::Io(e)) => return Err(ChromiaError::from(e)),
+        Err(ini::Error::Parse(e)) => return Err(ChromiaError::Config(format!("Malformed config file '{}' ({}). Please update document to follow correct formatting", path, e))),
     };
     
     let mut config_map: HashMap<String, HashMap<String, Vec<String>>> = HashMap::new();
@@ -98,11 +448,7 @@ pub fn read_csv(path: String) -> io::Result<HashMap<String,HashMap<String,Vec<St
             
             if key.ends_with("[]") {
                 arr_key = key.strip_suffix("[]").unwrap_or("undefined").to_owned();
-                if sec.contains_key(&arr_key){
-                    sec.get_mut(&arr_key).unwrap().push(value.to_owned());
-                }else{
-                    sec.insert(arr_key,vec![value.to_owned()]);
-                }
+                sec.entry(arr_key).or_default().push(value.to_owned());
             } else {
                 sec.insert(key.to_string(), vec![value.to_string()]);
             }
@@ -116,12 +462,30 @@ pub fn read_csv(path: String) -> io::Result<HashMap<String,HashMap<String,Vec<St
 
 
 // Function to write to a file, with a path and content to a prespecified file path based on another functions declaration.
-pub fn sys_file_write(filepath: &str, content: &str) -> io::Result<()> {
+pub fn sys_file_write(filepath: &str, content: &str) -> Result<(), ChromiaError> {
     let mut file = File::create(filepath)?;
     file.write_all(content.as_bytes())?;
     Ok(())
 }
 
+// Function to create a file that must not exist yet, hand it to a user and set its permission bits before anything is
+// written to it. The group becomes the user's primary group. If any step fails the file is removed again
+pub fn create_owned_file(filepath: &str, content: &str, uid: u32, mode: u32) -> Result<(), ChromiaError> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    // The umask can still take bits away, so the mode is set again once the file is owned by the user
+    let mut file = std::fs::OpenOptions::new().write(true).create_new(true).mode(mode).open(filepath)?;
+    let gid = users::get_user_by_uid(uid).map(|user| user.primary_group_id());
+    let result = std::os::unix::fs::fchown(&file, Some(uid), gid)
+        .and_then(|_| file.set_permissions(std::fs::Permissions::from_mode(mode)))
+        .and_then(|_| file.write_all(content.as_bytes()));
+    if let Err(e) = result {
+        drop(file);
+        let _ = std::fs::remove_file(filepath);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
 //Function to write to a file, with a path and content as parameters, and return a Result<()> type.
 pub fn file_write() {
     let filepath = "/home/erik/Documents/test.txt";
@@ -141,14 +505,9 @@ pub fn file_write() {
 
 
 
-use std::process::Command;
 //Function to call CPU usage directly from the top command, and return the CPU usage as a string.
-pub fn cpu_usage() -> String {
-    let output = Command::new("top")
-        .output()
-        .expect("Failed to execute command");
-    let last = String::from_utf8_lossy(&output.stdout);
-    return last.to_string();
+pub fn cpu_usage() -> Result<String, ChromiaError> {
+    run_command("top", &["-b", "-n", "1"])
 }
 
 
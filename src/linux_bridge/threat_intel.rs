@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use crate::lara_core::core_enums::ChromiaError;
+use crate::linux_bridge::network::Cidr;
+use crate::linux_bridge::source::SystemSource;
+use crate::linux_bridge::system::FileEntry;
+
+// Where an indicator came from, shown in alerts so whoever reads them knows which list to look at
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndicatorSource {
+    // Name of the file inside the threat intel folder
+    pub file: String,
+    // Eg the malware family or the STIX indicator name, when the list has one
+    pub description: Option<String>,
+}
+
+impl std::fmt::Display for IndicatorSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.description {
+            Some(description) => write!(f, "'{}' ({})", self.file, description),
+            None => write!(f, "'{}'", self.file),
+        }
+    }
+}
+
+// Every indicator from every file in the folder
+#[derive(Debug, Default)]
+pub struct IndicatorSet {
+    ips: HashMap<IpAddr, IndicatorSource>,
+    networks: Vec<(Cidr, IndicatorSource)>,
+    // Lowercase, a domain also matches everything under it
+    domains: HashMap<String, IndicatorSource>,
+    // Lowercase hex MD5, SHA-1, SHA-256 or SHA-512
+    hashes: HashMap<String, IndicatorSource>,
+}
+
+impl IndicatorSet {
+    // Works out what kind of indicator the text is. Returns false when it isn't one, Eg a CSV header or a port
+    pub fn add(&mut self, indicator: &str, source: &IndicatorSource) -> bool {
+        let indicator = indicator.trim().trim_matches(|c| c == '"' || c == '\'').to_lowercase();
+        if let Ok(ip) = indicator.parse::<IpAddr>() {
+            self.ips.insert(ip.to_canonical(), source.clone());
+        } else if indicator.contains('/') && indicator.parse::<Cidr>().is_ok() {
+            self.networks.push((indicator.parse().unwrap(), source.clone()));
+        } else if matches!(indicator.len(), 32 | 40 | 64 | 128) && indicator.chars().all(|c| c.is_ascii_hexdigit()) {
+            self.hashes.insert(indicator, source.clone());
+        } else if is_domain(indicator.trim_start_matches("*.")) {
+            self.domains.insert(indicator.trim_start_matches("*.").to_string(), source.clone());
+        } else {
+            return false;
+        }
+        true
+    }
+    fn len(&self) -> usize {
+        self.ips.len() + self.networks.len() + self.domains.len() + self.hashes.len()
+    }
+    pub fn match_ip(&self, ip: &str) -> Option<&IndicatorSource> {
+        let ip = ip.parse::<IpAddr>().ok()?.to_canonical();
+        self.ips.get(&ip).or_else(|| self.networks.iter().find(|(network, _)| network.contains(&ip)).map(|(_, source)| source))
+    }
+    // www.evil.example matches an indicator for evil.example
+    pub fn match_domain(&self, domain: &str) -> Option<&IndicatorSource> {
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        let mut rest = domain.as_str();
+        loop {
+            if let Some(source) = self.domains.get(rest) {
+                return Some(source);
+            }
+            rest = rest.split_once('.')?.1;
+        }
+    }
+    pub fn match_hash(&self, hash: &str) -> Option<&IndicatorSource> {
+        self.hashes.get(&hash.trim().to_lowercase())
+    }
+}
+
+fn is_domain(text: &str) -> bool {
+    text.contains('.')
+        && !text.starts_with('.')
+        && !text.ends_with('.')
+        && text.chars().any(|c| c.is_ascii_alphabetic())
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_')
+}
+
+// Adds the indicators in one file to the set, going by its extension: .json is a STIX 2.1 bundle, .csv is comma
+// separated and anything else is one indicator per line. Returns how many were added
+pub fn parse_indicator_file(file: &str, content: &str, set: &mut IndicatorSet) -> Result<usize, ChromiaError> {
+    let before = set.len();
+    if file.ends_with(".json") {
+        parse_stix(file, content, set)?;
+    } else if file.ends_with(".csv") {
+        parse_csv(file, content, set);
+    } else {
+        parse_text(file, content, set);
+    }
+    Ok(set.len() - before)
+}
+
+// One indicator per line, # starts a comment. Hosts files (0.0.0.0 evil.example) work as well
+fn parse_text(file: &str, content: &str, set: &mut IndicatorSet) {
+    let source = IndicatorSource { file: file.to_string(), description: None };
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        let indicator = match (fields.next(), fields.next()) {
+            (Some("0.0.0.0" | "127.0.0.1"), Some(domain)) => domain,
+            (Some(indicator), _) => indicator,
+            (None, _) => continue,
+        };
+        set.add(indicator, &source);
+    }
+}
+
+// The first field in each row that is an indicator is used, so the column order doesn't matter. When there is a
+// header, which can be commented out like abuse.ch does, a description, malware or threat column describes the row
+fn parse_csv(file: &str, content: &str, set: &mut IndicatorSet) {
+    let mut description_column: Option<usize> = None;
+    let split = |line: &str| -> Vec<String> { line.split(',').map(|field| field.trim().trim_matches('"').to_string()).collect() };
+    for line in content.lines() {
+        let (commented, line) = match line.trim().strip_prefix('#') {
+            Some(rest) => (true, rest),
+            None => (false, line.trim()),
+        };
+        let fields = split(line);
+        if line.is_empty() || (commented && !line.contains(',')) {
+            continue;
+        }
+        let mut probe = IndicatorSet::default();
+        let unknown = IndicatorSource { file: String::new(), description: None };
+        let column = fields.iter().position(|field| probe.add(field, &unknown));
+        match column {
+            Some(column) if !commented => {
+                let description = description_column.and_then(|i| fields.get(i)).filter(|field| !field.is_empty()).cloned();
+                set.add(&fields[column], &IndicatorSource { file: file.to_string(), description });
+            }
+            Some(_) => {}
+            None => {
+                let header: Vec<String> = fields.iter().map(|field| field.to_lowercase()).collect();
+                description_column = ["description", "malware", "threat", "name", "tags", "comment"].iter().find_map(|name| header.iter().position(|field| field == name));
+            }
+        }
+    }
+}
+
+// Indicator objects are read from their pattern, Eg [ipv4-addr:value = '198.51.100.7'], and IP, domain and file
+// observables from their values. Revoked and expired indicators are left out
+fn parse_stix(file: &str, content: &str, set: &mut IndicatorSet) -> Result<(), ChromiaError> {
+    let json: Value = serde_json::from_str(content).map_err(|e| ChromiaError::Parse(format!("'{}' is not a STIX bundle: {}", file, e)))?;
+    let objects = match json.get("objects").and_then(Value::as_array) {
+        Some(objects) => objects.clone(),
+        None if json.get("type").is_some() => vec![json],
+        None => return Err(ChromiaError::Parse(format!("'{}' is not a STIX bundle: it has no objects", file))),
+    };
+    let now = Utc::now();
+    for object in &objects {
+        let text = |key: &str| object.get(key).and_then(Value::as_str);
+        let source = IndicatorSource { file: file.to_string(), description: text("name").map(|name| name.to_string()) };
+        match text("type") {
+            Some("indicator") => {
+                let expired = text("valid_until").and_then(|time| DateTime::parse_from_rfc3339(time).ok()).is_some_and(|time| time < now);
+                if object.get("revoked").and_then(Value::as_bool) == Some(true) || expired {
+                    continue;
+                }
+                for value in pattern_values(text("pattern").unwrap_or("")) {
+                    set.add(&value, &source);
+                }
+            }
+            Some("ipv4-addr" | "ipv6-addr" | "domain-name") => {
+                if let Some(value) = text("value") {
+                    set.add(value, &source);
+                }
+            }
+            Some("file") => {
+                for hash in object.get("hashes").and_then(Value::as_object).into_iter().flat_map(|hashes| hashes.values()) {
+                    if let Some(hash) = hash.as_str() {
+                        set.add(hash, &source);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// The values compared for equality with an address, domain or file hash in a STIX pattern
+fn pattern_values(pattern: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut rest = pattern;
+    while let Some(equals) = rest.find('=') {
+        let (before, after) = (&rest[..equals], &rest[equals + 1..]);
+        // The object path is whatever follows the last bracket or boolean operator before the =
+        let path = before.rsplit(['[', '(', ' ']).find(|part| !part.is_empty()).unwrap_or("");
+        let negated = before.ends_with(['!', '<', '>']);
+        let after = after.trim_start();
+        let value_end = after.strip_prefix('\'').and_then(|quoted| quoted.find('\'').map(|end| (quoted[..end].to_string(), end + 2)));
+        match value_end {
+            Some((value, end)) => {
+                let wanted = ["ipv4-addr:value", "ipv6-addr:value", "domain-name:value"].contains(&path) || path.starts_with("file:hashes.");
+                if wanted && !negated {
+                    values.push(value);
+                }
+                rest = &after[end..];
+            }
+            None => rest = after,
+        }
+    }
+    values
+}
+
+// Indicator files in a folder, loaded again whenever one of them is added, removed or changed
+pub struct ThreatIntel {
+    folder: String,
+    source: Arc<dyn SystemSource>,
+    // The files as they were when last loaded, None when the folder couldn't be read
+    loaded: Mutex<Option<Vec<FileEntry>>>,
+    indicators: RwLock<IndicatorSet>,
+}
+
+impl ThreatIntel {
+    pub fn new(folder: &str, source: Arc<dyn SystemSource>) -> Self {
+        ThreatIntel { folder: folder.to_string(), source, loaded: Mutex::new(Some(Vec::new())), indicators: RwLock::new(IndicatorSet::default()) }
+    }
+    // Reloads the folder if anything in it changed. Files that can't be read or parsed are returned with what went
+    // wrong, the rest are still loaded. A folder that can't be read is only returned the first time
+    pub fn refresh(&self) -> Vec<(String, ChromiaError)> {
+        let mut problems = Vec::new();
+        let mut loaded = self.loaded.lock().unwrap();
+        let mut files = match self.source.list_files(&self.folder) {
+            Ok(files) => files,
+            Err(e) => {
+                if loaded.is_some() {
+                    problems.push((format!("Could not read threat intel folder '{}'", self.folder), e));
+                    *self.indicators.write().unwrap() = IndicatorSet::default();
+                    *loaded = None;
+                }
+                return problems;
+            }
+        };
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        if loaded.as_ref() == Some(&files) {
+            return problems;
+        }
+        let mut set = IndicatorSet::default();
+        for entry in &files {
+            let name = entry.path.strip_prefix(&self.folder).unwrap_or(&entry.path).trim_start_matches('/');
+            let result = self.source.read_to_string(&entry.path).and_then(|content| parse_indicator_file(name, &content, &mut set));
+            if let Err(e) = result {
+                problems.push((format!("Could not load threat intel file '{}'", entry.path), e));
+            }
+        }
+        *self.indicators.write().unwrap() = set;
+        *loaded = Some(files);
+        problems
+    }
+    pub fn match_ip(&self, ip: &str) -> Option<IndicatorSource> {
+        self.indicators.read().unwrap().match_ip(ip).cloned()
+    }
+    pub fn match_domain(&self, domain: &str) -> Option<IndicatorSource> {
+        self.indicators.read().unwrap().match_domain(domain).cloned()
+    }
+    pub fn match_hash(&self, hash: &str) -> Option<IndicatorSource> {
+        self.indicators.read().unwrap().match_hash(hash).cloned()
+    }
+}
+
+#[cfg(test)]
+impl ThreatIntel {
+    // Threat intel loaded from a single list, for testing the modules that use it
+    pub fn from_list(file: &str, content: &str) -> Self {
+        let source = Arc::new(crate::linux_bridge::source::FakeSource::new());
+        source.set_file(&format!("/etc/Chromia/threat_intel/{}", file), content);
+        let intel = ThreatIntel::new("/etc/Chromia/threat_intel", source);
+        assert!(intel.refresh().is_empty());
+        intel
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_bridge::source::FakeSource;
+
+    #[test]
+    fn test_indicator_formats() {
+        let mut set = IndicatorSet::default();
+        let text = "# Feodo blocklist\n198.51.100.7\n203.0.113.0/24 # whole range\n0.0.0.0 evil.example\nnot an indicator\n";
+        assert_eq!(parse_indicator_file("feodo.txt", text, &mut set).unwrap(), 3);
+        let csv = "# first_seen_utc,dst_ip,dst_port,malware\n\"2024-10-14 13:35:30\",\"192.0.2.44\",447,\"Emotet\"\n";
+        assert_eq!(parse_indicator_file("c2.csv", csv, &mut set).unwrap(), 1);
+        let stix = r#"{"type": "bundle", "id": "bundle--1", "objects": [
+            {"type": "indicator", "name": "Cobalt Strike beacon", "pattern": "[file:hashes.'SHA-256' = 'E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855'] OR [domain-name:value = 'c2.bad.example']"},
+            {"type": "indicator", "name": "Old", "revoked": true, "pattern": "[ipv4-addr:value = '192.0.2.99']"},
+            {"type": "indicator", "name": "Expired", "valid_until": "2020-01-01T00:00:00Z", "pattern": "[ipv4-addr:value = '192.0.2.98']"},
+            {"type": "indicator", "name": "Not this", "pattern": "[ipv4-addr:value != '192.0.2.97']"},
+            {"type": "ipv6-addr", "id": "ipv6-addr--1", "value": "2001:db8::bad"}
+        ]}"#;
+        assert_eq!(parse_indicator_file("intel.json", stix, &mut set).unwrap(), 3);
+        assert!(matches!(parse_indicator_file("broken.json", "{", &mut set), Err(ChromiaError::Parse(_))));
+
+        assert_eq!(set.match_ip("198.51.100.7").unwrap().to_string(), "'feodo.txt'");
+        assert_eq!(set.match_ip("::ffff:203.0.113.200").unwrap().file, "feodo.txt");
+        assert_eq!(set.match_ip("192.0.2.44").unwrap().to_string(), "'c2.csv' (Emotet)");
+        assert!(set.match_ip("2001:db8::bad").is_some());
+        assert!(set.match_ip("192.0.2.99").is_none() && set.match_ip("192.0.2.98").is_none() && set.match_ip("192.0.2.97").is_none());
+        assert!(set.match_domain("cdn.evil.example").is_some() && set.match_domain("notevil.example").is_none());
+        let beacon = set.match_hash("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855").unwrap();
+        assert_eq!(beacon.to_string(), "'intel.json' (Cobalt Strike beacon)");
+        assert_eq!(set.match_domain("C2.bad.example.").unwrap().file, "intel.json");
+    }
+
+    #[test]
+    fn test_folder_is_reloaded_when_it_changes() {
+        let source = Arc::new(FakeSource::new());
+        let intel = ThreatIntel::new("/etc/Chromia/threat_intel", source.clone());
+        // A missing folder is reported once
+        assert_eq!(intel.refresh().len(), 1);
+        assert!(intel.refresh().is_empty());
+
+        source.set_file("/etc/Chromia/threat_intel/blocklist.txt", "198.51.100.7\n");
+        assert!(intel.refresh().is_empty());
+        assert_eq!(intel.match_ip("198.51.100.7").unwrap().file, "blocklist.txt");
+
+        source.set_file("/etc/Chromia/threat_intel/blocklist.txt", "198.51.100.8\n");
+        source.set_file("/etc/Chromia/threat_intel/stix.json", "not json");
+        let problems = intel.refresh();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].0.contains("stix.json"));
+        assert!(intel.match_ip("198.51.100.7").is_none() && intel.match_ip("198.51.100.8").is_some());
+        assert_eq!(intel.indicators.read().unwrap().len(), 1);
+    }
+}
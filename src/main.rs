@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::collections::HashMap;
 use std::fs;
 use std::fs::OpenOptions;
@@ -10,9 +10,11 @@ use std::fs::File;
 use lara_core::core_traits::AnalysisModule;
 use std::thread;
 
+use crate::lara_core::core_enums::ChromiaError;
 use crate::lara_core::core_structs::*;
 pub mod analysis_modules;
 use crate::linux_bridge::*;
+use crate::linux_bridge::source::SystemSource;
 pub mod lara_core;
 pub mod linux_bridge;
 
@@ -22,6 +24,22 @@ struct Args {
     /// Activate debug mode
     #[arg(short, long, action)]
     debug: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print a snapshot of the host inventory (OS, kernel, packages, users, listening sockets, kernel modules and mounts) and exit
+    Inventory,
+    /// Scan a file, or every file in a folder, with the signature rules and exit. Exits with 1 when something matched
+    Scan {
+        /// File or folder to scan
+        path: String,
+        /// Rule file or folder of .yar files, instead of signatureRules from the config file
+        #[arg(long)]
+        rules: Option<String>,
+    },
 }
 
 fn main() {
@@ -30,6 +48,19 @@ fn main() {
     if args.debug {
         debug = true;
     }
+    if let Some(Command::Inventory) = args.command {
+        match analysis_modules::host_inventory::Inventory::collect(&source::LinuxSource::default()) {
+            Ok(inventory) => print!("{}", inventory.to_ini()),
+            Err(e) => {
+                eprintln!("Could not take host inventory: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(Command::Scan { path, rules }) = args.command {
+        std::process::exit(scan_command(&path, rules));
+    }
     // TODO: Put startup info in seperate function
     println!("Chromia({}) is starting", env!("CARGO_PKG_VERSION"));
 
@@ -42,7 +73,20 @@ fn main() {
         Box::new(<analysis_modules::network::Networking as std::default::Default>::default()),
         Box::new(<analysis_modules::authentication::Authentication as std::default::Default>::default()),
         Box::new(<analysis_modules::packet_sniffer::PacketSniffer as std::default::Default>::default()),
-        Box::new(<analysis_modules::httpserver::HTTPServer as std::default::Default>::default())
+        Box::new(<analysis_modules::httpserver::HTTPServer as std::default::Default>::default()),
+        Box::new(<analysis_modules::boot_tracker::BootTracker as std::default::Default>::default()),
+        Box::new(<analysis_modules::host_inventory::HostInventory as std::default::Default>::default()),
+        Box::new(<analysis_modules::process_monitor::ProcessMonitor as std::default::Default>::default()),
+        Box::new(<analysis_modules::rootkit_detection::RootkitDetector as std::default::Default>::default()),
+        Box::new(<analysis_modules::kernel_modules::KernelModuleMonitor as std::default::Default>::default()),
+        Box::new(<analysis_modules::persistence::PersistenceMonitor as std::default::Default>::default()),
+        Box::new(<analysis_modules::accounts::AccountMonitor as std::default::Default>::default()),
+        Box::new(<analysis_modules::privileged_files::PrivilegedFileMonitor as std::default::Default>::default()),
+        Box::new(<analysis_modules::outbound_connections::OutboundConnections as std::default::Default>::default()),
+        Box::new(<analysis_modules::cryptominer::CryptominerDetector as std::default::Default>::default()),
+        Box::new(<analysis_modules::webshell::WebshellDetector as std::default::Default>::default()),
+        Box::new(<analysis_modules::audit_log::AuditMonitor as std::default::Default>::default()),
+        Box::new(<analysis_modules::honeyfiles::HoneyFileMonitor as std::default::Default>::default())
     ];
 
     if !Path::new("/etc/Chromia/config.ini").exists() {
@@ -50,11 +94,14 @@ fn main() {
         return;
     }
 
-    let config_result: Result<HashMap<String, HashMap<String, Vec<String>>>, std::io::Error> =
+    let config_result: Result<HashMap<String, HashMap<String, Vec<String>>>, ChromiaError> =
         system::read_csv("/etc/Chromia/config.ini".to_owned());
     let config = match config_result {
         Ok(file) => file,
-        Err(error) => panic!("Problem opening the file: {error:?}"),
+        Err(error) => {
+            eprintln!("Problem opening the config file: {}", error);
+            std::process::exit(1);
+        }
     };
     println!("Successfully found config file!");
     // load core info
@@ -63,6 +110,10 @@ fn main() {
     core_fields_default.insert("logLocation".to_owned(), vec!["/var/log/Chormia.log".to_owned()]);
     core_fields_default.insert("verboseConsole".to_owned(), vec!["true".to_owned()]);
     core_fields_default.insert("printLogs".to_owned(), vec!["true".to_owned()]);
+    core_fields_default.insert("geoipLocationDatabase".to_owned(), vec!["".to_owned()]);
+    core_fields_default.insert("geoipAsnDatabase".to_owned(), vec!["".to_owned()]);
+    core_fields_default.insert("threatIntelFolder".to_owned(), vec!["".to_owned()]);
+    core_fields_default.insert("signatureRules".to_owned(), vec!["".to_owned()]);
   
     let core_fields: HashMap<String, Vec<String>> = match config.get("CoreSystem") {
         Some(s) => s.clone(),
@@ -123,6 +174,10 @@ fn main() {
     if verbose_output{
         println!("    loaded {} module/s", modules.len().to_string());
     }
+    let services = std::sync::Arc::new(build_services(&core_fields, &core_fields_default, verbose_output));
+    for module in modules.iter_mut() {
+        module.attach_services(services.clone());
+    }
 
     let mut logs: Vec<Log> = Vec::new();
     let mut i = 0;
@@ -133,6 +188,7 @@ fn main() {
         if verbose_output {
             println!("Starting Tick({})", i.to_string());
         }
+        logs.append(&mut services.refresh());
         for module in modules.iter_mut() {
             if module.get_data() {
                 if verbose_output {
@@ -159,6 +215,93 @@ fn main() {
         thread::sleep(tick_intervals)
     }
 }
+// Builds the helpers shared by modules. A GeoIP database that can't be opened is reported and left out, alerts
+// then just show bare addresses. The threat intel folder is loaded by the first tick
+fn build_services(core_fields: &HashMap<String, Vec<String>>, defaults: &HashMap<String, Vec<String>>, verbose_output: bool) -> CoreServices {
+    let field = |name: &str| -> String {
+        core_fields.get(name).or(defaults.get(name)).and_then(|vals| vals.first()).cloned().unwrap_or_default()
+    };
+    let (location, asn) = (field("geoipLocationDatabase"), field("geoipAsnDatabase"));
+    let mut services = CoreServices::default();
+    let intel_folder = field("threatIntelFolder");
+    if !intel_folder.is_empty() {
+        services.threat_intel = Some(std::sync::Arc::new(threat_intel::ThreatIntel::new(&intel_folder, std::sync::Arc::new(source::LinuxSource::default()))));
+    }
+    let rules_path = field("signatureRules");
+    if !rules_path.is_empty() {
+        let (rules, problems) = signatures::load_rules(&source::LinuxSource::default(), &rules_path);
+        for (context, e) in problems {
+            eprintln!("{}: {}", context, e);
+        }
+        if verbose_output {
+            println!("Loaded {} signature rule/s", rules.rules.len());
+        }
+        if !rules.rules.is_empty() {
+            services.signatures = Some(std::sync::Arc::new(rules));
+        }
+    }
+    if location.is_empty() && asn.is_empty() {
+        return services;
+    }
+    match geoip::MmdbGeoIp::open(&location, &asn) {
+        Ok(geoip) => {
+            if verbose_output {
+                println!("GeoIP enrichment enabled");
+            }
+            services.geoip = Some(std::sync::Arc::new(geoip));
+        }
+        Err(e) => eprintln!("GeoIP enrichment is disabled: {}", e),
+    }
+    services
+}
+// chromia scan <path>. Prints each match, returns 1 if anything matched and 2 if the rules couldn't be loaded
+fn scan_command(path: &str, rules_path: Option<String>) -> i32 {
+    let rules_path = match rules_path {
+        Some(rules_path) => rules_path,
+        None => match system::read_csv("/etc/Chromia/config.ini".to_owned()) {
+            Ok(config) => config.get("CoreSystem").and_then(|core| core.get("signatureRules")).and_then(|vals| vals.first()).cloned().unwrap_or_default(),
+            Err(e) => {
+                eprintln!("Problem opening the config file: {}", e);
+                return 2;
+            }
+        },
+    };
+    if rules_path.is_empty() {
+        eprintln!("No signature rules to scan with, set signatureRules in the config file or pass --rules");
+        return 2;
+    }
+    let source = source::LinuxSource::default();
+    let (rules, problems) = signatures::load_rules(&source, &rules_path);
+    for (context, e) in &problems {
+        eprintln!("{}: {}", context, e);
+    }
+    if rules.rules.is_empty() {
+        eprintln!("No signature rules could be loaded from '{}'", rules_path);
+        return 2;
+    }
+    let mut files: Vec<String> = match source.list_files(path) {
+        Ok(files) => files.into_iter().map(|file| file.path).collect(),
+        Err(_) => vec![path.to_owned()],
+    };
+    files.sort();
+    let mut matched = 0;
+    for file in &files {
+        match rules.scan_file(&source, file) {
+            Ok(Some(found)) => {
+                for rule in &found {
+                    println!("{}: {}", file, rule);
+                }
+                if !found.is_empty() {
+                    matched += 1;
+                }
+            }
+            Ok(None) => eprintln!("{}: skipped, bigger than {} MB", file, signatures::MAX_SCAN_SIZE / 1024 / 1024),
+            Err(e) => eprintln!("{}: could not be scanned ({})", file, e),
+        }
+    }
+    println!("Scanned {} file/s with {} rule/s, {} matched", files.len(), rules.rules.len(), matched);
+    if matched > 0 { 1 } else { 0 }
+}
 fn section_not_found(name: String) -> HashMap<String, Vec<String>> {
     println!(
         "Config for {} module was not found! Chromia will attempt to use default values",
@@ -172,7 +315,7 @@ fn create_config(mut modules: Vec<Box<dyn AnalysisModule>>) {
     let mut config_file_contents: String = String::new();
     let mut fields: Vec<ConfigField>;
     //Define core system fields
-    config_file_contents.push_str("[CoreSystem]\n;The time in milliseconds that the systems waits between checks \n;Higher numbers reduce performance impact and timeliness of alerts\ntickInterval=1000\n;Location to write log file\nlogLocation=/var/log/Chormia.log\n; Should Chromia print logs to console\nprintLogs=true\n; Print extra information about Chromia's status\nverboseConsole=true\n");
+    config_file_contents.push_str("[CoreSystem]\n;The time in milliseconds that the systems waits between checks \n;Higher numbers reduce performance impact and timeliness of alerts\ntickInterval=1000\n;Location to write log file\nlogLocation=/var/log/Chormia.log\n; Should Chromia print logs to console\nprintLogs=true\n; Print extra information about Chromia's status\nverboseConsole=true\n;Optional MaxMind format (mmdb) City or Country database, Eg /usr/share/GeoIP/GeoLite2-City.mmdb. Adds where an address is to alerts\ngeoipLocationDatabase=\n;Optional MaxMind format (mmdb) ASN database, Eg /usr/share/GeoIP/GeoLite2-ASN.mmdb. Adds who owns an address to alerts\ngeoipAsnDatabase=\n;Optional folder of threat intel indicator files: plain text lists, CSV or STIX 2.1 JSON bundles of IPs, CIDRs, domains and file hashes, Eg /etc/Chromia/threat_intel. Changes are picked up while running\nthreatIntelFolder=\n;Optional YARA style rule file, or folder of .yar files, that new and changed files are scanned with. Only text, hex and regex strings and conditions on them are supported\nsignatureRules=\n");
     for module in modules.iter_mut() {
         config_file_contents.push_str("[");
         config_file_contents.push_str(&module.get_name());
@@ -195,7 +338,10 @@ fn create_config(mut modules: Vec<Box<dyn AnalysisModule>>) {
     let file_result = system::sys_file_write("/etc/Chromia/config.ini", &config_file_contents);
     match file_result{
         Ok(_) => println!("Successfully created Config file.\n Please fill out file and re-run Chromia to activate"),
-        Err(_e) => panic!("Could not create file in current directory! (Does Chromia have write permissions?)"),
+        Err(e) => {
+            eprintln!("Could not create the config file ({}). Does Chromia have write permissions?", e);
+            std::process::exit(1);
+        }
     }
     return;
 }